@@ -0,0 +1,142 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Global allocator counting allocations and bytes by category. The
+// category is per thread and set with `scope` around the code to
+// attribute, everything else falls in `Other`. Reallocations count
+// as one allocation of the new size.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Category {
+    Other,
+    Loader,
+    Strings,
+    Records,
+}
+
+impl Category {
+    const ALL: [Category; 4] = [Self::Other, Self::Loader, Self::Strings, Self::Records];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Other => "other",
+            Self::Loader => "loader",
+            Self::Strings => "strings",
+            Self::Records => "records",
+        }
+    }
+}
+
+struct Counter {
+    count: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl Counter {
+    const fn new() -> Self {
+        Self {
+            count: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        }
+    }
+}
+
+static COUNTERS: [Counter; 4] = [
+    Counter::new(),
+    Counter::new(),
+    Counter::new(),
+    Counter::new(),
+];
+
+thread_local! {
+    static CURRENT: Cell<Category> = const { Cell::new(Category::Other) };
+}
+
+pub struct CountingAllocator;
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn record(size: usize) {
+    // the thread local is gone while the thread shuts down
+    let category = CURRENT.try_with(Cell::get).unwrap_or(Category::Other);
+    let counter = &COUNTERS[category as usize];
+    counter.count.fetch_add(1, Ordering::Relaxed);
+    counter.bytes.fetch_add(size as u64, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+pub struct Scope {
+    previous: Category,
+}
+
+// attribute the allocations of this thread to `category`
+// until the returned guard is dropped
+pub fn scope(category: Category) -> Scope {
+    let previous = CURRENT.with(|current| current.replace(category));
+    Scope { previous }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.previous));
+    }
+}
+
+pub fn totals(category: Category) -> (u64, u64) {
+    let counter = &COUNTERS[category as usize];
+    (
+        counter.count.load(Ordering::Relaxed),
+        counter.bytes.load(Ordering::Relaxed),
+    )
+}
+
+pub fn report() -> String {
+    let mut output = format!("{:<10} {:>12} {:>14}\n", "category", "allocations", "bytes");
+    for category in &Category::ALL {
+        let (count, bytes) = totals(*category);
+        output += &format!("{:<10} {:>12} {:>14}\n", category.name(), count, bytes);
+    }
+    output
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_scope_attribution() {
+        let (count, bytes) = totals(Category::Records);
+        {
+            let _scope = scope(Category::Records);
+            let data: Vec<u8> = Vec::with_capacity(100);
+            drop(data);
+        }
+        let after = totals(Category::Records);
+        assert!(after.0 > count);
+        assert!(after.1 >= bytes + 100);
+        assert_eq!(CURRENT.with(Cell::get), Category::Other);
+    }
+}
@@ -0,0 +1,207 @@
+use crate::command_definition::{
+    AddrSize, Block, BlockId, Command, Kind, Program, ProgramMemory, LOCAL_MASK,
+};
+use std::collections::HashSet;
+use std::fmt;
+
+#[derive(Debug)]
+pub struct MemoryWarning {
+    pub block: BlockId,
+    pub index: usize,
+    pub issue: MemoryIssue,
+}
+
+#[derive(Debug)]
+pub enum MemoryIssue {
+    GlobalLocalAlias(Kind, AddrSize),
+    ParamOutOfRange(usize, Kind, AddrSize),
+    ParamToGlobal(usize, Kind, AddrSize),
+}
+
+impl fmt::Display for MemoryWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, instruction {}: {}",
+            self.block, self.index, self.issue
+        )
+    }
+}
+
+impl fmt::Display for MemoryIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GlobalLocalAlias(kind, addr) => write!(
+                f,
+                "store to global {} {} also used as a local address",
+                kind, addr
+            ),
+            Self::ParamOutOfRange(func, kind, addr) => write!(
+                f,
+                "parameter {} {} is outside the memory declared by function {}",
+                kind,
+                addr - LOCAL_MASK,
+                func
+            ),
+            Self::ParamToGlobal(func, kind, addr) => write!(
+                f,
+                "parameter for function {} stored to global {} {}",
+                func, kind, addr
+            ),
+        }
+    }
+}
+
+pub fn memory_aliasing(prog: &Program, mem: &ProgramMemory) -> Vec<MemoryWarning> {
+    let mut output = Vec::new();
+    for (id, block) in prog.blocks() {
+        if let BlockId::Function(_) = id {
+            global_local_alias(id, block, &mut output);
+        }
+        record_protocol(id, block, mem, &mut output);
+    }
+    output
+}
+
+fn global_local_alias(id: BlockId, block: &Block, output: &mut Vec<MemoryWarning>) {
+    let locals: HashSet<(Kind, AddrSize)> = block
+        .code
+        .iter()
+        .filter_map(|cmd| match cmd {
+            Command::MemoryLoad(kind, addr) | Command::MemoryStore(kind, addr)
+                if addr & LOCAL_MASK != 0 =>
+            {
+                Some((*kind, addr - LOCAL_MASK))
+            }
+            _ => None,
+        })
+        .collect();
+
+    for (index, cmd) in block.code.iter().enumerate() {
+        if let Command::MemoryStore(kind, addr) = cmd {
+            if locals.contains(&(*kind, *addr)) {
+                let issue = MemoryIssue::GlobalLocalAlias(*kind, *addr);
+                output.push(MemoryWarning {
+                    block: id,
+                    index,
+                    issue,
+                });
+            }
+        }
+    }
+}
+
+// the loader already checks that every NewRecord is followed by its
+// parameters and by its call, look at where the parameters go
+fn record_protocol(
+    id: BlockId,
+    block: &Block,
+    mem: &ProgramMemory,
+    output: &mut Vec<MemoryWarning>,
+) {
+    let mut warn = |index, issue| {
+        output.push(MemoryWarning {
+            block: id,
+            index,
+            issue,
+        })
+    };
+
+    let mut pending: Option<usize> = None;
+    for (index, cmd) in block.code.iter().enumerate() {
+        match cmd {
+            Command::NewRecord(func) => pending = Some(*func),
+            Command::StoreParam(kind, addr) => {
+                if let Some(func) = pending {
+                    if addr & LOCAL_MASK == 0 {
+                        warn(index, MemoryIssue::ParamToGlobal(func, *kind, *addr));
+                    } else if let Some(size) = mem.func.get(func) {
+                        if (addr - LOCAL_MASK) as usize >= size.count(kind) {
+                            warn(index, MemoryIssue::ParamOutOfRange(func, *kind, *addr));
+                        }
+                    }
+                }
+            }
+            Command::Control(_, _) | Command::Exit => pending = None,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::command_definition::{ControlFlow, MemorySize};
+    use std::collections::HashMap;
+
+    fn memory(func: Vec<MemorySize>) -> ProgramMemory {
+        ProgramMemory {
+            main: MemorySize::default(),
+            func,
+            args: vec![],
+        }
+    }
+
+    #[test]
+    fn test_global_local_alias() {
+        let func = Block::new(vec![
+            Command::MemoryLoad(Kind::Integer, LOCAL_MASK | 1),
+            Command::MemoryStore(Kind::Integer, 1),
+            Command::MemoryStore(Kind::Real, 1),
+            Command::Control(ControlFlow::Ret, 0),
+        ]);
+        let prog = Program {
+            body: Block::new(vec![Command::MemoryStore(Kind::Integer, 1)]),
+            func: vec![func],
+            fini: None,
+            meta: vec![],
+            interactive: false,
+            pure: HashMap::new(),
+        };
+        let warnings = memory_aliasing(&prog, &memory(vec![MemorySize::default()]));
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert_eq!(warnings[0].block, BlockId::Function(0));
+        assert_eq!(warnings[0].index, 1);
+        assert!(matches!(
+            warnings[0].issue,
+            MemoryIssue::GlobalLocalAlias(Kind::Integer, 1)
+        ));
+    }
+
+    #[test]
+    fn test_record_protocol() {
+        let body = Block::new(vec![
+            Command::NewRecord(0),
+            Command::StoreParam(Kind::Integer, LOCAL_MASK),
+            Command::StoreParam(Kind::Integer, LOCAL_MASK | 1),
+            Command::Control(ControlFlow::Call, 0),
+            Command::NewRecord(0),
+            Command::StoreParam(Kind::Real, 0),
+            Command::Control(ControlFlow::Call, 0),
+        ]);
+        let prog = Program {
+            body,
+            func: vec![Block::new(vec![Command::Control(ControlFlow::Ret, 0)])],
+            fini: None,
+            meta: vec![],
+            interactive: false,
+            pure: HashMap::new(),
+        };
+        let size = MemorySize {
+            integer_count: 1,
+            ..MemorySize::default()
+        };
+        let warnings = memory_aliasing(&prog, &memory(vec![size]));
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+        assert!(matches!(
+            warnings[0].issue,
+            MemoryIssue::ParamOutOfRange(0, Kind::Integer, _)
+        ));
+        assert_eq!(warnings[0].index, 2);
+        assert!(matches!(
+            warnings[1].issue,
+            MemoryIssue::ParamToGlobal(0, Kind::Real, 0)
+        ));
+    }
+}
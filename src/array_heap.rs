@@ -0,0 +1,256 @@
+use crate::command_definition::Kind;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
+use std::mem::size_of;
+
+// Arrays allocated at run time by ANEWx. Programs refer to them by
+// handle, the integer ANEWx pushes, and every ALDx, ASTx and ALEN
+// checks the handle and the index before touching the array.
+// Arrays live until a collection of the heap module finds them
+// unreachable, the handles of swept arrays go to later ANEWx.
+
+// longest array ANEWx allocates
+pub const MAX_LENGTH: usize = 1 << 24;
+
+// arrays alive at the same time, handles are non negative integers
+pub const MAX_HANDLES: usize = i32::MAX as usize + 1;
+
+// bytes every array costs besides its elements: its slot in the
+// heap and its handle, so that empty arrays are not free
+pub const ARRAY_OVERHEAD: usize = size_of::<Option<Array>>() + size_of::<usize>();
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArrayError {
+    // requested length
+    Length(i32),
+    // handle and the kind of array it was used as, None
+    // when any kind would do
+    InvalidHandle(i32, Option<Kind>),
+    // index and length of the array
+    Index(i32, usize),
+    // MAX_HANDLES arrays alive
+    Handles,
+}
+
+impl std::error::Error for ArrayError {}
+
+impl fmt::Display for ArrayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Length(length) => write!(
+                f,
+                "cannot allocate an array of {} elements, the limit is {}",
+                length, MAX_LENGTH
+            ),
+            Self::InvalidHandle(handle, Some(kind)) => {
+                write!(f, "{} is not the handle of a {} array", handle, kind)
+            }
+            Self::InvalidHandle(handle, None) => {
+                write!(f, "{} is not the handle of an array", handle)
+            }
+            Self::Index(index, length) => write!(
+                f,
+                "index {} is outside an array of {} elements",
+                index, length
+            ),
+            Self::Handles => write!(
+                f,
+                "cannot allocate more than {} arrays at the same time",
+                MAX_HANDLES
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Array {
+    Integer(Vec<i32>),
+    Real(Vec<f64>),
+    Bool(Vec<bool>),
+    // indexes in the string memory
+    Str(Vec<usize>),
+}
+
+impl Array {
+    // filled with 0, 0.0, false and the empty string, which
+    // the string memory always holds at index 0
+    fn new(kind: Kind, length: usize) -> Self {
+        match kind {
+            Kind::Integer => Self::Integer(vec![0; length]),
+            Kind::Real => Self::Real(vec![0.0; length]),
+            Kind::Bool => Self::Bool(vec![false; length]),
+            Kind::Str => Self::Str(vec![0; length]),
+        }
+    }
+
+    pub fn kind(&self) -> Kind {
+        match self {
+            Self::Integer(_) => Kind::Integer,
+            Self::Real(_) => Kind::Real,
+            Self::Bool(_) => Kind::Bool,
+            Self::Str(_) => Kind::Str,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Integer(values) => values.len(),
+            Self::Real(values) => values.len(),
+            Self::Bool(values) => values.len(),
+            Self::Str(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn bytes(&self) -> usize {
+        let elements = match self {
+            Self::Integer(values) => values.len() * size_of::<i32>(),
+            Self::Real(values) => values.len() * size_of::<f64>(),
+            Self::Bool(values) => values.len() * size_of::<bool>(),
+            Self::Str(values) => values.len() * size_of::<usize>(),
+        };
+        elements + ARRAY_OVERHEAD
+    }
+
+    // `index` as a position in the array
+    pub fn position(&self, index: i32) -> Result<usize, ArrayError> {
+        let length = self.len();
+        usize::try_from(index)
+            .ok()
+            .filter(|position| *position < length)
+            .ok_or(ArrayError::Index(index, length))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ArrayHeap {
+    // indexed by handle, None once swept
+    arrays: Vec<Option<Array>>,
+    // swept slots, reused before `arrays` grows
+    free: Vec<usize>,
+    bytes: usize,
+}
+
+impl ArrayHeap {
+    pub fn new() -> Self {
+        Self {
+            arrays: vec![],
+            free: vec![],
+            bytes: 0,
+        }
+    }
+
+    // bytes held by the arrays not swept yet, overhead included
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    // the handle of the new array
+    pub fn allocate(&mut self, kind: Kind, length: i32) -> Result<i32, ArrayError> {
+        let size = usize::try_from(length)
+            .ok()
+            .filter(|size| *size <= MAX_LENGTH)
+            .ok_or(ArrayError::Length(length))?;
+        let slot = match self.free.pop() {
+            Some(slot) => slot,
+            None if self.arrays.len() < MAX_HANDLES => {
+                self.arrays.push(None);
+                self.arrays.len() - 1
+            }
+            None => return Err(ArrayError::Handles),
+        };
+        let array = Array::new(kind, size);
+        self.bytes += array.bytes();
+        self.arrays[slot] = Some(array);
+        Ok(slot as i32)
+    }
+
+    pub fn get(&self, handle: i32) -> Result<&Array, ArrayError> {
+        usize::try_from(handle)
+            .ok()
+            .and_then(|handle| self.arrays.get(handle))
+            .and_then(Option::as_ref)
+            .ok_or(ArrayError::InvalidHandle(handle, None))
+    }
+
+    // the array behind `handle`, if it holds `kind` values
+    pub fn typed(&self, handle: i32, kind: Kind) -> Result<&Array, ArrayError> {
+        self.get(handle)
+            .ok()
+            .filter(|array| array.kind() == kind)
+            .ok_or(ArrayError::InvalidHandle(handle, Some(kind)))
+    }
+
+    pub fn typed_mut(&mut self, handle: i32, kind: Kind) -> Result<&mut Array, ArrayError> {
+        let arrays = &mut self.arrays;
+        usize::try_from(handle)
+            .ok()
+            .and_then(move |handle| arrays.get_mut(handle))
+            .and_then(Option::as_mut)
+            .filter(|array| array.kind() == kind)
+            .ok_or(ArrayError::InvalidHandle(handle, Some(kind)))
+    }
+
+    // drop the arrays whose handle is not `live`
+    pub fn sweep(&mut self, live: &HashSet<i32>) {
+        for (handle, slot) in self.arrays.iter_mut().enumerate() {
+            if live.contains(&(handle as i32)) {
+                continue;
+            }
+            if let Some(array) = slot.take() {
+                self.bytes -= array.bytes();
+                self.free.push(handle);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_array_heap() {
+        let mut heap = ArrayHeap::new();
+        let ints = heap.allocate(Kind::Integer, 3).unwrap();
+        let reals = heap.allocate(Kind::Real, 0).unwrap();
+        assert_eq!((ints, reals), (0, 1));
+        assert_eq!(heap.get(ints).unwrap().len(), 3);
+        assert!(heap.get(reals).unwrap().is_empty());
+
+        let array = heap.typed(ints, Kind::Integer).unwrap();
+        assert_eq!(array.position(2), Ok(2));
+        assert_eq!(array.position(3), Err(ArrayError::Index(3, 3)));
+        assert_eq!(array.position(-1), Err(ArrayError::Index(-1, 3)));
+        assert!(matches!(
+            heap.typed(reals, Kind::Integer),
+            Err(ArrayError::InvalidHandle(1, Some(Kind::Integer)))
+        ));
+        assert!(matches!(
+            heap.get(2),
+            Err(ArrayError::InvalidHandle(2, None))
+        ));
+        assert_eq!(heap.allocate(Kind::Bool, -1), Err(ArrayError::Length(-1)));
+        assert_eq!(heap.bytes(), 3 * size_of::<i32>() + 2 * ARRAY_OVERHEAD);
+
+        heap.sweep(&[reals].iter().copied().collect());
+        assert!(matches!(
+            heap.get(ints),
+            Err(ArrayError::InvalidHandle(0, None))
+        ));
+        // the swept slot is reused
+        assert_eq!(heap.allocate(Kind::Bool, 1), Ok(0));
+        assert_eq!(heap.allocate(Kind::Bool, 0), Ok(2));
+        assert_eq!(heap.bytes(), size_of::<bool>() + 3 * ARRAY_OVERHEAD);
+        let too_long = MAX_LENGTH as i32 + 1;
+        assert_eq!(
+            heap.allocate(Kind::Str, too_long),
+            Err(ArrayError::Length(too_long))
+        );
+    }
+}
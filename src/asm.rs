@@ -0,0 +1,483 @@
+use crate::command_definition::LOCAL_MASK;
+use crate::opcode::{self, Operands, ISA};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// Textual assembler, writes big endian bytecode. One instruction per
+// line, `;` starts a comment. Mnemonics are the names in opcode::ISA,
+// operands are separated by spaces:
+//
+//   HDR interactive       header flags, first line only
+//   INIT 1 0 0 1          integer, real, boolean and string counts
+//   LDI 0  STRI local 1   addresses, `local` sets the local bit
+//   LDIC -3  LDRC 0.5  LDBC true  LDSC "text\n"
+//   loop:  JEQ loop       named labels, local to their block
+//   LBL 3  JUMP 3         numbered labels
+//   JUMPR -2              relative jumps
+//   PARAM 1  CALL 1  TIMER 1  FINI 1
+//   WRVN I R S            kinds: I, R, B and S
+//   PURE 1 I              function and returned kinds
+//   ARGS I 0 S local 1    kind and address of each argument
+//   META "key" "value"    key/value pairs
+//
+// LDIC 0, LDIC 1 and LDBC are written in their one byte forms,
+// LDI0, LDI1, LDFALSE and LDTRUE.
+
+#[derive(Debug)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl AsmError {
+    fn new(line: usize, message: String) -> Self {
+        Self { line, message }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+}
+
+enum Statement {
+    Header(Vec<Token>),
+    Label(String),
+    Instruction(u8, Operands, Vec<Token>),
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut statements = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let tokens = tokenize(line).map_err(|msg| AsmError::new(i + 1, msg))?;
+        if let Some(stat) = parse_statement(tokens).map_err(|msg| AsmError::new(i + 1, msg))? {
+            statements.push((i + 1, stat));
+        }
+    }
+
+    let mut output = Vec::new();
+    if let Some((line, Statement::Header(tokens))) = statements.first() {
+        let flags = header_flags(tokens).map_err(|msg| AsmError::new(*line, msg))?;
+        output.push(opcode::HDR);
+        output.push(flags);
+        statements.remove(0);
+    }
+    // labels are local to their block: FUNC starts a new one
+    let blocks = statements.split_inclusive(
+        |(_, stat)| matches!(stat, Statement::Instruction(byte, ..) if *byte == opcode::FUNC),
+    );
+    for block in blocks {
+        let labels = number_labels(block)?;
+        for (line, stat) in block {
+            if let Statement::Instruction(byte, operands, tokens) = stat {
+                if let Some(short) = short_form(*byte, tokens) {
+                    output.push(short);
+                    continue;
+                }
+                output.push(*byte);
+                encode_operands(*operands, tokens, &labels, &mut output)
+                    .map_err(|msg| AsmError::new(*line, msg))?;
+            } else if let Statement::Label(name) = stat {
+                output.push(opcode::LBL);
+                push_u16(&mut output, labels[name]);
+            } else {
+                return Err(AsmError::new(
+                    *line,
+                    "HDR must be the first line".to_owned(),
+                ));
+            }
+        }
+    }
+    Ok(output)
+}
+
+fn parse_statement(tokens: Vec<Token>) -> Result<Option<Statement>, String> {
+    let mut tokens = tokens.into_iter();
+    let first = match tokens.next() {
+        Some(Token::Word(word)) => word,
+        Some(Token::Str(s)) => return Err(format!("expected a mnemonic, found {:?}", s)),
+        None => return Ok(None),
+    };
+    if let Some(name) = first.strip_suffix(':') {
+        if !is_label_name(name) || tokens.next().is_some() {
+            return Err(format!("bad label definition {:?}", first));
+        }
+        return Ok(Some(Statement::Label(name.to_owned())));
+    }
+    let upper = first.to_uppercase();
+    if upper == "HDR" {
+        return Ok(Some(Statement::Header(tokens.collect())));
+    }
+    match ISA.iter().find(|instr| instr.name == upper) {
+        Some(instr) => Ok(Some(Statement::Instruction(
+            instr.byte,
+            instr.operands,
+            tokens.collect(),
+        ))),
+        None => Err(format!("unknown mnemonic {:?}", first)),
+    }
+}
+
+// the assembler writes big endian, so only the IO profile is left
+fn header_flags(tokens: &[Token]) -> Result<u8, String> {
+    let mut flags = 0;
+    for token in tokens {
+        match token {
+            Token::Word(word) if word == "interactive" => flags |= opcode::HDR_INTERACTIVE,
+            Token::Word(w) | Token::Str(w) => return Err(format!("unknown header flag {:?}", w)),
+        }
+    }
+    Ok(flags)
+}
+
+fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+// give named labels the numbers not used by the numbered ones
+fn number_labels(block: &[(usize, Statement)]) -> Result<HashMap<String, u16>, AsmError> {
+    let mut used = HashSet::new();
+    let mut defined = HashSet::new();
+    let mut names = Vec::new();
+    for (line, stat) in block {
+        match stat {
+            Statement::Label(name) => {
+                if !defined.insert(name.as_str()) {
+                    return Err(AsmError::new(
+                        *line,
+                        format!("label {} defined twice", name),
+                    ));
+                }
+                names.push(name.as_str());
+            }
+            Statement::Instruction(_, Operands::Label, tokens) => match tokens.as_slice() {
+                [Token::Word(word)] => match word.parse::<u16>() {
+                    Ok(label) => {
+                        used.insert(label);
+                    }
+                    Err(_) => names.push(word.as_str()),
+                },
+                _ => return Err(AsmError::new(*line, "expected a label".to_owned())),
+            },
+            _ => {}
+        }
+    }
+    let mut labels = HashMap::new();
+    let mut next = 0;
+    for (line, stat) in block {
+        if let Statement::Instruction(.., Operands::Label, tokens) = stat {
+            if let [Token::Word(word)] = tokens.as_slice() {
+                if word.parse::<u16>().is_err() && !defined.contains(word.as_str()) {
+                    return Err(AsmError::new(*line, format!("undefined label {}", word)));
+                }
+            }
+        }
+    }
+    for name in names {
+        if labels.contains_key(name) {
+            continue;
+        }
+        while used.contains(&next) {
+            next += 1;
+        }
+        labels.insert(name.to_owned(), next);
+        used.insert(next);
+    }
+    Ok(labels)
+}
+
+// the one byte load of the constants that have one
+fn short_form(byte: u8, tokens: &[Token]) -> Option<u8> {
+    let mut tokens = tokens.iter();
+    match byte {
+        opcode::LDIC => match number::<i32>(&mut tokens) {
+            Ok(0) => Some(opcode::LDI0),
+            Ok(1) => Some(opcode::LDI1),
+            _ => None,
+        },
+        opcode::LDBC => match number::<bool>(&mut tokens) {
+            Ok(false) => Some(opcode::LDFALSE),
+            Ok(true) => Some(opcode::LDTRUE),
+            _ => None,
+        },
+        _ => None,
+    }
+    .filter(|_| tokens.next().is_none())
+}
+
+fn encode_operands(
+    operands: Operands,
+    tokens: &[Token],
+    labels: &HashMap<String, u16>,
+    output: &mut Vec<u8>,
+) -> Result<(), String> {
+    let mut tokens = tokens.iter();
+    match operands {
+        Operands::Nothing => {}
+        Operands::Address => push_u16(output, address(&mut tokens)?),
+        Operands::Label => {
+            let word = word(&mut tokens)?;
+            let label = match word.parse() {
+                Ok(label) => label,
+                Err(_) => labels[word],
+            };
+            push_u16(output, label);
+        }
+        Operands::Function => push_u16(output, number(&mut tokens)?),
+        Operands::Integer => output.extend(&number::<i32>(&mut tokens)?.to_be_bytes()),
+        Operands::Real => output.extend(&number::<f64>(&mut tokens)?.to_be_bytes()),
+        // the loader wants true as 255
+        Operands::Bool => output.push(if number::<bool>(&mut tokens)? { 255 } else { 0 }),
+        Operands::Str => push_string(output, &string(&mut tokens)?)?,
+        Operands::Offset => output.extend(&number::<i16>(&mut tokens)?.to_be_bytes()),
+        Operands::Init => {
+            for _ in 0..4 {
+                push_u16(output, number(&mut tokens)?);
+            }
+        }
+        Operands::Kinds => {
+            let rest: Vec<&Token> = tokens.by_ref().collect();
+            push_kinds(output, rest.into_iter())?;
+        }
+        Operands::Pure => {
+            push_u16(output, number(&mut tokens)?);
+            let rest: Vec<&Token> = tokens.by_ref().collect();
+            push_kinds(output, rest.into_iter())?;
+        }
+        Operands::Args => {
+            let mut args = Vec::new();
+            while let Some(token) = tokens.next() {
+                args.push(kind(token)?);
+                args.extend(&address(&mut tokens)?.to_be_bytes());
+            }
+            if args.len() / 3 > u8::MAX as usize {
+                return Err("too many arguments".to_owned());
+            }
+            output.push((args.len() / 3) as u8);
+            output.extend(args);
+        }
+        Operands::Meta => {
+            let rest: Vec<&Token> = tokens.by_ref().collect();
+            if !rest.len().is_multiple_of(2) {
+                return Err("META takes key/value pairs".to_owned());
+            }
+            push_u16(output, (rest.len() / 2) as u16);
+            for token in rest {
+                match token {
+                    Token::Str(s) => push_string(output, s)?,
+                    Token::Word(w) => return Err(format!("expected a string, found {:?}", w)),
+                }
+            }
+        }
+    }
+    match tokens.next() {
+        Some(Token::Word(w)) | Some(Token::Str(w)) => Err(format!("unexpected operand {:?}", w)),
+        None => Ok(()),
+    }
+}
+
+fn word<'a>(tokens: &mut dyn Iterator<Item = &'a Token>) -> Result<&'a str, String> {
+    match tokens.next() {
+        Some(Token::Word(word)) => Ok(word),
+        Some(Token::Str(s)) => Err(format!("expected a word, found string {:?}", s)),
+        None => Err("missing operand".to_owned()),
+    }
+}
+
+fn string(tokens: &mut dyn Iterator<Item = &Token>) -> Result<String, String> {
+    match tokens.next() {
+        Some(Token::Str(s)) => Ok(s.clone()),
+        Some(Token::Word(w)) => Err(format!("expected a string, found {:?}", w)),
+        None => Err("missing operand".to_owned()),
+    }
+}
+
+fn number<T: std::str::FromStr>(tokens: &mut dyn Iterator<Item = &Token>) -> Result<T, String> {
+    let word = word(tokens)?;
+    word.parse()
+        .map_err(|_| format!("{:?} is not a valid operand", word))
+}
+
+// `N` for a global, `local N` for a local
+fn address(tokens: &mut dyn Iterator<Item = &Token>) -> Result<u16, String> {
+    let mut tokens = tokens.peekable();
+    if let Some(Token::Word(word)) = tokens.peek() {
+        if word == "local" {
+            tokens.next();
+            let addr: u16 = number(&mut tokens)?;
+            if addr & LOCAL_MASK != 0 {
+                return Err(format!("local address {} out of range", addr));
+            }
+            return Ok(addr | LOCAL_MASK);
+        }
+    }
+    number(&mut tokens)
+}
+
+fn kind(token: &Token) -> Result<u8, String> {
+    match token {
+        Token::Word(w) if w == "I" => Ok(0),
+        Token::Word(w) if w == "R" => Ok(1),
+        Token::Word(w) if w == "B" => Ok(2),
+        Token::Word(w) if w == "S" => Ok(3),
+        Token::Word(w) | Token::Str(w) => Err(format!("{:?} is not a kind: use I, R, B or S", w)),
+    }
+}
+
+// count followed by two bits per kind, low bits first
+fn push_kinds<'a>(
+    output: &mut Vec<u8>,
+    tokens: impl Iterator<Item = &'a Token>,
+) -> Result<(), String> {
+    let kinds = tokens.map(kind).collect::<Result<Vec<u8>, String>>()?;
+    if kinds.len() > u8::MAX as usize {
+        return Err("too many kinds".to_owned());
+    }
+    output.push(kinds.len() as u8);
+    for chunk in kinds.chunks(4) {
+        let packed = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (i, k)| acc | (k << (2 * i)));
+        output.push(packed);
+    }
+    Ok(())
+}
+
+fn push_u16(output: &mut Vec<u8>, value: u16) {
+    output.extend(&value.to_be_bytes());
+}
+
+fn push_string(output: &mut Vec<u8>, s: &str) -> Result<(), String> {
+    if s.len() > u16::MAX as usize {
+        return Err("string longer than 65535 bytes".to_owned());
+    }
+    push_u16(output, s.len() as u16);
+    output.extend(s.as_bytes());
+    Ok(())
+}
+
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c == ';' {
+            break;
+        } else if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            tokens.push(Token::Str(string_literal(&mut chars)?));
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == ';' || c == '"' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Word(word));
+        }
+    }
+    Ok(tokens)
+}
+
+fn string_literal(chars: &mut impl Iterator<Item = char>) -> Result<String, String> {
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(s),
+            Some('\\') => match chars.next() {
+                Some('n') => s.push('\n'),
+                Some('t') => s.push('\t'),
+                Some('\\') => s.push('\\'),
+                Some('"') => s.push('"'),
+                Some(c) => return Err(format!("unknown escape \\{}", c)),
+                None => return Err("unterminated string".to_owned()),
+            },
+            Some(c) => s.push(c),
+            None => return Err("unterminated string".to_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::engine::{run_program_with_io, EngineConfig};
+    use crate::program_load::{parse_data, LoadOptions};
+
+    #[test]
+    fn test_assemble() {
+        let source = r#"
+            ; count down from 3
+            META "name" "countdown"
+            INIT 1 0 0 1
+            LDSC "n ="
+            STRS 0
+            LDIC 3
+            STRI 0
+        loop:
+            LDS 0
+            LDI 0
+            WRVN S I
+            FLN
+            PARAM 0
+            LDI 0
+            STRIP local 0
+            CALL 0
+            STRI 0
+            LDI 0
+            LDI0
+            GRI
+            JEQ loop
+            FUNC
+            INIT 1 0 0 0
+            LDI local 0
+            LDI1
+            SUBI
+            RET
+        "#;
+        let data = assemble(source).unwrap();
+        let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        assert_eq!(prog.meta, vec![("name".to_owned(), "countdown".to_owned())]);
+        let mut output = Vec::new();
+        let config = EngineConfig::new();
+        run_program_with_io(prog, mem, str_mem, &config, &mut &b""[..], &mut output).unwrap();
+        assert_eq!(output, b"n = 3\nn = 2\nn = 1\n");
+
+        let data = assemble("LDIC 1\nLDBC false\nLDIC 2").unwrap();
+        let short = [opcode::LDI1, opcode::LDFALSE, opcode::LDIC, 0, 0, 0, 2];
+        assert_eq!(data, short);
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        let err = assemble("LDIC 1\nFOO").unwrap_err();
+        assert_eq!(
+            (err.line, err.message.as_str()),
+            (2, "unknown mnemonic \"FOO\"")
+        );
+        let err = assemble("JUMP nowhere").unwrap_err();
+        assert_eq!(err.line, 1);
+        assert!(assemble("LDSC \"open").is_err());
+        assert!(assemble("LDI 1 2").is_err());
+        assert!(assemble("INIT 0 0 0 0\nHDR interactive").is_err());
+        assert!(assemble("HDR little").is_err());
+        assert_eq!(
+            assemble("a:\nJUMP a\nLBL 0").unwrap(),
+            vec![opcode::LBL, 0, 1, opcode::JUMP, 0, 1, opcode::LBL, 0, 0]
+        );
+    }
+}
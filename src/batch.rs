@@ -0,0 +1,720 @@
+use crate::difftest::output_divergence;
+use crate::engine::{EngineConfig, LoadedProgram, Quota, RuntimeError, SecondaryError};
+use crate::program_load::{self, LoadOptions};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// A manifest lists the jobs to run, paths are relative to the
+// directory containing the manifest:
+//
+// {"budget_ms": 60000, "max_cpu_millis": 1000,
+//  "jobs": [{"name": "sum", "program": "sum.bin", "stdin": "sum.in",
+//            "expected": "sum.out", "args": ["3"], "max_output": 4096,
+//            "max_instructions": 100000, "max_memory": 65536,
+//            "defines": {"MODE": "fast"}, "exit_code": 0,
+//            "pre": "./make-input.sh", "post": "./compare.sh"}]}
+//
+// only `program` is required, a job without `expected` passes
+// when the program terminates without errors, or with the error
+// whose code is `exit_code`. Quotas given at the top level apply
+// to every job that does not set its own, jobs not started when
+// `budget_ms` runs out are skipped. `max_cpu_millis` counts the
+// CPU time of the thread running the job, so jobs waiting for a
+// core are not charged for it, `budget_ms` the wall clock time.
+//
+// `pre` and `post` are shell commands run in the manifest
+// directory, before loading the input and after the run. `post`
+// reads the program output on stdin, and a hook that fails
+// fails the job. A single job file is a run spec, see `Job::load`.
+
+const DEFAULT_MAX_OUTPUT: usize = 1 << 20;
+
+#[derive(Debug, Deserialize)]
+pub struct Manifest {
+    pub jobs: Vec<Job>,
+    // wall clock budget of the whole batch
+    #[serde(default)]
+    pub budget_ms: Option<u64>,
+    #[serde(flatten)]
+    pub limits: JobLimits,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct JobLimits {
+    #[serde(default)]
+    pub max_instructions: Option<u64>,
+    #[serde(default)]
+    pub max_cpu_millis: Option<u64>,
+    #[serde(default)]
+    pub max_memory: Option<usize>,
+}
+
+impl JobLimits {
+    fn or(&self, other: &Self) -> Self {
+        Self {
+            max_instructions: self.max_instructions.or(other.max_instructions),
+            max_cpu_millis: self.max_cpu_millis.or(other.max_cpu_millis),
+            max_memory: self.max_memory.or(other.max_memory),
+        }
+    }
+
+    // the wall clock quota is what is left of the batch budget
+    fn quota(&self, deadline: Option<Instant>) -> Quota {
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        Quota {
+            instructions: self.max_instructions,
+            time: remaining,
+            cpu_time: self.max_cpu_millis.map(Duration::from_millis),
+            memory: self.max_memory,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Job {
+    pub name: Option<String>,
+    pub program: PathBuf,
+    #[serde(default)]
+    pub stdin: Option<PathBuf>,
+    #[serde(default)]
+    pub expected: Option<PathBuf>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    // the job is stopped once it writes more bytes than this
+    #[serde(default = "default_max_output")]
+    pub max_output: usize,
+    #[serde(flatten)]
+    pub limits: JobLimits,
+    // name/value pairs read by GETDEF
+    #[serde(default)]
+    pub defines: HashMap<String, String>,
+    // RuntimeError::code of the expected error, 0 for a success
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    #[serde(default)]
+    pub pre: Option<String>,
+    #[serde(default)]
+    pub post: Option<String>,
+    // directory the hooks run in
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+fn default_max_output() -> usize {
+    DEFAULT_MAX_OUTPUT
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self, BatchError> {
+        let data = fs::read(path)?;
+        let mut manifest: Self = serde_json::from_slice(&data)?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        for job in &mut manifest.jobs {
+            job.resolve(base);
+            job.limits = job.limits.or(&manifest.limits);
+        }
+        Ok(manifest)
+    }
+}
+
+impl Job {
+    // a run spec: one job, with paths relative to the spec file
+    pub fn load(path: &Path) -> Result<Self, BatchError> {
+        let data = fs::read(path)?;
+        let mut job: Self = serde_json::from_slice(&data)?;
+        job.resolve(path.parent().unwrap_or_else(|| Path::new("")));
+        Ok(job)
+    }
+
+    fn resolve(&mut self, base: &Path) {
+        self.dir = base.to_path_buf();
+        self.program = base.join(&self.program);
+        self.stdin = self.stdin.as_ref().map(|path| base.join(path));
+        self.expected = self.expected.as_ref().map(|path| base.join(path));
+    }
+
+    pub fn name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => self.program.display().to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BatchError {
+    Io(io::Error),
+    Manifest(serde_json::Error),
+}
+
+impl std::error::Error for BatchError {}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Manifest(err) => write!(f, "malformed manifest: {}", err),
+        }
+    }
+}
+
+impl std::convert::From<io::Error> for BatchError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl std::convert::From<serde_json::Error> for BatchError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Manifest(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobStatus {
+    Passed,
+    WrongOutput,
+    RuntimeError,
+    LoadError,
+    OutputLimit,
+    OverQuota,
+    Skipped,
+    Crash,
+    WrongExitCode,
+    HookFailed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobReport {
+    pub name: String,
+    pub status: JobStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    // errors raised by the finalizer after the one in `message`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub secondary: Vec<SecondaryReport>,
+    pub millis: u128,
+    // META section of the program, when it loaded
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub meta: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SecondaryReport {
+    pub block: String,
+    pub instruction: usize,
+    pub code: i32,
+    pub message: String,
+}
+
+impl SecondaryReport {
+    fn new(err: &SecondaryError) -> Self {
+        Self {
+            block: err.block.to_string(),
+            instruction: err.index,
+            code: err.error.code(),
+            message: err.error.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchReport {
+    pub total: usize,
+    pub passed: usize,
+    pub jobs: Vec<JobReport>,
+}
+
+impl BatchReport {
+    fn new(jobs: Vec<JobReport>) -> Self {
+        let passed = jobs
+            .iter()
+            .filter(|job| job.status == JobStatus::Passed)
+            .count();
+        Self {
+            total: jobs.len(),
+            passed,
+            jobs,
+        }
+    }
+}
+
+impl JobStatus {
+    // JUnit tells apart failed checks from jobs that could not run
+    fn junit_tag(self) -> Option<&'static str> {
+        match self {
+            Self::Passed => None,
+            Self::WrongOutput | Self::OutputLimit | Self::WrongExitCode => Some("failure"),
+            Self::RuntimeError
+            | Self::LoadError
+            | Self::OverQuota
+            | Self::Crash
+            | Self::HookFailed => Some("error"),
+            Self::Skipped => Some("skipped"),
+        }
+    }
+}
+
+// JUnit XML rendering of the report, one testcase per job
+pub fn junit_report(report: &BatchReport) -> String {
+    let count = |tag| {
+        report
+            .jobs
+            .iter()
+            .filter(|job| job.status.junit_tag() == Some(tag))
+            .count()
+    };
+    let millis: u128 = report.jobs.iter().map(|job| job.millis).sum();
+    let mut output = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    output += &format!(
+        "<testsuite name=\"simpla batch\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{}\">\n",
+        report.total,
+        count("failure"),
+        count("error"),
+        count("skipped"),
+        seconds(millis)
+    );
+    for job in &report.jobs {
+        output += &format!(
+            "  <testcase name=\"{}\" classname=\"simpla.batch\" time=\"{}\"",
+            xml_escape(&job.name),
+            seconds(job.millis)
+        );
+        match job.status.junit_tag() {
+            Some(tag) => {
+                let status = serde_json::to_value(job.status).unwrap();
+                let message = job.message.as_deref().unwrap_or("");
+                output += &format!(
+                    ">\n    <{} type=\"{}\" message=\"{}\">{}</{}>\n  </testcase>\n",
+                    tag,
+                    status.as_str().unwrap_or(""),
+                    xml_escape(message.lines().next().unwrap_or("")),
+                    xml_escape(message),
+                    tag
+                );
+            }
+            None => output += "/>\n",
+        }
+    }
+    output += "</testsuite>\n";
+    output
+}
+
+fn seconds(millis: u128) -> String {
+    format!("{}.{:03}", millis / 1000, millis % 1000)
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => output += "&amp;",
+            '<' => output += "&lt;",
+            '>' => output += "&gt;",
+            '"' => output += "&quot;",
+            '\'' => output += "&apos;",
+            // control characters are not allowed in XML 1.0
+            c if c.is_control() && c != '\n' && c != '\t' && c != '\r' => {
+                output += &format!("\\u{{{:x}}}", c as u32)
+            }
+            c => output.push(c),
+        }
+    }
+    output
+}
+
+// jobs are taken in manifest order by `threads` workers,
+// the report keeps the manifest order
+pub fn run_batch(manifest: &Manifest, threads: usize, options: &LoadOptions) -> BatchReport {
+    let deadline = manifest
+        .budget_ms
+        .map(|budget| Instant::now() + Duration::from_millis(budget));
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, manifest.jobs.len().max(1)) {
+            scope.spawn(|| {
+                let mut loaded = ProgramCache::new();
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let job = match manifest.jobs.get(index) {
+                        Some(job) => job,
+                        None => break,
+                    };
+                    let report = run_job(job, options, deadline, &mut loaded);
+                    results.lock().unwrap().push((index, report));
+                }
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+    BatchReport::new(results.into_iter().map(|(_, report)| report).collect())
+}
+
+// programs already loaded by a worker: jobs running the
+// same file share the load and verification cost
+pub type ProgramCache = HashMap<PathBuf, LoadedProgram>;
+
+pub fn run_job(
+    job: &Job,
+    options: &LoadOptions,
+    deadline: Option<Instant>,
+    loaded: &mut ProgramCache,
+) -> JobReport {
+    let start = Instant::now();
+    let mut secondary = vec![];
+    let outcome = match deadline {
+        Some(deadline) if start >= deadline => {
+            let msg = "batch time budget exhausted".to_owned();
+            Err((JobStatus::Skipped, msg))
+        }
+        _ => job_outcome(job, options, deadline, loaded, &mut secondary),
+    };
+    let (status, message) = match outcome {
+        Ok(()) => (JobStatus::Passed, None),
+        Err((status, message)) => (status, Some(message)),
+    };
+    let meta = match loaded.get(&job.program) {
+        Some(program) => program.meta().iter().cloned().collect(),
+        None => BTreeMap::new(),
+    };
+    JobReport {
+        name: job.name(),
+        status,
+        message,
+        secondary,
+        millis: start.elapsed().as_millis(),
+        meta,
+    }
+}
+
+fn job_outcome(
+    job: &Job,
+    options: &LoadOptions,
+    deadline: Option<Instant>,
+    loaded: &mut ProgramCache,
+    secondary: &mut Vec<SecondaryReport>,
+) -> Result<(), (JobStatus, String)> {
+    if let Some(pre) = &job.pre {
+        run_hook("pre", pre, &job.dir, &[])?;
+    }
+    let read = |path: &Path| {
+        fs::read(path).map_err(|err| (JobStatus::LoadError, format!("{:?}: {}", path, err)))
+    };
+    let input = match &job.stdin {
+        Some(path) => read(path)?,
+        None => Vec::new(),
+    };
+    let expected = match &job.expected {
+        Some(path) => Some(read(path)?),
+        None => None,
+    };
+    let program = match loaded.entry(job.program.clone()) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            let (prog, prog_mem, str_mem, _) = program_load::load_program(&job.program, options)
+                .map_err(|err| (JobStatus::LoadError, err.to_string()))?;
+            let loaded = LoadedProgram::new(prog, prog_mem, str_mem)
+                .map_err(|err| (JobStatus::LoadError, err.to_string()))?;
+            entry.insert(loaded)
+        }
+    };
+
+    let config = EngineConfig::new()
+        .args(job.args.clone())
+        .defines(job.defines.clone())
+        .quota(job.limits.quota(deadline));
+    let mut in_stream = &input[..];
+    let mut output = LimitedOutput::new(job.max_output);
+    // a write past the output limit fails and stops the
+    // engine, panics are reported as crashes
+    let status = panic::catch_unwind(AssertUnwindSafe(|| {
+        program.run(&config, &mut in_stream, &mut output)
+    }));
+    if output.exceeded {
+        let msg = format!("more than {} bytes written", job.max_output);
+        return Err((JobStatus::OutputLimit, msg));
+    }
+    let status = match status {
+        Ok(status) => status,
+        Err(_) => return Err((JobStatus::Crash, "engine panic".to_owned())),
+    };
+    let code = status.as_ref().map_or_else(|err| err.code(), |_| 0);
+    // the message tells the primary error, the report lists
+    // the finalizer's ones on their own
+    if let Err(err) = &status {
+        secondary.extend(err.secondary().iter().map(SecondaryReport::new));
+    }
+    match (status, job.exit_code) {
+        (_, Some(exit_code)) if exit_code == code => {}
+        (Ok(_), None) => {}
+        (Err(err), _) if matches!(err.primary(), RuntimeError::QuotaExceeded(_)) => {
+            return Err((JobStatus::OverQuota, err.primary().to_string()))
+        }
+        (Err(err), None) => return Err((JobStatus::RuntimeError, err.primary().to_string())),
+        (status, Some(exit_code)) => {
+            let msg = match status {
+                Ok(_) => format!("the program succeeded, exit code {} expected", exit_code),
+                Err(err) => format!(
+                    "exit code {} ({}), {} expected",
+                    code,
+                    err.primary(),
+                    exit_code
+                ),
+            };
+            return Err((JobStatus::WrongExitCode, msg));
+        }
+    }
+    if let Some(expected) = expected {
+        if let Some(div) = output_divergence(&expected, &output.data) {
+            return Err((JobStatus::WrongOutput, div.to_string()));
+        }
+    }
+    if let Some(post) = &job.post {
+        run_hook("post", post, &job.dir, &output.data)?;
+    }
+    Ok(())
+}
+
+// run `cmd` with the shell in `dir`, `input` goes to its stdin
+fn run_hook(name: &str, cmd: &str, dir: &Path, input: &[u8]) -> Result<(), (JobStatus, String)> {
+    let failed = |msg: String| {
+        (
+            JobStatus::HookFailed,
+            format!("{} hook {:?} {}", name, cmd, msg),
+        )
+    };
+    let mut command = process::Command::new("sh");
+    command.arg("-c").arg(cmd);
+    // a spec in the working directory has an empty parent
+    if !dir.as_os_str().is_empty() {
+        command.current_dir(dir);
+    }
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| failed(format!("did not start: {}", err)))?;
+    let mut stdin = child.stdin.take().unwrap();
+    // feed stdin while the output is collected, a hook that stops
+    // reading early only closes the pipe
+    let result = thread::scope(|scope| {
+        scope.spawn(move || stdin.write_all(input));
+        child.wait_with_output()
+    });
+    let result = result.map_err(|err| failed(format!("failed: {}", err)))?;
+    if result.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    Err(failed(format!(
+        "failed with {}\n{}",
+        result.status,
+        stderr.trim_end()
+    )))
+}
+
+struct LimitedOutput {
+    data: Vec<u8>,
+    limit: usize,
+    exceeded: bool,
+}
+
+impl LimitedOutput {
+    fn new(limit: usize) -> Self {
+        Self {
+            data: Vec::new(),
+            limit,
+            exceeded: false,
+        }
+    }
+}
+
+impl Write for LimitedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.data.len() + buf.len() > self.limit {
+            self.exceeded = true;
+            return Err(io::Error::other("output limit exceeded"));
+        }
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::asm::assemble;
+    use crate::opcode;
+
+    #[test]
+    fn test_run_batch() {
+        let dir = std::env::temp_dir().join(format!("simpla-batch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let program = [
+            opcode::INIT,
+            0,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::RDI,
+            opcode::WRI,
+            opcode::FLN,
+        ];
+        fs::write(dir.join("echo.bin"), program).unwrap();
+        fs::write(dir.join("echo.in"), "12\n").unwrap();
+        fs::write(dir.join("echo.out"), "12\n").unwrap();
+        fs::write(dir.join("wrong.out"), "13\n").unwrap();
+        let endless = [
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::LBL,
+            0,
+            0,
+            opcode::JUMP,
+            0,
+            0,
+        ];
+        let meta = [
+            opcode::META,
+            0,
+            1,
+            0,
+            4,
+            b'n',
+            b'a',
+            b'm',
+            b'e',
+            0,
+            2,
+            b'l',
+            b'p',
+        ];
+        fs::write(dir.join("endless.bin"), [&endless[..], &meta].concat()).unwrap();
+        let manifest = r#"{"jobs": [
+            {"name": "echo", "program": "echo.bin", "stdin": "echo.in", "expected": "echo.out"},
+            {"program": "echo.bin", "stdin": "echo.in", "expected": "wrong.out"},
+            {"name": "limit", "program": "echo.bin", "stdin": "echo.in", "max_output": 1},
+            {"name": "no input", "program": "echo.bin"},
+            {"name": "missing", "program": "missing.bin"},
+            {"name": "endless", "program": "endless.bin", "max_instructions": 5000},
+            {"name": "slow", "program": "endless.bin"}
+        ], "max_cpu_millis": 50}"#;
+        fs::write(dir.join("manifest.json"), manifest).unwrap();
+
+        let manifest = Manifest::load(&dir.join("manifest.json")).unwrap();
+        let report = run_batch(&manifest, 2, &LoadOptions::default());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let status: Vec<JobStatus> = report.jobs.iter().map(|job| job.status).collect();
+        let expected = [
+            JobStatus::Passed,
+            JobStatus::WrongOutput,
+            JobStatus::OutputLimit,
+            JobStatus::RuntimeError,
+            JobStatus::LoadError,
+            JobStatus::OverQuota,
+            JobStatus::OverQuota,
+        ];
+        assert_eq!(status, expected);
+        assert_eq!((report.total, report.passed), (7, 1));
+        assert!(report.jobs[5]
+            .message
+            .as_ref()
+            .unwrap()
+            .contains("instruction"));
+        assert!(report.jobs[6].message.as_ref().unwrap().contains("time"));
+        assert!(report.jobs[1].name.ends_with("echo.bin"));
+        assert!(report.jobs[0].meta.is_empty());
+        assert_eq!(report.jobs[5].meta["name"], "lp");
+
+        let junit = junit_report(&report);
+        assert!(junit.contains("tests=\"7\" failures=\"2\" errors=\"4\" skipped=\"0\""));
+        assert!(junit.contains("<testcase name=\"echo\" classname=\"simpla.batch\""));
+        assert!(junit.contains("<failure type=\"output-limit\""));
+        assert!(junit.contains("name=\"no input\""));
+    }
+
+    #[test]
+    fn test_run_spec() {
+        let dir = std::env::temp_dir().join(format!("simpla-spec-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let program = assemble(
+            "
+            INIT 0 0 0 0
+            LDSC \"MODE\"
+            GETDEF
+            WRS
+            FLN
+            LDI1
+            LDI0
+            DIVI
+        ",
+        )
+        .unwrap();
+        fs::write(dir.join("mode.bin"), program).unwrap();
+        let run = |spec: &str| {
+            fs::write(dir.join("spec.json"), spec).unwrap();
+            let job = Job::load(&dir.join("spec.json")).unwrap();
+            run_job(
+                &job,
+                &LoadOptions::default(),
+                None,
+                &mut ProgramCache::new(),
+            )
+        };
+        let passed = run(r#"{"program": "mode.bin", "defines": {"MODE": "fast"},
+            "exit_code": 9, "pre": "echo ran > pre.txt", "post": "grep -q fast && test -f pre.txt"}"#);
+        let wrong_code = run(r#"{"program": "mode.bin", "exit_code": 0}"#);
+        let failed_hook = run(r#"{"program": "mode.bin", "exit_code": 9, "post": "grep -q slow"}"#);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(passed.status, JobStatus::Passed);
+        assert_eq!(wrong_code.status, JobStatus::WrongExitCode);
+        assert_eq!(failed_hook.status, JobStatus::HookFailed);
+    }
+
+    #[test]
+    fn test_exhausted_budget() {
+        let manifest: Manifest =
+            serde_json::from_str(r#"{"jobs": [{"program": "a.bin"}], "budget_ms": 0}"#).unwrap();
+        let report = run_batch(&manifest, 1, &LoadOptions::default());
+        assert_eq!(report.jobs[0].status, JobStatus::Skipped);
+    }
+
+    #[test]
+    fn test_xml_escape() {
+        assert_eq!(xml_escape("a<b & \"c\""), "a&lt;b &amp; &quot;c&quot;");
+        assert_eq!(xml_escape("\u{1}x"), "\\u{1}x");
+    }
+}
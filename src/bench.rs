@@ -0,0 +1,264 @@
+use crate::engine::{run_program_with_io, EngineConfig};
+use crate::opcode;
+use crate::program_load::{parse_data, LoadOptions};
+#[cfg(feature = "register-ir")]
+use crate::register_ir;
+use std::io;
+use std::time::{Duration, Instant};
+
+// Every family is measured by a generated program repeating its
+// body `copies` times inside a counted loop. The time of the same
+// loop with an empty body is subtracted, what is left is divided by
+// the number of body instructions executed. With the register-ir
+// feature every family is timed on the register IR too, unless the
+// translation does not support it.
+
+pub struct Family {
+    pub name: &'static str,
+    // body instructions, used to compute the per dispatch cost
+    pub instructions: usize,
+    body: fn(u16) -> Vec<u8>,
+}
+
+pub const FAMILIES: &[Family] = &[
+    Family {
+        name: "constant load",
+        instructions: 2,
+        body: |_| concat(&[&int_constant(3), &[opcode::STRI, 0, 1]]),
+    },
+    Family {
+        name: "memory load/store",
+        instructions: 2,
+        body: |_| vec![opcode::LDI, 0, 1, opcode::STRI, 0, 1],
+    },
+    Family {
+        name: "integer math",
+        instructions: 4,
+        body: |_| {
+            let load = [opcode::LDI, 0, 1];
+            concat(&[&load, &int_constant(3), &[opcode::ADDI, opcode::STRI, 0, 1]])
+        },
+    },
+    Family {
+        name: "real math",
+        instructions: 5,
+        body: |_| {
+            let tail = [opcode::ADDR, opcode::CSTI, opcode::STRI, 0, 1];
+            concat(&[&real_constant(1.5), &real_constant(2.5), &tail])
+        },
+    },
+    Family {
+        name: "boolean",
+        instructions: 3,
+        body: |_| vec![opcode::LDBC, 255, opcode::NOT, opcode::STRB, 0, 0],
+    },
+    Family {
+        name: "string compare",
+        instructions: 4,
+        body: |_| {
+            let tail = [opcode::NES, opcode::STRB, 0, 0];
+            concat(&[&str_constant("a"), &str_constant("b"), &tail])
+        },
+    },
+    Family {
+        name: "jump",
+        instructions: 2,
+        body: |label| {
+            let [high, low] = label.to_be_bytes();
+            vec![opcode::JUMP, high, low, opcode::LBL, high, low]
+        },
+    },
+    Family {
+        name: "call/return",
+        instructions: 3,
+        body: |_| vec![opcode::PARAM, 0, 0, opcode::CALL, 0, 0],
+    },
+    Family {
+        name: "integer output",
+        instructions: 2,
+        body: |_| concat(&[&int_constant(7), &[opcode::WRI]]),
+    },
+];
+
+impl Family {
+    pub fn program(&self, iterations: u32, copies: u16) -> Vec<u8> {
+        // label 0 is the loop head, copies get 1 and up
+        let body: Vec<u8> = (1..=copies).flat_map(self.body).collect();
+        counted_loop(&body, iterations)
+    }
+}
+
+pub struct Measure {
+    pub family: &'static str,
+    pub backend: &'static str,
+    pub instructions: usize,
+    pub nanos: f64,
+}
+
+// time to run a generated program, None when the backend cannot
+type Timer = fn(&[u8]) -> Option<Duration>;
+
+const BACKENDS: &[(&str, Timer)] = &[
+    ("stack", |data| Some(time_program(data))),
+    #[cfg(feature = "register-ir")]
+    ("register", time_register),
+];
+
+pub fn run_micro(iterations: u32, copies: u16) -> Vec<Measure> {
+    let empty = counted_loop(&[], iterations);
+    let baselines: Vec<Option<Duration>> = BACKENDS.iter().map(|(_, time)| time(&empty)).collect();
+    FAMILIES
+        .iter()
+        .flat_map(|family| {
+            let data = family.program(iterations, copies);
+            let backends = BACKENDS.iter().zip(baselines.clone());
+            backends.filter_map(move |((backend, time), baseline)| {
+                let extra = time(&data)?.saturating_sub(baseline?).as_nanos() as f64;
+                let count = iterations as f64 * copies as f64 * family.instructions as f64;
+                Some(Measure {
+                    family: family.name,
+                    backend,
+                    instructions: family.instructions,
+                    nanos: extra / count,
+                })
+            })
+        })
+        .collect()
+}
+
+pub fn format_table(measures: &[Measure]) -> String {
+    let mut output = format!(
+        "{:<20} {:<10} {:>12} {:>12}\n",
+        "family", "backend", "instructions", "ns/dispatch"
+    );
+    for measure in measures {
+        output += &format!(
+            "{:<20} {:<10} {:>12} {:>12.2}\n",
+            measure.family, measure.backend, measure.instructions, measure.nanos
+        );
+    }
+    output
+}
+
+// translation included, as the engine setup is for the stack
+#[cfg(feature = "register-ir")]
+fn time_register(data: &[u8]) -> Option<Duration> {
+    let (prog, prog_mem, str_mem, _) = parse_data(data, &LoadOptions::default()).unwrap();
+    let start = Instant::now();
+    let reg_prog = register_ir::translate(&prog, &prog_mem).ok()?;
+    register_ir::run_register_program(
+        &reg_prog,
+        &str_mem,
+        &EngineConfig::new(),
+        &mut io::empty(),
+        &mut io::sink(),
+    )
+    .unwrap();
+    Some(start.elapsed())
+}
+
+fn time_program(data: &[u8]) -> Duration {
+    let (prog, prog_mem, str_mem, _) = parse_data(data, &LoadOptions::default()).unwrap();
+    let start = Instant::now();
+    run_program_with_io(
+        prog,
+        prog_mem,
+        str_mem,
+        &EngineConfig::new(),
+        &mut io::empty(),
+        &mut io::sink(),
+    )
+    .unwrap();
+    start.elapsed()
+}
+
+// two integers (counter and scratch), one boolean and
+// an empty function for the call family
+fn counted_loop(body: &[u8], iterations: u32) -> Vec<u8> {
+    let mut output = vec![opcode::INIT, 0, 2, 0, 0, 0, 1, 0, 0];
+    output.extend(int_constant(0));
+    output.extend(&[opcode::STRI, 0, 0, opcode::LBL, 0, 0]);
+    output.extend(body);
+    output.extend(&[opcode::LDI, 0, 0]);
+    output.extend(int_constant(1));
+    output.extend(&[opcode::ADDI, opcode::STRI, 0, 0, opcode::LDI, 0, 0]);
+    output.extend(int_constant(iterations as i32));
+    output.extend(&[opcode::NEI, opcode::JEQ, 0, 0]);
+    output.extend(&[
+        opcode::FUNC,
+        opcode::INIT,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        opcode::RET,
+    ]);
+    output
+}
+
+fn concat(parts: &[&[u8]]) -> Vec<u8> {
+    parts.concat()
+}
+
+// the emitted code uses the compact encodings when available
+pub fn int_constant(value: i32) -> Vec<u8> {
+    match value {
+        0 => vec![opcode::LDI0],
+        1 => vec![opcode::LDI1],
+        _ => concat(&[&[opcode::LDIC], &value.to_be_bytes()]),
+    }
+}
+
+pub fn real_constant(value: f64) -> Vec<u8> {
+    concat(&[&[opcode::LDRC], &value.to_be_bytes()])
+}
+
+pub fn str_constant(value: &str) -> Vec<u8> {
+    let size = (value.len() as u16).to_be_bytes();
+    concat(&[&[opcode::LDSC], &size, value.as_bytes()])
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::command_definition::Command;
+
+    #[test]
+    fn test_family_programs() {
+        for family in FAMILIES {
+            time_program(&family.program(3, 2));
+        }
+
+        let data = FAMILIES[2].program(3, 2);
+        let (prog, _, _, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let body = prog
+            .body
+            .code
+            .iter()
+            .take_while(|cmd| !matches!(cmd, Command::MemoryLoad(_, 0)));
+        // counter initialization and loop label come first
+        assert_eq!(body.count() - 3, 2 * FAMILIES[2].instructions);
+        let measures = run_micro(10, 2);
+        let stack = measures.iter().filter(|m| m.backend == "stack");
+        assert_eq!(stack.count(), FAMILIES.len());
+    }
+
+    #[cfg(feature = "register-ir")]
+    #[test]
+    fn test_register_rows() {
+        let measures = run_micro(10, 2);
+        let register: Vec<&str> = measures
+            .iter()
+            .filter(|m| m.backend == "register")
+            .map(|m| m.family)
+            .collect();
+        assert!(register.contains(&"integer math"));
+        // calls are not translated
+        assert!(!register.contains(&"call/return"));
+    }
+}
@@ -0,0 +1,243 @@
+use crate::command_definition::{
+    AddrSize, Block, BlockId, Command, Constant, ControlFlow, Kind, MemorySize, Program,
+    ProgramMemory, LOCAL_MASK,
+};
+use crate::program_load::{self, LoadError};
+use crate::string_memory::StringMemory;
+use std::collections::HashMap;
+
+// Build a program in Rust code instead of writing bytecode by
+// hand. Commands go to the main body until `function` opens the
+// next function block. Memory sizes follow the slots the commands
+// use, STRxP grows the memory of the function being called, and
+// labels are allocated per block by `new_label`. `build` runs the
+// same checks as the loader.
+
+#[derive(Default)]
+struct BlockBuilder {
+    code: Vec<Command>,
+    memory: MemorySize,
+    next_label: usize,
+}
+
+impl BlockBuilder {
+    fn reserve(&mut self, kind: &Kind, count: usize) {
+        let size = self.memory.count_mut(kind);
+        *size = (*size).max(count);
+    }
+}
+
+pub struct ProgramBuilder {
+    body: BlockBuilder,
+    func: Vec<BlockBuilder>,
+    // function blocks opened so far
+    defined: usize,
+    current: BlockId,
+    // function of the record being filled by STRxP
+    record: Option<usize>,
+    str_mem: StringMemory,
+    args: Vec<(Kind, AddrSize)>,
+    fini: Option<usize>,
+    meta: Vec<(String, String)>,
+    pure: HashMap<usize, Vec<Kind>>,
+}
+
+impl Default for ProgramBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Self {
+            body: BlockBuilder::default(),
+            func: vec![],
+            defined: 0,
+            current: BlockId::Main,
+            record: None,
+            str_mem: StringMemory::new(),
+            args: vec![],
+            fini: None,
+            meta: vec![],
+            pure: HashMap::new(),
+        }
+    }
+
+    pub fn push(mut self, cmd: Command) -> Self {
+        match &cmd {
+            Command::MemoryLoad(kind, addr) | Command::MemoryStore(kind, addr) => {
+                let block = if addr & LOCAL_MASK == 0 {
+                    &mut self.body
+                } else {
+                    self.block(self.current)
+                };
+                block.reserve(kind, slot(*addr));
+            }
+            Command::NewRecord(func) => self.record = Some(*func),
+            Command::StoreParam(kind, addr) => {
+                if let Some(func) = self.record {
+                    self.block(BlockId::Function(func))
+                        .reserve(kind, slot(*addr));
+                }
+            }
+            Command::Control(ControlFlow::Call, _) => self.record = None,
+            _ => {}
+        }
+        self.block(self.current).code.push(cmd);
+        self
+    }
+
+    // load a string constant
+    pub fn string(mut self, value: &str) -> Self {
+        let index = self.str_mem.insert_static_string(value.to_owned());
+        self.push(Command::ConstantLoad(Constant::Str(index)))
+    }
+
+    // a label of the current block not used by `new_label` yet
+    pub fn new_label(&mut self) -> usize {
+        let block = self.block(self.current);
+        block.next_label += 1;
+        block.next_label - 1
+    }
+
+    // open the next function block, ids follow the opening order
+    pub fn function(mut self) -> Self {
+        self.current = BlockId::Function(self.defined);
+        self.defined += 1;
+        self.block(self.current);
+        self
+    }
+
+    // at least `count` slots of `kind` in the current block,
+    // for slots only the engine writes
+    pub fn reserve(mut self, kind: Kind, count: usize) -> Self {
+        self.block(self.current).reserve(&kind, count);
+        self
+    }
+
+    pub fn argument(mut self, kind: Kind, addr: AddrSize) -> Self {
+        self.body.reserve(&kind, slot(addr));
+        self.args.push((kind, addr));
+        self
+    }
+
+    pub fn finalizer(mut self, func: usize) -> Self {
+        self.fini = Some(func);
+        self
+    }
+
+    pub fn meta(mut self, key: &str, value: &str) -> Self {
+        self.meta.push((key.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn pure(mut self, func: usize, kinds: Vec<Kind>) -> Self {
+        self.pure.insert(func, kinds);
+        self
+    }
+
+    pub fn build(self) -> Result<(Program, ProgramMemory, StringMemory), LoadError> {
+        let (func, func_mem) = self
+            .func
+            .into_iter()
+            .map(|block| (Block::new(block.code), block.memory))
+            .unzip();
+        let prog = Program {
+            body: Block::new(self.body.code),
+            func,
+            fini: self.fini,
+            meta: self.meta,
+            interactive: false,
+            pure: self.pure,
+        };
+        let mem = ProgramMemory {
+            main: self.body.memory,
+            func: func_mem,
+            args: self.args,
+        };
+        program_load::validate(&prog, &mem)?;
+        Ok((prog, mem, self.str_mem))
+    }
+
+    // functions get their block when first used, even before
+    // they are opened
+    fn block(&mut self, id: BlockId) -> &mut BlockBuilder {
+        match id {
+            BlockId::Main => &mut self.body,
+            BlockId::Function(func) => {
+                if self.func.len() <= func {
+                    self.func.resize_with(func + 1, BlockBuilder::default);
+                }
+                &mut self.func[func]
+            }
+        }
+    }
+}
+
+// slots needed to hold `addr`
+fn slot(addr: AddrSize) -> usize {
+    (addr & !LOCAL_MASK) as usize + 1
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::command_definition::{FlushMode, MathOperator, Operator, RelationalOperator};
+    use crate::engine::{run_program_with_io, EngineConfig};
+    use std::io;
+
+    #[test]
+    fn test_builder() {
+        // print the numbers from 0 to 2 through a function
+        let mut builder = ProgramBuilder::new();
+        let head = builder.new_label();
+        let end = builder.new_label();
+        let builder = builder
+            .push(Command::ConstantLoad(Constant::Integer(0)))
+            .push(Command::MemoryStore(Kind::Integer, 0))
+            .push(Command::Control(ControlFlow::Label, head))
+            .push(Command::MemoryLoad(Kind::Integer, 0))
+            .push(Command::ConstantLoad(Constant::Integer(3)))
+            .push(Command::Integer(Operator::Rel(RelationalOperator::Less)))
+            .push(Command::Control(ControlFlow::JumpFalse, end))
+            .push(Command::NewRecord(0))
+            .push(Command::MemoryLoad(Kind::Integer, 0))
+            .push(Command::StoreParam(Kind::Integer, LOCAL_MASK))
+            .string("n = ")
+            .push(Command::StoreParam(Kind::Str, LOCAL_MASK + 1))
+            .push(Command::Control(ControlFlow::Call, 0))
+            .push(Command::MemoryLoad(Kind::Integer, 0))
+            .push(Command::ConstantLoad(Constant::Integer(1)))
+            .push(Command::Integer(Operator::Math(MathOperator::Add)))
+            .push(Command::MemoryStore(Kind::Integer, 0))
+            .push(Command::Control(ControlFlow::Jump, head))
+            .push(Command::Control(ControlFlow::Label, end))
+            .function()
+            .push(Command::MemoryLoad(Kind::Str, LOCAL_MASK + 1))
+            .push(Command::Output(Kind::Str))
+            .push(Command::MemoryLoad(Kind::Integer, LOCAL_MASK))
+            .push(Command::Output(Kind::Integer))
+            .push(Command::Flush(FlushMode::NewLine))
+            .push(Command::Control(ControlFlow::Ret, 0));
+        let (prog, prog_mem, str_mem) = builder.build().unwrap();
+        assert_eq!(prog_mem.main.integer_count, 1);
+        assert_eq!(prog_mem.func[0].integer_count, 1);
+        assert_eq!(prog_mem.func[0].string_count, 2);
+
+        let config = EngineConfig::new();
+        let mut input = io::empty();
+        let mut out = vec![];
+        run_program_with_io(prog, prog_mem, str_mem, &config, &mut input, &mut out).unwrap();
+        assert_eq!(out, b"n = 0\nn = 1\nn = 2\n");
+
+        // the loader checks apply to built programs too
+        let err = ProgramBuilder::new()
+            .push(Command::Control(ControlFlow::Call, 0))
+            .function()
+            .push(Command::Control(ControlFlow::Ret, 0))
+            .build();
+        assert!(matches!(err, Err(LoadError::CallProtocol(..))));
+    }
+}
@@ -0,0 +1,173 @@
+use crate::analysis::{memory_aliasing, MemoryWarning};
+use crate::command_definition::ProgramMemory;
+use crate::program_load::{
+    declared_byte_order, parse_data, ByteOrder, LoadError, LoadOptions, LoadWarning,
+};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Finding {
+    Load(LoadWarning),
+    ByteSwapped(ByteOrder),
+    Memory(MemoryWarning),
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Load(warn) => write!(f, "{}", warn),
+            Self::ByteSwapped(order) => write!(
+                f,
+                "memory declarations look byte-swapped: the file is probably encoded as {}, \
+                 set the header byte order accordingly",
+                order
+            ),
+            Self::Memory(warn) => write!(f, "{}", warn),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CheckError {
+    pub err: LoadError,
+    // byte order the file loads correctly with, if any
+    pub hint: Option<ByteOrder>,
+}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.err)?;
+        if let Some(order) = self.hint {
+            write!(
+                f,
+                "\nthe file loads correctly as {}: it was probably encoded with the wrong byte order",
+                order
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub fn check_program(
+    data: &[u8],
+    options: &LoadOptions,
+    memory_analysis: bool,
+) -> Result<Vec<Finding>, CheckError> {
+    match parse_data(data, options) {
+        Ok((prog, prog_mem, _, warnings)) => {
+            let mut findings: Vec<Finding> = warnings.into_iter().map(Finding::Load).collect();
+            if let Some(order) = byte_swap_hint(data, options, &prog_mem) {
+                findings.push(Finding::ByteSwapped(order));
+            }
+            if memory_analysis {
+                let warnings = memory_aliasing(&prog, &prog_mem);
+                findings.extend(warnings.into_iter().map(Finding::Memory));
+            }
+            Ok(findings)
+        }
+        Err(err) => {
+            let order = swapped_order(data, options);
+            let hint = if parse_data(data, &swapped_options(options, order)).is_ok() {
+                Some(order)
+            } else {
+                None
+            };
+            Err(CheckError { err, hint })
+        }
+    }
+}
+
+fn byte_swap_hint(data: &[u8], options: &LoadOptions, mem: &ProgramMemory) -> Option<ByteOrder> {
+    if !looks_swapped(mem) {
+        return None;
+    }
+    let order = swapped_order(data, options);
+    match parse_data(data, &swapped_options(options, order)) {
+        Ok((_, swapped_mem, _, _)) if !looks_swapped(&swapped_mem) => Some(order),
+        _ => None,
+    }
+}
+
+fn swapped_order(data: &[u8], options: &LoadOptions) -> ByteOrder {
+    let current = options
+        .byte_order
+        .unwrap_or_else(|| declared_byte_order(data));
+    current.swap()
+}
+
+fn swapped_options(options: &LoadOptions, order: ByteOrder) -> LoadOptions {
+    LoadOptions {
+        lenient_strings: options.lenient_strings,
+        byte_order: Some(order),
+    }
+}
+
+// real programs rarely declare hundreds of variables of the same
+// kind: when every non empty declaration is a multiple of 256 the
+// low and high bytes are very likely exchanged
+fn looks_swapped(mem: &ProgramMemory) -> bool {
+    let counts: Vec<usize> = std::iter::once(&mem.main)
+        .chain(mem.func.iter())
+        .flat_map(|size| {
+            vec![
+                size.integer_count,
+                size.real_count,
+                size.boolean_count,
+                size.string_count,
+            ]
+        })
+        .filter(|count| *count > 0)
+        .collect();
+    !counts.is_empty() && counts.iter().all(|count| count % 256 == 0)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::opcode;
+
+    #[test]
+    fn test_detect_swapped_memory() {
+        // little endian memory declaration without header
+        let data = vec![opcode::INIT, 3, 0, 1, 0, 0, 0, 0, 0, opcode::EXT];
+        let findings = check_program(&data, &LoadOptions::default(), false).unwrap();
+        // the swapped declarations are reported as unused too
+        assert_eq!(findings.len(), 3, "{:?}", findings);
+        assert!(matches!(
+            findings[2],
+            Finding::ByteSwapped(ByteOrder::Little)
+        ));
+
+        let mut with_header = vec![opcode::HDR, opcode::HDR_LITTLE_ENDIAN];
+        with_header.extend(data);
+        let findings = check_program(&with_header, &LoadOptions::default(), false).unwrap();
+        assert!(findings.is_empty(), "{:?}", findings);
+    }
+
+    #[test]
+    fn test_swapped_hint_on_error() {
+        // the string length reads as 768 in big endian
+        let data = vec![
+            opcode::HDR,
+            0,
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::LDSC,
+            3,
+            0,
+            b'a',
+            b'b',
+            b'c',
+        ];
+        let err = check_program(&data, &LoadOptions::default(), false).unwrap_err();
+        assert_eq!(err.hint, Some(ByteOrder::Little));
+    }
+}
@@ -3,25 +3,163 @@ use std::collections::HashMap;
 
 pub type AddrSize = u16;
 
+const ADDR_SIZE_ZERO: AddrSize = 0;
+pub const LOCAL_MASK: AddrSize = 1 << (ADDR_SIZE_ZERO.count_zeros() - 1);
+
+// an address split into its scope and its index in that scope
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Slot {
+    Global(usize),
+    Local(usize),
+}
+
+impl Slot {
+    pub fn new(addr: AddrSize) -> Self {
+        if addr & LOCAL_MASK == 0 {
+            Self::Global(addr as usize)
+        } else {
+            Self::Local((addr & !LOCAL_MASK) as usize)
+        }
+    }
+
+    // back to the masked address
+    pub fn addr(self) -> AddrSize {
+        match self {
+            Self::Global(index) => index as AddrSize,
+            Self::Local(index) => index as AddrSize | LOCAL_MASK,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Program {
     pub body: Block,
     pub func: Vec<Block>,
+    // function run when the program ends, even on errors
+    pub fini: Option<usize>,
+    // key/value pairs of the META section, in file order
+    pub meta: Vec<(String, String)>,
+    // the header declares the interactive IO profile
+    pub interactive: bool,
+    // functions declared pure, with the kinds they return
+    pub pure: HashMap<usize, Vec<Kind>>,
+}
+
+impl Program {
+    pub fn blocks(&self) -> impl Iterator<Item = (BlockId, &Block)> {
+        let body = std::iter::once((BlockId::Main, &self.body));
+        let func = self
+            .func
+            .iter()
+            .enumerate()
+            .map(|(id, blk)| (BlockId::Function(id), blk));
+        body.chain(func)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockId {
+    Main,
+    Function(usize),
+}
+
+impl std::fmt::Display for BlockId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Main => write!(f, "main body"),
+            Self::Function(id) => write!(f, "function {}", id),
+        }
+    }
+}
+
+// `main` or a function id
+impl std::str::FromStr for BlockId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "main" => Ok(Self::Main),
+            id => match id.parse() {
+                Ok(id) => Ok(Self::Function(id)),
+                Err(_) => Err(format!("{:?} is not a block", id)),
+            },
+        }
+    }
+}
+
+// violations of the PARAM, STRxP, CALL protocol: an activation
+// record is created, filled and consumed by a call to the same
+// function inside a single basic block
+#[derive(Debug, Clone, PartialEq)]
+pub enum CallError {
+    NestedRecord(usize, usize),
+    ParamWithoutRecord,
+    CallWithoutRecord(usize),
+    CallMismatch(usize, usize),
+    UnmatchedRecord(usize),
+    ReturnOutsideFunction,
+    // record or timer for a function that does not exist
+    UndefinedFunction(usize),
+}
+
+impl std::fmt::Display for CallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NestedRecord(pending, func) => write!(
+                f,
+                "activation record for function {} created while the one for function {} is pending",
+                func, pending
+            ),
+            Self::ParamWithoutRecord => write!(f, "parameter stored without an activation record"),
+            Self::CallWithoutRecord(func) => {
+                write!(f, "call to function {} without an activation record", func)
+            }
+            Self::CallMismatch(record, func) => write!(
+                f,
+                "call to function {} with the activation record of function {}",
+                func, record
+            ),
+            Self::UnmatchedRecord(func) => write!(
+                f,
+                "activation record for function {} is not called in the same basic block",
+                func
+            ),
+            Self::ReturnOutsideFunction => write!(f, "return outside a function body"),
+            Self::UndefinedFunction(func) => write!(f, "function {} does not exist", func),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Block {
     pub code: Vec<Command>,
     pub labels: HashMap<usize, usize>,
+    // byte offset of every command in the file it was loaded
+    // from, empty for blocks built or rewritten in memory
+    pub offsets: Vec<usize>,
 }
 
 #[derive(Debug)]
 pub struct ProgramMemory {
     pub main: MemorySize,
     pub func: Vec<MemorySize>,
+    // global slots initialized from the program arguments
+    pub args: Vec<(Kind, AddrSize)>,
 }
 
-#[derive(Debug, std::default::Default)]
+impl ProgramMemory {
+    pub fn blocks(&self) -> impl Iterator<Item = (BlockId, &MemorySize)> {
+        let body = std::iter::once((BlockId::Main, &self.main));
+        let func = self
+            .func
+            .iter()
+            .enumerate()
+            .map(|(id, size)| (BlockId::Function(id), size));
+        body.chain(func)
+    }
+}
+
+#[derive(Debug, Clone, std::default::Default)]
 pub struct MemorySize {
     pub integer_count: usize,
     pub real_count: usize,
@@ -29,10 +167,99 @@ pub struct MemorySize {
     pub string_count: usize,
 }
 
+impl MemorySize {
+    pub fn count(&self, kind: &Kind) -> usize {
+        match kind {
+            Kind::Integer => self.integer_count,
+            Kind::Real => self.real_count,
+            Kind::Bool => self.boolean_count,
+            Kind::Str => self.string_count,
+        }
+    }
+
+    pub fn count_mut(&mut self, kind: &Kind) -> &mut usize {
+        match kind {
+            Kind::Integer => &mut self.integer_count,
+            Kind::Real => &mut self.real_count,
+            Kind::Bool => &mut self.boolean_count,
+            Kind::Str => &mut self.string_count,
+        }
+    }
+}
+
 impl Block {
     pub fn new(code: Vec<Command>) -> Self {
+        Self::with_offsets(code, vec![])
+    }
+
+    pub fn with_offsets(code: Vec<Command>, offsets: Vec<usize>) -> Self {
         let labels = Self::build_labels(&code);
-        Self { code, labels }
+        Self {
+            code,
+            labels,
+            offsets,
+        }
+    }
+
+    // byte offset of the command at `index`, when known: code
+    // edited in place without keeping the table in step has none
+    pub fn offset(&self, index: usize) -> Option<usize> {
+        if self.offsets.len() != self.code.len() {
+            return None;
+        }
+        self.offsets.get(index).copied()
+    }
+
+    // targets of relative jumps have no LBL in the code, so
+    // indices stay those of the file: (index, label) pairs
+    pub fn implicit_labels(&self) -> Vec<(usize, usize)> {
+        let mut output: Vec<(usize, usize)> = self
+            .labels
+            .iter()
+            .filter(|(label, index)| {
+                !matches!(self.code.get(**index),
+                    Some(Command::Control(ControlFlow::Label, found)) if found == *label)
+            })
+            .map(|(label, index)| (*index, *label))
+            .collect();
+        output.sort_unstable();
+        output
+    }
+
+    // put a LBL in front of every implicit target, for passes
+    // telling basic blocks apart by their labels. New labels
+    // share the offset of the command after them
+    pub fn make_labels_explicit(&mut self) {
+        let implicit = self.implicit_labels();
+        if implicit.is_empty() {
+            return;
+        }
+        let keep_offsets = self.offsets.len() == self.code.len();
+        let last = self.offsets.last().copied().unwrap_or(0);
+        let mut code = Vec::with_capacity(self.code.len() + implicit.len());
+        let mut offsets = Vec::new();
+        let mut pending = implicit.iter().peekable();
+        let commands = std::mem::take(&mut self.code);
+        for (index, cmd) in commands.into_iter().enumerate() {
+            while let Some((_, label)) = pending.next_if(|(target, _)| *target == index) {
+                code.push(Command::Control(ControlFlow::Label, *label));
+                if keep_offsets {
+                    offsets.push(self.offsets[index]);
+                }
+            }
+            code.push(cmd);
+            if keep_offsets {
+                offsets.push(self.offsets[index]);
+            }
+        }
+        // targets at the end of the block
+        for (_, label) in pending {
+            code.push(Command::Control(ControlFlow::Label, *label));
+            if keep_offsets {
+                offsets.push(last);
+            }
+        }
+        *self = Self::with_offsets(code, offsets);
     }
 
     fn build_labels(code: &[Command]) -> HashMap<usize, usize> {
@@ -46,7 +273,7 @@ impl Block {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Command {
     Integer(Operator),
     Real(Operator),
@@ -57,6 +284,12 @@ pub enum Command {
     Control(ControlFlow, usize),
     Input(Kind),
     Output(Kind),
+    OutputMany(Vec<Kind>),
+    // WRS immediately followed by FLN, fused by the loader
+    OutputLine,
+    RawInput,
+    RawOutput,
+    SetBoolFormat,
     Flush(FlushMode),
     ForControl(ForControl),
     Exit,
@@ -64,10 +297,42 @@ pub enum Command {
     StoreParam(Kind, AddrSize),
     NewRecord(usize),
     Unary(Kind),
+    Duplicate(Kind),
+    StringStats,
+    SetTimer(usize),
+    GetDefine,
     StrCompare(RelationalOperator),
     BoolCompare(RelationalOperator),
+    // SBNEW, SBAPPx and SBFINISH
+    NewBuilder,
+    Append(Kind),
+    FinishBuilder,
+    // MemoryLoad and MemoryStore after `resolve_slots`
+    LoadSlot(Kind, Slot),
+    StoreSlot(Kind, Slot),
+    HashString,
+    // ANDI..SHRI
+    Bitwise(BitOp),
+    // SQRT..EXP
+    MathIntrinsic(Intrinsic),
+    // ABSI and ABSR
+    Absolute(Kind),
+    // CSTI_TRUNC..CSTI_EVEN
+    RoundInt(Rounding),
+    Substring,
+    CharAt,
+    // UPPER, LOWER and TRIM
+    Transform(TextOp),
+    // STR2INT and STR2REAL, INT2STR and REAL2STR
+    ParseNumber(Kind),
+    FormatNumber(Kind),
+    // ANEWx, ALDx, ASTx and ALEN
+    ArrayNew(Kind),
+    ArrayLoad(Kind),
+    ArrayStore(Kind),
+    ArrayLength,
 }
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Kind {
     Integer,
     Real,
@@ -75,7 +340,21 @@ pub enum Kind {
     Bool,
 }
 
+impl std::fmt::Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Integer => "integer",
+            Self::Real => "real",
+            Self::Bool => "boolean",
+            Self::Str => "string",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 impl Kind {
+    pub const ALL: [Kind; 4] = [Self::Integer, Self::Real, Self::Bool, Self::Str];
+
     pub fn new(byte: u8) -> Self {
         match byte % 4 {
             0 => Self::Integer,
@@ -87,7 +366,7 @@ impl Kind {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Operator {
     Math(MathOperator),
     Rel(RelationalOperator),
@@ -103,7 +382,7 @@ impl Operator {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum RelationalOperator {
     GreatEq,
     Greater,
@@ -127,12 +406,17 @@ impl RelationalOperator {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum MathOperator {
     Add,
     Sub,
     Mul,
     Div,
+    // MODx, POWx, MINx and MAXx, outside the ADDx..NEx groups
+    Mod,
+    Pow,
+    Min,
+    Max,
 }
 
 impl MathOperator {
@@ -147,7 +431,126 @@ impl MathOperator {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+    Not,
+    Shl,
+    Shr,
+}
+
+impl BitOp {
+    pub fn new(byte: u8) -> Self {
+        match byte {
+            opcode::ANDI => Self::And,
+            opcode::ORI => Self::Or,
+            opcode::XORI => Self::Xor,
+            opcode::NOTI => Self::Not,
+            opcode::SHLI => Self::Shl,
+            opcode::SHRI => Self::Shr,
+            _ => unreachable!(),
+        }
+    }
+
+    // integers popped from the stack
+    pub fn arity(self) -> usize {
+        match self {
+            Self::Not => 1,
+            _ => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Intrinsic {
+    Sqrt,
+    Sin,
+    Cos,
+    Log,
+    Exp,
+}
+
+impl Intrinsic {
+    pub fn new(byte: u8) -> Self {
+        match byte {
+            opcode::SQRT => Self::Sqrt,
+            opcode::SIN => Self::Sin,
+            opcode::COS => Self::Cos,
+            opcode::LOG => Self::Log,
+            opcode::EXP => Self::Exp,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn apply(self, value: f64) -> f64 {
+        match self {
+            Self::Sqrt => value.sqrt(),
+            Self::Sin => value.sin(),
+            Self::Cos => value.cos(),
+            Self::Log => value.ln(),
+            Self::Exp => value.exp(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rounding {
+    Trunc,
+    Floor,
+    Ceil,
+    HalfEven,
+}
+
+impl Rounding {
+    pub fn new(byte: u8) -> Self {
+        match byte {
+            opcode::CSTI_TRUNC => Self::Trunc,
+            opcode::CSTI_FLOOR => Self::Floor,
+            opcode::CSTI_CEIL => Self::Ceil,
+            opcode::CSTI_EVEN => Self::HalfEven,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn apply(self, value: f64) -> f64 {
+        match self {
+            Self::Trunc => value.trunc(),
+            Self::Floor => value.floor(),
+            Self::Ceil => value.ceil(),
+            Self::HalfEven => value.round_ties_even(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextOp {
+    Upper,
+    Lower,
+    Trim,
+}
+
+impl TextOp {
+    pub fn new(byte: u8) -> Self {
+        match byte {
+            opcode::UPPER => Self::Upper,
+            opcode::LOWER => Self::Lower,
+            opcode::TRIM => Self::Trim,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            Self::Upper => text.to_uppercase(),
+            Self::Lower => text.to_lowercase(),
+            Self::Trim => text.trim().to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum ControlFlow {
     Jump,
     JumpTrue,
@@ -171,7 +574,7 @@ impl ControlFlow {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Constant {
     Integer(i32),
     Real(f64),
@@ -179,13 +582,13 @@ pub enum Constant {
     Bool(bool),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum FlushMode {
     Flush,
     NewLine,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ForControl {
     New,
     End,
@@ -220,4 +623,32 @@ mod test {
             assert_eq!(mapping.get(lbl).unwrap(), index);
         }
     }
+
+    #[test]
+    fn test_explicit_labels() {
+        let code = vec![
+            Command::Control(ControlFlow::Label, 0),
+            Command::Control(ControlFlow::Jump, 1),
+            Command::Exit,
+        ];
+        let mut block = Block::with_offsets(code, vec![0, 1, 4]);
+        block.labels.insert(1, 2);
+        block.labels.insert(2, 3);
+        assert_eq!(block.implicit_labels(), vec![(2, 1), (3, 2)]);
+
+        block.make_labels_explicit();
+        assert_eq!(block.code.len(), 5);
+        assert!(matches!(
+            block.code[2],
+            Command::Control(ControlFlow::Label, 1)
+        ));
+        assert!(matches!(
+            block.code[4],
+            Command::Control(ControlFlow::Label, 2)
+        ));
+        assert_eq!(block.labels[&1], 2);
+        assert_eq!(block.labels[&2], 4);
+        assert_eq!(block.offsets, vec![0, 1, 4, 4, 4]);
+        assert!(block.implicit_labels().is_empty());
+    }
 }
@@ -0,0 +1,317 @@
+use crate::command_definition::BlockId;
+use crate::engine::{Engine, RuntimeError};
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+// Remote control of a running program. A listener thread accepts
+// TCP connections and serves each one on its own thread, reading
+// one command per line. The engine polls the commands of all the
+// clients between two instructions, in arrival order, and sends
+// back a one line reply. Commands:
+//
+//   pause                  stop before the next instruction
+//   resume                 go on after a pause or a breakpoint
+//   dump-state             position, executed instructions and globals
+//   set-breakpoint B N     pause before instruction N of block B,
+//                          `main` or a function id
+//   cancel                 stop the program, the finalizer still runs
+
+// instructions executed between two polls of the commands
+pub const CONTROL_CHECK_PERIOD: u64 = 1024;
+
+#[derive(Debug, PartialEq)]
+pub enum ControlCommand {
+    Pause,
+    Resume,
+    DumpState,
+    SetBreakpoint(BlockId, usize),
+    Cancel,
+}
+
+impl std::str::FromStr for ControlCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        match words.as_slice() {
+            ["pause"] => Ok(Self::Pause),
+            ["resume"] => Ok(Self::Resume),
+            ["dump-state"] => Ok(Self::DumpState),
+            ["cancel"] => Ok(Self::Cancel),
+            ["set-breakpoint", block, index] => {
+                let block = match *block {
+                    "main" => BlockId::Main,
+                    id => match id.parse() {
+                        Ok(id) => BlockId::Function(id),
+                        Err(_) => return Err(format!("{:?} is not a block", id)),
+                    },
+                };
+                match index.parse() {
+                    Ok(index) => Ok(Self::SetBreakpoint(block, index)),
+                    Err(_) => Err(format!("{:?} is not an instruction index", index)),
+                }
+            }
+            _ => Err(format!("unknown command {:?}", s.trim())),
+        }
+    }
+}
+
+pub struct Request {
+    pub command: ControlCommand,
+    pub reply: Sender<String>,
+}
+
+pub struct ControlServer {
+    addr: SocketAddr,
+    requests: Receiver<Request>,
+}
+
+impl ControlServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let sender = sender.clone();
+                // a client going away does not stop the listener
+                thread::spawn(move || serve_client(stream, &sender));
+            }
+        });
+        Ok(Self { addr, requests })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn requests(&self) -> &Receiver<Request> {
+        &self.requests
+    }
+}
+
+fn serve_client(stream: TcpStream, sender: &Sender<Request>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match line.parse() {
+            Ok(command) => forward(command, sender),
+            Err(err) => format!("error: {}", err),
+        };
+        writeln!(writer, "{}", reply)?;
+    }
+    Ok(())
+}
+
+fn forward(command: ControlCommand, sender: &Sender<Request>) -> String {
+    let (reply, answer) = mpsc::channel();
+    let ended = || "error: the program is over".to_owned();
+    if sender.send(Request { command, reply }).is_err() {
+        return ended();
+    }
+    answer.recv().unwrap_or_else(|_| ended())
+}
+
+// send a single command to a control socket, returns the reply
+pub fn send_command<A: ToSocketAddrs>(addr: A, command: &str) -> io::Result<String> {
+    let mut stream = TcpStream::connect(addr)?;
+    writeln!(stream, "{}", command)?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    Ok(reply.trim_end().to_owned())
+}
+
+// run `engine` to the end, finalizer included, serving
+// the commands coming from `requests`
+pub fn run_controlled(
+    engine: &mut Engine,
+    requests: &Receiver<Request>,
+    out: &mut dyn Write,
+) -> Result<(), RuntimeError> {
+    let status = serve(engine, requests, out);
+    engine.finalize(status, out)
+}
+
+struct ControlState {
+    paused: bool,
+    cancelled: bool,
+    breakpoints: HashSet<(BlockId, usize)>,
+}
+
+fn serve(
+    engine: &mut Engine,
+    requests: &Receiver<Request>,
+    out: &mut dyn Write,
+) -> Result<(), RuntimeError> {
+    let mut state = ControlState {
+        paused: false,
+        cancelled: false,
+        breakpoints: HashSet::new(),
+    };
+    while engine.is_running() {
+        let request = if state.paused {
+            // nobody is left to resume the program: let it go
+            requests.recv().ok()
+        } else if engine.executed().is_multiple_of(CONTROL_CHECK_PERIOD) {
+            requests.try_recv().ok()
+        } else {
+            None
+        };
+        match request {
+            Some(request) => {
+                let reply = state.handle(request.command, engine);
+                let _ = request.reply.send(reply);
+                if state.cancelled {
+                    return Err(RuntimeError::Cancelled);
+                }
+            }
+            None => {
+                state.paused = false;
+                engine.step(out)?;
+                let position = (engine.current_block(), engine.index());
+                state.paused = state.breakpoints.contains(&position);
+            }
+        }
+    }
+    Ok(())
+}
+
+impl ControlState {
+    fn handle(&mut self, command: ControlCommand, engine: &Engine) -> String {
+        match command {
+            ControlCommand::Pause => {
+                self.paused = true;
+                format!("paused {}", position(engine))
+            }
+            ControlCommand::Resume => {
+                self.paused = false;
+                "resumed".to_owned()
+            }
+            ControlCommand::DumpState => {
+                let state = if self.paused { "paused" } else { "running" };
+                let snapshot = engine.snapshot();
+                format!(
+                    "{} {}, {} executed, call depth {}, integers {:?} reals {:?} booleans {:?} strings {:?}",
+                    state,
+                    position(engine),
+                    engine.executed(),
+                    engine.depth(),
+                    snapshot.integers,
+                    snapshot.reals,
+                    snapshot.booleans,
+                    snapshot.strings
+                )
+            }
+            ControlCommand::SetBreakpoint(block, index) => {
+                self.breakpoints.insert((block, index));
+                format!("breakpoint at {} instruction {}", block, index)
+            }
+            ControlCommand::Cancel => {
+                self.cancelled = true;
+                "cancelled".to_owned()
+            }
+        }
+    }
+}
+
+fn position(engine: &Engine) -> String {
+    format!(
+        "at {} instruction {}",
+        engine.current_block(),
+        engine.index()
+    )
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::engine::EngineConfig;
+    use crate::program_load::{parse_data, LoadOptions};
+    use crate::stress::{generate, Shape};
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!("pause".parse(), Ok(ControlCommand::Pause));
+        assert_eq!(
+            " set-breakpoint 3  12 ".parse(),
+            Ok(ControlCommand::SetBreakpoint(BlockId::Function(3), 12))
+        );
+        assert_eq!(
+            "set-breakpoint main 0".parse(),
+            Ok(ControlCommand::SetBreakpoint(BlockId::Main, 0))
+        );
+        assert!("set-breakpoint f 1".parse::<ControlCommand>().is_err());
+        assert!("stop".parse::<ControlCommand>().is_err());
+    }
+
+    #[test]
+    fn test_run_controlled() {
+        let shape = Shape {
+            call_depth: 2,
+            constants: 0,
+            iterations: 1_000_000,
+        };
+        let data = generate(&shape);
+        let (mut prog, prog_mem, mut str_mem, _) =
+            parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        let mut input = io::empty();
+        let mut engine =
+            Engine::new(&mut prog, &prog_mem, &mut str_mem, &config, &mut input).unwrap();
+
+        let (sender, requests) = mpsc::channel();
+        let client = thread::spawn(move || {
+            let breakpoint = ControlCommand::SetBreakpoint(BlockId::Function(1), 1);
+            forward(breakpoint, &sender);
+            let state = loop {
+                let state = forward(ControlCommand::DumpState, &sender);
+                if state.starts_with("paused") {
+                    break state;
+                }
+            };
+            forward(ControlCommand::Cancel, &sender);
+            state
+        });
+        let status = run_controlled(&mut engine, &requests, &mut io::sink());
+        assert!(matches!(status, Err(RuntimeError::Cancelled)));
+        let state = client.join().unwrap();
+        assert!(state.starts_with("paused at function 1 instruction 1"));
+        assert!(state.contains("call depth 2"));
+    }
+
+    #[test]
+    fn test_concurrent_clients() {
+        let shape = Shape {
+            call_depth: 1,
+            constants: 0,
+            iterations: 1_000_000,
+        };
+        let data = generate(&shape);
+        let (mut prog, prog_mem, mut str_mem, _) =
+            parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        let mut input = io::empty();
+        let mut engine =
+            Engine::new(&mut prog, &prog_mem, &mut str_mem, &config, &mut input).unwrap();
+
+        let server = ControlServer::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr();
+        // an idle client does not hold back the next one
+        let _idle = TcpStream::connect(addr).unwrap();
+        let client = thread::spawn(move || {
+            let state = send_command(addr, "dump-state").unwrap();
+            send_command(addr, "cancel").unwrap();
+            state
+        });
+        let status = run_controlled(&mut engine, server.requests(), &mut io::sink());
+        assert!(matches!(status, Err(RuntimeError::Cancelled)));
+        assert!(client.join().unwrap().starts_with("running"));
+    }
+}
@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+// CPU time used so far by the calling thread, None where
+// the platform cannot tell it apart from the other threads
+#[cfg(unix)]
+pub fn thread_time() -> Option<Duration> {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `time` is a valid timespec for the call to fill
+    let res = unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) };
+    if res == 0 {
+        Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+pub fn thread_time() -> Option<Duration> {
+    None
+}
+
+#[cfg(all(test, unix))]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_thread_time() {
+        let before = thread_time().unwrap();
+        let mut sum = 0u64;
+        for i in 0..1_000_000u64 {
+            sum = sum.wrapping_add(i * i);
+        }
+        assert!(sum > 0);
+        // sleeping takes no CPU time
+        std::thread::sleep(Duration::from_millis(50));
+        let spent = thread_time().unwrap() - before;
+        assert!(spent > Duration::ZERO);
+        assert!(spent < Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,164 @@
+use crate::command_definition::{AddrSize, Kind, LOCAL_MASK};
+use crate::disasm;
+use crate::engine::{Engine, MemorySnapshot, RuntimeError};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// What a failed run leaves for post-mortem debugging: the error,
+// where it happened and the stacks and memory as the failing
+// instruction left them, before the finalizer runs. Values are
+// kept as the debugger prints them, JSON has no room for non
+// finite reals. Arrays are not saved.
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct CrashDump {
+    pub error: String,
+    // the failing instruction
+    pub instruction: String,
+    // the failing position then the calls that led there
+    pub backtrace: Vec<String>,
+    pub stacks: Cells,
+    pub global: Cells,
+    // memory of the failing function, none in the main body
+    pub local: Option<Cells>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Cells {
+    pub integers: Vec<String>,
+    pub reals: Vec<String>,
+    pub booleans: Vec<String>,
+    pub strings: Vec<String>,
+}
+
+impl From<MemorySnapshot> for Cells {
+    fn from(mem: MemorySnapshot) -> Self {
+        Self {
+            integers: mem.integers.iter().map(|v| v.to_string()).collect(),
+            reals: mem.reals.iter().map(|v| v.to_string()).collect(),
+            booleans: mem.booleans.iter().map(|v| v.to_string()).collect(),
+            strings: mem.strings.iter().map(|v| format!("{:?}", v)).collect(),
+        }
+    }
+}
+
+impl Cells {
+    pub fn of(&self, kind: Kind) -> &[String] {
+        match kind {
+            Kind::Integer => &self.integers,
+            Kind::Real => &self.reals,
+            Kind::Bool => &self.booleans,
+            Kind::Str => &self.strings,
+        }
+    }
+}
+
+impl CrashDump {
+    // `engine` has just failed with `err`
+    pub fn capture(engine: &Engine, err: &RuntimeError) -> Self {
+        let trace = engine.fault_trace();
+        let instruction = match trace.first() {
+            Some((block, index)) => {
+                let code = engine.code(*block);
+                disasm::instruction(&code.code[*index], code, engine.strings())
+            }
+            None => String::new(),
+        };
+        Self {
+            error: err.to_string(),
+            instruction,
+            backtrace: trace
+                .iter()
+                .map(|(block, index)| format!("{} instruction {}", block, index))
+                .collect(),
+            stacks: engine.stacks().into(),
+            global: engine.memory(false).unwrap().into(),
+            local: engine.memory(true).map(Cells::from),
+        }
+    }
+
+    // a memory slot, as `Engine::memory_value`
+    pub fn memory_value(&self, kind: Kind, addr: AddrSize) -> Option<&String> {
+        let cells = if addr & LOCAL_MASK == 0 {
+            &self.global
+        } else {
+            self.local.as_ref()?
+        };
+        cells.of(kind).get((addr & !LOCAL_MASK) as usize)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut data = serde_json::to_vec_pretty(self)?;
+        data.push(b'\n');
+        fs::write(path, data)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::asm::assemble;
+    use crate::debugger;
+    use crate::engine::EngineConfig;
+    use crate::program_load::{parse_data, LoadOptions};
+
+    #[test]
+    fn test_post_mortem() {
+        let source = "
+            INIT 0 0 0 1
+            LDSC \"one\"
+            STRS 0
+            PARAM 0
+            CALL 0
+            FUNC
+            INIT 0 1 0 0
+            LDRC 0.5
+            STRR local 0
+            LDIC 3
+            LDI0
+            DIVI
+            RET
+        ";
+        let data = assemble(source).unwrap();
+        let (mut prog, prog_mem, mut str_mem, _) =
+            parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        let mut input = io::empty();
+        let mut engine =
+            Engine::new(&mut prog, &prog_mem, &mut str_mem, &config, &mut input).unwrap();
+        let err = engine.run_until(u64::MAX, &mut io::sink()).unwrap_err();
+        let dump = CrashDump::capture(&engine, &err);
+
+        let path = std::env::temp_dir().join(format!("simpla-dump-{}", std::process::id()));
+        dump.save(&path).unwrap();
+        let loaded = CrashDump::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, dump);
+
+        let mut commands = "print mem str 0\nprint mem real local 0\nprint mem int local 0\n\
+            dump-mem real local\nbacktrace\ncontinue\nquit\n"
+            .as_bytes();
+        let mut console = vec![];
+        debugger::post_mortem(&loaded, &mut commands, &mut console).unwrap();
+        let console = String::from_utf8(console).unwrap();
+        let expected = "integer division by zero
+function 0 instruction 4: DIVI
+(debug) \"one\"
+(debug) 0.5
+(debug) error: no such memory slot
+(debug) 0: 0.5
+(debug) #0 function 0 instruction 4
+#1 main body instruction 3
+(debug) error: the program is over, only its state can be printed
+(debug) ";
+        assert_eq!(console, expected);
+    }
+}
@@ -0,0 +1,930 @@
+use crate::array_heap::{Array, ArrayError};
+use crate::command_definition::{AddrSize, BlockId, Command, Kind, LOCAL_MASK};
+use crate::crash_dump::{Cells, CrashDump};
+use crate::disasm;
+use crate::engine::{Engine, RuntimeError};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, Write};
+
+// Interactive debugger on top of `Engine::step`: the program runs
+// in the usual engine, one instruction at a time, while commands
+// are read one per line. Commands:
+//
+//   break [B] N            stop before instruction N of block B,
+//                          `main` or a function id, the current
+//                          block when missing
+//   break [B] N if A OP C  same, only when A OP C holds: OP is one
+//                          of < <= > >= == != and A, C are numbers,
+//                          quoted strings, memory slots K[N],
+//                          K[local N] or stack tops K[top]
+//   break-on-input         also stop before every read
+//   break-on-output        also stop before every write
+//   break-on-error         stop on a runtime error instead of
+//                          ending the session, with the machine as
+//                          the failing instruction left it
+//   step [N]               execute N instructions, 1 when missing
+//   continue               run up to the next breakpoint
+//   print stack            content of the value stacks
+//   print mem K [local] N  memory slot N of kind K: int, real,
+//                          bool or str
+//   dump-mem K global|local
+//                          every memory slot of kind K, the local
+//                          ones of the running function
+//   set-mem K [local] N V  store V in memory slot N of kind K
+//   print array H [I]      the array of handle H, or the one whose
+//                          handle is element I of H, and whether
+//                          the program can still reach it
+//   backtrace              current position and the callers
+//   quit                   stop the program, the finalizer still runs
+//
+// `post_mortem` answers print stack, print mem, dump-mem and
+// backtrace from a crash dump instead.
+
+// what break-on stops at, besides the breakpoints
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    Input,
+    Output,
+    Error,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DebugCommand {
+    Break(Option<BlockId>, usize, Option<Condition>),
+    BreakOn(Stop),
+    Step(u64),
+    Continue,
+    PrintStack,
+    PrintMem(Kind, AddrSize),
+    PrintArray(i32, Option<usize>),
+    DumpMem(Kind, bool),
+    SetMem(Kind, AddrSize, String),
+    Backtrace,
+    Quit,
+}
+
+impl std::str::FromStr for DebugCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // a condition is not split into words
+        let (s, condition) = match s.find(" if ") {
+            Some(at) if s.trim_start().starts_with("break ") => {
+                (&s[..at], Some(s[at + 4..].parse()?))
+            }
+            _ => (s, None),
+        };
+        let words: Vec<&str> = s.split_whitespace().collect();
+        match words.as_slice() {
+            ["break", index] => Ok(Self::Break(None, parse_index(index)?, condition)),
+            ["break", block, index] => Ok(Self::Break(
+                Some(block.parse()?),
+                parse_index(index)?,
+                condition,
+            )),
+            _ if condition.is_some() => Err(format!("unknown command {:?}", s.trim())),
+            ["break-on-input"] => Ok(Self::BreakOn(Stop::Input)),
+            ["break-on-output"] => Ok(Self::BreakOn(Stop::Output)),
+            ["break-on-error"] => Ok(Self::BreakOn(Stop::Error)),
+            ["step"] => Ok(Self::Step(1)),
+            ["step", count] => match count.parse() {
+                Ok(count) => Ok(Self::Step(count)),
+                Err(_) => Err(format!("{:?} is not a step count", count)),
+            },
+            ["continue"] => Ok(Self::Continue),
+            ["print", "stack"] => Ok(Self::PrintStack),
+            ["print", "mem", kind, addr] => {
+                Ok(Self::PrintMem(parse_kind(kind)?, parse_addr(addr)?))
+            }
+            ["print", "mem", kind, "local", addr] => {
+                let addr = parse_addr(addr)?;
+                Ok(Self::PrintMem(parse_kind(kind)?, addr | LOCAL_MASK))
+            }
+            ["print", "array", handle] => Ok(Self::PrintArray(parse_handle(handle)?, None)),
+            ["print", "array", handle, index] => Ok(Self::PrintArray(
+                parse_handle(handle)?,
+                Some(parse_index(index)?),
+            )),
+            ["dump-mem", kind, "global"] => Ok(Self::DumpMem(parse_kind(kind)?, false)),
+            ["dump-mem", kind, "local"] => Ok(Self::DumpMem(parse_kind(kind)?, true)),
+            ["set-mem", kind, "local", addr, _, ..] => Ok(Self::SetMem(
+                parse_kind(kind)?,
+                parse_addr(addr)? | LOCAL_MASK,
+                skip_words(s, 4).to_owned(),
+            )),
+            ["set-mem", kind, addr, _, ..] => Ok(Self::SetMem(
+                parse_kind(kind)?,
+                parse_addr(addr)?,
+                skip_words(s, 3).to_owned(),
+            )),
+            ["backtrace"] => Ok(Self::Backtrace),
+            ["quit"] => Ok(Self::Quit),
+            _ => Err(format!("unknown command {:?}", s.trim())),
+        }
+    }
+}
+
+// what follows the first `count` words, a string value may hold spaces
+fn skip_words(s: &str, count: usize) -> &str {
+    let mut rest = s.trim();
+    for _ in 0..count {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        rest = rest[end..].trim_start();
+    }
+    rest
+}
+
+fn parse_index(index: &str) -> Result<usize, String> {
+    index
+        .parse()
+        .map_err(|_| format!("{:?} is not an instruction index", index))
+}
+
+fn parse_handle(handle: &str) -> Result<i32, String> {
+    handle
+        .parse()
+        .map_err(|_| format!("{:?} is not an array handle", handle))
+}
+
+fn parse_kind(kind: &str) -> Result<Kind, String> {
+    match kind {
+        "int" | "I" => Ok(Kind::Integer),
+        "real" | "R" => Ok(Kind::Real),
+        "bool" | "B" => Ok(Kind::Bool),
+        "str" | "S" => Ok(Kind::Str),
+        _ => Err(format!("{:?} is not a kind", kind)),
+    }
+}
+
+fn parse_addr(addr: &str) -> Result<AddrSize, String> {
+    match addr.parse() {
+        Ok(addr) if addr < LOCAL_MASK => Ok(addr),
+        _ => Err(format!("{:?} is not an address", addr)),
+    }
+}
+
+// `A OP C` of a conditional breakpoint
+#[derive(Debug, PartialEq)]
+pub struct Condition {
+    source: String,
+    lhs: Operand,
+    op: &'static str,
+    rhs: Operand,
+}
+
+#[derive(Debug, PartialEq)]
+enum Operand {
+    Literal(String),
+    Slot(Kind, AddrSize),
+    Top(Kind),
+}
+
+// longer operators first, `<` is a prefix of `<=`
+const COMPARISONS: [&str; 6] = ["<=", ">=", "==", "!=", "<", ">"];
+
+impl std::str::FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let source = s.trim();
+        // the first operator, a string on the right may hold others
+        let found = COMPARISONS
+            .iter()
+            .filter_map(|op| source.find(op).map(|at| (at, *op)))
+            .min_by_key(|(at, _)| *at);
+        match found {
+            Some((at, op)) => Ok(Self {
+                source: source.to_owned(),
+                lhs: parse_operand(&source[..at])?,
+                op,
+                rhs: parse_operand(&source[at + op.len()..])?,
+            }),
+            None => Err(format!("{:?} is not a comparison", source)),
+        }
+    }
+}
+
+fn parse_operand(operand: &str) -> Result<Operand, String> {
+    let operand = operand.trim();
+    let (kind, slot) = match operand.find('[') {
+        Some(at) if operand.ends_with(']') => (&operand[..at], &operand[at + 1..operand.len() - 1]),
+        // numbers and strings are kept as written
+        _ => return Ok(Operand::Literal(operand.to_owned())),
+    };
+    let kind = parse_kind(kind)?;
+    let words: Vec<&str> = slot.split_whitespace().collect();
+    match words.as_slice() {
+        ["top"] => Ok(Operand::Top(kind)),
+        [addr] => Ok(Operand::Slot(kind, parse_addr(addr)?)),
+        ["local", addr] => Ok(Operand::Slot(kind, parse_addr(addr)? | LOCAL_MASK)),
+        _ => Err(format!("{:?} is not a memory slot", operand)),
+    }
+}
+
+impl Condition {
+    // false when an operand does not exist, as a slot of a function
+    // not running or the top of an empty stack
+    fn holds(&self, engine: &Engine) -> bool {
+        let (lhs, rhs) = match (value(&self.lhs, engine), value(&self.rhs, engine)) {
+            (Some(lhs), Some(rhs)) => (lhs, rhs),
+            _ => return false,
+        };
+        // numbers compare by value, anything else as written
+        let order = match (lhs.parse::<f64>(), rhs.parse::<f64>()) {
+            (Ok(lhs), Ok(rhs)) => lhs.partial_cmp(&rhs),
+            _ => Some(lhs.cmp(&rhs)),
+        };
+        match (self.op, order) {
+            ("<", Some(order)) => order == Ordering::Less,
+            ("<=", Some(order)) => order != Ordering::Greater,
+            (">", Some(order)) => order == Ordering::Greater,
+            (">=", Some(order)) => order != Ordering::Less,
+            ("==", order) => order == Some(Ordering::Equal),
+            ("!=", order) => order != Some(Ordering::Equal),
+            _ => false,
+        }
+    }
+}
+
+// the operand as `print mem` shows it
+fn value(operand: &Operand, engine: &Engine) -> Option<String> {
+    match operand {
+        Operand::Literal(value) => Some(value.clone()),
+        Operand::Slot(kind, addr) => engine.memory_value(*kind, *addr),
+        Operand::Top(kind) => {
+            let stacks = engine.stacks();
+            match kind {
+                Kind::Integer => stacks.integers.last().map(|v| v.to_string()),
+                Kind::Real => stacks.reals.last().map(|v| v.to_string()),
+                Kind::Bool => stacks.booleans.last().map(|v| v.to_string()),
+                Kind::Str => stacks.strings.last().map(|v| format!("{:?}", v)),
+            }
+        }
+    }
+}
+
+// run `engine` under the commands read from `commands`, replies go
+// to `console` and the program output to `out`. The finalizer runs
+// once the program is over or the session ends
+pub fn run_debugger(
+    engine: &mut Engine,
+    commands: &mut dyn BufRead,
+    console: &mut dyn Write,
+    out: &mut dyn Write,
+) -> Result<(), RuntimeError> {
+    let status = debug(engine, commands, console, out, false);
+    engine.finalize(status, out)
+}
+
+// like `run_debugger`, but every command is echoed after its prompt
+// so that `log` reads as the transcript of an interactive session
+pub fn run_script(
+    engine: &mut Engine,
+    script: &mut dyn BufRead,
+    log: &mut dyn Write,
+    out: &mut dyn Write,
+) -> Result<(), RuntimeError> {
+    let status = debug(engine, script, log, out, true);
+    engine.finalize(status, out)
+}
+
+// answer the commands read from `commands` about a failed run saved
+// in `dump`. Only what reads the saved state makes sense there
+pub fn post_mortem(
+    dump: &CrashDump,
+    commands: &mut dyn BufRead,
+    console: &mut dyn Write,
+) -> Result<(), RuntimeError> {
+    let mut reply = match dump.backtrace.first() {
+        Some(position) => format!("{}\n{}: {}\n", dump.error, position, dump.instruction),
+        None => format!("{}\n", dump.error),
+    };
+    loop {
+        console
+            .write_all(reply.as_bytes())
+            .and_then(|_| console.write_all(b"(debug) "))
+            .and_then(|_| console.flush())
+            .map_err(RuntimeError::WriteError)?;
+        let mut line = String::new();
+        if commands
+            .read_line(&mut line)
+            .map_err(|err| RuntimeError::ReadError(err.into()))?
+            == 0
+        {
+            return Ok(());
+        }
+        if line.trim().is_empty() {
+            reply.clear();
+            continue;
+        }
+        reply = match line.parse() {
+            Ok(DebugCommand::Quit) => return Ok(()),
+            Ok(DebugCommand::PrintStack) => {
+                let stacks = &dump.stacks;
+                format!(
+                    "integers [{}]\nreals [{}]\nbooleans [{}]\nstrings [{}]\n",
+                    stacks.integers.join(", "),
+                    stacks.reals.join(", "),
+                    stacks.booleans.join(", "),
+                    stacks.strings.join(", ")
+                )
+            }
+            Ok(DebugCommand::PrintMem(kind, addr)) => match dump.memory_value(kind, addr) {
+                Some(value) => format!("{}\n", value),
+                None => "error: no such memory slot\n".to_owned(),
+            },
+            Ok(DebugCommand::DumpMem(kind, local)) => {
+                let cells = if local {
+                    dump.local.as_ref()
+                } else {
+                    Some(&dump.global)
+                };
+                match cells {
+                    Some(cells) => table(cells.of(kind)),
+                    None => "error: no function was running\n".to_owned(),
+                }
+            }
+            Ok(DebugCommand::Backtrace) => {
+                let mut reply = String::new();
+                for (depth, position) in dump.backtrace.iter().enumerate() {
+                    writeln!(reply, "#{} {}", depth, position).unwrap();
+                }
+                reply
+            }
+            Ok(_) => "error: the program is over, only its state can be printed\n".to_owned(),
+            Err(err) => format!("error: {}\n", err),
+        };
+    }
+}
+
+// state of a debugging session
+#[derive(Default)]
+struct Session {
+    breakpoints: HashMap<(BlockId, usize), Option<Condition>>,
+    stops: Vec<Stop>,
+    // error caught by break-on-error, raised when the session goes on
+    failure: Option<RuntimeError>,
+}
+
+impl Session {
+    // whether `continue` stops before the next instruction
+    fn stops_at(&self, engine: &Engine) -> bool {
+        if !engine.is_running() {
+            return false;
+        }
+        let position = (engine.current_block(), engine.index());
+        let cmd = &engine.current_code().code[engine.index()];
+        let breakpoint = match self.breakpoints.get(&position) {
+            Some(Some(condition)) => condition.holds(engine),
+            Some(None) => true,
+            None => false,
+        };
+        breakpoint
+            || (self.stops.contains(&Stop::Input) && is_input(cmd))
+            || (self.stops.contains(&Stop::Output) && is_output(cmd))
+    }
+}
+
+fn is_input(cmd: &Command) -> bool {
+    matches!(cmd, Command::Input(_) | Command::RawInput)
+}
+
+fn is_output(cmd: &Command) -> bool {
+    matches!(
+        cmd,
+        Command::Output(_)
+            | Command::OutputMany(_)
+            | Command::OutputLine
+            | Command::RawOutput
+            | Command::Flush(_)
+    )
+}
+
+fn debug(
+    engine: &mut Engine,
+    commands: &mut dyn BufRead,
+    console: &mut dyn Write,
+    out: &mut dyn Write,
+    echo: bool,
+) -> Result<(), RuntimeError> {
+    let mut session = Session::default();
+    let mut reply = position(engine);
+    loop {
+        let over = !engine.is_running() && session.failure.is_none();
+        if over {
+            reply.push_str("program over\n");
+        }
+        console
+            .write_all(reply.as_bytes())
+            .map_err(RuntimeError::WriteError)?;
+        if over {
+            return Ok(());
+        }
+        console
+            .write_all(b"(debug) ")
+            .and_then(|_| console.flush())
+            .map_err(RuntimeError::WriteError)?;
+        let mut line = String::new();
+        // the end of the commands ends the session
+        if commands
+            .read_line(&mut line)
+            .map_err(|err| RuntimeError::ReadError(err.into()))?
+            == 0
+        {
+            return Err(session.failure.unwrap_or(RuntimeError::Cancelled));
+        }
+        if echo {
+            writeln!(console, "{}", line.trim_end()).map_err(RuntimeError::WriteError)?;
+        }
+        if line.trim().is_empty() {
+            reply.clear();
+            continue;
+        }
+        reply = match line.parse() {
+            Ok(DebugCommand::Quit) => {
+                return Err(session.failure.unwrap_or(RuntimeError::Cancelled))
+            }
+            // a caught error ends the program once it goes on
+            Ok(DebugCommand::Step(_)) | Ok(DebugCommand::Continue) if session.failure.is_some() => {
+                return Err(session.failure.unwrap())
+            }
+            Ok(command) => handle(command, engine, &mut session, out)?,
+            Err(err) => format!("error: {}\n", err),
+        };
+    }
+}
+
+fn handle(
+    command: DebugCommand,
+    engine: &mut Engine,
+    session: &mut Session,
+    out: &mut dyn Write,
+) -> Result<String, RuntimeError> {
+    let reply = match command {
+        DebugCommand::Break(block, index, condition) => {
+            let block = block.unwrap_or_else(|| engine.current_block());
+            let reply = match &condition {
+                Some(condition) => format!(
+                    "breakpoint at {} instruction {} if {}\n",
+                    block, index, condition.source
+                ),
+                None => format!("breakpoint at {} instruction {}\n", block, index),
+            };
+            session.breakpoints.insert((block, index), condition);
+            reply
+        }
+        DebugCommand::BreakOn(stop) => {
+            session.stops.push(stop);
+            match stop {
+                Stop::Input => "stopping before every read\n".to_owned(),
+                Stop::Output => "stopping before every write\n".to_owned(),
+                Stop::Error => "stopping on runtime errors\n".to_owned(),
+            }
+        }
+        DebugCommand::Step(count) => resume(engine, session, Some(count), out)?,
+        DebugCommand::Continue => resume(engine, session, None, out)?,
+        DebugCommand::PrintStack => {
+            let stacks = engine.stacks();
+            format!(
+                "integers {:?}\nreals {:?}\nbooleans {:?}\nstrings {:?}\n",
+                stacks.integers, stacks.reals, stacks.booleans, stacks.strings
+            )
+        }
+        DebugCommand::PrintMem(kind, addr) => match engine.memory_value(kind, addr) {
+            Some(value) => format!("{}\n", value),
+            None => "error: no such memory slot\n".to_owned(),
+        },
+        DebugCommand::PrintArray(handle, index) => match print_array(engine, handle, index) {
+            Ok(reply) => reply,
+            Err(err) => format!("error: {}\n", err),
+        },
+        DebugCommand::DumpMem(kind, local) => match engine.memory(local) {
+            Some(mem) => table(Cells::from(mem).of(kind)),
+            None => "error: no function is running\n".to_owned(),
+        },
+        DebugCommand::SetMem(kind, addr, value) => {
+            match engine.set_memory_value(kind, addr, &value) {
+                Ok(()) => format!("{}\n", engine.memory_value(kind, addr).unwrap()),
+                Err(err) => format!("error: {}\n", err),
+            }
+        }
+        DebugCommand::Backtrace => {
+            let mut reply = String::new();
+            for (depth, (block, index)) in engine.backtrace().iter().enumerate() {
+                writeln!(reply, "#{} {} instruction {}", depth, block, index).unwrap();
+            }
+            reply
+        }
+        DebugCommand::Quit => unreachable!(),
+    };
+    Ok(reply)
+}
+
+// run `count` instructions, or up to the next stop when None
+fn resume(
+    engine: &mut Engine,
+    session: &mut Session,
+    count: Option<u64>,
+    out: &mut dyn Write,
+) -> Result<String, RuntimeError> {
+    let mut left = count;
+    while left != Some(0) {
+        left = left.map(|left| left - 1);
+        match engine.step(out) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) if session.stops.contains(&Stop::Error) => {
+                let (block, index) = engine.fault().unwrap();
+                let reply = format!(
+                    "stopped by an error at {} instruction {}: {}\n",
+                    block, index, err
+                );
+                session.failure = Some(err);
+                return Ok(reply);
+            }
+            Err(err) => return Err(err),
+        }
+        if count.is_none() && session.stops_at(engine) {
+            break;
+        }
+    }
+    Ok(position(engine))
+}
+
+// one `index: value` line per memory slot
+fn table(cells: &[String]) -> String {
+    if cells.is_empty() {
+        return "no slots\n".to_owned();
+    }
+    let mut reply = String::new();
+    for (index, value) in cells.iter().enumerate() {
+        writeln!(reply, "{}: {}", index, value).unwrap();
+    }
+    reply
+}
+
+// elements shown by `print array`
+const SHOWN_ELEMENTS: usize = 64;
+
+fn print_array(engine: &Engine, handle: i32, index: Option<usize>) -> Result<String, ArrayError> {
+    let handle = match index {
+        Some(index) => match engine.array(handle)? {
+            Array::Integer(values) => *values
+                .get(index)
+                .ok_or(ArrayError::Index(index as i32, values.len()))?,
+            _ => return Err(ArrayError::InvalidHandle(handle, Some(Kind::Integer))),
+        },
+        None => handle,
+    };
+    let array = engine.array(handle)?;
+    let shown = array.len().min(SHOWN_ELEMENTS);
+    let elements = match array {
+        Array::Integer(values) => format!("{:?}", &values[..shown]),
+        Array::Real(values) => format!("{:?}", &values[..shown]),
+        Array::Bool(values) => format!("{:?}", &values[..shown]),
+        Array::Str(values) => {
+            let strings = engine.strings();
+            let values: Vec<&str> = values[..shown]
+                .iter()
+                .map(|index| strings.get_string(*index))
+                .collect();
+            format!("{:?}", values)
+        }
+    };
+    let more = match array.len() - shown {
+        0 => String::new(),
+        hidden => format!(" and {} more", hidden),
+    };
+    let reach = if engine.is_reachable(handle) {
+        "reachable"
+    } else {
+        "unreachable"
+    };
+    Ok(format!(
+        "{} array {}, {} elements, {}\n{}{}\n",
+        array.kind(),
+        handle,
+        array.len(),
+        reach,
+        elements,
+        more
+    ))
+}
+
+// where the program stopped and the instruction that runs next
+fn position(engine: &Engine) -> String {
+    if !engine.is_running() {
+        return String::new();
+    }
+    let block = engine.current_code();
+    let cmd = &block.code[engine.index()];
+    format!(
+        "{} instruction {}: {}\n",
+        engine.current_block(),
+        engine.index(),
+        disasm::instruction(cmd, block, engine.strings())
+    )
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::asm::assemble;
+    use crate::engine::EngineConfig;
+    use crate::program_load::{parse_data, LoadOptions};
+    use std::io;
+
+    #[test]
+    fn test_debug_session() {
+        let source = "
+            INIT 1 0 0 0
+            LDIC 7
+            STRI 0
+            PARAM 0
+            LDIC 5
+            STRIP local 0
+            CALL 0
+            FUNC
+            INIT 1 0 0 0
+            LDI local 0
+            WRI
+            FLN
+            RET
+        ";
+        let data = assemble(source).unwrap();
+        let (mut prog, prog_mem, mut str_mem, _) =
+            parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        let mut input = io::empty();
+        let mut engine =
+            Engine::new(&mut prog, &prog_mem, &mut str_mem, &config, &mut input).unwrap();
+
+        let mut commands = "step 2\nprint mem int 0\nbreak 0 1\ncontinue\nprint stack\n\
+            print mem I local 0\nbacktrace\nbreak main 5\nbogus\ncontinue\n"
+            .as_bytes();
+        let mut console = vec![];
+        let mut out = vec![];
+        run_debugger(&mut engine, &mut commands, &mut console, &mut out).unwrap();
+        assert_eq!(out, b"5\n");
+        let console = String::from_utf8(console).unwrap();
+        let expected = "main body instruction 0: LDIC 7
+(debug) main body instruction 2: PARAM function 0
+(debug) 7
+(debug) breakpoint at function 0 instruction 1
+(debug) function 0 instruction 1: WRI
+(debug) integers [5]
+reals []
+booleans []
+strings []
+(debug) 5
+(debug) #0 function 0 instruction 1
+#1 main body instruction 6
+(debug) breakpoint at main body instruction 5
+(debug) error: unknown command \"bogus\"
+(debug) program over
+";
+        assert_eq!(console, expected);
+    }
+
+    #[test]
+    fn test_break_on_io_and_errors() {
+        let source = "
+            INIT 0 0 0 0
+            LDIC 1
+            RDI
+            WRI
+            FLN
+            LDI0
+            DIVI
+            WRI
+        ";
+        let data = assemble(source).unwrap();
+        let (mut prog, prog_mem, mut str_mem, _) =
+            parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        let mut input = "4\n".as_bytes();
+        let mut engine =
+            Engine::new(&mut prog, &prog_mem, &mut str_mem, &config, &mut input).unwrap();
+
+        let mut commands = "break-on-input\nbreak-on-output\nbreak-on-error\ncontinue\n\
+            continue\ncontinue\ncontinue\nprint stack\ncontinue\n"
+            .as_bytes();
+        let mut console = vec![];
+        let mut out = vec![];
+        let stat = run_debugger(&mut engine, &mut commands, &mut console, &mut out);
+        assert!(matches!(stat, Err(RuntimeError::DivisionByZero)));
+        assert_eq!(out, b"4\n");
+        let console = String::from_utf8(console).unwrap();
+        let expected = "main body instruction 0: LDIC 1
+(debug) stopping before every read
+(debug) stopping before every write
+(debug) stopping on runtime errors
+(debug) main body instruction 1: RDI
+(debug) main body instruction 2: WRI
+(debug) main body instruction 3: FLN
+(debug) stopped by an error at main body instruction 5: integer division by zero
+(debug) integers []
+reals []
+booleans []
+strings []
+(debug) ";
+        assert_eq!(console, expected);
+    }
+
+    #[test]
+    fn test_conditional_breakpoint() {
+        let source = "
+            INIT 1 0 0 0
+            LDIC 0
+            STRI 0
+            top:
+            LDI 0
+            LDI1
+            ADDI
+            STRI 0
+            LDI 0
+            LDIC 5
+            EQI
+            JNE top
+        ";
+        let data = assemble(source).unwrap();
+        let (mut prog, prog_mem, mut str_mem, _) =
+            parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        let mut input = io::empty();
+        let mut engine =
+            Engine::new(&mut prog, &prog_mem, &mut str_mem, &config, &mut input).unwrap();
+
+        let mut commands = "break 6 if int[top] == 3\ncontinue\nprint mem int 0\n\
+            break main 4 if int[0]==4\ncontinue\nprint stack\n\
+            break 2 if bogus[0] < 1\nbreak 2 if 1\ncontinue\n"
+            .as_bytes();
+        let mut console = vec![];
+        run_debugger(&mut engine, &mut commands, &mut console, &mut io::sink()).unwrap();
+        let console = String::from_utf8(console).unwrap();
+        let expected = "main body instruction 0: LDIC 0
+(debug) breakpoint at main body instruction 6 if int[top] == 3
+(debug) main body instruction 6: STRI global 0
+(debug) 2
+(debug) breakpoint at main body instruction 4 if int[0]==4
+(debug) main body instruction 4: LDIC 1
+(debug) integers [4]
+reals []
+booleans []
+strings []
+(debug) error: \"bogus\" is not a kind
+(debug) error: \"1\" is not a comparison
+(debug) program over
+";
+        assert_eq!(console, expected);
+    }
+
+    #[test]
+    fn test_memory_commands() {
+        let source = "
+            INIT 2 0 1 1
+            LDIC 7
+            STRI 0
+            PARAM 0
+            CALL 0
+            LDS 0
+            WRS
+            FLN
+            FUNC
+            INIT 0 1 0 0
+            LDR local 0
+            WRR
+            FLN
+            RET
+        ";
+        let data = assemble(source).unwrap();
+        let (mut prog, prog_mem, mut str_mem, _) =
+            parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        let mut input = io::empty();
+        let mut engine =
+            Engine::new(&mut prog, &prog_mem, &mut str_mem, &config, &mut input).unwrap();
+
+        let mut commands = "step 2\ndump-mem int global\ndump-mem real global\n\
+            dump-mem real local\nset-mem int 1 -3\nset-mem str 0 \"a b\"\nset-mem int 2 1\n\
+            set-mem bool 0 yes\nstep 2\ndump-mem int global\nset-mem real local 0 2.5\n\
+            set-mem int local 0 1\ncontinue\n"
+            .as_bytes();
+        let mut console = vec![];
+        let mut out = vec![];
+        run_debugger(&mut engine, &mut commands, &mut console, &mut out).unwrap();
+        assert_eq!(out, b"2.5\na b\n");
+        let console = String::from_utf8(console).unwrap();
+        let expected = "main body instruction 0: LDIC 7
+(debug) main body instruction 2: PARAM function 0
+(debug) 0: 7
+1: 0
+(debug) no slots
+(debug) error: no function is running
+(debug) -3
+(debug) \"a b\"
+(debug) error: no such memory slot
+(debug) error: \"yes\" is not a valid boolean
+(debug) function 0 instruction 0: LDR local 0
+(debug) 0: 7
+1: -3
+(debug) 2.5
+(debug) error: no such memory slot
+(debug) program over
+";
+        assert_eq!(console, expected);
+    }
+
+    #[test]
+    fn test_script() {
+        let data = assemble("INIT 0 0 0 0\nLDIC 3\nWRI\nFLN\n").unwrap();
+        let (mut prog, prog_mem, mut str_mem, _) =
+            parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        let mut input = io::empty();
+        let mut engine =
+            Engine::new(&mut prog, &prog_mem, &mut str_mem, &config, &mut input).unwrap();
+
+        let mut script = "break 2\ncontinue\nprint stack\ncontinue".as_bytes();
+        let mut log = vec![];
+        let mut out = vec![];
+        run_script(&mut engine, &mut script, &mut log, &mut out).unwrap();
+        assert_eq!(out, b"3\n");
+        let log = String::from_utf8(log).unwrap();
+        let expected = "main body instruction 0: LDIC 3
+(debug) break 2
+breakpoint at main body instruction 2
+(debug) continue
+main body instruction 2: FLN
+(debug) print stack
+integers []
+reals []
+booleans []
+strings []
+(debug) continue
+program over
+";
+        assert_eq!(log, expected);
+    }
+
+    #[test]
+    fn test_print_array() {
+        let source = "
+            INIT 2 0 0 0
+            LDIC 2
+            ANEWI
+            STRI 0
+            LDIC 1
+            ANEWS
+            STRI 1
+            LDI 0
+            LDIC 0
+            LDI 1
+            ASTI
+            LDI 1
+            LDIC 0
+            LDSC \"hi\"
+            ASTS
+            LDIC 3
+            ANEWR
+            STRI 1
+            LDIC 2
+            ANEWB
+            STRI 1
+            LDI 0
+            WRI
+        ";
+        let data = assemble(source).unwrap();
+        let (mut prog, prog_mem, mut str_mem, _) =
+            parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        let mut input = io::empty();
+        let mut engine =
+            Engine::new(&mut prog, &prog_mem, &mut str_mem, &config, &mut input).unwrap();
+
+        // the string array is only reachable through the integer one
+        let mut commands = "break 20\ncontinue\nprint array 0\nprint array 0 0\n\
+            print array 2\nprint array 9\nprint array 0 5\ncontinue\n"
+            .as_bytes();
+        let mut console = vec![];
+        run_debugger(&mut engine, &mut commands, &mut console, &mut io::sink()).unwrap();
+        let console = String::from_utf8(console).unwrap();
+        let expected = "main body instruction 0: LDIC 2
+(debug) breakpoint at main body instruction 20
+(debug) main body instruction 20: LDI global 0
+(debug) integer array 0, 2 elements, reachable
+[1, 0]
+(debug) string array 1, 1 elements, reachable
+[\"hi\"]
+(debug) real array 2, 3 elements, unreachable
+[0.0, 0.0, 0.0]
+(debug) error: 9 is not the handle of an array
+(debug) error: index 5 is outside an array of 2 elements
+(debug) program over
+";
+        assert_eq!(console, expected);
+    }
+}
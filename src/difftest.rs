@@ -0,0 +1,329 @@
+use crate::command_definition::{Kind, Program, ProgramMemory};
+use crate::engine::{Engine, EngineConfig, MemorySnapshot, RuntimeError};
+use crate::optimizer;
+#[cfg(feature = "register-ir")]
+use crate::register_ir;
+use crate::string_memory::StringMemory;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+pub struct RunResult {
+    pub output: Vec<u8>,
+    pub status: Result<MemorySnapshot, String>,
+}
+
+pub fn run_captured(
+    prog: Program,
+    prog_mem: ProgramMemory,
+    str_mem: StringMemory,
+    input: &[u8],
+) -> RunResult {
+    let (mut prog, mut str_mem) = (prog, str_mem);
+    run_with_config(
+        &mut prog,
+        &prog_mem,
+        &mut str_mem,
+        &EngineConfig::default(),
+        input,
+    )
+}
+
+// same as `run_captured` under `config`, the program
+// can run again with the same string memory
+pub fn run_with_config(
+    prog: &mut Program,
+    prog_mem: &ProgramMemory,
+    str_mem: &mut StringMemory,
+    config: &EngineConfig,
+    input: &[u8],
+) -> RunResult {
+    str_mem.reset();
+    let mut in_stream = input;
+    let mut output = Vec::new();
+    // malformed programs can still make the engine panic:
+    // that is a divergence too, not a reason to stop
+    let status = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut engine = Engine::new(prog, prog_mem, str_mem, config, &mut in_stream)?;
+        engine.run(&mut output)?;
+        Ok(engine.snapshot())
+    }));
+    let status = match status {
+        Ok(status) => status.map_err(|err: RuntimeError| err.to_string()),
+        Err(_) => Err("engine panic".to_owned()),
+    };
+    RunResult { output, status }
+}
+
+// ways of running a program that must not change what it does
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Plain,
+    Optimized,
+    #[cfg(feature = "register-ir")]
+    RegisterIr,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Plain => "plain",
+            Self::Optimized => "optimized",
+            #[cfg(feature = "register-ir")]
+            Self::RegisterIr => "register-ir",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::str::FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(Self::Plain),
+            "optimized" => Ok(Self::Optimized),
+            #[cfg(feature = "register-ir")]
+            "register-ir" => Ok(Self::RegisterIr),
+            _ => Err(format!("`{}` is not a known backend", s)),
+        }
+    }
+}
+
+// run on `backend`, the error tells why the
+// program cannot run there at all
+pub fn run_backend(
+    backend: Backend,
+    mut prog: Program,
+    mut prog_mem: ProgramMemory,
+    str_mem: StringMemory,
+    input: &[u8],
+) -> Result<RunResult, String> {
+    match backend {
+        Backend::Plain => Ok(run_captured(prog, prog_mem, str_mem, input)),
+        Backend::Optimized => {
+            optimizer::optimize(&mut prog, &mut prog_mem);
+            Ok(run_captured(prog, prog_mem, str_mem, input))
+        }
+        #[cfg(feature = "register-ir")]
+        Backend::RegisterIr => {
+            let reg_prog = register_ir::translate(&prog, &prog_mem).map_err(|e| e.to_string())?;
+            let mut in_stream = input;
+            let mut output = Vec::new();
+            let status = panic::catch_unwind(AssertUnwindSafe(|| {
+                register_ir::run_register_snapshot(
+                    &reg_prog,
+                    &str_mem,
+                    &EngineConfig::default(),
+                    &mut in_stream,
+                    &mut output,
+                )
+            }));
+            let status = match status {
+                Ok(status) => status.map_err(|err| err.to_string()),
+                Err(_) => Err("engine panic".to_owned()),
+            };
+            Ok(RunResult { output, status })
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Divergence {
+    Output(usize, Option<String>, Option<String>),
+    Status(Option<String>, Option<String>),
+    MemorySize(Kind, usize, usize),
+    Memory(Kind, usize, String, String),
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Output(line, left, right) => write!(
+                f,
+                "output differs at line {}\n< {}\n> {}",
+                line,
+                show_line(left),
+                show_line(right)
+            ),
+            Self::Status(left, right) => write!(
+                f,
+                "exit status differs\n< {}\n> {}",
+                show_status(left),
+                show_status(right)
+            ),
+            Self::MemorySize(kind, left, right) => write!(
+                f,
+                "global {} memory size differs: {} against {}",
+                kind, left, right
+            ),
+            Self::Memory(kind, addr, left, right) => write!(
+                f,
+                "global {} {} differs at exit\n< {}\n> {}",
+                kind, addr, left, right
+            ),
+        }
+    }
+}
+
+fn show_line(line: &Option<String>) -> &str {
+    match line {
+        Some(line) => line,
+        None => "<end of output>",
+    }
+}
+
+fn show_status(err: &Option<String>) -> &str {
+    match err {
+        Some(err) => err,
+        None => "<success>",
+    }
+}
+
+pub fn first_divergence(left: &RunResult, right: &RunResult) -> Option<Divergence> {
+    if let Some(div) = output_divergence(&left.output, &right.output) {
+        return Some(div);
+    }
+    match (&left.status, &right.status) {
+        (Ok(left), Ok(right)) => memory_divergence(left, right),
+        (Err(left), Err(right)) if left == right => None,
+        (left, right) => Some(Divergence::Status(
+            left.as_ref().err().cloned(),
+            right.as_ref().err().cloned(),
+        )),
+    }
+}
+
+pub fn output_divergence(left: &[u8], right: &[u8]) -> Option<Divergence> {
+    let mut left_lines = left.split(|b| *b == b'\n');
+    let mut right_lines = right.split(|b| *b == b'\n');
+    let mut line = 1;
+    loop {
+        match (left_lines.next(), right_lines.next()) {
+            (None, None) => return None,
+            (l, r) if l == r => line += 1,
+            (l, r) => {
+                let l = l.map(|l| String::from_utf8_lossy(l).into_owned());
+                let r = r.map(|r| String::from_utf8_lossy(r).into_owned());
+                return Some(Divergence::Output(line, l, r));
+            }
+        }
+    }
+}
+
+fn memory_divergence(left: &MemorySnapshot, right: &MemorySnapshot) -> Option<Divergence> {
+    slot_divergence(Kind::Integer, &left.integers, &right.integers, |l, r| {
+        l == r
+    })
+    // compare bit patterns so that NaN matches NaN
+    .or_else(|| {
+        slot_divergence(Kind::Real, &left.reals, &right.reals, |l, r| {
+            l.to_bits() == r.to_bits()
+        })
+    })
+    .or_else(|| slot_divergence(Kind::Bool, &left.booleans, &right.booleans, |l, r| l == r))
+    .or_else(|| slot_divergence(Kind::Str, &left.strings, &right.strings, |l, r| l == r))
+}
+
+fn slot_divergence<T, F>(kind: Kind, left: &[T], right: &[T], same: F) -> Option<Divergence>
+where
+    T: fmt::Debug,
+    F: Fn(&T, &T) -> bool,
+{
+    if left.len() != right.len() {
+        return Some(Divergence::MemorySize(kind, left.len(), right.len()));
+    }
+    left.iter()
+        .zip(right.iter())
+        .position(|(l, r)| !same(l, r))
+        .map(|addr| {
+            Divergence::Memory(
+                kind,
+                addr,
+                format!("{:?}", left[addr]),
+                format!("{:?}", right[addr]),
+            )
+        })
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::opcode;
+    use crate::program_load::{parse_data, LoadOptions};
+
+    fn run(code: Vec<u8>, input: &[u8]) -> RunResult {
+        let mut data = vec![opcode::INIT, 0, 1, 0, 0, 0, 0, 0, 0];
+        data.extend(code);
+        let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        run_captured(prog, mem, str_mem, input)
+    }
+
+    #[test]
+    fn test_same_program() {
+        let code = vec![opcode::RDI, opcode::WRI, opcode::FLN];
+        let left = run(code.clone(), b"12\n");
+        let right = run(code, b"12\n");
+        assert_eq!(left.output, b"12\n");
+        assert_eq!(first_divergence(&left, &right), None);
+    }
+
+    #[test]
+    fn test_output_divergence() {
+        let left = run(vec![opcode::LDIC, 0, 0, 0, 1, opcode::WRI], b"");
+        let right = run(vec![opcode::LDIC, 0, 0, 0, 2, opcode::WRI], b"");
+        let div = first_divergence(&left, &right).unwrap();
+        assert_eq!(
+            div,
+            Divergence::Output(1, Some("1".to_owned()), Some("2".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_memory_divergence() {
+        let left = run(vec![opcode::RDI, opcode::STRI, 0, 0], b"5\n");
+        let right = run(vec![opcode::RDI, opcode::STRI, 0, 0], b"7\n");
+        let div = first_divergence(&left, &right).unwrap();
+        assert_eq!(
+            div,
+            Divergence::Memory(Kind::Integer, 0, "5".to_owned(), "7".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_backends() {
+        // the optimizer turns the second load into a DUP
+        let code = vec![
+            opcode::RDI,
+            opcode::STRI,
+            0,
+            0,
+            opcode::LDI,
+            0,
+            0,
+            opcode::LDI,
+            0,
+            0,
+            opcode::ADDI,
+            opcode::WRI,
+        ];
+        let mut data = vec![opcode::INIT, 0, 1, 0, 0, 0, 0, 0, 0];
+        data.extend(code);
+        let load = || parse_data(&data, &LoadOptions::default()).unwrap();
+        let (prog, mem, str_mem, _) = load();
+        let plain = run_backend(Backend::Plain, prog, mem, str_mem, b"4\n").unwrap();
+        let (prog, mem, str_mem, _) = load();
+        let optimized = run_backend(Backend::Optimized, prog, mem, str_mem, b"4\n").unwrap();
+        assert_eq!(optimized.output, b"8");
+        assert_eq!(first_divergence(&plain, &optimized), None);
+        assert_eq!("optimized".parse(), Ok(Backend::Optimized));
+    }
+
+    #[test]
+    fn test_status_divergence() {
+        let left = run(vec![opcode::RDI], b"5\n");
+        let right = run(vec![opcode::RDI], b"");
+        let div = first_divergence(&left, &right).unwrap();
+        assert!(matches!(div, Divergence::Status(None, Some(_))));
+    }
+}
@@ -0,0 +1,300 @@
+use crate::command_definition::{
+    AddrSize, BitOp, Block, BlockId, Command, Constant, ControlFlow, FlushMode, ForControl,
+    Intrinsic, Kind, MathOperator, MemorySize, Operator, Program, ProgramMemory,
+    RelationalOperator, Rounding, TextOp, LOCAL_MASK,
+};
+use crate::string_memory::StringMemory;
+use std::fmt::Write;
+
+// Human readable listing of a loaded program: one line per
+// instruction with its index in the block, the opcode name and
+// the decoded operands. The listing shows the program as the
+// loader left it, so relative jumps appear as plain jumps to
+// generated labels, which have no LBL line of their own, and
+// WRS FLN pairs as a single line.
+
+pub fn disassemble(prog: &Program, prog_mem: &ProgramMemory, str_mem: &StringMemory) -> String {
+    let mut output = String::new();
+    for (key, value) in &prog.meta {
+        writeln!(output, "meta {} = {:?}", key, value).unwrap();
+    }
+    for (i, (kind, addr)) in prog_mem.args.iter().enumerate() {
+        writeln!(output, "argument {}: {} {}", i, kind, address(*addr)).unwrap();
+    }
+    if let Some(fini) = prog.fini {
+        writeln!(output, "finalizer: function {}", fini).unwrap();
+    }
+    let mut pure: Vec<_> = prog.pure.iter().collect();
+    pure.sort_by_key(|(func, _)| **func);
+    for (func, kinds) in pure {
+        let kinds: Vec<String> = kinds.iter().map(|k| k.to_string()).collect();
+        writeln!(output, "pure function {} -> {}", func, kinds.join(" ")).unwrap();
+    }
+    for ((id, block), (_, size)) in prog.blocks().zip(prog_mem.blocks()) {
+        disassemble_block(id, block, size, str_mem, &mut output);
+    }
+    output
+}
+
+fn disassemble_block(
+    id: BlockId,
+    block: &Block,
+    size: &MemorySize,
+    str_mem: &StringMemory,
+    output: &mut String,
+) {
+    writeln!(
+        output,
+        "\n{}: {} integer, {} real, {} boolean, {} string",
+        id, size.integer_count, size.real_count, size.boolean_count, size.string_count
+    )
+    .unwrap();
+    for (index, cmd) in block.code.iter().enumerate() {
+        writeln!(output, "{:6}  {}", index, instruction(cmd, block, str_mem)).unwrap();
+    }
+}
+
+// a single instruction, jumps are resolved through the labels of `block`
+pub fn instruction(cmd: &Command, block: &Block, str_mem: &StringMemory) -> String {
+    let name = mnemonic(cmd);
+    match cmd {
+        Command::MemoryLoad(_, addr)
+        | Command::MemoryStore(_, addr)
+        | Command::StoreParam(_, addr) => format!("{} {}", name, address(*addr)),
+        Command::LoadSlot(_, slot) | Command::StoreSlot(_, slot) => {
+            format!("{} {}", name, address(slot.addr()))
+        }
+        Command::Control(ctrl, addr) => control(&name, ctrl, *addr, block),
+        Command::OutputMany(kinds) => {
+            let kinds: Vec<&str> = kinds.iter().map(suffix).collect();
+            format!("{} {}", name, kinds.join(" "))
+        }
+        Command::ConstantLoad(Constant::Integer(n)) => format!("{} {}", name, n),
+        Command::ConstantLoad(Constant::Real(n)) => format!("{} {:?}", name, n),
+        Command::ConstantLoad(Constant::Bool(b)) => format!("{} {}", name, b),
+        Command::ConstantLoad(Constant::Str(index)) => {
+            format!("{} {:?}", name, str_mem.get_string(*index))
+        }
+        Command::NewRecord(func) | Command::SetTimer(func) => {
+            format!("{} function {}", name, func)
+        }
+        _ => name,
+    }
+}
+
+// opcode name of an instruction, without its operands
+pub fn mnemonic(cmd: &Command) -> String {
+    let name = match cmd {
+        Command::Integer(op) => return operator(op, "I"),
+        Command::Real(op) => return operator(op, "R"),
+        Command::StrCompare(op) => return format!("{}S", relational(op)),
+        Command::BoolCompare(op) => return format!("{}B", relational(op)),
+        Command::MemoryLoad(k, _) | Command::LoadSlot(k, _) => return format!("LD{}", suffix(k)),
+        Command::MemoryStore(k, _) | Command::StoreSlot(k, _) => {
+            return format!("STR{}", suffix(k))
+        }
+        Command::StoreParam(k, _) => return format!("STR{}P", suffix(k)),
+        Command::Input(k) => return format!("RD{}", suffix(k)),
+        Command::Output(k) => return format!("WR{}", suffix(k)),
+        Command::Duplicate(k) => return format!("DUP{}", suffix(k)),
+        Command::Append(k) => return format!("SBAPP{}", suffix(k)),
+        Command::ArrayNew(k) => return format!("ANEW{}", suffix(k)),
+        Command::ArrayLoad(k) => return format!("ALD{}", suffix(k)),
+        Command::ArrayStore(k) => return format!("AST{}", suffix(k)),
+        Command::Control(ControlFlow::Jump, _) => "JUMP",
+        Command::Control(ControlFlow::JumpTrue, _) => "JEQ",
+        Command::Control(ControlFlow::JumpFalse, _) => "JNE",
+        Command::Control(ControlFlow::Label, _) => "LBL",
+        Command::Control(ControlFlow::Call, _) => "CALL",
+        Command::Control(ControlFlow::Ret, _) => "RET",
+        Command::CastInt => "CSTI",
+        Command::CastReal => "CSTR",
+        Command::OutputMany(_) => "WRVN",
+        Command::OutputLine => "WRS FLN",
+        Command::RawInput => "RDRAW",
+        Command::RawOutput => "WRRAW",
+        Command::SetBoolFormat => "SETBOOLFMT",
+        Command::Flush(FlushMode::Flush) => "FLU",
+        Command::Flush(FlushMode::NewLine) => "FLN",
+        Command::ForControl(ForControl::New) => "BFOR",
+        Command::ForControl(ForControl::Check) => "CFOR",
+        Command::ForControl(ForControl::End) => "EFOR",
+        Command::Exit => "EXT",
+        Command::ConstantLoad(Constant::Integer(_)) => "LDIC",
+        Command::ConstantLoad(Constant::Real(_)) => "LDRC",
+        Command::ConstantLoad(Constant::Bool(_)) => "LDBC",
+        Command::ConstantLoad(Constant::Str(_)) => "LDSC",
+        Command::NewRecord(_) => "PARAM",
+        Command::Unary(Kind::Integer) => "NEGI",
+        Command::Unary(Kind::Real) => "NEGR",
+        Command::Unary(_) => "NOT",
+        Command::StringStats => "STRSTAT",
+        Command::SetTimer(_) => "TIMER",
+        Command::GetDefine => "GETDEF",
+        Command::NewBuilder => "SBNEW",
+        Command::FinishBuilder => "SBFINISH",
+        Command::HashString => "HASHS",
+        Command::Bitwise(BitOp::And) => "ANDI",
+        Command::Bitwise(BitOp::Or) => "ORI",
+        Command::Bitwise(BitOp::Xor) => "XORI",
+        Command::Bitwise(BitOp::Not) => "NOTI",
+        Command::Bitwise(BitOp::Shl) => "SHLI",
+        Command::Bitwise(BitOp::Shr) => "SHRI",
+        Command::MathIntrinsic(Intrinsic::Sqrt) => "SQRT",
+        Command::MathIntrinsic(Intrinsic::Sin) => "SIN",
+        Command::MathIntrinsic(Intrinsic::Cos) => "COS",
+        Command::MathIntrinsic(Intrinsic::Log) => "LOG",
+        Command::MathIntrinsic(Intrinsic::Exp) => "EXP",
+        Command::Absolute(Kind::Integer) => "ABSI",
+        Command::Absolute(_) => "ABSR",
+        Command::RoundInt(Rounding::Trunc) => "CSTI_TRUNC",
+        Command::RoundInt(Rounding::Floor) => "CSTI_FLOOR",
+        Command::RoundInt(Rounding::Ceil) => "CSTI_CEIL",
+        Command::RoundInt(Rounding::HalfEven) => "CSTI_EVEN",
+        Command::Substring => "SUBSTR",
+        Command::CharAt => "CHARAT",
+        Command::Transform(TextOp::Upper) => "UPPER",
+        Command::Transform(TextOp::Lower) => "LOWER",
+        Command::Transform(TextOp::Trim) => "TRIM",
+        Command::ParseNumber(Kind::Integer) => "STR2INT",
+        Command::ParseNumber(_) => "STR2REAL",
+        Command::FormatNumber(Kind::Integer) => "INT2STR",
+        Command::FormatNumber(_) => "REAL2STR",
+        Command::ArrayLength => "ALEN",
+    };
+    name.to_owned()
+}
+
+fn control(name: &str, ctrl: &ControlFlow, addr: usize, block: &Block) -> String {
+    match (ctrl, block.labels.get(&addr)) {
+        (ControlFlow::Label, _) => format!("{} L{}", name, addr),
+        (ControlFlow::Call, _) => format!("{} function {}", name, addr),
+        (ControlFlow::Ret, _) => name.to_owned(),
+        (_, Some(target)) => format!("{} L{} (-> {})", name, addr, target),
+        (_, None) => format!("{} L{}", name, addr),
+    }
+}
+
+fn operator(op: &Operator, kind: &str) -> String {
+    let name = match op {
+        Operator::Math(MathOperator::Add) => "ADD",
+        Operator::Math(MathOperator::Sub) => "SUB",
+        Operator::Math(MathOperator::Mul) => "MUL",
+        Operator::Math(MathOperator::Div) => "DIV",
+        Operator::Math(MathOperator::Mod) => "MOD",
+        Operator::Math(MathOperator::Pow) => "POW",
+        Operator::Math(MathOperator::Min) => "MIN",
+        Operator::Math(MathOperator::Max) => "MAX",
+        Operator::Rel(op) => relational(op),
+    };
+    format!("{}{}", name, kind)
+}
+
+fn relational(op: &RelationalOperator) -> &'static str {
+    match op {
+        RelationalOperator::GreatEq => "GEQ",
+        RelationalOperator::Greater => "GR",
+        RelationalOperator::LessEq => "LEQ",
+        RelationalOperator::Less => "LESQ",
+        RelationalOperator::Equal => "EQ",
+        RelationalOperator::NotEqual => "NE",
+    }
+}
+
+fn suffix(kind: &Kind) -> &'static str {
+    match kind {
+        Kind::Integer => "I",
+        Kind::Real => "R",
+        Kind::Bool => "B",
+        Kind::Str => "S",
+    }
+}
+
+fn address(addr: AddrSize) -> String {
+    if addr & LOCAL_MASK != 0 {
+        format!("local {}", addr & !LOCAL_MASK)
+    } else {
+        format!("global {}", addr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::opcode;
+    use crate::program_load::{parse_data, LoadOptions};
+
+    #[test]
+    fn test_disassemble() {
+        let data = vec![
+            opcode::INIT,
+            0,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+            1,
+            opcode::LDSC,
+            0,
+            2,
+            b'h',
+            b'i',
+            opcode::STRS,
+            0,
+            0,
+            opcode::LBL,
+            0,
+            3,
+            opcode::LDI,
+            0,
+            0,
+            opcode::LDI1,
+            opcode::GEQI,
+            opcode::JEQ,
+            0,
+            3,
+            opcode::PARAM,
+            0,
+            0,
+            opcode::CALL,
+            0,
+            0,
+            opcode::FUNC,
+            opcode::INIT,
+            0,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::STRI,
+            0x80,
+            0,
+            opcode::RET,
+        ];
+        let (prog, prog_mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let listing = disassemble(&prog, &prog_mem, &str_mem);
+        let expected = "
+main body: 1 integer, 0 real, 0 boolean, 1 string
+     0  LDSC \"hi\"
+     1  STRS global 0
+     2  LBL L3
+     3  LDI global 0
+     4  LDIC 1
+     5  GEQI
+     6  JEQ L3 (-> 2)
+     7  PARAM function 0
+     8  CALL function 0
+
+function 0: 1 integer, 0 real, 0 boolean, 0 string
+     0  STRI local 0
+     1  RET
+";
+        assert_eq!(listing, expected);
+    }
+}
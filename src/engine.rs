@@ -1,52 +1,767 @@
+use crate::array_heap::{Array, ArrayError, ArrayHeap};
 use crate::command_definition::{
-    AddrSize, Block, Command, Constant, ControlFlow, FlushMode, Kind, MathOperator, MemorySize,
-    Operator, Program, ProgramMemory, RelationalOperator,
+    AddrSize, BitOp, Block, BlockId, CallError, Command, Constant, ControlFlow, FlushMode,
+    ForControl, Kind, MathOperator, MemorySize, Operator, Program, ProgramMemory,
+    RelationalOperator, Slot, LOCAL_MASK,
 };
+use crate::cpu_clock;
+use crate::disasm;
 use crate::for_loop_stack::ForLoopStack;
+use crate::heap::{self, Collector, Roots};
 use crate::line_reader::{LineReader, ReadError};
-use crate::reference_memory::{ReferenceCount, ReferenceStack};
+use crate::livelock::{LivelockDetector, LIVELOCK_THRESHOLD};
+use crate::memo::{MemoCache, MemoKey, MemoValues};
+use crate::profiler::Profiler;
+use crate::program_load::resolve_slots;
 use crate::string_memory::StringMemory;
+use crate::timer::Timers;
+use crate::trace::{TraceOptions, Tracer, Watermarks};
+use std::cell::RefCell;
 use std::cmp::{PartialEq, PartialOrd};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
 use std::fmt;
-use std::io::{stdout, Write};
-use std::ops::{Add, Div, Mul, Sub};
-
-const ADDR_SIZE_ZERO: AddrSize = 0;
-const LOCAL_MASK: AddrSize = 1 << (ADDR_SIZE_ZERO.count_zeros() - 1);
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
+// run the program on stdin and stdout
 pub fn run_program(
     prog: Program,
     prog_mem: ProgramMemory,
-    mut string_memory: StringMemory,
-) -> Result<(), RuntimeError> {
-    let mut stack_vect: Vec<Record> = Vec::new();
+    string_memory: StringMemory,
+    config: &EngineConfig,
+) -> Result<MemorySnapshot, RuntimeError> {
+    let stdin = io::stdin();
+    let mut output = io::stdout();
+    let status = run_program_with_io(
+        prog,
+        prog_mem,
+        string_memory,
+        config,
+        &mut stdin.lock(),
+        &mut output,
+    );
+    // an error of the program wins over the one of the flush
+    let flushed = output.flush();
+    let snapshot = status?;
+    flushed?;
+    Ok(snapshot)
+}
+
+// run the program on the given streams, returns
+// the content of the global memory at exit
+pub fn run_program_with_io(
+    prog: Program,
+    prog_mem: ProgramMemory,
+    string_memory: StringMemory,
+    config: &EngineConfig,
+    in_stream: &mut dyn BufRead,
+    out: &mut dyn Write,
+) -> Result<MemorySnapshot, RuntimeError> {
+    let (mut prog, mut string_memory) = (prog, string_memory);
+    run_loaded(
+        &mut prog,
+        &prog_mem,
+        &mut string_memory,
+        config,
+        in_stream,
+        out,
+        None,
+    )
+}
+
+// same as `run_program_with_io`, the call stack is sampled
+// and the function calls are timed into `profiler` while the
+// program runs
+pub fn run_program_profiled(
+    prog: Program,
+    prog_mem: ProgramMemory,
+    string_memory: StringMemory,
+    config: &EngineConfig,
+    in_stream: &mut dyn BufRead,
+    out: &mut dyn Write,
+    profiler: &mut Profiler,
+) -> Result<MemorySnapshot, RuntimeError> {
+    let (mut prog, mut string_memory) = (prog, string_memory);
+    run_loaded(
+        &mut prog,
+        &prog_mem,
+        &mut string_memory,
+        config,
+        in_stream,
+        out,
+        Some(profiler),
+    )
+}
+
+// a loaded program that can run many times: every run starts
+// from a fresh machine and drops the strings left by the previous
+// one, the program itself and its constants are never touched.
+// The memory operands are resolved once, before the first run.
+pub struct LoadedProgram {
+    prog: Program,
+    prog_mem: ProgramMemory,
+    string_memory: StringMemory,
+}
+
+impl LoadedProgram {
+    pub fn new(
+        prog: Program,
+        prog_mem: ProgramMemory,
+        string_memory: StringMemory,
+    ) -> Result<Self, RuntimeError> {
+        let mut prog = prog;
+        resolve_slots(&mut prog, &prog_mem).map_err(|err| invalid_address(err.kind, err.addr))?;
+        Ok(Self {
+            prog,
+            prog_mem,
+            string_memory,
+        })
+    }
+
+    pub fn meta(&self) -> &[(String, String)] {
+        &self.prog.meta
+    }
+
+    pub fn run(
+        &mut self,
+        config: &EngineConfig,
+        in_stream: &mut dyn BufRead,
+        out: &mut dyn Write,
+    ) -> Result<MemorySnapshot, RuntimeError> {
+        self.string_memory.reset();
+        run_loaded(
+            &mut self.prog,
+            &self.prog_mem,
+            &mut self.string_memory,
+            config,
+            in_stream,
+            out,
+            None,
+        )
+    }
+}
+
+fn run_loaded(
+    prog: &mut Program,
+    prog_mem: &ProgramMemory,
+    string_memory: &mut StringMemory,
+    config: &EngineConfig,
+    in_stream: &mut dyn BufRead,
+    out: &mut dyn Write,
+    profiler: Option<&mut Profiler>,
+) -> Result<MemorySnapshot, RuntimeError> {
+    let mut engine = Engine::new(prog, prog_mem, string_memory, config, in_stream)?;
+    if let Some(profiler) = profiler {
+        engine = engine.profiler(profiler);
+    }
+    engine.run(out)?;
+    Ok(engine.snapshot())
+}
+
+// state shared by the main body and the finalizer
+struct Machine<'a> {
+    global_memory: EngineMemory,
+    string_memory: &'a mut StringMemory,
+    engine_stack: EngineStack,
+    bool_format: BoolFormat,
+    reader: LineReader<'a>,
+    executed: u64,
+    start: Instant,
+    // CPU time of the thread before the run, when known
+    cpu_start: Option<Duration>,
+    collector: Collector,
+    profiler: Option<&'a mut Profiler>,
+    tracer: Option<Tracer<'a>>,
+}
+
+// a program being executed one instruction at a time, starting
+// from the main body. `step` and `run_until` stop at the end of
+// the main body or at an EXT: the finalizer only runs through `run`
+pub struct Engine<'a> {
+    prog: &'a Program,
+    prog_mem: &'a ProgramMemory,
+    config: &'a EngineConfig,
+    io: IoPolicy,
+    machine: Machine<'a>,
+    curr_block: &'a Block,
+    index: usize,
+    stack_vect: Vec<Record<'a>>,
+    next_record: Option<Record<'a>>,
+    for_loop_stack: ForLoopStack,
+    timers: Timers,
+    in_handler: bool,
+    memo: Option<MemoCache>,
+    livelock: Option<LivelockDetector>,
+    // set by EXT and by any runtime error
+    halted: bool,
+    // call stack when the first runtime error was raised,
+    // empty until then
+    fault: Vec<(BlockId, usize)>,
+}
+
+impl<'a> Engine<'a> {
+    // memory operands of `prog` become slot references, checked
+    // once here: the engine only runs LoadSlot and StoreSlot
+    pub fn new(
+        prog: &'a mut Program,
+        prog_mem: &'a ProgramMemory,
+        string_memory: &'a mut StringMemory,
+        config: &'a EngineConfig,
+        in_stream: &'a mut dyn BufRead,
+    ) -> Result<Self, RuntimeError> {
+        resolve_slots(prog, prog_mem).map_err(|err| invalid_address(err.kind, err.addr))?;
+        let prog: &'a Program = prog;
+        let mut global_memory = EngineMemory::new(&prog_mem.main, config.audit_init);
+        bind_arguments(
+            &prog_mem.args,
+            &config.args,
+            &mut global_memory,
+            string_memory,
+        )?;
+
+        let machine = Machine {
+            global_memory,
+            string_memory,
+            engine_stack: EngineStack::new(),
+            bool_format: config.bool_format.clone(),
+            reader: LineReader::new(in_stream, config.echo_input),
+            executed: 0,
+            start: Instant::now(),
+            cpu_start: cpu_clock::thread_time(),
+            collector: Collector::new(),
+            profiler: None,
+            tracer: None,
+        };
+        Ok(Self {
+            prog,
+            prog_mem,
+            config,
+            io: config.io_policy(prog),
+            machine,
+            curr_block: &prog.body,
+            index: 0,
+            stack_vect: vec![],
+            next_record: None,
+            for_loop_stack: ForLoopStack::new(),
+            timers: Timers::new(),
+            in_handler: false,
+            memo: config.memo_limit.map(MemoCache::new),
+            livelock: new_livelock_detector(config),
+            halted: false,
+            fault: vec![],
+        })
+    }
+
+    // sample the call stack into `profiler` while running
+    // and time every function call
+    pub fn profiler(mut self, profiler: &'a mut Profiler) -> Self {
+        self.machine.profiler = Some(profiler);
+        self
+    }
+
+    // write every executed instruction to `tracer`, with
+    // its block and index
+    pub fn trace(self, tracer: &'a mut dyn Write) -> Self {
+        self.trace_with(tracer, TraceOptions::default())
+    }
+
+    pub fn trace_with(mut self, tracer: &'a mut dyn Write, options: TraceOptions) -> Self {
+        self.machine.tracer = Some(Tracer::new(tracer, options));
+        self
+    }
+
+    pub fn current_block(&self) -> BlockId {
+        block_id(self.prog, self.curr_block)
+    }
+
+    // index of the next instruction in the current block
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    // number of activation records on the call stack
+    pub fn depth(&self) -> usize {
+        self.stack_vect.len()
+    }
+
+    // block and index of the instruction that failed first, the
+    // finalizer does not replace the error of the program
+    pub fn fault(&self) -> Option<(BlockId, usize)> {
+        self.fault.first().copied()
+    }
+
+    // `call_stack` at the time of the fault
+    pub fn fault_trace(&self) -> &[(BlockId, usize)] {
+        &self.fault
+    }
+
+    pub fn executed(&self) -> u64 {
+        self.machine.executed
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.halted && self.index < self.curr_block.code.len()
+    }
+
+    pub fn snapshot(&self) -> MemorySnapshot {
+        MemorySnapshot::new(&self.machine.global_memory, self.machine.string_memory)
+    }
+
+    // capacity of the value stacks, now and at their peak
+    pub fn stack_stats(&self) -> StackStats {
+        self.machine.engine_stack.stats()
+    }
+
+    // estimated bytes in use, the figure the memory quota checks
+    pub fn memory_usage(&self) -> usize {
+        memory_usage(
+            &self.stack_vect,
+            &self.machine.global_memory,
+            &self.machine.engine_stack,
+            self.machine.string_memory,
+        )
+    }
+
+    // content of the value stacks, bottom first
+    pub fn stacks(&self) -> MemorySnapshot {
+        let stack = &self.machine.engine_stack;
+        MemorySnapshot {
+            integers: stack.int_stack.clone(),
+            reals: stack.real_stack.clone(),
+            booleans: stack.bool_stack.clone(),
+            strings: stack
+                .str_stack
+                .iter()
+                .map(|index| self.machine.string_memory.get_string(*index).to_owned())
+                .collect(),
+        }
+    }
+
+    // every slot of the global memory, or of the innermost
+    // activation record when `local`
+    pub fn memory(&self, local: bool) -> Option<MemorySnapshot> {
+        let mem = if local {
+            &self.stack_vect.last()?.func_mem
+        } else {
+            &self.machine.global_memory
+        };
+        Some(MemorySnapshot::new(mem, self.machine.string_memory))
+    }
+
+    // value of a memory slot, local addresses refer to the innermost
+    // activation record. None when the slot does not exist
+    pub fn memory_value(&self, kind: Kind, addr: AddrSize) -> Option<String> {
+        let mem = if addr & LOCAL_MASK == 0 {
+            &self.machine.global_memory
+        } else {
+            &self.stack_vect.last()?.func_mem
+        };
+        let slot = (addr & !LOCAL_MASK) as usize;
+        let value = match kind {
+            Kind::Integer => mem.int_mem.get(slot)?.to_string(),
+            Kind::Real => mem.real_mem.get(slot)?.to_string(),
+            Kind::Bool => mem.bool_mem.get(slot)?.to_string(),
+            Kind::Str => {
+                let index = *mem.str_mem.get(slot)?;
+                format!("{:?}", self.machine.string_memory.get_string(index))
+            }
+        };
+        Some(value)
+    }
+
+    // store `value`, written the way `memory_value` shows it,
+    // into a memory slot as a store instruction would
+    pub fn set_memory_value(
+        &mut self,
+        kind: Kind,
+        addr: AddrSize,
+        value: &str,
+    ) -> Result<(), String> {
+        let mem = if addr & LOCAL_MASK == 0 {
+            &mut self.machine.global_memory
+        } else {
+            match self.stack_vect.last_mut() {
+                Some(rec) => &mut rec.func_mem,
+                None => return Err("no function is running".to_owned()),
+            }
+        };
+        if !mem.contains(kind, addr) {
+            return Err("no such memory slot".to_owned());
+        }
+        let slot = (addr & !LOCAL_MASK) as usize;
+        let invalid = || format!("{:?} is not a valid {}", value, kind);
+        match kind {
+            Kind::Integer => mem.int_mem[slot] = value.parse().map_err(|_| invalid())?,
+            Kind::Real => mem.real_mem[slot] = value.parse().map_err(|_| invalid())?,
+            Kind::Bool => mem.bool_mem[slot] = value.parse().map_err(|_| invalid())?,
+            Kind::Str => {
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+                mem.str_mem[slot] = self.machine.string_memory.insert_string(value.to_owned());
+            }
+        }
+        mem.mark_written(kind, Slot::new(addr).addr());
+        Ok(())
+    }
+
+    // the current position then, for every caller, the
+    // position execution goes back to, innermost first
+    pub fn backtrace(&self) -> Vec<(BlockId, usize)> {
+        let callers = self
+            .stack_vect
+            .iter()
+            .rev()
+            .map(|rec| (block_id(self.prog, rec.return_block), rec.return_index));
+        std::iter::once((self.current_block(), self.index))
+            .chain(callers)
+            .collect()
+    }
+
+    // the current position then, for every caller, the
+    // instruction that made the call, innermost first
+    pub fn call_stack(&self) -> Vec<(BlockId, usize)> {
+        let callers = self.stack_vect.iter().rev().map(|rec| {
+            // timer handlers run before the next instruction
+            // of the caller, not from a CALL
+            let index = if rec.interrupt.is_some() {
+                rec.return_index
+            } else {
+                rec.return_index.saturating_sub(1)
+            };
+            (block_id(self.prog, rec.return_block), index)
+        });
+        std::iter::once((self.current_block(), self.index))
+            .chain(callers)
+            .collect()
+    }
+
+    // block being executed and strings of the program, to
+    // show the next instruction
+    pub fn current_code(&self) -> &'a Block {
+        self.curr_block
+    }
+
+    pub fn code(&self, block: BlockId) -> &'a Block {
+        match block {
+            BlockId::Main => &self.prog.body,
+            BlockId::Function(id) => &self.prog.func[id],
+        }
+    }
+
+    pub fn strings(&self) -> &StringMemory {
+        self.machine.string_memory
+    }
+
+    pub fn array(&self, handle: i32) -> Result<&Array, ArrayError> {
+        self.machine.engine_stack.arrays.get(handle)
+    }
+
+    // whether the program can still reach the array of `handle`,
+    // the arrays it cannot are swept by the next collection
+    pub fn is_reachable(&self, handle: i32) -> bool {
+        let roots = roots(
+            &self.stack_vect,
+            self.next_record.as_ref(),
+            &self.machine.global_memory,
+            &self.machine.engine_stack,
+            &self.for_loop_stack,
+            self.memo.as_ref(),
+        );
+        let (_, handles) = heap::mark(roots, &self.machine.engine_stack.arrays);
+        handles.contains(&handle)
+    }
+
+    // run the whole program, finalizer included, what it left
+    // unreachable is swept before returning
+    pub fn run(&mut self, out: &mut dyn Write) -> Result<(), RuntimeError> {
+        let status = self.run_to_end(out);
+        let status = self.finalize(status, out);
+        self.collect_garbage();
+        status
+    }
+
+    // sweep the strings and arrays the program cannot reach
+    pub fn collect_garbage(&mut self) {
+        let machine = &mut self.machine;
+        let roots = roots(
+            &self.stack_vect,
+            self.next_record.as_ref(),
+            &machine.global_memory,
+            &machine.engine_stack,
+            &self.for_loop_stack,
+            self.memo.as_ref(),
+        );
+        machine.collector.collect(
+            roots,
+            machine.string_memory,
+            &mut machine.engine_stack.arrays,
+        );
+    }
+
+    // run the finalizer, if any, of a program that stopped with
+    // `status`. The finalizer gets the exit code in its first local
+    // integer, an error raised by the program stays the primary one
+    // and the finalizer's own is added to it
+    pub fn finalize(
+        &mut self,
+        status: Result<(), RuntimeError>,
+        out: &mut dyn Write,
+    ) -> Result<(), RuntimeError> {
+        let fini = match self.prog.fini {
+            Some(fini) => fini,
+            None => return status,
+        };
+        let code = match &status {
+            Ok(()) => 0,
+            Err(err) => err.code(),
+        };
+        self.enter_finalizer(fini, code);
+        let fini_status = self.run_to_end(out);
+        match (status, fini_status) {
+            (Err(primary), Err(error)) => Err(RuntimeError::Aggregate {
+                primary: Box::new(primary),
+                secondary: vec![SecondaryError {
+                    block: self.current_block(),
+                    index: self.index,
+                    error,
+                }],
+            }),
+            (status, fini_status) => status.and(fini_status),
+        }
+    }
 
-    let mut curr_block = &prog.body;
-    let mut index: usize = 0;
+    // execute at most `count` instructions, true while
+    // there is something left to run
+    pub fn run_until(&mut self, count: u64, out: &mut dyn Write) -> Result<bool, RuntimeError> {
+        for _ in 0..count {
+            if !self.step(out)? {
+                return Ok(false);
+            }
+        }
+        Ok(self.is_running())
+    }
+
+    // execute the next instruction, true while there is something
+    // left to run. After an error the engine stays halted
+    pub fn step(&mut self, out: &mut dyn Write) -> Result<bool, RuntimeError> {
+        if !self.is_running() {
+            return Ok(false);
+        }
+        let status = self.execute_next(out);
+        let traced = self.trace_executed(status.is_ok());
+        if let Err(err) = status.and(traced) {
+            self.halted = true;
+            if self.fault.is_empty() {
+                self.fault = self.call_stack();
+            }
+            return Err(err);
+        }
+        Ok(self.is_running())
+    }
 
-    let mut global_memory = EngineMemory::new(&prog_mem.main);
-    let mut engine_stack = EngineStack::new();
+    // end the trace line of the last instruction, with
+    // the watermarks when it succeeded
+    fn trace_executed(&mut self, ok: bool) -> Result<(), RuntimeError> {
+        let machine = &mut self.machine;
+        let tracer = match machine.tracer.as_mut() {
+            Some(tracer) => tracer,
+            None => return Ok(()),
+        };
+        let stack = &machine.engine_stack;
+        let marks = Watermarks {
+            integers: stack.int_stack.len(),
+            reals: stack.real_stack.len(),
+            booleans: stack.bool_stack.len(),
+            strings: stack.str_stack.len(),
+            held_strings: machine.string_memory.dynamic_count(),
+        };
+        let marks = Some(marks).filter(|_| ok && tracer.wants_watermarks());
+        tracer.executed(marks).map_err(RuntimeError::WriteError)
+    }
+
+    fn run_to_end(&mut self, out: &mut dyn Write) -> Result<(), RuntimeError> {
+        while self.step(out)? {}
+        Ok(())
+    }
+
+    // returning from the finalizer lands past the end of the main body
+    fn enter_finalizer(&mut self, fini: usize, code: i32) {
+        let prog = self.prog;
+        let mut record = Record::new(
+            &prog.body,
+            fini,
+            &self.prog_mem.func[fini],
+            self.config.audit_init,
+        );
+        record.return_index = prog.body.code.len();
+        record.func_mem.int_mem[0] = code;
+        record.func_mem.mark_written(Kind::Integer, LOCAL_MASK);
+        self.curr_block = &prog.func[fini];
+        self.index = 0;
+        self.stack_vect = vec![record];
+        self.next_record = None;
+        self.for_loop_stack = ForLoopStack::new();
+        self.timers = Timers::new();
+        self.in_handler = false;
+        self.memo = self.config.memo_limit.map(MemoCache::new);
+        self.livelock = new_livelock_detector(self.config);
+        self.halted = false;
+    }
 
-    let mut reader = LineReader::new();
+    fn execute_next(&mut self, out: &mut dyn Write) -> Result<(), RuntimeError> {
+        let prog = self.prog;
+        let prog_mem = self.prog_mem;
+        let config = self.config;
+        let io = self.io;
+        let Machine {
+            global_memory,
+            string_memory,
+            engine_stack,
+            bool_format,
+            reader,
+            executed,
+            start,
+            cpu_start,
+            collector,
+            profiler,
+            tracer,
+        } = &mut self.machine;
+        let string_memory = &mut **string_memory;
+        let stack_vect = &mut self.stack_vect;
+        let for_loop_stack = &mut self.for_loop_stack;
+        let timers = &mut self.timers;
+        let memo = &mut self.memo;
+        let livelock = &mut self.livelock;
 
-    let mut next_record: Option<Record> = None;
-    let mut for_loop_stack = ForLoopStack::new();
+        let mut curr_block = self.curr_block;
+        let mut index = self.index;
+        let mut next_record = self.next_record.take();
+        let mut in_handler = self.in_handler;
 
-    while index < curr_block.code.len() {
+        // handlers run between two instructions, never inside
+        // another handler or while a call is being set up
+        let check_timers = *executed % TIMER_CHECK_PERIOD == 0 && !timers.is_empty();
+        if check_timers && !in_handler && next_record.is_none() {
+            if let Some(func) = timers.expired(Instant::now()) {
+                let mut record =
+                    Record::new(curr_block, func, &prog_mem.func[func], config.audit_init);
+                record.return_index = index;
+                // the handler may replace the scratch string
+                if let Some(top) = engine_stack.str_stack.last_mut() {
+                    *top = string_memory.keep_scratch(*top);
+                }
+                record.interrupt = Some(engine_stack.interrupt());
+                record.called_at = profiler.as_ref().map(|_| Instant::now());
+                stack_vect.push(record);
+                curr_block = &prog.func[func];
+                index = 0;
+                in_handler = true;
+            }
+        }
         let cmd = &curr_block.code[index];
+        if let Some(tracer) = tracer.as_mut() {
+            let block = block_id(prog, curr_block);
+            if tracer.accepts(*executed, block, index, cmd) {
+                let text = disasm::instruction(cmd, curr_block, string_memory);
+                tracer
+                    .instruction(block, index, stack_vect.len(), cmd, text)
+                    .map_err(RuntimeError::WriteError)?;
+            }
+        }
         index += 1;
-        string_memory.clean();
+        *executed += 1;
+        if collector.is_due(string_memory, &engine_stack.arrays) {
+            let roots = roots(
+                stack_vect,
+                next_record.as_ref(),
+                global_memory,
+                engine_stack,
+                for_loop_stack,
+                memo.as_ref(),
+            );
+            collector.collect(roots, string_memory, &mut engine_stack.arrays);
+        }
+        if let Some(profiler) = profiler.as_mut() {
+            if profiler.is_counting() {
+                profiler.count(block_id(prog, curr_block), index - 1, cmd);
+            }
+            if *executed % profiler.period() == 0 {
+                let callers = stack_vect
+                    .iter()
+                    .map(|rec| block_id(prog, rec.return_block));
+                let chain = callers.chain(Some(block_id(prog, curr_block))).collect();
+                profiler.record(chain);
+            }
+        }
+        if let Some(max) = config.quota.instructions {
+            if *executed > max {
+                return Err(RuntimeError::QuotaExceeded(QuotaKind::Instructions(max)));
+            }
+        }
+        if *executed % QUOTA_CHECK_PERIOD == 0 {
+            // garbage does not count against the memory quota
+            let usage = memory_usage(stack_vect, global_memory, engine_stack, string_memory);
+            if config.quota.memory.is_some_and(|memory| usage > memory) {
+                let roots = roots(
+                    stack_vect,
+                    next_record.as_ref(),
+                    global_memory,
+                    engine_stack,
+                    for_loop_stack,
+                    memo.as_ref(),
+                );
+                collector.collect(roots, string_memory, &mut engine_stack.arrays);
+            }
+            let usage = || memory_usage(stack_vect, global_memory, engine_stack, string_memory);
+            check_quota(&config.quota, *start, *cpu_start, usage)?;
+        }
+        if let Some(period) = config.compact_period {
+            if *executed % period == 0 {
+                engine_stack.compact();
+            }
+        }
+        if let Some(detector) = livelock {
+            detector.observe(cmd);
+        }
+        if let Some(kind) = underflow(cmd, engine_stack, for_loop_stack) {
+            let instruction = index - 1;
+            return Err(RuntimeError::StackUnderflow { kind, instruction });
+        }
         match cmd {
             Command::Integer(cmd) => full_math_operation(
-                &cmd,
+                cmd,
                 &mut engine_stack.int_stack,
                 &mut engine_stack.bool_stack,
-            ),
-            Command::Real(cmd) => full_math_operation(
-                &cmd,
-                &mut engine_stack.real_stack,
-                &mut engine_stack.bool_stack,
-            ),
+                match config.integer_semantics {
+                    IntegerSemantics::Wrapping => arithmetic,
+                    IntegerSemantics::Checked => checked_arithmetic,
+                },
+            )?,
+            Command::Real(cmd) => {
+                full_math_operation(
+                    cmd,
+                    &mut engine_stack.real_stack,
+                    &mut engine_stack.bool_stack,
+                    arithmetic,
+                )?;
+                if let Operator::Math(_) = cmd {
+                    narrow(config.real_precision, &mut engine_stack.real_stack);
+                }
+            }
+            Command::StrCompare(RelationalOperator::Equal) => {
+                let res = string_memory.equal_operation(&mut engine_stack.str_stack);
+                engine_stack.bool_stack.push(res);
+            }
+            Command::StrCompare(RelationalOperator::NotEqual) => {
+                let res = string_memory.equal_operation(&mut engine_stack.str_stack);
+                engine_stack.bool_stack.push(!res);
+            }
             Command::StrCompare(cmd) => {
                 let res = string_memory.binary_operation(
                     |l, r| binary_rel_operation(cmd, l, r),
@@ -60,109 +775,473 @@ pub fn run_program(
             }
             Command::CastInt => {
                 let n = engine_stack.real_stack.pop().unwrap();
-                let i = n as i32;
+                let i = match config.integer_semantics {
+                    IntegerSemantics::Wrapping => n as i32,
+                    IntegerSemantics::Checked => {
+                        checked_cast(n).ok_or(RuntimeError::IntegerOverflow)?
+                    }
+                };
+                engine_stack.int_stack.push(i);
+            }
+            Command::RoundInt(mode) => {
+                let n = engine_stack.real_stack.pop().unwrap();
+                let i = checked_cast(mode.apply(n)).ok_or(RuntimeError::InvalidCast(n))?;
                 engine_stack.int_stack.push(i);
             }
             Command::CastReal => {
                 let i = engine_stack.int_stack.pop().unwrap();
                 let n = i as f64;
                 engine_stack.real_stack.push(n);
+                narrow(config.real_precision, &mut engine_stack.real_stack);
             }
-            Command::MemoryLoad(load, add) => {
-                let local = if let Some(last) = stack_vect.last_mut() {
-                    Some(&last.func_mem)
-                } else {
-                    None
+            // `Engine::new` turned these into slots
+            Command::MemoryLoad(..) | Command::MemoryStore(..) => {
+                unreachable!("unresolved memory operand")
+            }
+            Command::LoadSlot(kind, slot) => {
+                let source = match slot {
+                    Slot::Global(_) => &*global_memory,
+                    Slot::Local(_) => &stack_vect.last().unwrap().func_mem,
                 };
-                memory_load(
-                    load,
-                    *add,
-                    &mut engine_stack,
-                    &global_memory,
-                    local,
-                    &mut string_memory,
-                );
+                source.load(*kind, *slot, engine_stack)?;
             }
-            Command::MemoryStore(store, add) => {
-                let local = if let Some(last) = stack_vect.last_mut() {
-                    Some(&mut last.func_mem)
-                } else {
-                    None
+            Command::StoreSlot(kind, slot) => {
+                let target = match slot {
+                    Slot::Global(_) => &mut *global_memory,
+                    Slot::Local(_) => &mut stack_vect.last_mut().unwrap().func_mem,
                 };
-                memory_store(
-                    store,
-                    *add,
-                    &mut engine_stack,
-                    &mut global_memory,
-                    local,
-                    &mut string_memory,
-                )
+                target.store(*kind, *slot, engine_stack);
             }
             Command::Control(ctrl, addr) => match ctrl {
                 ControlFlow::Call => {
-                    if let Some(block) = next_record {
-                        let mut block = block;
+                    let mut block = match next_record {
+                        Some(block) if block.func == *addr => block,
+                        Some(block) => {
+                            let err = CallError::CallMismatch(block.func, *addr);
+                            return Err(call_error(prog, curr_block, index, err));
+                        }
+                        None => {
+                            let err = CallError::CallWithoutRecord(*addr);
+                            return Err(call_error(prog, curr_block, index, err));
+                        }
+                    };
+                    let cached = match (&memo, prog.pure.contains_key(addr)) {
+                        (Some(memo), true) => {
+                            let key = memo_key(*addr, &block.func_mem, string_memory);
+                            let cached = memo.get(&key).cloned();
+                            block.memo_key = Some(key);
+                            cached
+                        }
+                        _ => None,
+                    };
+                    if let Some(values) = cached {
+                        push_values(values, engine_stack, string_memory);
+                    } else if stack_vect.len() >= config.call_depth_limit() {
+                        let calls = stack_vect.iter().map(|rec| rec.func);
+                        return Err(RuntimeError::StackOverflow {
+                            depth: stack_vect.len(),
+                            trace: calls.chain(Some(*addr)).collect(),
+                        });
+                    } else {
                         block.return_index = index;
+                        block.called_at = profiler.as_ref().map(|_| Instant::now());
                         curr_block = &prog.func[*addr];
                         index = 0;
                         stack_vect.push(block);
-                        next_record = None;
                     }
+                    next_record = None;
                 }
                 ControlFlow::Ret => {
                     if let Some(top) = stack_vect.pop() {
+                        if let (Some(memo), Some(key)) = (memo.as_mut(), top.memo_key) {
+                            let values =
+                                peek_values(&prog.pure[&key.func], engine_stack, string_memory);
+                            memo.insert(key, values);
+                        }
+                        if let (Some(profiler), Some(at)) = (profiler.as_mut(), top.called_at) {
+                            profiler.record_call(block_id(prog, curr_block), at.elapsed());
+                        }
                         index = top.return_index;
                         curr_block = top.return_block;
-
-                        string_memory.remove_strings(&top.func_mem.str_mem);
+                        if let Some(saved) = top.interrupt {
+                            engine_stack.resume(saved);
+                            in_handler = false;
+                        }
                     } else {
-                        panic!("return outside function body");
+                        let err = CallError::ReturnOutsideFunction;
+                        return Err(call_error(prog, curr_block, index, err));
                     }
                 }
                 ControlFlow::Label => {}
                 jump => {
                     let next_addr = curr_block.labels[addr];
+                    let from = index - 1;
                     index = run_jump(jump, index, next_addr, &mut engine_stack.bool_stack);
+                    // an armed timer can still end the loop
+                    let back = index <= from && timers.is_empty();
+                    if let (Some(detector), true) = (livelock.as_mut(), back) {
+                        let location = (curr_block as *const Block as usize, from);
+                        let fingerprint = engine_stack.fingerprint(stack_vect.len());
+                        if detector.back_jump(location, fingerprint) {
+                            let block = block_id(prog, curr_block);
+                            return Err(RuntimeError::Livelock(block, from));
+                        }
+                    }
                 }
             },
-            Command::Input(k) => input(k, &mut engine_stack, &mut reader, &mut string_memory)?,
-            Command::Output(k) => output(k, &mut engine_stack, &mut string_memory),
-            Command::Flush(mode) => handle_flush(mode),
-            Command::Exit => break,
+            Command::Input(k) => {
+                let next = curr_block.code.get(index);
+                let read =
+                    |reader: &mut LineReader| input(k, engine_stack, reader, string_memory, next);
+                if read_input(&io, config.notices.as_ref(), reader, out, read)?.is_none() {
+                    self.halted = true;
+                    return Ok(());
+                }
+                reader.flush_echo(out)?;
+                if let Kind::Real = k {
+                    narrow(config.real_precision, &mut engine_stack.real_stack);
+                }
+            }
+            Command::Output(k) => output(k, engine_stack, string_memory, bool_format, out)?,
+            Command::OutputLine => {
+                let index = engine_stack.str_stack.pop().unwrap();
+                write_line(string_memory.get_string(index), out)?;
+            }
+            Command::SetBoolFormat => {
+                let false_word = engine_stack.str_stack.pop().unwrap();
+                let true_word = engine_stack.str_stack.pop().unwrap();
+                *bool_format = BoolFormat::new(
+                    string_memory.get_string(true_word),
+                    string_memory.get_string(false_word),
+                );
+            }
+            Command::RawInput => raw_input(&mut engine_stack.int_stack, reader)?,
+            Command::OutputMany(kinds) => {
+                output_many(kinds, engine_stack, string_memory, bool_format, out)?
+            }
+            Command::RawOutput => raw_output(&mut engine_stack.int_stack, index - 1, out)?,
+            Command::Flush(mode) => handle_flush(mode, out)?,
+            Command::Exit => self.halted = true,
             Command::ConstantLoad(load) => {
-                load_constant(load, &mut engine_stack, &mut string_memory)
+                load_constant(load, engine_stack);
+                if let Constant::Real(_) = load {
+                    narrow(config.real_precision, &mut engine_stack.real_stack);
+                }
             }
             Command::StoreParam(k, addr) => {
                 if let Some(ref mut record) = next_record {
                     let local_memory = Some(&mut record.func_mem);
-                    memory_store(
-                        k,
-                        *addr,
-                        &mut engine_stack,
-                        &mut global_memory,
-                        local_memory,
-                        &mut string_memory,
-                    );
+                    memory_store(k, *addr, engine_stack, global_memory, local_memory)?;
                 } else {
-                    panic!("cannot store parameter before initializing a new activation record");
+                    let err = CallError::ParamWithoutRecord;
+                    return Err(call_error(prog, curr_block, index, err));
                 }
             }
             Command::NewRecord(f_id) => {
-                if next_record.is_none() {
-                    debug_assert!(*f_id < prog_mem.func.len());
-                    let mem_size = prog_mem.func.get(*f_id).unwrap();
-                    next_record = Some(Record::new(curr_block, mem_size));
-                } else {
-                    panic!("cannot initialize a new activation record")
+                if let Some(pending) = &next_record {
+                    let err = CallError::NestedRecord(pending.func, *f_id);
+                    return Err(call_error(prog, curr_block, index, err));
                 }
+                let mem_size = match prog_mem.func.get(*f_id) {
+                    Some(size) if *f_id < prog.func.len() => size,
+                    _ => {
+                        let err = CallError::UndefinedFunction(*f_id);
+                        return Err(call_error(prog, curr_block, index, err));
+                    }
+                };
+                next_record = Some(Record::new(curr_block, *f_id, mem_size, config.audit_init));
             }
             Command::ForControl(control) => {
                 for_loop_stack.process_command(control, &mut engine_stack.int_stack)
             }
-            Command::Unary(kind) => unary_operator(kind, &mut engine_stack),
+            Command::Unary(Kind::Integer)
+                if config.integer_semantics == IntegerSemantics::Checked =>
+            {
+                let n = engine_stack.int_stack.pop().unwrap();
+                let n = n.checked_neg().ok_or(RuntimeError::IntegerOverflow)?;
+                engine_stack.int_stack.push(n);
+            }
+            Command::Unary(kind) => unary_operator(kind, engine_stack),
+            Command::Absolute(Kind::Integer) => {
+                let n = engine_stack.int_stack.pop().unwrap();
+                let n = match config.integer_semantics {
+                    IntegerSemantics::Wrapping => n.wrapping_abs(),
+                    IntegerSemantics::Checked => {
+                        n.checked_abs().ok_or(RuntimeError::IntegerOverflow)?
+                    }
+                };
+                engine_stack.int_stack.push(n);
+            }
+            Command::Absolute(_) => {
+                let value = engine_stack.real_stack.pop().unwrap();
+                engine_stack.real_stack.push(value.abs());
+            }
+            Command::Bitwise(op) => bitwise(*op, &mut engine_stack.int_stack),
+            Command::MathIntrinsic(func) => {
+                let value = engine_stack.real_stack.pop().unwrap();
+                engine_stack.real_stack.push(func.apply(value));
+                narrow(config.real_precision, &mut engine_stack.real_stack);
+            }
+            Command::Duplicate(kind) => duplicate(kind, engine_stack),
+            Command::SetTimer(func) => {
+                if *func >= prog.func.len() || *func >= prog_mem.func.len() {
+                    let err = CallError::UndefinedFunction(*func);
+                    return Err(call_error(prog, curr_block, index, err));
+                }
+                let millis = engine_stack.int_stack.pop().unwrap();
+                timers.set(*func, millis, Instant::now());
+            }
+            Command::GetDefine => {
+                let name = engine_stack.str_stack.pop().unwrap();
+                let value = config.defines.get(string_memory.get_string(name));
+                let index = string_memory.insert_string(value.cloned().unwrap_or_default());
+                engine_stack.str_stack.push(index);
+            }
+            Command::NewBuilder => engine_stack.builders.push(String::new()),
+            Command::Append(kind) => {
+                let text = pop_text(kind, engine_stack, string_memory, bool_format);
+                let instruction = index - 1;
+                let builder = engine_stack
+                    .builders
+                    .last_mut()
+                    .ok_or(RuntimeError::NoBuilder { instruction })?;
+                builder.push_str(&text);
+            }
+            Command::FinishBuilder => {
+                let instruction = index - 1;
+                let text = engine_stack
+                    .builders
+                    .pop()
+                    .ok_or(RuntimeError::NoBuilder { instruction })?;
+                let index = new_string(text, curr_block.code.get(index), string_memory);
+                engine_stack.str_stack.push(index);
+            }
+            Command::HashString => {
+                let index = engine_stack.str_stack.pop().unwrap();
+                let hash = string_memory.hash(index);
+                engine_stack.int_stack.push((hash ^ (hash >> 32)) as i32);
+            }
+            Command::Substring => {
+                let length = engine_stack.int_stack.pop().unwrap();
+                let start = engine_stack.int_stack.pop().unwrap();
+                let string = engine_stack.str_stack.pop().unwrap();
+                let text = substring(string_memory.get_string(string), start, length)?;
+                let index = new_string(text, curr_block.code.get(index), string_memory);
+                engine_stack.str_stack.push(index);
+            }
+            Command::CharAt => {
+                let position = engine_stack.int_stack.pop().unwrap();
+                let string = engine_stack.str_stack.pop().unwrap();
+                let text = string_memory.get_string(string);
+                let c = usize::try_from(position)
+                    .ok()
+                    .and_then(|position| text.chars().nth(position))
+                    .ok_or_else(|| string_index(text, position as i64))?;
+                engine_stack.int_stack.push(c as i32);
+            }
+            Command::Transform(op) => {
+                let string = engine_stack.str_stack.pop().unwrap();
+                let text = op.apply(string_memory.get_string(string));
+                let index = new_string(text, curr_block.code.get(index), string_memory);
+                engine_stack.str_stack.push(index);
+            }
+            Command::ParseNumber(Kind::Integer) => {
+                let string = engine_stack.str_stack.pop().unwrap();
+                let parsed = string_memory.get_string(string).trim().parse().ok();
+                engine_stack.int_stack.push(parsed.unwrap_or(0));
+                engine_stack.bool_stack.push(parsed.is_some());
+            }
+            Command::ParseNumber(_) => {
+                let string = engine_stack.str_stack.pop().unwrap();
+                let parsed = string_memory.get_string(string).trim().parse().ok();
+                engine_stack.real_stack.push(parsed.unwrap_or(0.0));
+                engine_stack.bool_stack.push(parsed.is_some());
+                narrow(config.real_precision, &mut engine_stack.real_stack);
+            }
+            Command::FormatNumber(kind) => {
+                let text = pop_text(kind, engine_stack, string_memory, bool_format);
+                let index = new_string(text, curr_block.code.get(index), string_memory);
+                engine_stack.str_stack.push(index);
+            }
+            Command::ArrayNew(kind) => {
+                let length = engine_stack.int_stack.pop().unwrap();
+                let handle = engine_stack.arrays.allocate(*kind, length)?;
+                engine_stack.int_stack.push(handle);
+            }
+            Command::ArrayLoad(kind) => array_load(*kind, engine_stack)?,
+            Command::ArrayStore(kind) => array_store(*kind, engine_stack)?,
+            Command::ArrayLength => {
+                let handle = engine_stack.int_stack.pop().unwrap();
+                let length = engine_stack.arrays.get(handle)?.len();
+                engine_stack.int_stack.push(length as i32);
+            }
+            Command::StringStats => {
+                // count only the strings still reachable
+                let roots = roots(
+                    stack_vect,
+                    next_record.as_ref(),
+                    global_memory,
+                    engine_stack,
+                    for_loop_stack,
+                    memo.as_ref(),
+                );
+                collector.collect(roots, string_memory, &mut engine_stack.arrays);
+                let count = string_memory.dynamic_count();
+                let bytes = string_memory.dynamic_bytes();
+                engine_stack
+                    .int_stack
+                    .push(count.min(i32::MAX as usize) as i32);
+                engine_stack
+                    .int_stack
+                    .push(bytes.min(i32::MAX as usize) as i32);
+            }
+        }
+
+        self.curr_block = curr_block;
+        self.index = index;
+        self.next_record = next_record;
+        self.in_handler = in_handler;
+        Ok(())
+    }
+}
+
+fn new_livelock_detector(config: &EngineConfig) -> Option<LivelockDetector> {
+    if config.detect_livelock {
+        Some(LivelockDetector::new(LIVELOCK_THRESHOLD))
+    } else {
+        None
+    }
+}
+
+// `index` already points past the offending instruction
+fn call_error(prog: &Program, block: &Block, index: usize, err: CallError) -> RuntimeError {
+    RuntimeError::CallProtocol(block_id(prog, block), index - 1, err)
+}
+
+// functions live in a single vector: the offset
+// of the block pointer gives the function index
+fn block_id(prog: &Program, block: &Block) -> BlockId {
+    let start = prog.func.as_ptr() as usize;
+    let offset = (block as *const Block as usize).wrapping_sub(start);
+    let id = offset / std::mem::size_of::<Block>();
+    if id < prog.func.len() {
+        BlockId::Function(id)
+    } else {
+        BlockId::Main
+    }
+}
+
+// everything a collection starts marking from: the stacks, the
+// memories of the program and of the records, loop counters and
+// the results of pure functions, which may hold array handles
+fn roots(
+    stack_vect: &[Record],
+    next_record: Option<&Record>,
+    global_memory: &EngineMemory,
+    engine_stack: &EngineStack,
+    for_loop_stack: &ForLoopStack,
+    memo: Option<&MemoCache>,
+) -> Roots {
+    let mut roots = Roots {
+        strings: engine_stack.str_stack.clone(),
+        integers: engine_stack.int_stack.clone(),
+    };
+    let records = stack_vect.iter().chain(next_record);
+    let memories = records.map(|rec| &rec.func_mem).chain(Some(global_memory));
+    for mem in memories {
+        roots.strings.extend(&mem.str_mem);
+        roots.integers.extend(&mem.int_mem);
+    }
+    for saved in stack_vect.iter().filter_map(|rec| rec.interrupt.as_ref()) {
+        roots.strings.extend(&saved.str_stack);
+        roots.integers.extend(&saved.int_stack);
+    }
+    roots.integers.extend(for_loop_stack.counters());
+    if let Some(memo) = memo {
+        roots.integers.extend(memo.integers());
+    }
+    roots
+}
+
+// estimated bytes of memories, stacks, run time strings and arrays
+fn memory_usage(
+    stack_vect: &[Record],
+    global_memory: &EngineMemory,
+    engine_stack: &EngineStack,
+    string_memory: &StringMemory,
+) -> usize {
+    let records: usize = stack_vect
+        .iter()
+        .map(|r| r.func_mem.bytes() + r.interrupt.as_ref().map_or(0, Operands::bytes))
+        .sum();
+    records + global_memory.bytes() + engine_stack.bytes() + string_memory.dynamic_footprint()
+}
+
+// expired timers are looked for once every this many instructions
+const TIMER_CHECK_PERIOD: u64 = 256;
+
+// time and memory are checked once every this many instructions
+const QUOTA_CHECK_PERIOD: u64 = 1024;
+
+// function calls in progress at the same time, unless configured
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+fn check_quota<F>(
+    quota: &Quota,
+    start: Instant,
+    cpu_start: Option<Duration>,
+    usage: F,
+) -> Result<(), RuntimeError>
+where
+    F: Fn() -> usize,
+{
+    if let Some(time) = quota.time {
+        if start.elapsed() > time {
+            return Err(RuntimeError::QuotaExceeded(QuotaKind::Time(time)));
+        }
+    }
+    if let Some(cpu_time) = quota.cpu_time {
+        // wall clock time where the CPU time is unknown
+        let spent = match (cpu_start, cpu_clock::thread_time()) {
+            (Some(begin), Some(now)) => now.saturating_sub(begin),
+            _ => start.elapsed(),
+        };
+        if spent > cpu_time {
+            return Err(RuntimeError::QuotaExceeded(QuotaKind::CpuTime(cpu_time)));
         }
     }
+    if let Some(memory) = quota.memory {
+        if usage() > memory {
+            return Err(RuntimeError::QuotaExceeded(QuotaKind::Memory(memory)));
+        }
+    }
+    Ok(())
+}
 
+// convert every argument before the first instruction runs
+fn bind_arguments(
+    bindings: &[(Kind, AddrSize)],
+    args: &[String],
+    global: &mut EngineMemory,
+    str_mem: &mut StringMemory,
+) -> Result<(), RuntimeError> {
+    if bindings.len() != args.len() {
+        return Err(RuntimeError::ArgumentCount(bindings.len(), args.len()));
+    }
+    for (i, ((kind, addr), arg)) in bindings.iter().zip(args).enumerate() {
+        let slot = *addr as usize;
+        let bad_arg = || RuntimeError::BadArgument(i, *kind, arg.clone());
+        match kind {
+            Kind::Integer => global.int_mem[slot] = arg.parse().map_err(|_| bad_arg())?,
+            Kind::Real => global.real_mem[slot] = arg.parse().map_err(|_| bad_arg())?,
+            Kind::Bool => global.bool_mem[slot] = arg.parse().map_err(|_| bad_arg())?,
+            Kind::Str => {
+                let index = str_mem.insert_string(arg.clone());
+                global.str_mem[slot] = index;
+            }
+        }
+        global.mark_written(*kind, *addr);
+    }
     Ok(())
 }
 
@@ -174,7 +1253,7 @@ fn unary_operator(kind: &Kind, stack: &mut EngineStack) {
         }
         Kind::Integer => {
             let tmp = stack.int_stack.pop().unwrap();
-            stack.int_stack.push(-tmp);
+            stack.int_stack.push(tmp.wrapping_neg());
         }
         Kind::Real => {
             let tmp = stack.real_stack.pop().unwrap();
@@ -184,28 +1263,311 @@ fn unary_operator(kind: &Kind, stack: &mut EngineStack) {
     }
 }
 
-struct EngineStack {
-    int_stack: Vec<i32>,
-    real_stack: Vec<f64>,
-    bool_stack: Vec<bool>,
-    str_stack: ReferenceStack,
+fn bitwise(op: BitOp, stack: &mut Vec<i32>) {
+    let rhs = stack.pop().unwrap();
+    if op == BitOp::Not {
+        stack.push(!rhs);
+        return;
+    }
+    let lhs = stack.pop().unwrap();
+    let value = match op {
+        BitOp::And => lhs & rhs,
+        BitOp::Or => lhs | rhs,
+        BitOp::Xor => lhs ^ rhs,
+        BitOp::Shl => lhs.wrapping_shl(rhs as u32),
+        BitOp::Shr => lhs.wrapping_shr(rhs as u32),
+        BitOp::Not => unreachable!(),
+    };
+    stack.push(value);
 }
 
-impl EngineStack {
-    fn new() -> Self {
-        Self {
-            int_stack: vec![],
-            real_stack: vec![],
-            bool_stack: vec![],
-            str_stack: ReferenceStack::new(),
+// characters from `start` to `start + length`
+fn substring(text: &str, start: i32, length: i32) -> Result<String, RuntimeError> {
+    let end = start as i64 + length as i64;
+    let count = text.chars().count() as i64;
+    if start < 0 || start as i64 > count {
+        return Err(string_index(text, start as i64));
+    }
+    if end < start as i64 || end > count {
+        return Err(string_index(text, end));
+    }
+    Ok(text
+        .chars()
+        .skip(start as usize)
+        .take(length as usize)
+        .collect())
+}
+
+fn string_index(text: &str, position: i64) -> RuntimeError {
+    RuntimeError::StringIndex {
+        position,
+        length: text.chars().count(),
+    }
+}
+
+// pop the index and the handle, push the element
+fn array_load(kind: Kind, stack: &mut EngineStack) -> Result<(), ArrayError> {
+    let index = stack.int_stack.pop().unwrap();
+    let handle = stack.int_stack.pop().unwrap();
+    let array = stack.arrays.typed(handle, kind)?;
+    let position = array.position(index)?;
+    match array {
+        Array::Integer(values) => stack.int_stack.push(values[position]),
+        Array::Real(values) => stack.real_stack.push(values[position]),
+        Array::Bool(values) => stack.bool_stack.push(values[position]),
+        Array::Str(values) => stack.str_stack.push(values[position]),
+    }
+    Ok(())
+}
+
+// pop the value, the index and the handle, the value is
+// on top of the other two when they share the stack
+fn array_store(kind: Kind, stack: &mut EngineStack) -> Result<(), ArrayError> {
+    let integer = if kind == Kind::Integer {
+        stack.int_stack.pop()
+    } else {
+        None
+    };
+    let index = stack.int_stack.pop().unwrap();
+    let handle = stack.int_stack.pop().unwrap();
+    let array = stack.arrays.typed_mut(handle, kind)?;
+    let position = array.position(index)?;
+    match array {
+        Array::Integer(values) => values[position] = integer.unwrap(),
+        Array::Real(values) => values[position] = stack.real_stack.pop().unwrap(),
+        Array::Bool(values) => values[position] = stack.bool_stack.pop().unwrap(),
+        Array::Str(values) => {
+            values[position] = stack.str_stack.pop().unwrap();
         }
     }
+    Ok(())
 }
 
-fn run_jump(j: &ControlFlow, curr: usize, next: usize, stack: &mut Vec<bool>) -> usize {
-    match j {
-        ControlFlow::Jump => next,
-        ControlFlow::JumpTrue => {
+fn duplicate(kind: &Kind, stack: &mut EngineStack) {
+    match kind {
+        Kind::Bool => {
+            let tmp = *stack.bool_stack.last().unwrap();
+            stack.bool_stack.push(tmp);
+        }
+        Kind::Integer => {
+            let tmp = *stack.int_stack.last().unwrap();
+            stack.int_stack.push(tmp);
+        }
+        Kind::Real => {
+            let tmp = *stack.real_stack.last().unwrap();
+            stack.real_stack.push(tmp);
+        }
+        Kind::Str => {
+            let tmp = *stack.str_stack.last().unwrap();
+            stack.str_stack.push(tmp);
+        }
+    }
+}
+
+struct EngineStack {
+    int_stack: Vec<i32>,
+    real_stack: Vec<f64>,
+    bool_stack: Vec<bool>,
+    // indexes in the string memory
+    str_stack: Vec<usize>,
+    // texts of the open string builders, latest last
+    builders: Vec<String>,
+    arrays: ArrayHeap,
+    // capacity before the latest compaction, if the largest so far
+    peak_capacity: usize,
+    compactions: u64,
+}
+
+impl EngineStack {
+    fn new() -> Self {
+        Self {
+            int_stack: vec![],
+            real_stack: vec![],
+            bool_stack: vec![],
+            str_stack: vec![],
+            builders: vec![],
+            arrays: ArrayHeap::new(),
+            peak_capacity: 0,
+            compactions: 0,
+        }
+    }
+
+    // sizes and tops of the stacks, enough to tell
+    // apart most loop iterations
+    fn fingerprint(&self, depth: usize) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        depth.hash(&mut hasher);
+        self.int_stack.len().hash(&mut hasher);
+        self.int_stack.last().hash(&mut hasher);
+        self.real_stack.len().hash(&mut hasher);
+        self.real_stack
+            .last()
+            .map(|r| r.to_bits())
+            .hash(&mut hasher);
+        self.bool_stack.len().hash(&mut hasher);
+        self.bool_stack.last().hash(&mut hasher);
+        self.str_stack.len().hash(&mut hasher);
+        self.builders.len().hash(&mut hasher);
+        self.builders.last().map(String::len).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn depth(&self, kind: Kind) -> usize {
+        match kind {
+            Kind::Integer => self.int_stack.len(),
+            Kind::Real => self.real_stack.len(),
+            Kind::Bool => self.bool_stack.len(),
+            Kind::Str => self.str_stack.len(),
+        }
+    }
+
+    fn bytes(&self) -> usize {
+        self.int_stack.len() * size_of::<i32>()
+            + self.real_stack.len() * size_of::<f64>()
+            + self.bool_stack.len() * size_of::<bool>()
+            + self.str_stack.len() * size_of::<usize>()
+            + self.builders.iter().map(String::capacity).sum::<usize>()
+            + self.arrays.bytes()
+    }
+
+    // bytes reserved by the value stacks
+    fn capacity(&self) -> usize {
+        self.int_stack.capacity() * size_of::<i32>()
+            + self.real_stack.capacity() * size_of::<f64>()
+            + self.bool_stack.capacity() * size_of::<bool>()
+            + self.str_stack.capacity() * size_of::<usize>()
+    }
+
+    // stacks grow back by doubling, so only the ones
+    // using less than half of their capacity shrink
+    fn compact(&mut self) {
+        self.peak_capacity = self.peak_capacity.max(self.capacity());
+        shrink(&mut self.int_stack);
+        shrink(&mut self.real_stack);
+        shrink(&mut self.bool_stack);
+        shrink(&mut self.str_stack);
+        self.compactions += 1;
+    }
+
+    // a timer handler starts on empty stacks, so it can neither
+    // see nor clobber the operands of the code it interrupted
+    fn interrupt(&mut self) -> Operands {
+        Operands {
+            int_stack: std::mem::take(&mut self.int_stack),
+            real_stack: std::mem::take(&mut self.real_stack),
+            bool_stack: std::mem::take(&mut self.bool_stack),
+            str_stack: std::mem::take(&mut self.str_stack),
+            builders: std::mem::take(&mut self.builders),
+        }
+    }
+
+    // whatever the handler left on the stacks is dropped
+    fn resume(&mut self, saved: Operands) {
+        self.int_stack = saved.int_stack;
+        self.real_stack = saved.real_stack;
+        self.bool_stack = saved.bool_stack;
+        self.str_stack = saved.str_stack;
+        self.builders = saved.builders;
+    }
+
+    // stacks only shrink in `compact`, so the peak is either
+    // the current capacity or one seen there
+    fn stats(&self) -> StackStats {
+        let capacity = self.capacity();
+        StackStats {
+            capacity,
+            peak_capacity: self.peak_capacity.max(capacity),
+            compactions: self.compactions,
+        }
+    }
+}
+
+// value stacks put aside while a timer handler runs
+struct Operands {
+    int_stack: Vec<i32>,
+    real_stack: Vec<f64>,
+    bool_stack: Vec<bool>,
+    str_stack: Vec<usize>,
+    builders: Vec<String>,
+}
+
+impl Operands {
+    fn bytes(&self) -> usize {
+        self.int_stack.len() * size_of::<i32>()
+            + self.real_stack.len() * size_of::<f64>()
+            + self.bool_stack.len() * size_of::<bool>()
+            + self.str_stack.len() * size_of::<usize>()
+            + self.builders.iter().map(String::capacity).sum::<usize>()
+    }
+}
+
+fn shrink<T>(stack: &mut Vec<T>) {
+    if stack.capacity() > 2 * stack.len() {
+        stack.shrink_to_fit();
+    }
+}
+
+// bytes reserved by the value stacks, see `EngineConfig::compact_period`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct StackStats {
+    pub capacity: usize,
+    pub peak_capacity: usize,
+    pub compactions: u64,
+}
+
+// the kind of the first stack a command would pop from while
+// empty, checked before running it so malformed bytecode fails
+// with an error instead of a panic. WRRAW checks its bytes itself
+fn underflow(cmd: &Command, stack: &EngineStack, for_loops: &ForLoopStack) -> Option<Kind> {
+    let short = |kind: Kind, count: usize| Some(kind).filter(|_| stack.depth(kind) < count);
+    match cmd {
+        Command::Integer(_) => short(Kind::Integer, 2),
+        Command::Real(_) => short(Kind::Real, 2),
+        Command::StrCompare(_) => short(Kind::Str, 2),
+        Command::BoolCompare(_) => short(Kind::Bool, 2),
+        Command::CastInt | Command::RoundInt(_) | Command::MathIntrinsic(_) => short(Kind::Real, 1),
+        Command::CastReal
+        | Command::RawInput
+        | Command::RawOutput
+        | Command::SetTimer(_)
+        | Command::ForControl(ForControl::New) => short(Kind::Integer, 1),
+        // CFOR pushes the counter of the innermost loop
+        Command::ForControl(ForControl::Check) if for_loops.depth() == 0 => Some(Kind::Integer),
+        Command::MemoryStore(kind, _)
+        | Command::StoreSlot(kind, _)
+        | Command::StoreParam(kind, _)
+        | Command::Output(kind)
+        | Command::Unary(kind)
+        | Command::Absolute(kind)
+        | Command::FormatNumber(kind)
+        | Command::Duplicate(kind)
+        | Command::Append(kind) => short(*kind, 1),
+        Command::Control(ControlFlow::JumpTrue, _)
+        | Command::Control(ControlFlow::JumpFalse, _) => short(Kind::Bool, 1),
+        Command::OutputLine
+        | Command::GetDefine
+        | Command::HashString
+        | Command::Transform(_)
+        | Command::ParseNumber(_) => short(Kind::Str, 1),
+        Command::Bitwise(op) => short(Kind::Integer, op.arity()),
+        Command::Substring => short(Kind::Str, 1).or_else(|| short(Kind::Integer, 2)),
+        Command::ArrayNew(_) | Command::ArrayLength => short(Kind::Integer, 1),
+        Command::ArrayLoad(_) => short(Kind::Integer, 2),
+        Command::ArrayStore(Kind::Integer) => short(Kind::Integer, 3),
+        Command::ArrayStore(kind) => short(*kind, 1).or_else(|| short(Kind::Integer, 2)),
+        Command::CharAt => short(Kind::Str, 1).or_else(|| short(Kind::Integer, 1)),
+        Command::SetBoolFormat => short(Kind::Str, 2),
+        Command::OutputMany(kinds) => kinds
+            .iter()
+            .find_map(|kind| short(*kind, kinds.iter().filter(|k| *k == kind).count())),
+        _ => None,
+    }
+}
+
+fn run_jump(j: &ControlFlow, curr: usize, next: usize, stack: &mut Vec<bool>) -> usize {
+    match j {
+        ControlFlow::Jump => next,
+        ControlFlow::JumpTrue => {
             let b = stack.pop().unwrap();
             if b {
                 next
@@ -225,157 +1587,70 @@ fn run_jump(j: &ControlFlow, curr: usize, next: usize, stack: &mut Vec<bool>) ->
     }
 }
 
-fn memory_load(
-    k: &Kind,
-    addr: AddrSize,
-    stack: &mut EngineStack,
-    global: &EngineMemory,
-    local: Option<&EngineMemory>,
-    str_mem: &mut StringMemory,
-) {
-    match k {
-        Kind::Bool => {
-            let loc = if let Some(mem) = local {
-                Some(&mem.bool_mem)
-            } else {
-                None
-            };
-            let b = get_value(&global.bool_mem, loc, addr);
-            stack.bool_stack.push(*b);
-        }
-        Kind::Integer => {
-            let loc = if let Some(mem) = local {
-                Some(&mem.int_mem)
-            } else {
-                None
-            };
-            let i = get_value(&global.int_mem, loc, addr);
-            stack.int_stack.push(*i);
-        }
-        Kind::Real => {
-            let loc = if let Some(mem) = local {
-                Some(&mem.real_mem)
-            } else {
-                None
-            };
-            let r = get_value(&global.real_mem, loc, addr);
-            stack.real_stack.push(*r);
-        }
-        Kind::Str => {
-            let loc = if let Some(mem) = local {
-                Some(&mem.str_mem)
-            } else {
-                None
-            };
-            let s = get_value(&global.str_mem, loc, addr);
-            stack.str_stack.push(str_mem, *s)
-        }
-    }
-}
-
 fn memory_store(
     k: &Kind,
     addr: AddrSize,
     stack: &mut EngineStack,
     global: &mut EngineMemory,
     local: Option<&mut EngineMemory>,
-    str_mem: &mut StringMemory,
-) {
-    match k {
-        Kind::Bool => {
-            let loc = if let Some(mem) = local {
-                Some(&mut mem.bool_mem)
-            } else {
-                None
-            };
-            let b = stack.bool_stack.pop().unwrap();
-            set_value(&mut global.bool_mem, loc, addr, b);
-        }
-        Kind::Integer => {
-            let loc = if let Some(mem) = local {
-                Some(&mut mem.int_mem)
-            } else {
-                None
-            };
-            let b = stack.int_stack.pop().unwrap();
-            set_value(&mut global.int_mem, loc, addr, b);
-        }
-        Kind::Real => {
-            let loc = if let Some(mem) = local {
-                Some(&mut mem.real_mem)
-            } else {
-                None
-            };
-            let b = stack.real_stack.pop().unwrap();
-            set_value(&mut global.real_mem, loc, addr, b);
-        }
-        Kind::Str => {
-            let loc = if let Some(mem) = local {
-                Some(&mut mem.str_mem)
-            } else {
-                None
-            };
-            let b = stack.str_stack.pop(str_mem);
-            str_mem.increment(&b);
-            let prev = set_value(&mut global.str_mem, loc, addr, b);
-            clean_prev(prev, str_mem);
-        }
-    }
-}
-
-fn clean_prev(prev: Option<usize>, str_mem: &mut StringMemory) {
-    if let Some(prev) = prev {
-        str_mem.decrement(&prev);
-    }
-}
-
-fn get_value<'a, T>(glob: &'a Vec<T>, loc: Option<&'a Vec<T>>, addr: AddrSize) -> &'a T {
-    if addr & LOCAL_MASK == 0 {
-        glob.get(addr as usize).unwrap()
-    } else {
-        let loc = loc.unwrap();
-        let addr = addr - LOCAL_MASK;
-        loc.get(addr as usize).unwrap()
+) -> Result<(), RuntimeError> {
+    let target = match local {
+        Some(mem) if addr & LOCAL_MASK != 0 => mem,
+        None if addr & LOCAL_MASK != 0 => return Err(invalid_address(*k, addr)),
+        _ => global,
+    };
+    if !target.contains(*k, addr) {
+        return Err(invalid_address(*k, addr));
     }
+    target.store(*k, Slot::new(addr), stack);
+    Ok(())
 }
 
-fn set_value<'a, T>(
-    glob: &'a mut Vec<T>,
-    loc: Option<&'a mut Vec<T>>,
-    addr: AddrSize,
-    value: T,
-) -> Option<T>
-where
-    T: Copy,
-{
-    if addr & LOCAL_MASK == 0 {
-        insert_and_get_prev(glob, addr, value)
-    } else {
-        let loc = loc.unwrap();
-        let addr = addr - LOCAL_MASK;
-        insert_and_get_prev(loc, addr, value)
+fn invalid_address(kind: Kind, addr: AddrSize) -> RuntimeError {
+    RuntimeError::InvalidAddress {
+        kind,
+        addr: addr & !LOCAL_MASK,
+        local: addr & LOCAL_MASK != 0,
     }
 }
 
-fn insert_and_get_prev<T>(map: &mut Vec<T>, addr: AddrSize, value: T) -> Option<T>
-where
-    T: Copy,
-{
-    let output = if let Some(prev) = map.get(addr as usize) {
-        Some(*prev)
-    } else {
-        None
-    };
-    map[addr as usize] = value;
-    output
-}
-
-fn load_constant(load: &Constant, stack: &mut EngineStack, str_mem: &mut StringMemory) {
+fn load_constant(load: &Constant, stack: &mut EngineStack) {
     match load {
         Constant::Bool(b) => stack.bool_stack.push(*b),
         Constant::Integer(i) => stack.int_stack.push(*i),
         Constant::Real(r) => stack.real_stack.push(*r),
-        Constant::Str(s) => stack.str_stack.push(str_mem, *s),
+        Constant::Str(s) => stack.str_stack.push(*s),
+    }
+}
+
+// run `read` the way the IO policy says, None when the input
+// is over and the program has to stop as if it ran EXT
+fn read_input<'r, T>(
+    io: &IoPolicy,
+    notices: Option<&Notices>,
+    reader: &mut LineReader<'r>,
+    out: &mut dyn Write,
+    mut read: impl FnMut(&mut LineReader<'r>) -> Result<T, ReadError>,
+) -> Result<Option<T>, RuntimeError> {
+    loop {
+        // the prompt written so far has to be visible while
+        // the engine waits for the answer
+        if io.flush_before_read {
+            out.flush()?;
+        }
+        match read(reader) {
+            Ok(value) => return Ok(Some(value)),
+            Err(ReadError::Eof) if io.eof == EofPolicy::Exit => return Ok(None),
+            // the program output stays what the program wrote
+            Err(err) if io.retry_on_parse_error && err.is_parse_error() => {
+                reader.discard_line();
+                if let Some(notices) = notices {
+                    let msg = format!("{}, try again\n", err);
+                    notices.0.borrow_mut().write_all(msg.as_bytes())?;
+                }
+            }
+            Err(err) => return Err(err.into()),
+        }
     }
 }
 
@@ -384,6 +1659,7 @@ fn input(
     stack: &mut EngineStack,
     reader: &mut LineReader,
     str_mem: &mut StringMemory,
+    next: Option<&Command>,
 ) -> Result<(), ReadError> {
     match k {
         Kind::Bool => {
@@ -399,56 +1675,187 @@ fn input(
             stack.real_stack.push(tmp);
         }
         Kind::Str => {
+            #[cfg(feature = "alloc-stats")]
+            let _scope = crate::alloc_stats::scope(crate::alloc_stats::Category::Strings);
             let tmp = reader.next_string()?;
-            let index = str_mem.insert_string(tmp);
-            stack.str_stack.push(str_mem, index);
-            str_mem.decrement(&index);
+            let index = new_string(tmp, next, str_mem);
+            stack.str_stack.push(index);
         }
     }
     Ok(())
 }
 
-fn output(k: &Kind, stack: &mut EngineStack, str_mem: &mut StringMemory) {
+// a string the next instruction consumes right away never escapes:
+// it goes to the scratch string instead of the string memory
+fn new_string(text: String, next: Option<&Command>, str_mem: &mut StringMemory) -> usize {
+    match next {
+        Some(Command::Output(Kind::Str))
+        | Some(Command::OutputLine)
+        | Some(Command::StrCompare(_))
+        | Some(Command::HashString)
+        | Some(Command::ParseNumber(_)) => str_mem.set_scratch(text),
+        _ => str_mem.insert_string(text),
+    }
+}
+
+fn memo_key(func: usize, mem: &EngineMemory, str_mem: &StringMemory) -> MemoKey {
+    let strings = mem
+        .str_mem
+        .iter()
+        .map(|index| str_mem.get_string(*index).to_owned())
+        .collect();
+    MemoKey::new(func, &mem.int_mem, &mem.real_mem, &mem.bool_mem, strings)
+}
+
+// copy the values returned by a pure function, the last
+// kind of the descriptor is the last one pushed
+fn peek_values(kinds: &[Kind], stack: &EngineStack, str_mem: &StringMemory) -> MemoValues {
+    let count = |kind| kinds.iter().filter(|k| **k == kind).count();
+    let top = |len: usize, kind| len.saturating_sub(count(kind))..len;
+    let strings = &stack.str_stack[top(stack.str_stack.len(), Kind::Str)];
+    MemoValues {
+        ints: stack.int_stack[top(stack.int_stack.len(), Kind::Integer)].to_vec(),
+        reals: stack.real_stack[top(stack.real_stack.len(), Kind::Real)].to_vec(),
+        bools: stack.bool_stack[top(stack.bool_stack.len(), Kind::Bool)].to_vec(),
+        strings: strings
+            .iter()
+            .map(|index| str_mem.get_string(*index).to_owned())
+            .collect(),
+    }
+}
+
+fn push_values(values: MemoValues, stack: &mut EngineStack, str_mem: &mut StringMemory) {
+    stack.int_stack.extend(values.ints);
+    stack.real_stack.extend(values.reals);
+    stack.bool_stack.extend(values.bools);
+    for s in values.strings {
+        let index = str_mem.insert_string(s);
+        stack.str_stack.push(index);
+    }
+}
+
+fn output(
+    k: &Kind,
+    stack: &mut EngineStack,
+    str_mem: &mut StringMemory,
+    bool_format: &BoolFormat,
+    out: &mut dyn Write,
+) -> io::Result<()> {
     match k {
         Kind::Bool => {
             let b = stack.bool_stack.pop().unwrap();
-            print!("{}", b);
+            write!(out, "{}", bool_format.format(b))
         }
         Kind::Integer => {
             let i = stack.int_stack.pop().unwrap();
-            print!("{}", i);
+            write!(out, "{}", i)
         }
         Kind::Real => {
             let r = stack.real_stack.pop().unwrap();
-            print!("{}", r);
+            write!(out, "{}", r)
         }
         Kind::Str => {
-            let index = stack.str_stack.pop(str_mem);
-            let s = str_mem.get_string(index);
-            print!("{}", s);
+            // stored bytes go straight to the writer
+            let index = stack.str_stack.pop().unwrap();
+            out.write_all(str_mem.get_string(index).as_bytes())
         }
-    };
+    }
+}
+
+fn write_line(s: &str, out: &mut dyn Write) -> io::Result<()> {
+    out.write_all(s.as_bytes())?;
+    out.write_all(b"\n")
+}
+
+// values are popped in reverse order and
+// sent to the output with a single write
+fn output_many(
+    kinds: &[Kind],
+    stack: &mut EngineStack,
+    str_mem: &mut StringMemory,
+    bool_format: &BoolFormat,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let mut values: Vec<String> = kinds
+        .iter()
+        .rev()
+        .map(|k| pop_text(k, stack, str_mem, bool_format))
+        .collect();
+    values.reverse();
+    out.write_all(values.join(" ").as_bytes())
+}
+
+// pop a value as the matching WRx would write it
+fn pop_text(
+    k: &Kind,
+    stack: &mut EngineStack,
+    str_mem: &mut StringMemory,
+    bool_format: &BoolFormat,
+) -> String {
+    match k {
+        Kind::Bool => bool_format
+            .format(stack.bool_stack.pop().unwrap())
+            .to_owned(),
+        Kind::Integer => stack.int_stack.pop().unwrap().to_string(),
+        Kind::Real => stack.real_stack.pop().unwrap().to_string(),
+        Kind::Str => {
+            let index = stack.str_stack.pop().unwrap();
+            str_mem.get_string(index).to_owned()
+        }
+    }
+}
+
+// pop the maximum byte count, push every byte read
+// followed by the actual count
+fn raw_input(stack: &mut Vec<i32>, reader: &mut LineReader) -> Result<(), ReadError> {
+    let count = stack.pop().unwrap();
+    let bytes = reader.next_bytes(count.max(0) as usize)?;
+    stack.extend(bytes.iter().map(|b| *b as i32));
+    stack.push(bytes.len() as i32);
+    Ok(())
+}
+
+// pop the byte count, then the bytes, written in push order.
+// Values outside 0..=255 are truncated to their lowest byte
+fn raw_output(
+    stack: &mut Vec<i32>,
+    instruction: usize,
+    out: &mut dyn Write,
+) -> Result<(), RuntimeError> {
+    let count = stack.pop().unwrap().max(0) as usize;
+    let begin = stack
+        .len()
+        .checked_sub(count)
+        .ok_or(RuntimeError::StackUnderflow {
+            kind: Kind::Integer,
+            instruction,
+        })?;
+    let bytes: Vec<u8> = stack.drain(begin..).map(|b| b as u8).collect();
+    out.write_all(&bytes)?;
+    Ok(())
 }
 
-fn handle_flush(mode: &FlushMode) {
+pub fn handle_flush(mode: &FlushMode, out: &mut dyn Write) -> io::Result<()> {
     match mode {
-        FlushMode::Flush => stdout().flush().unwrap(),
-        FlushMode::NewLine => println!(),
+        FlushMode::Flush => out.flush(),
+        FlushMode::NewLine => writeln!(out),
     }
 }
 
-fn full_math_operation<T>(op: &Operator, numbers: &mut Vec<T>, booleans: &mut Vec<bool>)
+fn full_math_operation<T>(
+    op: &Operator,
+    numbers: &mut Vec<T>,
+    booleans: &mut Vec<bool>,
+    apply: fn(&MathOperator, T, T) -> Result<T, RuntimeError>,
+) -> Result<(), RuntimeError>
 where
-    T: Add<Output = T>
-        + Sub<Output = T>
-        + Mul<Output = T>
-        + Div<Output = T>
-        + PartialOrd
-        + PartialEq,
+    T: PartialOrd,
 {
     match op {
         Operator::Math(m) => {
-            let res = math_operation(m, numbers);
+            let rhs = numbers.pop().unwrap();
+            let lhs = numbers.pop().unwrap();
+            let res = apply(m, lhs, rhs)?;
             numbers.push(res);
         }
         Operator::Rel(r) => {
@@ -456,19 +1863,99 @@ where
             booleans.push(res);
         }
     };
+    Ok(())
 }
 
-fn math_operation<T>(op: &MathOperator, stack: &mut Vec<T>) -> T
-where
-    T: Add<Output = T> + Sub<Output = T> + Mul<Output = T> + Div<Output = T>,
-{
-    let rhs = stack.pop().unwrap();
-    let lhs = stack.pop().unwrap();
-    match op {
-        MathOperator::Add => lhs + rhs,
-        MathOperator::Sub => lhs - rhs,
-        MathOperator::Mul => lhs * rhs,
-        MathOperator::Div => lhs / rhs,
+// integers wrap around on overflow, None on a division by zero
+pub trait Arithmetic: Sized {
+    fn apply(op: &MathOperator, lhs: Self, rhs: Self) -> Option<Self>;
+}
+
+impl Arithmetic for i32 {
+    fn apply(op: &MathOperator, lhs: i32, rhs: i32) -> Option<i32> {
+        match op {
+            MathOperator::Add => Some(lhs.wrapping_add(rhs)),
+            MathOperator::Sub => Some(lhs.wrapping_sub(rhs)),
+            MathOperator::Mul => Some(lhs.wrapping_mul(rhs)),
+            MathOperator::Div | MathOperator::Mod if rhs == 0 => None,
+            MathOperator::Div => Some(lhs.wrapping_div(rhs)),
+            MathOperator::Mod => Some(lhs.wrapping_rem(rhs)),
+            MathOperator::Pow if rhs < 0 => negative_power(lhs, rhs),
+            MathOperator::Pow => Some(lhs.wrapping_pow(rhs as u32)),
+            MathOperator::Min => Some(lhs.min(rhs)),
+            MathOperator::Max => Some(lhs.max(rhs)),
+        }
+    }
+}
+
+// lhs^rhs for a negative rhs is 1 / lhs^-rhs, truncated like
+// DIVI: None when lhs is 0
+fn negative_power(lhs: i32, rhs: i32) -> Option<i32> {
+    match lhs {
+        0 => None,
+        1 => Some(1),
+        -1 if rhs % 2 == 0 => Some(1),
+        -1 => Some(-1),
+        _ => Some(0),
+    }
+}
+
+impl Arithmetic for f64 {
+    fn apply(op: &MathOperator, lhs: f64, rhs: f64) -> Option<f64> {
+        match op {
+            MathOperator::Add => Some(lhs + rhs),
+            MathOperator::Sub => Some(lhs - rhs),
+            MathOperator::Mul => Some(lhs * rhs),
+            MathOperator::Div => Some(lhs / rhs),
+            MathOperator::Mod => Some(lhs % rhs),
+            MathOperator::Pow => Some(lhs.powf(rhs)),
+            MathOperator::Min => Some(lhs.min(rhs)),
+            MathOperator::Max => Some(lhs.max(rhs)),
+        }
+    }
+}
+
+fn arithmetic<T: Arithmetic>(op: &MathOperator, lhs: T, rhs: T) -> Result<T, RuntimeError> {
+    T::apply(op, lhs, rhs).ok_or(RuntimeError::DivisionByZero)
+}
+
+// integer operations under IntegerSemantics::Checked
+fn checked_arithmetic(op: &MathOperator, lhs: i32, rhs: i32) -> Result<i32, RuntimeError> {
+    let res = match op {
+        MathOperator::Add => lhs.checked_add(rhs),
+        MathOperator::Sub => lhs.checked_sub(rhs),
+        MathOperator::Mul => lhs.checked_mul(rhs),
+        MathOperator::Div | MathOperator::Mod if rhs == 0 => {
+            return Err(RuntimeError::DivisionByZero)
+        }
+        MathOperator::Div => lhs.checked_div(rhs),
+        // the remainder always fits, even for i32::MIN % -1
+        MathOperator::Mod => Some(lhs.wrapping_rem(rhs)),
+        MathOperator::Pow if rhs < 0 => {
+            return negative_power(lhs, rhs).ok_or(RuntimeError::DivisionByZero)
+        }
+        MathOperator::Pow => lhs.checked_pow(rhs as u32),
+        MathOperator::Min => Some(lhs.min(rhs)),
+        MathOperator::Max => Some(lhs.max(rhs)),
+    };
+    res.ok_or(RuntimeError::IntegerOverflow)
+}
+
+// None when the truncated real is not an integer, `as` would
+// saturate it instead
+fn checked_cast(n: f64) -> Option<i32> {
+    let n = n.trunc();
+    if n >= i32::MIN as f64 && n <= i32::MAX as f64 {
+        Some(n as i32)
+    } else {
+        None
+    }
+}
+
+// round the real on top of the stack to the configured precision
+fn narrow(precision: RealPrecision, reals: &mut [f64]) {
+    if let (RealPrecision::Single, Some(top)) = (precision, reals.last_mut()) {
+        *top = *top as f32 as f64;
     }
 }
 
@@ -481,7 +1968,7 @@ where
     binary_rel_operation(op, lhs, rhs)
 }
 
-fn binary_rel_operation<T>(op: &RelationalOperator, lhs: T, rhs: T) -> bool
+pub fn binary_rel_operation<T>(op: &RelationalOperator, lhs: T, rhs: T) -> bool
 where
     T: PartialEq + PartialOrd,
 {
@@ -500,52 +1987,2374 @@ struct EngineMemory {
     real_mem: Vec<f64>,
     bool_mem: Vec<bool>,
     str_mem: Vec<usize>,
+    // addresses stored at least once, tracked
+    // only when the initialization audit is on
+    written: Option<HashSet<(Kind, AddrSize)>>,
 }
 
 impl EngineMemory {
-    fn new(size: &MemorySize) -> Self {
+    fn new(size: &MemorySize, audit_init: bool) -> Self {
         Self {
             int_mem: (0..size.integer_count).map(|_| 0).collect(),
             real_mem: (0..size.real_count).map(|_| 0.0).collect(),
             bool_mem: (0..size.boolean_count).map(|_| false).collect(),
             str_mem: (0..size.string_count).map(|_| 0).collect(),
+            written: if audit_init {
+                Some(HashSet::new())
+            } else {
+                None
+            },
         }
     }
-}
 
-#[derive(Debug)]
-pub enum RuntimeError {
-    ReadError(ReadError),
-}
+    fn bytes(&self) -> usize {
+        self.int_mem.len() * size_of::<i32>()
+            + self.real_mem.len() * size_of::<f64>()
+            + self.bool_mem.len() * size_of::<bool>()
+            + self.str_mem.len() * size_of::<usize>()
+    }
 
-impl std::error::Error for RuntimeError {}
+    // whether the slot of `addr` exists, local or global
+    fn contains(&self, kind: Kind, addr: AddrSize) -> bool {
+        let slot = (addr & !LOCAL_MASK) as usize;
+        match kind {
+            Kind::Integer => slot < self.int_mem.len(),
+            Kind::Real => slot < self.real_mem.len(),
+            Kind::Bool => slot < self.bool_mem.len(),
+            Kind::Str => slot < self.str_mem.len(),
+        }
+    }
 
-impl fmt::Display for RuntimeError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::ReadError(io_err) => write!(f, "{}", io_err),
+    // push the value of a slot known to exist
+    fn load(&self, kind: Kind, slot: Slot, stack: &mut EngineStack) -> Result<(), RuntimeError> {
+        if !self.is_written(kind, slot.addr()) {
+            return Err(RuntimeError::UninitializedRead(kind, slot.addr()));
         }
+        let (Slot::Global(index) | Slot::Local(index)) = slot;
+        match kind {
+            Kind::Integer => stack.int_stack.push(self.int_mem[index]),
+            Kind::Real => stack.real_stack.push(self.real_mem[index]),
+            Kind::Bool => stack.bool_stack.push(self.bool_mem[index]),
+            Kind::Str => stack.str_stack.push(self.str_mem[index]),
+        }
+        Ok(())
     }
-}
 
-impl std::convert::From<ReadError> for RuntimeError {
-    fn from(e: ReadError) -> RuntimeError {
-        RuntimeError::ReadError(e)
+    // pop a value into a slot known to exist
+    fn store(&mut self, kind: Kind, slot: Slot, stack: &mut EngineStack) {
+        self.mark_written(kind, slot.addr());
+        let (Slot::Global(index) | Slot::Local(index)) = slot;
+        match kind {
+            Kind::Integer => self.int_mem[index] = stack.int_stack.pop().unwrap(),
+            Kind::Real => self.real_mem[index] = stack.real_stack.pop().unwrap(),
+            Kind::Bool => self.bool_mem[index] = stack.bool_stack.pop().unwrap(),
+            Kind::Str => self.str_mem[index] = stack.str_stack.pop().unwrap(),
+        }
+    }
+
+    fn is_written(&self, kind: Kind, addr: AddrSize) -> bool {
+        match &self.written {
+            Some(written) => written.contains(&(kind, addr)),
+            None => true,
+        }
+    }
+
+    fn mark_written(&mut self, kind: Kind, addr: AddrSize) {
+        if let Some(written) = &mut self.written {
+            written.insert((kind, addr));
+        }
     }
 }
 
-struct Record<'a> {
-    return_index: usize,
-    return_block: &'a Block,
-    func_mem: EngineMemory,
+// knobs shared by the command line and by every other
+// engine user, new ones get a default and a setter here
+#[derive(Debug, Clone, Default)]
+pub struct EngineConfig {
+    bool_format: BoolFormat,
+    echo_input: bool,
+    audit_init: bool,
+    args: Vec<String>,
+    quota: Quota,
+    detect_livelock: bool,
+    defines: HashMap<String, String>,
+    memo_limit: Option<usize>,
+    integer_semantics: IntegerSemantics,
+    real_precision: RealPrecision,
+    max_call_depth: Option<usize>,
+    // None leaves the choice to the program
+    io_profile: Option<IoProfile>,
+    flush_before_read: Option<bool>,
+    retry_on_parse_error: Option<bool>,
+    eof_policy: Option<EofPolicy>,
+    compact_period: Option<u64>,
+    notices: Option<Notices>,
 }
 
-impl<'a> Record<'a> {
-    fn new(return_block: &'a Block, func_mem_size: &MemorySize) -> Self {
-        Self {
-            return_index: 0,
-            return_block,
-            func_mem: EngineMemory::new(func_mem_size),
-        }
+impl EngineConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bool_format(mut self, bool_format: BoolFormat) -> Self {
+        self.bool_format = bool_format;
+        self
+    }
+
+    pub fn echo_input(mut self, echo_input: bool) -> Self {
+        self.echo_input = echo_input;
+        self
+    }
+
+    #[cfg(feature = "register-ir")]
+    pub fn get_bool_format(&self) -> &BoolFormat {
+        &self.bool_format
+    }
+
+    #[cfg(feature = "register-ir")]
+    pub fn get_echo_input(&self) -> bool {
+        self.echo_input
+    }
+
+    // fail on reads of variables never stored to
+    pub fn audit_init(mut self, audit_init: bool) -> Self {
+        self.audit_init = audit_init;
+        self
+    }
+
+    // values for the globals bound by the ARGS section
+    pub fn args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    pub fn quota(mut self, quota: Quota) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    // stop loops that keep running without any visible effect
+    pub fn detect_livelock(mut self, detect_livelock: bool) -> Self {
+        self.detect_livelock = detect_livelock;
+        self
+    }
+
+    // cache up to this many results of the functions declared pure
+    pub fn memo_limit(mut self, memo_limit: Option<usize>) -> Self {
+        self.memo_limit = memo_limit;
+        self
+    }
+
+    // name/value pairs read by GETDEF
+    pub fn defines(mut self, defines: HashMap<String, String>) -> Self {
+        self.defines = defines;
+        self
+    }
+
+    // give back the spare capacity of the value stacks every
+    // this many instructions, None or 0 never does
+    pub fn compact_period(mut self, compact_period: Option<u64>) -> Self {
+        self.compact_period = compact_period.filter(|period| *period > 0);
+        self
+    }
+
+    pub fn integer_semantics(mut self, integer_semantics: IntegerSemantics) -> Self {
+        self.integer_semantics = integer_semantics;
+        self
+    }
+
+    pub fn real_precision(mut self, real_precision: RealPrecision) -> Self {
+        self.real_precision = real_precision;
+        self
+    }
+
+    // calls in progress at the same time, MAX_CALL_DEPTH by default
+    pub fn max_call_depth(mut self, max_call_depth: usize) -> Self {
+        self.max_call_depth = Some(max_call_depth);
+        self
+    }
+
+    fn call_depth_limit(&self) -> usize {
+        self.max_call_depth.unwrap_or(MAX_CALL_DEPTH)
+    }
+
+    // run every program with this profile, whatever it declares
+    pub fn io_profile(mut self, io_profile: IoProfile) -> Self {
+        self.io_profile = Some(io_profile);
+        self
+    }
+
+    // the settings below override the one of the profile
+
+    pub fn flush_before_read(mut self, flush_before_read: bool) -> Self {
+        self.flush_before_read = Some(flush_before_read);
+        self
+    }
+
+    pub fn retry_on_parse_error(mut self, retry_on_parse_error: bool) -> Self {
+        self.retry_on_parse_error = Some(retry_on_parse_error);
+        self
+    }
+
+    pub fn eof_policy(mut self, eof_policy: EofPolicy) -> Self {
+        self.eof_policy = Some(eof_policy);
+        self
+    }
+
+    // where the engine tells the user about a malformed value
+    // before asking it again, without one nothing is told
+    pub fn notices(mut self, notices: Rc<RefCell<dyn Write>>) -> Self {
+        self.notices = Some(Notices(notices));
+        self
+    }
+
+    // the IO behaviour of `prog`: the profile it declares, unless
+    // the config has one, with the single settings on top
+    pub fn io_policy(&self, prog: &Program) -> IoPolicy {
+        let profile = self
+            .io_profile
+            .or_else(|| IoProfile::declared(prog))
+            .unwrap_or_default();
+        let policy = profile.io_policy();
+        IoPolicy {
+            flush_before_read: self.flush_before_read.unwrap_or(policy.flush_before_read),
+            retry_on_parse_error: self
+                .retry_on_parse_error
+                .unwrap_or(policy.retry_on_parse_error),
+            eof: self.eof_policy.unwrap_or(policy.eof),
+        }
+    }
+}
+
+// who provides the input of a program
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IoProfile {
+    // someone at a console: prompts show up before every read,
+    // malformed values are asked again and EOF ends the program
+    Interactive,
+    // a prepared input, e.g. an automated grader: malformed
+    // values and EOF are errors, the behaviour of older programs
+    #[default]
+    Batch,
+}
+
+impl IoProfile {
+    // the profile in the file header, programs
+    // without the interactive flag declare none
+    pub fn declared(prog: &Program) -> Option<Self> {
+        if prog.interactive {
+            Some(Self::Interactive)
+        } else {
+            None
+        }
+    }
+
+    pub fn io_policy(self) -> IoPolicy {
+        match self {
+            Self::Interactive => IoPolicy {
+                flush_before_read: true,
+                retry_on_parse_error: true,
+                eof: EofPolicy::Exit,
+            },
+            Self::Batch => IoPolicy {
+                flush_before_read: false,
+                retry_on_parse_error: false,
+                eof: EofPolicy::Error,
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for IoProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interactive" => Ok(Self::Interactive),
+            "batch" => Ok(Self::Batch),
+            _ => Err(format!("`{}` is not one of interactive and batch", s)),
+        }
+    }
+}
+
+// what a read does when the input is over
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EofPolicy {
+    // stop with RuntimeError::ReadError
+    Error,
+    // stop as if the program ran EXT
+    Exit,
+}
+
+impl std::str::FromStr for EofPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Self::Error),
+            "exit" => Ok(Self::Exit),
+            _ => Err(format!("`{}` is not one of error and exit", s)),
+        }
+    }
+}
+
+// the messages of the engine itself, shared with whoever
+// else writes to the same place
+#[derive(Clone)]
+pub struct Notices(Rc<RefCell<dyn Write>>);
+
+impl fmt::Debug for Notices {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Notices")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IoPolicy {
+    pub flush_before_read: bool,
+    // the error and `, try again` go to the notices of the config
+    pub retry_on_parse_error: bool,
+    pub eof: EofPolicy,
+}
+
+// what happens when an integer operation overflows
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IntegerSemantics {
+    // wrap around in two's complement, the reference behaviour
+    #[default]
+    Wrapping,
+    // stop with RuntimeError::IntegerOverflow
+    Checked,
+}
+
+// precision of the results of real operations, constants and
+// inputs; values are always stored as f64
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RealPrecision {
+    #[default]
+    Double,
+    // rounded to the nearest f32
+    Single,
+}
+
+// resource limits, the run fails once one is exceeded
+#[derive(Debug, Clone, Default)]
+pub struct Quota {
+    pub instructions: Option<u64>,
+    // wall clock time
+    pub time: Option<Duration>,
+    // CPU time of the thread running the program
+    pub cpu_time: Option<Duration>,
+    // estimated bytes of memories, stacks and run time strings
+    pub memory: Option<usize>,
+}
+
+#[derive(Debug)]
+pub enum QuotaKind {
+    Instructions(u64),
+    Time(Duration),
+    CpuTime(Duration),
+    Memory(usize),
+}
+
+impl fmt::Display for QuotaKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Instructions(count) => write!(f, "instruction quota of {} exceeded", count),
+            Self::Time(time) => write!(f, "time quota of {} ms exceeded", time.as_millis()),
+            Self::CpuTime(time) => {
+                write!(f, "CPU time quota of {} ms exceeded", time.as_millis())
+            }
+            Self::Memory(bytes) => write!(f, "memory quota of {} bytes exceeded", bytes),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BoolFormat {
+    true_word: String,
+    false_word: String,
+}
+
+impl BoolFormat {
+    pub fn new(true_word: &str, false_word: &str) -> Self {
+        Self {
+            true_word: true_word.to_owned(),
+            false_word: false_word.to_owned(),
+        }
+    }
+
+    pub fn format(&self, b: bool) -> &str {
+        if b {
+            &self.true_word
+        } else {
+            &self.false_word
+        }
+    }
+}
+
+impl Default for BoolFormat {
+    fn default() -> Self {
+        Self::new("true", "false")
+    }
+}
+
+// parse the TRUE/FALSE command line syntax
+impl std::str::FromStr for BoolFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split('/');
+        match (words.next(), words.next(), words.next()) {
+            (Some(t), Some(f), None) => Ok(Self::new(t, f)),
+            _ => Err(format!("`{}` is not in the TRUE/FALSE form", s)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MemorySnapshot {
+    pub integers: Vec<i32>,
+    pub reals: Vec<f64>,
+    pub booleans: Vec<bool>,
+    pub strings: Vec<String>,
+}
+
+impl MemorySnapshot {
+    fn new(mem: &EngineMemory, str_mem: &StringMemory) -> Self {
+        Self {
+            integers: mem.int_mem.clone(),
+            reals: mem.real_mem.clone(),
+            booleans: mem.bool_mem.clone(),
+            strings: mem
+                .str_mem
+                .iter()
+                .map(|index| str_mem.get_string(*index).to_owned())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RuntimeError {
+    ReadError(ReadError),
+    UninitializedRead(Kind, AddrSize),
+    ArgumentCount(usize, usize),
+    BadArgument(usize, Kind, String),
+    QuotaExceeded(QuotaKind),
+    Livelock(BlockId, usize),
+    WriteError(io::Error),
+    Cancelled,
+    CallProtocol(BlockId, usize, CallError),
+    DivisionByZero,
+    IntegerOverflow,
+    // real given to CSTI_x that is NaN or outside the integer range
+    InvalidCast(f64),
+    // SUBSTR or CHARAT position and the length of the
+    // string, both in characters
+    StringIndex {
+        position: i64,
+        length: usize,
+    },
+    Array(ArrayError),
+    // the index of the instruction in its block
+    StackUnderflow {
+        kind: Kind,
+        instruction: usize,
+    },
+    // SBAPPx or SBFINISH without an open builder
+    NoBuilder {
+        instruction: usize,
+    },
+    // address without its mask, and whether it is local
+    InvalidAddress {
+        kind: Kind,
+        addr: AddrSize,
+        local: bool,
+    },
+    // calls in progress and the functions called, outermost
+    // first, up to the one that did not fit
+    StackOverflow {
+        depth: usize,
+        trace: Vec<usize>,
+    },
+    // the error that stopped the program and those raised
+    // afterwards, while finalizing it
+    Aggregate {
+        primary: Box<RuntimeError>,
+        secondary: Vec<SecondaryError>,
+    },
+}
+
+// an error raised after the primary one, with the
+// instruction that raised it
+#[derive(Debug)]
+pub struct SecondaryError {
+    pub block: BlockId,
+    pub index: usize,
+    pub error: RuntimeError,
+}
+
+impl std::error::Error for RuntimeError {}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReadError(io_err) => write!(f, "{}", io_err),
+            Self::UninitializedRead(kind, addr) => {
+                let (scope, addr) = if addr & LOCAL_MASK == 0 {
+                    ("global", *addr)
+                } else {
+                    ("local", addr - LOCAL_MASK)
+                };
+                write!(f, "{} {} {} read before being assigned", scope, kind, addr)
+            }
+            Self::ArgumentCount(expected, given) => write!(
+                f,
+                "the program takes {} argument(s), {} given",
+                expected, given
+            ),
+            Self::BadArgument(index, kind, arg) => {
+                write!(f, "argument {} `{}` is not a valid {}", index, arg, kind)
+            }
+            Self::QuotaExceeded(quota) => write!(f, "{}", quota),
+            Self::Livelock(block, index) => write!(
+                f,
+                "likely infinite loop: {}, instruction {} jumped back {} times without I/O, stores or stack changes",
+                block, index, LIVELOCK_THRESHOLD
+            ),
+            Self::WriteError(err) => write!(f, "cannot write the output: {}", err),
+            Self::Cancelled => write!(f, "cancelled by the user"),
+            Self::CallProtocol(block, index, err) => {
+                write!(f, "{}, instruction {}: {}", block, index, err)
+            }
+            Self::DivisionByZero => write!(f, "integer division by zero"),
+            Self::IntegerOverflow => write!(f, "integer overflow"),
+            Self::InvalidCast(n) => write!(f, "{} cannot be converted to an integer", n),
+            Self::Array(err) => write!(f, "{}", err),
+            Self::StringIndex { position, length } => write!(
+                f,
+                "position {} is outside a string of {} characters",
+                position, length
+            ),
+            Self::StackUnderflow { kind, instruction } => write!(
+                f,
+                "instruction {} pops a {} from an empty stack",
+                instruction, kind
+            ),
+            Self::InvalidAddress { kind, addr, local } => {
+                let scope = if *local { "local" } else { "global" };
+                write!(
+                    f,
+                    "{} {} {} is outside the declared memory",
+                    scope, kind, addr
+                )
+            }
+            Self::StackOverflow { depth, trace } => write!(
+                f,
+                "more than {} calls in progress: {}",
+                depth,
+                call_trace(trace)
+            ),
+            Self::NoBuilder { instruction } => write!(
+                f,
+                "instruction {} uses a string builder, but none is open",
+                instruction
+            ),
+            Self::Aggregate { primary, secondary } => {
+                write!(f, "{}", primary)?;
+                for err in secondary {
+                    write!(
+                        f,
+                        "\nthen the finalizer failed at {}, instruction {}: {}",
+                        err.block, err.index, err.error
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl RuntimeError {
+    // exit code handed to the finalizer
+    pub fn code(&self) -> i32 {
+        match self {
+            Self::ReadError(_) => 1,
+            Self::UninitializedRead(..) => 2,
+            Self::ArgumentCount(..) | Self::BadArgument(..) => 3,
+            Self::QuotaExceeded(_) => 4,
+            Self::Livelock(..) => 5,
+            Self::WriteError(_) => 6,
+            Self::Cancelled => 7,
+            Self::CallProtocol(..) => 8,
+            Self::DivisionByZero => 9,
+            Self::IntegerOverflow => 10,
+            Self::StackUnderflow { .. } | Self::NoBuilder { .. } => 11,
+            Self::InvalidAddress { .. } => 12,
+            Self::StackOverflow { .. } => 13,
+            Self::InvalidCast(_) => 14,
+            Self::StringIndex { .. } => 15,
+            Self::Array(_) => 16,
+            Self::Aggregate { primary, .. } => primary.code(),
+        }
+    }
+
+    // the error that stopped the program, the root cause
+    // of those raised while finalizing it
+    pub fn primary(&self) -> &RuntimeError {
+        match self {
+            Self::Aggregate { primary, .. } => primary,
+            err => err,
+        }
+    }
+
+    pub fn secondary(&self) -> &[SecondaryError] {
+        match self {
+            Self::Aggregate { secondary, .. } => secondary,
+            _ => &[],
+        }
+    }
+
+    // the reader of the output went away, e.g. `| head`
+    pub fn is_broken_pipe(&self) -> bool {
+        matches!(self.primary(), Self::WriteError(err) if err.kind() == io::ErrorKind::BrokenPipe)
+    }
+}
+
+// consecutive calls of the same function are collapsed,
+// deep recursion would make the trace unreadable otherwise
+fn call_trace(trace: &[usize]) -> String {
+    let mut calls: Vec<(usize, usize)> = vec![];
+    for func in trace {
+        match calls.last_mut() {
+            Some((last, count)) if last == func => *count += 1,
+            _ => calls.push((*func, 1)),
+        }
+    }
+    let calls = calls.iter().map(|(func, count)| match count {
+        1 => format!("function {}", func),
+        _ => format!("function {} x{}", func, count),
+    });
+    std::iter::once("main body".to_owned())
+        .chain(calls)
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+impl std::convert::From<ReadError> for RuntimeError {
+    fn from(e: ReadError) -> RuntimeError {
+        RuntimeError::ReadError(e)
+    }
+}
+
+impl std::convert::From<ArrayError> for RuntimeError {
+    fn from(e: ArrayError) -> RuntimeError {
+        RuntimeError::Array(e)
+    }
+}
+
+impl std::convert::From<io::Error> for RuntimeError {
+    fn from(e: io::Error) -> RuntimeError {
+        RuntimeError::WriteError(e)
+    }
+}
+
+struct Record<'a> {
+    // function the record belongs to
+    func: usize,
+    return_index: usize,
+    return_block: &'a Block,
+    func_mem: EngineMemory,
+    // activation of a timer handler, with the
+    // operands of the code it interrupted
+    interrupt: Option<Operands>,
+    // call of a pure function whose result is not cached yet
+    memo_key: Option<MemoKey>,
+    // start of the call, only while profiling
+    called_at: Option<Instant>,
+}
+
+impl<'a> Record<'a> {
+    fn new(
+        return_block: &'a Block,
+        func: usize,
+        func_mem_size: &MemorySize,
+        audit_init: bool,
+    ) -> Self {
+        #[cfg(feature = "alloc-stats")]
+        let _scope = crate::alloc_stats::scope(crate::alloc_stats::Category::Records);
+        Self {
+            func,
+            return_index: 0,
+            return_block,
+            func_mem: EngineMemory::new(func_mem_size, audit_init),
+            interrupt: None,
+            memo_key: None,
+            called_at: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::opcode;
+    use crate::program_load::{parse_data, LoadOptions};
+
+    fn try_run(code: Vec<u8>, config: EngineConfig) -> Result<String, RuntimeError> {
+        let mut data = vec![opcode::INIT, 0, 1, 0, 0, 0, 0, 0, 0];
+        data.extend(code);
+        let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let mut output = Vec::new();
+        let mut input = &b"12 ab\n"[..];
+        run_program_with_io(prog, mem, str_mem, &config, &mut input, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    fn run(code: Vec<u8>, config: EngineConfig) -> String {
+        try_run(code, config).unwrap()
+    }
+
+    #[test]
+    fn test_bool_format() {
+        let format: BoolFormat = "1/0".parse().unwrap();
+        assert_eq!(format.format(true), "1");
+        assert_eq!(format.format(false), "0");
+        assert!("yes".parse::<BoolFormat>().is_err());
+        assert!("a/b/c".parse::<BoolFormat>().is_err());
+
+        let code = vec![
+            opcode::LDBC,
+            255,
+            opcode::WRB,
+            opcode::LDSC,
+            0,
+            2,
+            b's',
+            b'i',
+            opcode::LDSC,
+            0,
+            2,
+            b'n',
+            b'o',
+            opcode::SETBOOLFMT,
+            opcode::LDBC,
+            255,
+            opcode::WRB,
+            opcode::LDBC,
+            0,
+            opcode::WRB,
+        ];
+        assert_eq!(run(code, EngineConfig::new().bool_format(format)), "1sino");
+    }
+
+    #[test]
+    fn test_echo_input() {
+        let code = vec![
+            opcode::LDSC,
+            0,
+            1,
+            b'>',
+            opcode::WRS,
+            opcode::RDI,
+            opcode::RDS,
+            opcode::WRS,
+            opcode::WRI,
+        ];
+        let output = run(code.clone(), EngineConfig::new());
+        assert_eq!(output, "> ab12");
+        let output = run(code, EngineConfig::new().echo_input(true));
+        assert_eq!(output, ">12\n ab\n ab12");
+    }
+
+    #[test]
+    fn test_scratch_string() {
+        let code = vec![
+            opcode::RDI,
+            opcode::LDSC,
+            0,
+            3,
+            b' ',
+            b'a',
+            b'b',
+            opcode::RDS,
+            opcode::NES,
+            opcode::WRB,
+        ];
+        assert_eq!(run(code, EngineConfig::new()), "false");
+
+        // only the string that is stored reaches the string memory
+        let data = vec![
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            1,
+            opcode::RDS,
+            opcode::WRS,
+            opcode::RDS,
+            opcode::STRS,
+            0,
+            0,
+            opcode::LDS,
+            0,
+            0,
+            opcode::UPPER,
+            opcode::WRS,
+        ];
+        let (mut prog, mem, mut str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        let mut input = &b"ab\ncd\n"[..];
+        let mut output = vec![];
+        let mut engine = Engine::new(&mut prog, &mem, &mut str_mem, &config, &mut input).unwrap();
+        engine.run(&mut output).unwrap();
+        drop(engine);
+        assert_eq!(output, b"abCD");
+        assert_eq!(str_mem.dynamic_count(), 1);
+    }
+
+    #[test]
+    fn test_output_many() {
+        let code = vec![
+            opcode::LDIC,
+            0,
+            0,
+            0,
+            5,
+            opcode::LDSC,
+            0,
+            2,
+            b'a',
+            b'b',
+            opcode::LDBC,
+            255,
+            opcode::RDI,
+            opcode::WRVN,
+            4,
+            0b00101100,
+        ];
+        assert_eq!(run(code, EngineConfig::new()), "5 ab true 12");
+    }
+
+    #[test]
+    fn test_bind_arguments() {
+        let code = vec![opcode::ARGS, 1, 0, 0, 0, opcode::LDI, 0, 0, opcode::WRI];
+        let config = EngineConfig::new().audit_init(true);
+        let output = run(code.clone(), config.clone().args(vec!["42".to_owned()]));
+        assert_eq!(output, "42");
+
+        let stat = try_run(code.clone(), config.clone());
+        assert!(matches!(stat, Err(RuntimeError::ArgumentCount(1, 0))));
+        let stat = try_run(code, config.args(vec!["x".to_owned()]));
+        assert!(matches!(
+            stat,
+            Err(RuntimeError::BadArgument(0, Kind::Integer, _))
+        ));
+    }
+
+    #[test]
+    fn test_finalizer() {
+        let finalizer = vec![
+            opcode::FINI,
+            0,
+            0,
+            opcode::FUNC,
+            opcode::INIT,
+            0,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::LDI,
+            0x80,
+            0,
+            opcode::WRI,
+            opcode::RET,
+        ];
+        let mut code = vec![opcode::LDIC, 0, 0, 0, 7, opcode::WRI, opcode::EXT];
+        code.extend(&finalizer);
+        assert_eq!(run(code, EngineConfig::new()), "70");
+
+        let mut code = vec![opcode::LDI, 0, 0, opcode::WRI];
+        code.extend(&finalizer);
+        let mut data = vec![opcode::INIT, 0, 1, 0, 0, 0, 0, 0, 0];
+        data.extend(code);
+        let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let mut output = Vec::new();
+        let config = EngineConfig::new().audit_init(true);
+        let stat = run_program_with_io(prog, mem, str_mem, &config, &mut &b""[..], &mut output);
+        assert!(matches!(stat, Err(RuntimeError::UninitializedRead(..))));
+        assert_eq!(output, b"2");
+
+        // a failing finalizer keeps the error of the program
+        let data = vec![
+            opcode::INIT,
+            0,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::LDI,
+            0,
+            0,
+            opcode::FINI,
+            0,
+            0,
+            opcode::FUNC,
+            opcode::INIT,
+            0,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::LDI,
+            0x80,
+            0,
+            opcode::LDI0,
+            opcode::DIVI,
+            opcode::RET,
+        ];
+        let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let stat = run_program_with_io(prog, mem, str_mem, &config, &mut &b""[..], &mut output);
+        let err = stat.unwrap_err();
+        assert!(matches!(err.primary(), RuntimeError::UninitializedRead(..)));
+        assert_eq!(err.code(), 2);
+        assert_eq!(err.secondary().len(), 1);
+        assert_eq!(err.secondary()[0].block, BlockId::Function(0));
+        assert!(matches!(
+            err.secondary()[0].error,
+            RuntimeError::DivisionByZero
+        ));
+        assert_eq!(
+            err.to_string(),
+            "global integer 0 read before being assigned\nthen the finalizer failed at function 0, instruction 2: integer division by zero"
+        );
+    }
+
+    #[test]
+    fn test_engine_step() {
+        // main body: call function 0, which writes 5, then write 1
+        let data = vec![
+            opcode::INIT,
+            0,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::PARAM,
+            0,
+            0,
+            opcode::CALL,
+            0,
+            0,
+            opcode::LDI1,
+            opcode::WRI,
+            opcode::FUNC,
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::LDIC,
+            0,
+            0,
+            0,
+            5,
+            opcode::WRI,
+            opcode::RET,
+        ];
+        let (mut prog, mem, mut str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        let mut input = &b""[..];
+        let mut output = Vec::new();
+        let mut engine = Engine::new(&mut prog, &mem, &mut str_mem, &config, &mut input).unwrap();
+        assert_eq!((engine.current_block(), engine.index()), (BlockId::Main, 0));
+        assert!(engine.step(&mut output).unwrap());
+        assert!(engine.step(&mut output).unwrap());
+        assert_eq!(engine.current_block(), BlockId::Function(0));
+        assert!(engine.run_until(2, &mut output).unwrap());
+        assert_eq!(output, b"5");
+        assert!(!engine.run_until(10, &mut output).unwrap());
+        assert_eq!((engine.current_block(), engine.index()), (BlockId::Main, 4));
+        assert_eq!(engine.executed(), 7);
+        assert_eq!(output, b"51");
+        assert!(!engine.step(&mut output).unwrap());
+    }
+
+    #[test]
+    fn test_call_protocol_error() {
+        // built by hand: the loader rejects this program
+        let prog = Program {
+            body: Block::new(vec![Command::Control(ControlFlow::Ret, 0)]),
+            func: vec![],
+            fini: None,
+            meta: vec![],
+            interactive: false,
+            pure: HashMap::new(),
+        };
+        let mem = ProgramMemory {
+            main: MemorySize::default(),
+            func: vec![],
+            args: vec![],
+        };
+        let stat = run_program_with_io(
+            prog,
+            mem,
+            StringMemory::new(),
+            &EngineConfig::new(),
+            &mut io::empty(),
+            &mut io::sink(),
+        );
+        let err = stat.unwrap_err();
+        assert!(matches!(
+            err,
+            RuntimeError::CallProtocol(BlockId::Main, 0, CallError::ReturnOutsideFunction)
+        ));
+        assert_eq!(err.code(), 8);
+
+        let run = |code: Vec<Command>| {
+            let prog = Program {
+                body: Block::new(code),
+                func: vec![Block::new(vec![Command::Control(ControlFlow::Ret, 0)])],
+                fini: None,
+                meta: vec![],
+                interactive: false,
+                pure: HashMap::new(),
+            };
+            let mem = ProgramMemory {
+                main: MemorySize::default(),
+                func: vec![MemorySize {
+                    integer_count: 1,
+                    ..MemorySize::default()
+                }],
+                args: vec![],
+            };
+            let config = EngineConfig::new();
+            run_program_with_io(
+                prog,
+                mem,
+                StringMemory::new(),
+                &config,
+                &mut io::empty(),
+                &mut io::sink(),
+            )
+            .unwrap_err()
+            .to_string()
+        };
+        let param = run(vec![
+            Command::ConstantLoad(Constant::Integer(1)),
+            Command::StoreParam(Kind::Integer, LOCAL_MASK),
+        ]);
+        assert_eq!(
+            param,
+            "main body, instruction 1: parameter stored without an activation record"
+        );
+        let nested = run(vec![Command::NewRecord(0), Command::NewRecord(0)]);
+        assert!(nested.starts_with("main body, instruction 1: activation record"));
+        let missing = run(vec![Command::NewRecord(3)]);
+        assert_eq!(
+            missing,
+            "main body, instruction 0: function 3 does not exist"
+        );
+    }
+
+    #[test]
+    fn test_string_stats() {
+        let code = vec![
+            opcode::RDI,
+            opcode::RDS,
+            opcode::STRS,
+            0,
+            0,
+            opcode::STRSTAT,
+            opcode::WRVN,
+            2,
+            0,
+            opcode::LDSC,
+            0,
+            0,
+            opcode::STRS,
+            0,
+            0,
+            opcode::STRSTAT,
+            opcode::WRVN,
+            2,
+            0,
+        ];
+        let mut data = vec![opcode::INIT, 0, 0, 0, 0, 0, 0, 0, 1];
+        data.extend(code);
+        let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let mut output = Vec::new();
+        let config = EngineConfig::new();
+        run_program_with_io(
+            prog,
+            mem,
+            str_mem,
+            &config,
+            &mut &b"12 ab\n"[..],
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(output, b"1 30 0");
+    }
+
+    #[test]
+    fn test_timer_handler() {
+        // spin until the handler stores 7 in the global integer
+        let code = vec![
+            opcode::LDIC,
+            0,
+            0,
+            0,
+            1,
+            opcode::TIMER,
+            0,
+            0,
+            opcode::LBL,
+            0,
+            0,
+            opcode::LDI,
+            0,
+            0,
+            opcode::LDIC,
+            0,
+            0,
+            0,
+            0,
+            opcode::NEI,
+            opcode::JNE,
+            0,
+            0,
+            opcode::LDI,
+            0,
+            0,
+            opcode::WRI,
+            opcode::FUNC,
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::LDIC,
+            0,
+            0,
+            0,
+            7,
+            opcode::STRI,
+            0,
+            0,
+            opcode::RET,
+        ];
+        assert_eq!(run(code, EngineConfig::new()), "7");
+    }
+
+    #[test]
+    fn test_timer_handler_stacks() {
+        // 5 waits on the stack while the handler runs and
+        // leaves 9 behind: the handler's value is dropped
+        let code = vec![
+            opcode::LDIC,
+            0,
+            0,
+            0,
+            1,
+            opcode::TIMER,
+            0,
+            0,
+            opcode::LDIC,
+            0,
+            0,
+            0,
+            5,
+            opcode::LBL,
+            0,
+            0,
+            opcode::LDI,
+            0,
+            0,
+            opcode::LDIC,
+            0,
+            0,
+            0,
+            0,
+            opcode::NEI,
+            opcode::JNE,
+            0,
+            0,
+            opcode::WRI,
+            opcode::FUNC,
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::LDIC,
+            0,
+            0,
+            0,
+            7,
+            opcode::STRI,
+            0,
+            0,
+            opcode::LDIC,
+            0,
+            0,
+            0,
+            9,
+            opcode::RET,
+        ];
+        assert_eq!(run(code, EngineConfig::new()), "5");
+    }
+
+    #[test]
+    fn test_livelock() {
+        let code = vec![opcode::LBL, 0, 0, opcode::LDBC, 255, opcode::JEQ, 0, 0];
+        let stat = try_run(code, EngineConfig::new().detect_livelock(true));
+        assert!(matches!(
+            stat,
+            Err(RuntimeError::Livelock(BlockId::Main, 2))
+        ));
+    }
+
+    #[test]
+    fn test_livelock_timer() {
+        // the busy wait ends when the timer fires, 10s from now
+        let code = vec![
+            opcode::LDIC,
+            0,
+            0,
+            0x27,
+            0x10,
+            opcode::TIMER,
+            0,
+            0,
+            opcode::LBL,
+            0,
+            0,
+            opcode::LDI,
+            0,
+            0,
+            opcode::LDIC,
+            0,
+            0,
+            0,
+            0,
+            opcode::NEI,
+            opcode::JNE,
+            0,
+            0,
+            opcode::FUNC,
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::RET,
+        ];
+        // five instructions per iteration, past the detector threshold
+        let quota = Quota {
+            instructions: Some(6 * LIVELOCK_THRESHOLD),
+            ..Quota::default()
+        };
+        let config = EngineConfig::new().detect_livelock(true).quota(quota);
+        assert!(matches!(
+            try_run(code, config),
+            Err(RuntimeError::QuotaExceeded(QuotaKind::Instructions(_)))
+        ));
+    }
+
+    #[test]
+    fn test_profiler() {
+        let shape = crate::stress::Shape {
+            call_depth: 2,
+            constants: 0,
+            iterations: 10,
+        };
+        let data = crate::stress::generate(&shape);
+        let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let mut profiler = Profiler::new(1);
+        let config = EngineConfig::new();
+        let mut output = Vec::new();
+        run_program_profiled(
+            prog,
+            mem,
+            str_mem,
+            &config,
+            &mut &b""[..],
+            &mut output,
+            &mut profiler,
+        )
+        .unwrap();
+        let folded = profiler.folded();
+        assert!(folded.starts_with("main "));
+        assert!(folded.contains("\nmain;function_0;function_1 "));
+    }
+
+    #[test]
+    fn test_loaded_program_reruns() {
+        let data = vec![
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            1,
+            opcode::RDS,
+            opcode::STRS,
+            0,
+            0,
+            opcode::RDS,
+            opcode::WRS,
+        ];
+        let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let mut program = LoadedProgram::new(prog, mem, str_mem).unwrap();
+        assert!(matches!(
+            program.prog.body.code[1],
+            Command::StoreSlot(Kind::Str, Slot::Global(0))
+        ));
+        let config = EngineConfig::new();
+        for _ in 0..3 {
+            let mut output = Vec::new();
+            let snapshot = program
+                .run(&config, &mut &b"ab\ncd\n"[..], &mut output)
+                .unwrap();
+            assert_eq!(snapshot.strings, vec!["ab".to_owned()]);
+            assert_eq!(output, b"cd");
+            assert_eq!(program.string_memory.dynamic_count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_get_define() {
+        let code = vec![
+            opcode::LDSC,
+            0,
+            1,
+            b'A',
+            opcode::GETDEF,
+            opcode::WRS,
+            opcode::LDSC,
+            0,
+            1,
+            b'B',
+            opcode::GETDEF,
+            opcode::WRS,
+            opcode::FLN,
+        ];
+        let mut defines = HashMap::new();
+        defines.insert("A".to_owned(), "on".to_owned());
+        assert_eq!(run(code, EngineConfig::new().defines(defines)), "on\n");
+    }
+
+    #[test]
+    fn test_string_builder() {
+        // the inner builder is finished and appended to the outer one
+        let code = vec![
+            opcode::SBNEW,
+            opcode::LDSC,
+            0,
+            2,
+            b'x',
+            b'=',
+            opcode::SBAPPS,
+            opcode::LDIC,
+            0,
+            0,
+            0,
+            42,
+            opcode::SBAPPI,
+            opcode::SBNEW,
+            opcode::LDTRUE,
+            opcode::SBAPPB,
+            opcode::LDRC,
+            63,
+            224,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::SBAPPR,
+            opcode::SBFINISH,
+            opcode::SBAPPS,
+            opcode::SBFINISH,
+            opcode::WRS,
+            opcode::FLN,
+        ];
+        let format = "yes/no".parse().unwrap();
+        let config = EngineConfig::new().bool_format(format);
+        assert_eq!(run(code, config), "x=42yes0.5\n");
+    }
+
+    #[test]
+    fn test_raw_round_trip() {
+        // the bytes after the token go back out as they came in
+        let code = vec![
+            opcode::INIT,
+            0,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::RDI,
+            opcode::WRI,
+            opcode::LDIC,
+            0,
+            0,
+            0,
+            8,
+            opcode::RDRAW,
+            opcode::STRI,
+            0,
+            0,
+            opcode::LDI,
+            0,
+            0,
+            opcode::WRRAW,
+            opcode::LDI,
+            0,
+            0,
+            opcode::WRI,
+        ];
+        let (prog, mem, str_mem, _) = parse_data(&code, &LoadOptions::default()).unwrap();
+        let mut input = &b"12 a\n\xffb"[..];
+        let mut output = Vec::new();
+        let config = EngineConfig::new();
+        run_program_with_io(prog, mem, str_mem, &config, &mut input, &mut output).unwrap();
+        assert_eq!(output, b"12 a\n\xffb5");
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        let err = try_run(
+            vec![opcode::LDI1, opcode::WRI, opcode::ADDI],
+            EngineConfig::new(),
+        );
+        assert!(matches!(
+            err,
+            Err(RuntimeError::StackUnderflow {
+                kind: Kind::Integer,
+                instruction: 2
+            })
+        ));
+        // the count is there, the bytes are not
+        let err = try_run(vec![opcode::LDI1, opcode::WRRAW], EngineConfig::new());
+        assert!(matches!(
+            err,
+            Err(RuntimeError::StackUnderflow {
+                kind: Kind::Integer,
+                instruction: 1
+            })
+        ));
+        let err = try_run(vec![opcode::SBFINISH], EngineConfig::new());
+        assert!(matches!(
+            err,
+            Err(RuntimeError::NoBuilder { instruction: 0 })
+        ));
+        let err = try_run(vec![opcode::RDS, opcode::EQS], EngineConfig::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "instruction 1 pops a string from an empty stack"
+        );
+        assert_eq!(err.code(), 11);
+    }
+
+    #[test]
+    fn test_invalid_address() {
+        // the loader rejects these, programs changed in memory may not
+        let data = vec![
+            opcode::INIT,
+            0,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::LDI1,
+            opcode::STRI,
+            0,
+            0,
+        ];
+        let (mut prog, mem, mut str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        let mut run = |prog: &mut Program| {
+            let mut input = io::empty();
+            let mut engine = Engine::new(prog, &mem, &mut str_mem, &config, &mut input)?;
+            engine.run(&mut Vec::new())
+        };
+        run(&mut prog).unwrap();
+
+        prog.body.code[1] = Command::MemoryStore(Kind::Integer, 3);
+        let err = run(&mut prog).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "global integer 3 is outside the declared memory"
+        );
+        assert_eq!(err.code(), 12);
+
+        prog.body.code[1] = Command::MemoryLoad(Kind::Real, LOCAL_MASK);
+        assert!(matches!(
+            run(&mut prog),
+            Err(RuntimeError::InvalidAddress {
+                kind: Kind::Real,
+                addr: 0,
+                local: true
+            })
+        ));
+    }
+
+    #[test]
+    fn test_call_depth_limit() {
+        // function 0 calls itself forever
+        let source = "
+            INIT 0 0 0 0
+            PARAM 0
+            CALL 0
+            FUNC
+            INIT 0 0 0 0
+            PARAM 0
+            CALL 0
+            RET
+        ";
+        let data = crate::asm::assemble(source).unwrap();
+        let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new().max_call_depth(5);
+        let err = run_program_with_io(
+            prog,
+            mem,
+            str_mem,
+            &config,
+            &mut io::empty(),
+            &mut io::sink(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            &err,
+            RuntimeError::StackOverflow { depth: 5, trace } if trace == &[0; 6]
+        ));
+        assert_eq!(
+            err.to_string(),
+            "more than 5 calls in progress: main body -> function 0 x6"
+        );
+        assert_eq!(
+            call_trace(&[1, 0, 0, 2]),
+            "main body -> function 1 -> function 0 x2 -> function 2"
+        );
+    }
+
+    #[test]
+    fn test_io_profile() {
+        let source = "
+            HDR interactive
+            INIT 0 0 0 0
+            RDI
+            WRI
+            FLN
+            RDI
+            WRI
+        ";
+        let data = crate::asm::assemble(source).unwrap();
+        let (mut prog, mem, _, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let mut run = |config: EngineConfig| {
+            let (_, _, mut str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+            let mut input = &b"x 1\n4\n"[..];
+            let mut out = vec![];
+            let mut engine =
+                Engine::new(&mut prog, &mem, &mut str_mem, &config, &mut input).unwrap();
+            let status = engine.run(&mut out);
+            (status, String::from_utf8(out).unwrap())
+        };
+
+        // the malformed line is asked again, EOF ends the program
+        let (status, out) = run(EngineConfig::new());
+        assert!(status.is_ok());
+        assert_eq!(out, "4\n");
+
+        // and the reason is told apart from the program output
+        let notices = Rc::new(RefCell::new(Vec::new()));
+        let (_, out) = run(EngineConfig::new().notices(notices.clone()));
+        assert_eq!(out, "4\n");
+        let told = String::from_utf8(notices.borrow().clone()).unwrap();
+        assert_eq!(told.lines().count(), 1);
+        assert!(told.ends_with(", try again\n"));
+
+        let (status, _) = run(EngineConfig::new().eof_policy(EofPolicy::Error));
+        assert!(matches!(
+            status,
+            Err(RuntimeError::ReadError(ReadError::Eof))
+        ));
+
+        let (status, out) = run(EngineConfig::new().io_profile(IoProfile::Batch));
+        assert!(matches!(
+            status,
+            Err(RuntimeError::ReadError(ReadError::IntParseError(_)))
+        ));
+        assert_eq!(out, "");
+        assert_eq!(
+            EngineConfig::new().io_policy(&prog),
+            IoProfile::Interactive.io_policy()
+        );
+        prog.interactive = false;
+        assert_eq!(
+            EngineConfig::new().io_policy(&prog),
+            IoProfile::Batch.io_policy()
+        );
+    }
+
+    struct ClosedPipe;
+
+    impl Write for ClosedPipe {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_broken_pipe() {
+        let data = vec![
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::LBL,
+            0,
+            0,
+            opcode::LDI1,
+            opcode::WRI,
+            opcode::JUMP,
+            0,
+            0,
+        ];
+        let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        let stat = run_program_with_io(
+            prog,
+            mem,
+            str_mem,
+            &config,
+            &mut io::empty(),
+            &mut ClosedPipe,
+        );
+        let err = stat.unwrap_err();
+        assert!(err.is_broken_pipe());
+        assert_eq!(err.code(), 6);
+    }
+
+    #[test]
+    fn test_memoize() {
+        // naive recursive fibonacci of 20, declared pure
+        let call = |arg: &[u8]| {
+            let mut code = vec![opcode::PARAM, 0, 0];
+            code.extend(arg);
+            code.extend(&[opcode::STRIP, 0x80, 0, opcode::CALL, 0, 0]);
+            code
+        };
+        let mut code = vec![opcode::PURE, 0, 0, 1, 0];
+        code.extend(call(&[opcode::LDIC, 0, 0, 0, 20]));
+        code.extend(&[
+            opcode::WRI,
+            opcode::FUNC,
+            opcode::INIT,
+            0,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ]);
+        code.extend(&[opcode::LDI, 0x80, 0, opcode::LDIC, 0, 0, 0, 2, opcode::GEQI]);
+        code.extend(&[opcode::JEQ, 0, 1, opcode::LDI, 0x80, 0, opcode::RET]);
+        code.extend(&[opcode::LBL, 0, 1]);
+        code.extend(call(&[opcode::LDI, 0x80, 0, opcode::LDI1, opcode::SUBI]));
+        code.extend(call(&[
+            opcode::LDI,
+            0x80,
+            0,
+            opcode::LDIC,
+            0,
+            0,
+            0,
+            2,
+            opcode::SUBI,
+        ]));
+        code.extend(&[opcode::ADDI, opcode::RET]);
+
+        let quota = Quota {
+            instructions: Some(2000),
+            ..Quota::default()
+        };
+        let config = EngineConfig::new().quota(quota);
+        assert!(matches!(
+            try_run(code.clone(), config.clone()),
+            Err(RuntimeError::QuotaExceeded(_))
+        ));
+        assert_eq!(run(code, config.memo_limit(Some(100))), "6765");
+    }
+
+    #[test]
+    fn test_memory_quota() {
+        let code = vec![
+            opcode::LBL,
+            0,
+            0,
+            opcode::LDIC,
+            0,
+            0,
+            0,
+            1,
+            opcode::JUMP,
+            0,
+            0,
+        ];
+        let quota = Quota {
+            memory: Some(4096),
+            ..Quota::default()
+        };
+        let stat = try_run(code, EngineConfig::new().quota(quota));
+        assert!(matches!(
+            stat,
+            Err(RuntimeError::QuotaExceeded(QuotaKind::Memory(4096)))
+        ));
+    }
+
+    #[test]
+    fn test_integer_arithmetic() {
+        // i32::MAX + 1 wraps, then 7 / 0
+        let code = vec![
+            opcode::LDIC,
+            0x7f,
+            0xff,
+            0xff,
+            0xff,
+            opcode::LDI1,
+            opcode::ADDI,
+            opcode::WRI,
+            opcode::FLN,
+            opcode::LDIC,
+            0,
+            0,
+            0,
+            7,
+            opcode::LDI0,
+            opcode::DIVI,
+            opcode::WRI,
+        ];
+        let mut output = vec![];
+        let mut data = vec![opcode::INIT, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.extend(code);
+        let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        let stat = run_program_with_io(prog, mem, str_mem, &config, &mut io::empty(), &mut output);
+        assert!(matches!(stat, Err(RuntimeError::DivisionByZero)));
+        assert_eq!(output, b"-2147483648\n");
+    }
+
+    #[test]
+    fn test_arrays() {
+        let run = |source: &str| {
+            let data = crate::asm::assemble(source).unwrap();
+            let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+            let config = EngineConfig::new();
+            let mut output = vec![];
+            run_program_with_io(prog, mem, str_mem, &config, &mut io::empty(), &mut output)
+                .map(|_| String::from_utf8(output).unwrap())
+        };
+        // squares of 0..4 in an integer array, a name in a
+        // string array built at run time
+        let output = run("
+            INIT 2 0 0 0
+            LDIC 4
+            ANEWI
+            STRI 0
+            LDI0
+            STRI 1
+            LBL 0
+            LDI 1
+            LDIC 4
+            LESQI
+            JNE 1
+            LDI 0
+            LDI 1
+            LDI 1
+            LDI 1
+            MULI
+            ASTI
+            LDI 1
+            LDI1
+            ADDI
+            STRI 1
+            JUMP 0
+            LBL 1
+            LDI 0
+            LDIC 3
+            ALDI
+            WRI
+            FLN
+            LDI 0
+            ALEN
+            WRI
+            FLN
+            LDIC 2
+            ANEWS
+            DUPI
+            LDI1
+            SBNEW
+            LDSC \"ab\"
+            SBAPPS
+            LDSC \"c\"
+            SBAPPS
+            SBFINISH
+            ASTS
+            DUPI
+            LDI1
+            ALDS
+            WRS
+            LDI0
+            ALDS
+            WRS
+        ");
+        assert_eq!(output.unwrap(), "9\n4\nabc");
+
+        let err = run("
+            INIT 0 0 0 0
+            LDIC 2
+            ANEWB
+            LDIC 2
+            ALDB
+        ");
+        assert!(matches!(
+            err,
+            Err(RuntimeError::Array(ArrayError::Index(2, 2)))
+        ));
+        let err = run("
+            INIT 0 0 0 0
+            LDIC 2
+            ANEWB
+            LDI0
+            ALDR
+        ");
+        assert!(matches!(
+            err,
+            Err(RuntimeError::Array(ArrayError::InvalidHandle(
+                0,
+                Some(Kind::Real)
+            )))
+        ));
+    }
+
+    #[test]
+    fn test_number_conversion() {
+        let source = "
+            INIT 0 0 0 0
+            LDSC \" 42 \"
+            STR2INT
+            WRB
+            FLN
+            WRI
+            FLN
+            LDSC \"4x\"
+            STR2INT
+            WRB
+            FLN
+            WRI
+            FLN
+            LDSC \"2.5\"
+            STR2REAL
+            WRB
+            FLN
+            LDRC 2.0
+            MULR
+            REAL2STR
+            LDIC -7
+            INT2STR
+            WRS
+            WRS
+        ";
+        let data = crate::asm::assemble(source).unwrap();
+        let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        let mut output = vec![];
+        let stat = run_program_with_io(prog, mem, str_mem, &config, &mut io::empty(), &mut output);
+        assert!(stat.is_ok());
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "true\n42\nfalse\n0\ntrue\n-75"
+        );
+    }
+
+    #[test]
+    fn test_transform() {
+        let source = "
+            INIT 0 0 0 0
+            LDSC \"  Città \\n\"
+            DUPS
+            TRIM
+            UPPER
+            WRS
+            FLN
+            LOWER
+            WRS
+        ";
+        let data = crate::asm::assemble(source).unwrap();
+        let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        let mut output = vec![];
+        let stat = run_program_with_io(prog, mem, str_mem, &config, &mut io::empty(), &mut output);
+        assert!(stat.is_ok());
+        assert_eq!(String::from_utf8(output).unwrap(), "CITTÀ\n  città \n");
+    }
+
+    #[test]
+    fn test_substring() {
+        let run = |source: &str| {
+            let data = crate::asm::assemble(source).unwrap();
+            let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+            let config = EngineConfig::new();
+            let mut output = vec![];
+            run_program_with_io(prog, mem, str_mem, &config, &mut io::empty(), &mut output)
+                .map(|_| String::from_utf8(output).unwrap())
+        };
+        let output = run("
+            INIT 0 0 0 0
+            LDSC \"città\"
+            LDIC 2
+            LDIC 3
+            SUBSTR
+            WRS
+            FLN
+            LDSC \"città\"
+            LDIC 4
+            CHARAT
+            WRI
+            FLN
+            LDSC \"città\"
+            LDIC 5
+            LDIC 0
+            SUBSTR
+            WRS
+        ");
+        assert_eq!(output.unwrap(), "ttà\n224\n");
+
+        let err = run("
+            INIT 0 0 0 0
+            LDSC \"abc\"
+            LDIC 1
+            LDIC 3
+            SUBSTR
+        ");
+        assert!(matches!(
+            err,
+            Err(RuntimeError::StringIndex {
+                position: 4,
+                length: 3
+            })
+        ));
+        let err = run("
+            INIT 0 0 0 0
+            LDSC \"abc\"
+            LDIC -1
+            CHARAT
+        ");
+        assert!(matches!(
+            err,
+            Err(RuntimeError::StringIndex {
+                position: -1,
+                length: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn test_rounding_casts() {
+        let cast = |value: &str, opcode: &str| {
+            let source = format!("INIT 0 0 0 0\nLDRC {}\n{}\nWRI\n", value, opcode);
+            let data = crate::asm::assemble(&source).unwrap();
+            let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+            let config = EngineConfig::new();
+            let mut output = vec![];
+            run_program_with_io(prog, mem, str_mem, &config, &mut io::empty(), &mut output)
+                .map(|_| String::from_utf8(output).unwrap())
+        };
+        let cases = [
+            ("-2.5", ["-2", "-3", "-2", "-2"]),
+            ("2.5", ["2", "2", "3", "2"]),
+            ("3.5", ["3", "3", "4", "4"]),
+            ("-0.7", ["0", "-1", "0", "-1"]),
+        ];
+        let opcodes = ["CSTI_TRUNC", "CSTI_FLOOR", "CSTI_CEIL", "CSTI_EVEN"];
+        for (value, expected) in cases.iter() {
+            for (opcode, expected) in opcodes.iter().zip(expected.iter()) {
+                assert_eq!(cast(value, opcode).unwrap(), *expected);
+            }
+        }
+        // in range once rounded down, not once rounded up
+        assert_eq!(cast("2147483647.5", "CSTI_FLOOR").unwrap(), "2147483647");
+        let err = cast("2147483647.5", "CSTI_CEIL").unwrap_err();
+        assert!(matches!(err, RuntimeError::InvalidCast(_)));
+        assert_eq!(err.code(), 14);
+    }
+
+    #[test]
+    fn test_abs_min_max() {
+        let source = "
+            INIT 0 0 0 0
+            LDIC -5
+            ABSI
+            LDIC 3
+            MINI
+            WRI
+            FLN
+            LDIC -5
+            LDIC 3
+            MAXI
+            WRI
+            FLN
+            LDRC -2.5
+            ABSR
+            LDRC 1.5
+            MAXR
+            LDRC 4.0
+            MINR
+            WRR
+            FLN
+            LDIC -2147483648
+            ABSI
+            WRI
+        ";
+        let data = crate::asm::assemble(source).unwrap();
+        let run = |semantics| {
+            let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+            let config = EngineConfig::new().integer_semantics(semantics);
+            let mut output = vec![];
+            let stat =
+                run_program_with_io(prog, mem, str_mem, &config, &mut io::empty(), &mut output);
+            (stat, String::from_utf8(output).unwrap())
+        };
+        let (stat, output) = run(IntegerSemantics::Wrapping);
+        assert!(stat.is_ok());
+        assert_eq!(output, "3\n3\n2.5\n-2147483648");
+        let (stat, _) = run(IntegerSemantics::Checked);
+        assert!(matches!(stat, Err(RuntimeError::IntegerOverflow)));
+    }
+
+    #[test]
+    fn test_intrinsics() {
+        let source = "
+            INIT 0 0 0 0
+            LDRC 16.0
+            SQRT
+            WRR
+            FLN
+            LDRC 0.0
+            SIN
+            WRR
+            FLN
+            LDRC 0.0
+            COS
+            WRR
+            FLN
+            LDRC 1.0
+            EXP
+            LOG
+            WRR
+            FLN
+            LDRC -1.0
+            SQRT
+            WRR
+        ";
+        let data = crate::asm::assemble(source).unwrap();
+        let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        let mut output = vec![];
+        let stat = run_program_with_io(prog, mem, str_mem, &config, &mut io::empty(), &mut output);
+        assert!(stat.is_ok());
+        assert_eq!(String::from_utf8(output).unwrap(), "4\n0\n1\n1\nNaN");
+    }
+
+    #[test]
+    fn test_bitwise() {
+        let source = "
+            INIT 0 0 0 0
+            LDIC 12
+            LDIC 10
+            ANDI
+            WRI
+            FLN
+            LDIC 12
+            LDIC 10
+            ORI
+            WRI
+            FLN
+            LDIC 12
+            LDIC 10
+            XORI
+            WRI
+            FLN
+            LDIC 0
+            NOTI
+            WRI
+            FLN
+            LDIC 1
+            LDIC 33
+            SHLI
+            WRI
+            FLN
+            LDIC -16
+            LDIC 2
+            SHRI
+            WRI
+            FLN
+        ";
+        let data = crate::asm::assemble(source).unwrap();
+        let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        let mut output = vec![];
+        let stat = run_program_with_io(prog, mem, str_mem, &config, &mut io::empty(), &mut output);
+        assert!(stat.is_ok());
+        assert_eq!(String::from_utf8(output).unwrap(), "8\n14\n6\n-1\n2\n-4\n");
+    }
+
+    #[test]
+    fn test_power() {
+        let source = "
+            INIT 0 0 0 0
+            LDIC 3
+            LDIC 4
+            POWI
+            WRI
+            FLN
+            LDIC -1
+            LDIC -3
+            POWI
+            WRI
+            FLN
+            LDIC 2
+            LDIC -1
+            POWI
+            WRI
+            FLN
+            LDRC 2.0
+            LDRC 0.5
+            POWR
+            WRR
+            FLN
+            LDIC 2
+            LDIC 31
+            POWI
+            WRI
+        ";
+        let data = crate::asm::assemble(source).unwrap();
+        let run = |semantics| {
+            let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+            let config = EngineConfig::new().integer_semantics(semantics);
+            let mut output = vec![];
+            let stat =
+                run_program_with_io(prog, mem, str_mem, &config, &mut io::empty(), &mut output);
+            (stat, String::from_utf8(output).unwrap())
+        };
+        let lines = format!("81\n-1\n0\n{}\n", 2f64.sqrt());
+        let (stat, output) = run(IntegerSemantics::Wrapping);
+        assert!(stat.is_ok());
+        assert_eq!(output, lines + "-2147483648");
+        let (stat, _) = run(IntegerSemantics::Checked);
+        assert!(matches!(stat, Err(RuntimeError::IntegerOverflow)));
+        assert_eq!(negative_power(0, -2), None);
+    }
+
+    #[test]
+    fn test_modulo() {
+        let source = "
+            INIT 0 0 0 0
+            LDIC -7
+            LDIC 3
+            MODI
+            WRI
+            FLN
+            LDIC -2147483648
+            LDIC -1
+            MODI
+            WRI
+            FLN
+            LDRC 7.5
+            LDRC -2.0
+            MODR
+            WRR
+            FLN
+            LDIC 5
+            LDI0
+            MODI
+        ";
+        let data = crate::asm::assemble(source).unwrap();
+        for semantics in [IntegerSemantics::Wrapping, IntegerSemantics::Checked] {
+            let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+            let config = EngineConfig::new().integer_semantics(semantics);
+            let mut output = vec![];
+            let stat =
+                run_program_with_io(prog, mem, str_mem, &config, &mut io::empty(), &mut output);
+            assert!(matches!(stat, Err(RuntimeError::DivisionByZero)));
+            assert_eq!(output, b"-1\n0\n1.5\n");
+        }
+    }
+
+    #[test]
+    fn test_trace() {
+        let data = vec![
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::LDI1,
+            opcode::WRI,
+        ];
+        let (mut prog, mem, mut str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        let mut input = io::empty();
+        let mut trace = vec![];
+        let mut output = vec![];
+        Engine::new(&mut prog, &mem, &mut str_mem, &config, &mut input)
+            .unwrap()
+            .trace(&mut trace)
+            .run(&mut output)
+            .unwrap();
+        assert_eq!(output, b"1");
+        let trace = String::from_utf8(trace).unwrap();
+        assert_eq!(
+            trace,
+            "main body, instruction 0: LDIC 1\nmain body, instruction 1: WRI\n"
+        );
+
+        // the instruction using a string just read is traced too
+        let data = vec![
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::RDS,
+            opcode::WRS,
+        ];
+        let (mut prog, mem, mut str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let mut input = &b"ab\n"[..];
+        let mut trace = vec![];
+        let mut engine = Engine::new(&mut prog, &mem, &mut str_mem, &config, &mut input).unwrap();
+        engine = engine.trace(&mut trace);
+        engine.run(&mut io::sink()).unwrap();
+        assert_eq!(engine.executed(), 2);
+        drop(engine);
+        assert!(String::from_utf8(trace)
+            .unwrap()
+            .ends_with("instruction 1: WRS\n"));
+    }
+
+    #[test]
+    fn test_audit_init() {
+        let code = vec![opcode::LDI, 0, 0, opcode::WRI];
+        assert_eq!(run(code.clone(), EngineConfig::new()), "0");
+        let stat = try_run(code, EngineConfig::new().audit_init(true));
+        assert!(matches!(
+            stat,
+            Err(RuntimeError::UninitializedRead(Kind::Integer, 0))
+        ));
+
+        let code = vec![
+            opcode::RDI,
+            opcode::STRI,
+            0,
+            0,
+            opcode::LDI,
+            0,
+            0,
+            opcode::WRI,
+        ];
+        let output = run(code, EngineConfig::new().audit_init(true));
+        assert_eq!(output, "12");
+    }
+
+    #[test]
+    fn test_compact_stacks() {
+        // 64 integers on the stack, then back to none
+        let mut data = vec![opcode::INIT, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.extend(vec![opcode::LDI1; 64]);
+        data.extend(vec![opcode::ADDI; 63]);
+        data.push(opcode::WRI);
+        let (mut prog, mem, mut str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let mut run = |config: &EngineConfig, str_mem: &mut StringMemory| {
+            let mut input = io::empty();
+            let mut engine = Engine::new(&mut prog, &mem, str_mem, config, &mut input).unwrap();
+            engine.run(&mut io::sink()).unwrap();
+            engine.stack_stats()
+        };
+        let stats = run(&EngineConfig::new(), &mut str_mem);
+        assert_eq!(stats.capacity, stats.peak_capacity);
+        assert_eq!(stats.compactions, 0);
+
+        let config = EngineConfig::new().compact_period(Some(1));
+        let stats = run(&config, &mut str_mem);
+        // compacted before WRI popped the last integer
+        assert_eq!(stats.capacity, size_of::<i32>());
+        assert!(stats.peak_capacity >= 64 * size_of::<i32>());
+        assert_eq!(stats.compactions, 128);
     }
 }
@@ -1,5 +1,6 @@
 use crate::command_definition::ForControl;
 
+#[derive(Default)]
 pub struct ForLoopStack {
     stack: Vec<i32>,
 }
@@ -9,6 +10,16 @@ impl ForLoopStack {
         Self { stack: Vec::new() }
     }
 
+    // loops open at the moment
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    // counters of the open loops, outermost first
+    pub fn counters(&self) -> &[i32] {
+        &self.stack
+    }
+
     pub fn process_command(&mut self, ctrl: &ForControl, int_stack: &mut Vec<i32>) {
         match ctrl {
             ForControl::Check => self.process_check(int_stack),
@@ -0,0 +1,398 @@
+use crate::asm::assemble;
+use crate::command_definition::{Program, ProgramMemory};
+use crate::engine::{Engine, EngineConfig, Quota};
+use crate::program_load::{parse_data, LoadOptions};
+use crate::string_memory::StringMemory;
+use std::any::Any;
+use std::fmt::{self, Write};
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Duration;
+
+// Random programs for the engine itself. The generator writes
+// assembler source that is valid by construction: typed stacks stay
+// balanced, addresses fit the declared memories, calls follow the
+// PARAM STRxP CALL protocol and every jump has its label. Each
+// program runs under tight quotas and must end with a result or a
+// runtime error, never with a panic, with its memory usage close
+// to the memory quota at every step.
+
+pub const FUZZ_INSTRUCTIONS: u64 = 20_000;
+pub const FUZZ_MEMORY: usize = 64 * 1024;
+
+// memory is checked against the quota once every 1024
+// instructions, each one can add a few bytes in between
+const MEMORY_SLACK: usize = 1024 * 64;
+
+// lines read by the RDx instructions, some do not parse
+const FUZZ_INPUT: &str = "12\n-0.5\ntrue\nword\n\n2147483648\nnan\n";
+
+const SUFFIX: [&str; 4] = ["I", "R", "B", "S"];
+
+// statements nest at most this deep, expressions as well
+const MAX_NESTING: usize = 3;
+
+// xorshift64*, enough to spread the programs around
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+// memory counts per kind, in the INIT order
+type Counts = [u16; 4];
+
+struct Generator {
+    rng: Rng,
+    globals: Counts,
+    functions: Vec<Counts>,
+    // locals of the function being written, none in the main body
+    locals: Option<Counts>,
+    labels: usize,
+    // between PARAM and CALL: no jumps, the record must be
+    // called in the basic block that creates it
+    in_call: bool,
+    source: String,
+}
+
+pub fn random_program(seed: u64) -> String {
+    let mut rng = Rng::new(seed);
+    let globals = [1, 1, 1, 1].map(|min| min + rng.below(3) as u16);
+    let functions = (0..rng.below(4))
+        .map(|_| [0, 0, 0, 0].map(|_| rng.below(3) as u16))
+        .collect();
+    let mut gen = Generator {
+        rng,
+        globals,
+        functions,
+        locals: None,
+        labels: 0,
+        in_call: false,
+        source: String::new(),
+    };
+    gen.init(globals);
+    gen.statements(0);
+    if gen.rng.below(4) == 0 {
+        gen.line("EXT");
+    }
+    for func in 0..gen.functions.len() {
+        let locals = gen.functions[func];
+        gen.locals = Some(locals);
+        gen.line("FUNC");
+        gen.init(locals);
+        gen.statements(0);
+        gen.line("RET");
+    }
+    gen.source
+}
+
+impl Generator {
+    fn line(&mut self, text: &str) {
+        writeln!(self.source, "{}", text).unwrap();
+    }
+
+    fn init(&mut self, counts: Counts) {
+        let [i, r, b, s] = counts;
+        self.line(&format!("INIT {} {} {} {}", i, r, b, s));
+    }
+
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!("l{}", self.labels)
+    }
+
+    fn statements(&mut self, nesting: usize) {
+        for _ in 0..1 + self.rng.below(6) {
+            self.statement(nesting);
+        }
+    }
+
+    fn statement(&mut self, nesting: usize) {
+        let kind = self.rng.below(4);
+        match self.rng.below(8) {
+            0 | 1 => {
+                self.expression(kind, 0);
+                let addr = self.address(kind);
+                self.line(&format!("STR{} {}", SUFFIX[kind], addr));
+            }
+            2 => {
+                self.expression(kind, 0);
+                self.line(&format!("WR{}", SUFFIX[kind]));
+                if self.rng.below(2) == 0 {
+                    self.line("FLN");
+                }
+            }
+            3 => {
+                let kinds: Vec<usize> = (0..1 + self.rng.below(3))
+                    .map(|_| self.rng.below(4))
+                    .collect();
+                for kind in &kinds {
+                    self.expression(*kind, 0);
+                }
+                let kinds: Vec<&str> = kinds.iter().map(|k| SUFFIX[*k]).collect();
+                self.line(&format!("WRVN {}", kinds.join(" ")));
+            }
+            4 => {
+                self.line(&format!("RD{}", SUFFIX[kind]));
+                let addr = self.address(kind);
+                self.line(&format!("STR{} {}", SUFFIX[kind], addr));
+            }
+            5 if !self.functions.is_empty() => self.call(),
+            6 if nesting < MAX_NESTING => {
+                let end = self.label();
+                self.expression(2, 0);
+                self.line(&format!("JNE {}", end));
+                self.statements(nesting + 1);
+                self.line(&format!("{}:", end));
+            }
+            7 if nesting < MAX_NESTING => {
+                let begin = self.label();
+                self.line(&format!("{}:", begin));
+                self.statements(nesting + 1);
+                self.expression(2, 0);
+                self.line(&format!("JEQ {}", begin));
+            }
+            _ => {
+                self.expression(kind, 0);
+                self.line(&format!("WR{}", SUFFIX[kind]));
+            }
+        }
+    }
+
+    fn call(&mut self) {
+        let func = self.rng.below(self.functions.len());
+        self.line(&format!("PARAM {}", func));
+        let callee = self.functions[func];
+        self.in_call = true;
+        for (kind, count) in callee.iter().enumerate() {
+            for addr in 0..*count {
+                if self.rng.below(2) == 0 {
+                    self.expression(kind, 0);
+                    self.line(&format!("STR{}P local {}", SUFFIX[kind], addr));
+                }
+            }
+        }
+        self.in_call = false;
+        self.line(&format!("CALL {}", func));
+    }
+
+    // a global or, inside a function, a local of `kind`
+    fn address(&mut self, kind: usize) -> String {
+        match self.locals {
+            Some(locals) if locals[kind] > 0 && self.rng.below(2) == 0 => {
+                format!("local {}", self.rng.below(locals[kind] as usize))
+            }
+            _ => format!("{}", self.rng.below(self.globals[kind] as usize)),
+        }
+    }
+
+    // push one value of `kind`
+    fn expression(&mut self, kind: usize, depth: usize) {
+        let leaf = depth >= MAX_NESTING || self.rng.below(3) == 0;
+        match (kind, leaf) {
+            (0, true) => match self.rng.below(3) {
+                0 => {
+                    let value = *self.rng.pick(&[0, 1, -1, 7, -13, i32::MAX, i32::MIN]);
+                    self.line(&format!("LDIC {}", value));
+                }
+                1 => {
+                    let name = self.rng.pick(&["LDI0", "LDI1"]).to_string();
+                    self.line(&name);
+                }
+                _ => self.load(0),
+            },
+            (1, true) => match self.rng.below(2) {
+                0 => {
+                    let value = *self.rng.pick(&[0.0, 0.5, -2.25, 1e300, -1e-300]);
+                    self.line(&format!("LDRC {:?}", value));
+                }
+                _ => self.load(1),
+            },
+            (2, true) => match self.rng.below(3) {
+                0 => {
+                    let name = self.rng.pick(&["LDTRUE", "LDFALSE"]).to_string();
+                    self.line(&name);
+                }
+                1 => {
+                    let value = *self.rng.pick(&[true, false]);
+                    self.line(&format!("LDBC {}", value));
+                }
+                _ => self.load(2),
+            },
+            (3, _) if leaf || self.in_call => match self.rng.below(2) {
+                0 => {
+                    let value = *self
+                        .rng
+                        .pick(&["", "a", "fuzz", "two\\nlines", "\\\"q\\\""]);
+                    self.line(&format!("LDSC \"{}\"", value));
+                }
+                _ => self.load(3),
+            },
+            (0, false) | (1, false) => match self.rng.below(4) {
+                0 => {
+                    self.expression(kind, depth + 1);
+                    self.line(&format!("NEG{}", SUFFIX[kind]));
+                }
+                1 => {
+                    self.expression(1 - kind, depth + 1);
+                    self.line(if kind == 0 { "CSTI" } else { "CSTR" });
+                }
+                2 => {
+                    self.expression(kind, depth + 1);
+                    self.line(&format!("DUP{}", SUFFIX[kind]));
+                    self.arithmetic(kind);
+                }
+                _ => {
+                    self.expression(kind, depth + 1);
+                    self.expression(kind, depth + 1);
+                    self.arithmetic(kind);
+                }
+            },
+            (2, false) => match self.rng.below(2) {
+                0 => {
+                    self.expression(2, depth + 1);
+                    self.line("NOT");
+                }
+                _ => {
+                    let operand = self.rng.below(4);
+                    self.expression(operand, depth + 1);
+                    self.expression(operand, depth + 1);
+                    let op = self.rng.pick(&["GEQ", "GR", "LEQ", "LESQ", "EQ", "NE"]);
+                    self.line(&format!("{}{}", op, SUFFIX[operand]));
+                }
+            },
+            _ => {
+                self.expression(3, depth + 1);
+                self.expression(3, depth + 1);
+                let (then, end) = (self.label(), self.label());
+                self.line("EQS");
+                self.line(&format!("JEQ {}", then));
+                self.line("LDSC \"else\"");
+                self.line(&format!("JUMP {}", end));
+                self.line(&format!("{}:", then));
+                self.line("LDSC \"then\"");
+                self.line(&format!("{}:", end));
+            }
+        }
+    }
+
+    fn arithmetic(&mut self, kind: usize) {
+        let op = self.rng.pick(&["ADD", "SUB", "MUL", "DIV"]);
+        self.line(&format!("{}{}", op, SUFFIX[kind]));
+    }
+
+    fn load(&mut self, kind: usize) {
+        let addr = self.address(kind);
+        self.line(&format!("LD{} {}", SUFFIX[kind], addr));
+    }
+}
+
+#[derive(Debug)]
+pub struct FuzzFailure {
+    pub seed: u64,
+    pub source: String,
+    pub reason: String,
+}
+
+impl fmt::Display for FuzzFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "seed {}: {}\n{}", self.seed, self.reason, self.source)
+    }
+}
+
+// generate the program of `seed` and run it, runtime errors are a
+// fine outcome: only panics and memory past the quota are failures
+pub fn run_case(seed: u64) -> Result<(), FuzzFailure> {
+    let source = random_program(seed);
+    let fail = |reason| FuzzFailure {
+        seed,
+        source: source.clone(),
+        reason,
+    };
+    let data = assemble(&source).map_err(|err| fail(format!("cannot assemble: {}", err)))?;
+    let (mut prog, prog_mem, mut str_mem, _) = parse_data(&data, &LoadOptions::default())
+        .map_err(|err| fail(format!("cannot load: {}", err)))?;
+    let quota = Quota {
+        instructions: Some(FUZZ_INSTRUCTIONS),
+        time: Some(Duration::from_secs(1)),
+        memory: Some(FUZZ_MEMORY),
+        ..Quota::default()
+    };
+    let config = EngineConfig::new().quota(quota);
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+        execute(&mut prog, &prog_mem, &mut str_mem, &config)
+    }));
+    match outcome {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(reason)) => Err(fail(reason)),
+        Err(payload) => Err(fail(format!(
+            "the engine panicked: {}",
+            panic_message(payload)
+        ))),
+    }
+}
+
+// the text given to `panic!`, when there is one
+pub fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|msg| msg.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_default()
+}
+
+fn execute(
+    prog: &mut Program,
+    prog_mem: &ProgramMemory,
+    str_mem: &mut StringMemory,
+    config: &EngineConfig,
+) -> Result<(), String> {
+    let mut input = io::Cursor::new(FUZZ_INPUT);
+    let mut engine = match Engine::new(prog, prog_mem, str_mem, config, &mut input) {
+        Ok(engine) => engine,
+        Err(_) => return Ok(()),
+    };
+    let mut out = io::sink();
+    while let Ok(true) = engine.step(&mut out) {
+        let usage = engine.memory_usage();
+        if usage > FUZZ_MEMORY + MEMORY_SLACK {
+            return Err(format!(
+                "{} bytes in use, the quota is {}",
+                usage, FUZZ_MEMORY
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_random_programs() {
+        for seed in 0..100 {
+            if let Err(failure) = run_case(seed) {
+                panic!("{}", failure);
+            }
+        }
+    }
+}
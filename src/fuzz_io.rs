@@ -0,0 +1,282 @@
+use crate::command_definition::{Program, ProgramMemory};
+use crate::engine::{Engine, EngineConfig, QuotaKind, RuntimeError};
+use crate::fuzz::{panic_message, Rng};
+use crate::string_memory::StringMemory;
+use std::fmt;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+
+// Random inputs for a single program. The spec lists the values
+// the program reads, in order, with their kind and range:
+//
+//   i:-10..10   integer between -10 and 10, both included
+//   r:0..1      real between 0 and 1
+//   b           true or false
+//   s:1..8      string of 1 to 8 lowercase letters
+//
+// ranges are optional and `*n` repeats a field n times. Every value
+// goes on its own line. Runs ending with a panic of the engine, a
+// time or instruction quota or a runtime error are failures, the
+// latter is also how compiled programs report a failed assertion.
+
+const INTEGER_RANGE: (i32, i32) = (-1000, 1000);
+const REAL_RANGE: (f64, f64) = (-1000.0, 1000.0);
+const STRING_LENGTH: (usize, usize) = (0, 16);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    Integer(i32, i32),
+    Real(f64, f64),
+    Bool,
+    // length range
+    Str(usize, usize),
+}
+
+impl Field {
+    fn generate(&self, rng: &mut Rng) -> String {
+        match self {
+            Self::Integer(min, max) => {
+                let span = (*max as i64 - *min as i64 + 1) as usize;
+                (*min as i64 + rng.below(span) as i64).to_string()
+            }
+            Self::Real(min, max) => {
+                // 53 random bits, uniform in [0, 1)
+                let unit = (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+                (min + unit * (max - min)).to_string()
+            }
+            Self::Bool => (rng.below(2) == 1).to_string(),
+            Self::Str(min, max) => {
+                let len = min + rng.below(max - min + 1);
+                (0..len)
+                    .map(|_| (b'a' + rng.below(26) as u8) as char)
+                    .collect()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputSpec {
+    // repetitions already expanded
+    pub fields: Vec<Field>,
+}
+
+impl InputSpec {
+    // one input set, a value per line
+    pub fn generate(&self, rng: &mut Rng) -> String {
+        self.fields
+            .iter()
+            .map(|field| field.generate(rng) + "\n")
+            .collect()
+    }
+}
+
+impl FromStr for InputSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = vec![];
+        for token in s.split_whitespace() {
+            let (field, count) = match token.split_once('*') {
+                Some((field, count)) => {
+                    let count = count
+                        .parse()
+                        .map_err(|_| format!("`{}`: `{}` is not a repeat count", token, count))?;
+                    (field, count)
+                }
+                None => (token, 1),
+            };
+            let field = parse_field(field).map_err(|err| format!("`{}`: {}", token, err))?;
+            fields.extend(std::iter::repeat_n(field, count));
+        }
+        Ok(Self { fields })
+    }
+}
+
+fn parse_field(field: &str) -> Result<Field, String> {
+    let (kind, range) = match field.split_once(':') {
+        Some((kind, range)) => (kind, Some(range)),
+        None => (field, None),
+    };
+    match (kind, range) {
+        ("i", range) => {
+            let (min, max) = parse_range(range, INTEGER_RANGE)?;
+            Ok(Field::Integer(min, max))
+        }
+        ("r", range) => {
+            let (min, max) = parse_range(range, REAL_RANGE)?;
+            if !(min.is_finite() && max.is_finite()) {
+                return Err("real bounds must be finite".to_owned());
+            }
+            Ok(Field::Real(min, max))
+        }
+        ("b", None) => Ok(Field::Bool),
+        ("b", Some(_)) => Err("booleans take no range".to_owned()),
+        ("s", range) => {
+            let (min, max) = parse_range(range, STRING_LENGTH)?;
+            Ok(Field::Str(min, max))
+        }
+        _ => Err(format!("`{}` is not one of i, r, b and s", kind)),
+    }
+}
+
+fn parse_range<T>(range: Option<&str>, default: (T, T)) -> Result<(T, T), String>
+where
+    T: FromStr + PartialOrd + fmt::Display,
+{
+    let range = match range {
+        Some(range) => range,
+        None => return Ok(default),
+    };
+    let (min, max) = range
+        .split_once("..")
+        .ok_or_else(|| format!("`{}` is not in the MIN..MAX form", range))?;
+    let bound = |s: &str| {
+        s.parse::<T>()
+            .map_err(|_| format!("`{}` is not a valid bound", s))
+    };
+    let (min, max) = (bound(min)?, bound(max)?);
+    if min > max {
+        return Err(format!("{} is greater than {}", min, max));
+    }
+    Ok((min, max))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Timeout,
+    Error(String),
+    Crash(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Passed => write!(f, "passed"),
+            Self::Timeout => write!(f, "timed out"),
+            Self::Error(err) => write!(f, "runtime error: {}", err),
+            Self::Crash(msg) => write!(f, "the engine panicked: {}", msg),
+        }
+    }
+}
+
+pub struct Failure {
+    pub run: u64,
+    pub input: String,
+    pub outcome: Outcome,
+}
+
+// run the program on `runs` input sets, all drawn from `seed`
+pub fn fuzz(
+    prog: &mut Program,
+    prog_mem: &ProgramMemory,
+    str_mem: &mut StringMemory,
+    config: &EngineConfig,
+    spec: &InputSpec,
+    runs: u64,
+    seed: u64,
+) -> Vec<Failure> {
+    let mut rng = Rng::new(seed);
+    (0..runs)
+        .filter_map(|run| {
+            let input = spec.generate(&mut rng);
+            let outcome = run_once(prog, prog_mem, str_mem, config, &input);
+            match outcome {
+                Outcome::Passed => None,
+                outcome => Some(Failure {
+                    run,
+                    input,
+                    outcome,
+                }),
+            }
+        })
+        .collect()
+}
+
+pub fn run_once(
+    prog: &mut Program,
+    prog_mem: &ProgramMemory,
+    str_mem: &mut StringMemory,
+    config: &EngineConfig,
+    input: &str,
+) -> Outcome {
+    str_mem.reset();
+    let mut in_stream = input.as_bytes();
+    let status = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut engine = Engine::new(prog, prog_mem, str_mem, config, &mut in_stream)?;
+        engine.run(&mut io::sink())
+    }));
+    match status {
+        Ok(Ok(())) => Outcome::Passed,
+        Ok(Err(err)) => match err.primary() {
+            RuntimeError::QuotaExceeded(QuotaKind::Time(_))
+            | RuntimeError::QuotaExceeded(QuotaKind::Instructions(_)) => Outcome::Timeout,
+            _ => Outcome::Error(err.to_string()),
+        },
+        Err(payload) => Outcome::Crash(panic_message(payload)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::asm::assemble;
+    use crate::engine::Quota;
+    use crate::program_load::{parse_data, LoadOptions};
+
+    #[test]
+    fn test_fuzz_inputs() {
+        let spec: InputSpec = "i:1..3 r:0..1 b s:2..2*2".parse().unwrap();
+        assert_eq!(spec.fields.len(), 5);
+        assert_eq!(spec.fields[4], Field::Str(2, 2));
+        let input = spec.generate(&mut Rng::new(7));
+        let lines: Vec<&str> = input.lines().collect();
+        assert!((1..=3).contains(&lines[0].parse::<i32>().unwrap()));
+        assert!((0.0..1.0).contains(&lines[1].parse::<f64>().unwrap()));
+        assert!(lines[2] == "true" || lines[2] == "false");
+        assert_eq!(lines[3].len(), 2);
+        assert!("i:3..1".parse::<InputSpec>().is_err());
+        assert!("x".parse::<InputSpec>().is_err());
+        assert!("b:0..1".parse::<InputSpec>().is_err());
+
+        // 10 / (n - 2) fails on one of the three values, loops on another
+        let source = "
+            INIT 1 0 0 0
+            RDI
+            STRI 0
+            LDI 0
+            LDIC 3
+            EQI
+            JNE 1
+            LBL 0
+            JUMP 0
+            LBL 1
+            LDIC 10
+            LDI 0
+            LDIC 2
+            SUBI
+            DIVI
+            WRI
+        ";
+        let data = assemble(source).unwrap();
+        let (mut prog, prog_mem, mut str_mem, _) =
+            parse_data(&data, &LoadOptions::default()).unwrap();
+        let quota = Quota {
+            instructions: Some(10_000),
+            ..Quota::default()
+        };
+        let config = EngineConfig::new().quota(quota);
+        let spec = "i:1..3".parse().unwrap();
+        let failures = fuzz(&mut prog, &prog_mem, &mut str_mem, &config, &spec, 30, 0);
+        assert!(failures.iter().all(|failure| match failure.input.as_str() {
+            "2\n" => failure.outcome == Outcome::Error("integer division by zero".to_owned()),
+            "3\n" => failure.outcome == Outcome::Timeout,
+            _ => false,
+        }));
+        assert!(failures.iter().any(|f| f.outcome == Outcome::Timeout));
+        assert!(failures.len() < 30);
+    }
+}
@@ -0,0 +1,134 @@
+use crate::array_heap::{Array, ArrayHeap};
+use crate::string_memory::StringMemory;
+use std::collections::HashSet;
+
+// Mark and sweep collection of the values created at run time: the
+// strings in the string memory and the arrays in the array heap. A
+// collection marks what the roots reach, that is the stacks, the
+// global memory and the activation records, and sweeps the rest.
+// Integers do not say whether they are array handles, so any root
+// integer equal to the handle of a live array keeps that array.
+
+// heap size that triggers the first collection
+pub const MIN_THRESHOLD: usize = 1 << 20;
+
+// values the running program can still reach
+#[derive(Debug, Default)]
+pub struct Roots {
+    // indexes in the string memory
+    pub strings: Vec<usize>,
+    pub integers: Vec<i32>,
+}
+
+#[derive(Debug)]
+pub struct Collector {
+    // heap size that triggers the next collection
+    threshold: usize,
+    collections: u64,
+}
+
+impl Collector {
+    pub fn new() -> Self {
+        Self {
+            threshold: MIN_THRESHOLD,
+            collections: 0,
+        }
+    }
+
+    pub fn collections(&self) -> u64 {
+        self.collections
+    }
+
+    pub fn is_due(&self, str_mem: &StringMemory, arrays: &ArrayHeap) -> bool {
+        heap_bytes(str_mem, arrays) >= self.threshold
+    }
+
+    // the next collection waits for the heap to double
+    pub fn collect(&mut self, roots: Roots, str_mem: &mut StringMemory, arrays: &mut ArrayHeap) {
+        let (strings, handles) = mark(roots, arrays);
+        str_mem.sweep(&strings);
+        arrays.sweep(&handles);
+        self.collections += 1;
+        self.threshold = MIN_THRESHOLD.max(2 * heap_bytes(str_mem, arrays));
+    }
+}
+
+impl Default for Collector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// the strings and the array handles reachable from `roots`
+pub fn mark(roots: Roots, arrays: &ArrayHeap) -> (HashSet<usize>, HashSet<i32>) {
+    let mut strings: HashSet<usize> = roots.strings.into_iter().collect();
+    let mut handles = HashSet::new();
+    let mut pending = roots.integers;
+    while let Some(handle) = pending.pop() {
+        let array = match arrays.get(handle) {
+            Ok(array) => array,
+            Err(_) => continue,
+        };
+        if !handles.insert(handle) {
+            continue;
+        }
+        match array {
+            Array::Integer(values) => pending.extend(values),
+            Array::Str(values) => strings.extend(values),
+            Array::Real(_) | Array::Bool(_) => {}
+        }
+    }
+    (strings, handles)
+}
+
+fn heap_bytes(str_mem: &StringMemory, arrays: &ArrayHeap) -> usize {
+    str_mem.dynamic_footprint() + arrays.bytes()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::array_heap::ARRAY_OVERHEAD;
+    use crate::command_definition::Kind;
+
+    #[test]
+    fn test_collect() {
+        let mut str_mem = StringMemory::new();
+        let mut arrays = ArrayHeap::new();
+        let kept = str_mem.insert_string("kept".to_owned());
+        str_mem.insert_string("dropped".to_owned());
+        // the string array reachable from the integer array
+        let outer = arrays.allocate(Kind::Integer, 1).unwrap();
+        let inner = arrays.allocate(Kind::Str, 1).unwrap();
+        let lost = arrays.allocate(Kind::Real, 4).unwrap();
+        if let Ok(Array::Integer(values)) = arrays.typed_mut(outer, Kind::Integer) {
+            values[0] = inner;
+        }
+        if let Ok(Array::Str(values)) = arrays.typed_mut(inner, Kind::Str) {
+            values[0] = kept;
+        }
+
+        let mut collector = Collector::new();
+        assert!(!collector.is_due(&str_mem, &arrays));
+        let roots = Roots {
+            strings: vec![],
+            integers: vec![outer, -1],
+        };
+        collector.collect(roots, &mut str_mem, &mut arrays);
+        assert_eq!(collector.collections(), 1);
+        assert_eq!(str_mem.dynamic_count(), 1);
+        assert_eq!(str_mem.get_string(kept), "kept");
+        assert!(arrays.get(inner).is_ok());
+        assert!(arrays.get(lost).is_err());
+
+        collector.collect(Roots::default(), &mut str_mem, &mut arrays);
+        assert_eq!((str_mem.dynamic_count(), arrays.bytes()), (0, 0));
+
+        // empty arrays fill the heap with their overhead
+        for _ in 0..=MIN_THRESHOLD / ARRAY_OVERHEAD {
+            arrays.allocate(Kind::Integer, 0).unwrap();
+        }
+        assert!(collector.is_due(&str_mem, &arrays));
+    }
+}
@@ -0,0 +1,126 @@
+use crate::opcode::{Instruction, Operands, ISA};
+use serde::Serialize;
+use std::fmt::Write;
+
+// Instruction set reference generated from opcode::ISA, so it
+// follows the table the assembler uses. Available as a Markdown
+// document for people and as JSON for tools.
+
+#[derive(Serialize)]
+struct Entry {
+    mnemonic: &'static str,
+    opcode: u8,
+    operands: &'static str,
+    encoding: &'static str,
+    stack: &'static str,
+    description: &'static str,
+}
+
+// assembler syntax and binary encoding of the operands
+fn operands(operands: Operands) -> (&'static str, &'static str) {
+    match operands {
+        Operands::Nothing => ("", ""),
+        Operands::Address => ("address", "u16, the high bit selects the local memory"),
+        Operands::Label => ("label", "u16 label number"),
+        Operands::Function => ("function", "u16 function id"),
+        Operands::Integer => ("integer", "i32"),
+        Operands::Real => ("real", "f64"),
+        Operands::Bool => ("boolean", "u8, 0 for false and 255 for true"),
+        Operands::Str => ("string", "u16 length and the UTF-8 bytes"),
+        Operands::Offset => ("offset", "i16, counted in instructions from the jump"),
+        Operands::Init => ("integers reals booleans strings", "four u16 counts"),
+        Operands::Kinds => (
+            "kinds...",
+            "u8 count, then two bits per kind packed four per byte, low bits first",
+        ),
+        Operands::Pure => (
+            "function kinds...",
+            "u16 function id, then the kinds as in WRVN",
+        ),
+        Operands::Args => (
+            "(kind address)...",
+            "u8 count, then a u8 kind and a u16 address for each argument",
+        ),
+        Operands::Meta => (
+            "(key value)...",
+            "u16 pair count, then every key and value as strings",
+        ),
+    }
+}
+
+fn entry(instr: &Instruction) -> Entry {
+    let (syntax, encoding) = operands(instr.operands);
+    Entry {
+        mnemonic: instr.name,
+        opcode: instr.byte,
+        operands: syntax,
+        encoding,
+        stack: instr.stack,
+        description: instr.description,
+    }
+}
+
+pub fn json() -> String {
+    let entries: Vec<Entry> = ISA.iter().map(entry).collect();
+    serde_json::to_string_pretty(&entries).unwrap()
+}
+
+pub fn markdown() -> String {
+    let mut output = String::from(
+        "# Simpla instruction set\n\n\
+         Stack effects list the values popped, then `--`, then the values\n\
+         pushed, rightmost on top: `i` integer, `r` real, `b` boolean, `s` string.\n\n\
+         | Mnemonic | Opcode | Operands | Stack | Description |\n\
+         |---|---|---|---|---|\n",
+    );
+    for instr in ISA {
+        let entry = entry(instr);
+        writeln!(
+            output,
+            "| `{}` | {} | {} | `{}` | {} |",
+            entry.mnemonic, entry.opcode, entry.operands, entry.stack, entry.description
+        )
+        .unwrap();
+    }
+    output.push_str("\n## Operands\n\n| Operands | Encoding |\n|---|---|\n");
+    let mut seen = Vec::new();
+    for instr in ISA {
+        let (syntax, encoding) = operands(instr.operands);
+        if !syntax.is_empty() && !seen.contains(&syntax) {
+            seen.push(syntax);
+            writeln!(output, "| {} | {} |", syntax, encoding).unwrap();
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::opcode;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_isa_table() {
+        let mut names = HashSet::new();
+        let mut bytes = HashSet::new();
+        for instr in ISA {
+            assert!(names.insert(instr.name), "{} listed twice", instr.name);
+            assert!(bytes.insert(instr.byte), "{} listed twice", instr.byte);
+            assert!(!opcode::is_reserved(instr.byte));
+            assert!(instr.stack.contains("--"), "{}", instr.name);
+        }
+        // everything but the header and the reserved slots
+        let reserved: usize = opcode::RESERVED
+            .iter()
+            .map(|(first, last)| (last - first + 1) as usize)
+            .sum();
+        assert_eq!(ISA.len() + reserved + 1, opcode::ALEN as usize + 1);
+
+        let reference = markdown();
+        assert!(reference.contains("| `LDIC` | 51 | integer | `-- i` | load an integer constant |"));
+        let entries: serde_json::Value = serde_json::from_str(&json()).unwrap();
+        assert_eq!(entries.as_array().unwrap().len(), ISA.len());
+    }
+}
@@ -0,0 +1,52 @@
+// The Simpla engine as a library: load a bytecode file with
+// `load_program` and run it with `run_program`, or on any other
+// streams with `run_program_with_io`. The command line tool in
+// main.rs is built on top of this.
+
+#[cfg(feature = "alloc-stats")]
+pub mod alloc_stats;
+pub mod analysis;
+pub mod array_heap;
+pub mod asm;
+pub mod batch;
+pub mod bench;
+pub mod builder;
+pub mod check;
+pub mod command_definition;
+pub mod control;
+pub mod cpu_clock;
+pub mod crash_dump;
+pub mod debugger;
+pub mod difftest;
+pub mod disasm;
+pub mod engine;
+pub mod for_loop_stack;
+pub mod fuzz;
+pub mod fuzz_io;
+pub mod heap;
+pub mod isa;
+pub mod line_reader;
+pub mod livelock;
+pub mod memo;
+pub mod opcode;
+pub mod optimizer;
+pub mod profiler;
+pub mod program_load;
+pub mod program_store;
+#[cfg(feature = "register-ir")]
+pub mod register_ir;
+pub mod report;
+pub mod semantics;
+pub mod stress;
+pub mod string_memory;
+pub mod timer;
+pub mod trace;
+pub mod transcript;
+pub mod verify;
+
+pub use command_definition::*;
+pub use engine::{
+    run_program, run_program_with_io, Engine, EngineConfig, LoadedProgram, MemorySnapshot,
+    RuntimeError,
+};
+pub use program_load::{load_program, parse_data, LoadError, LoadOptions, LoadWarning};
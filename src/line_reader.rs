@@ -1,5 +1,5 @@
 use std::fmt;
-use std::io::{self, BufRead, Error};
+use std::io::{BufRead, Error, Read, Write};
 use std::str::FromStr;
 
 #[derive(Debug)]
@@ -8,7 +8,7 @@ pub enum ReadError {
     IntParseError(String),
     RealParseError(String),
     BoolParseError(String),
-    EOF,
+    Eof,
 }
 
 impl fmt::Display for ReadError {
@@ -18,11 +18,21 @@ impl fmt::Display for ReadError {
             Self::IntParseError(err) => write!(f, "{}", parse_error_mgs(err, "integer")),
             Self::RealParseError(err) => write!(f, "{}", parse_error_mgs(err, "real")),
             Self::BoolParseError(err) => write!(f, "{}", parse_error_mgs(err, "boolean")),
-            Self::EOF => write!(f, "STDIN reach EOF: no more input available"),
+            Self::Eof => write!(f, "STDIN reach EOF: no more input available"),
         }
     }
 }
 
+impl ReadError {
+    // the input was there, but not a value of the requested type
+    pub fn is_parse_error(&self) -> bool {
+        matches!(
+            self,
+            Self::IntParseError(_) | Self::RealParseError(_) | Self::BoolParseError(_)
+        )
+    }
+}
+
 fn parse_error_mgs(token: &str, expect: &str) -> String {
     format!(
         "Parse Error: `{}` cannot be converted into type {}",
@@ -48,7 +58,7 @@ enum ParseError<'a> {
 }
 
 impl<'a> ParseError<'a> {
-    fn to_read_error(self, k: Kind) -> ReadError {
+    fn into_read_error(self, k: Kind) -> ReadError {
         match self {
             Self::Parse(s) => match k {
                 Kind::Integer => ReadError::IntParseError(s.to_owned()),
@@ -66,15 +76,34 @@ impl<'a> From<Error> for ParseError<'a> {
     }
 }
 
-pub struct LineReader {
+pub struct LineReader<'a> {
+    input: &'a mut dyn BufRead,
     string_buff: StringBuffer,
+    // consumed tokens and lines waiting to be echoed
+    echo: Option<String>,
 }
 
-impl LineReader {
-    pub fn new() -> Self {
+impl<'a> LineReader<'a> {
+    pub fn new(input: &'a mut dyn BufRead, echo: bool) -> Self {
         Self {
+            input,
             string_buff: StringBuffer::new(),
+            echo: if echo { Some(String::new()) } else { None },
+        }
+    }
+
+    pub fn flush_echo(&mut self, out: &mut dyn Write) -> Result<(), Error> {
+        if let Some(echo) = &mut self.echo {
+            out.write_all(echo.as_bytes())?;
+            echo.clear();
         }
+        Ok(())
+    }
+
+    // drop what is left of the current line, the next
+    // read starts from a new one
+    pub fn discard_line(&mut self) {
+        self.string_buff.buff = None;
     }
 
     pub fn next_i32(&mut self) -> Result<i32, ReadError> {
@@ -93,13 +122,30 @@ impl LineReader {
         loop {
             let buff = self.string_buff.get_buffer();
             if let Some(buff) = buff {
+                if let Some(echo) = &mut self.echo {
+                    echo.push_str(&buff);
+                    echo.push('\n');
+                }
                 return Ok(buff);
             } else {
-                self.string_buff.read_from_stdin()?;
+                self.string_buff.read_from(self.input)?;
             }
         }
     }
 
+    // raw bytes start with what is left of the line already
+    // read for tokens, then come straight from the input: the
+    // result may be shorter than count only when the input
+    // reaches EOF
+    pub fn next_bytes(&mut self, count: usize) -> Result<Vec<u8>, ReadError> {
+        let mut buff = self.string_buff.take_bytes(count);
+        let missing = count - buff.len();
+        (&mut *self.input)
+            .take(missing as u64)
+            .read_to_end(&mut buff)?;
+        Ok(buff)
+    }
+
     fn next<T>(&mut self, k: Kind) -> Result<T, ReadError>
     where
         T: FromStr,
@@ -107,10 +153,14 @@ impl LineReader {
         loop {
             let token = self.string_buff.next_token();
             if let Some(token) = token {
+                if let Some(echo) = &mut self.echo {
+                    echo.push_str(token);
+                    echo.push('\n');
+                }
                 let res = parse_token(token);
                 return convert_result(res, k);
             } else {
-                self.string_buff.read_from_stdin()?;
+                self.string_buff.read_from(self.input)?;
             }
         }
     }
@@ -119,11 +169,11 @@ impl LineReader {
 fn convert_result<'a, T>(res: Result<T, ParseError<'a>>, k: Kind) -> Result<T, ReadError> {
     match res {
         Ok(t) => Ok(t),
-        Err(err) => Err(err.to_read_error(k)),
+        Err(err) => Err(err.into_read_error(k)),
     }
 }
 
-fn parse_token<T>(tok: &str) -> Result<T, ParseError>
+fn parse_token<T>(tok: &str) -> Result<T, ParseError<'_>>
 where
     T: FromStr,
 {
@@ -137,6 +187,8 @@ where
 struct StringBuffer {
     buff: Option<String>,
     begin: usize,
+    // the line ended with a newline, removed from buff
+    newline: bool,
 }
 
 impl StringBuffer {
@@ -145,6 +197,7 @@ impl StringBuffer {
         Self {
             buff: Some(s),
             begin: 0,
+            newline: false,
         }
     }
 
@@ -152,12 +205,16 @@ impl StringBuffer {
         Self {
             buff: None,
             begin: 0,
+            newline: false,
         }
     }
 
-    fn read_from_stdin(&mut self) -> Result<(), ReadError> {
-        let mut buff = get_line()?;
-        buff.pop();
+    fn read_from(&mut self, input: &mut dyn BufRead) -> Result<(), ReadError> {
+        let mut buff = get_line(input)?;
+        self.newline = buff.ends_with('\n');
+        if self.newline {
+            buff.pop();
+        }
         self.begin = 0;
         self.buff = Some(buff);
         Ok(())
@@ -166,7 +223,7 @@ impl StringBuffer {
     fn get_buffer(&mut self) -> Option<String> {
         let s = self.buff.take();
         if let Some(s) = s {
-            if s.len() == 0 || self.begin == 0 {
+            if s.is_empty() || self.begin == 0 {
                 Some(s)
             } else if self.begin == s.len() {
                 None
@@ -179,9 +236,33 @@ impl StringBuffer {
         }
     }
 
+    // up to count bytes of the rest of the line, newline included.
+    // A multi byte character split in two leaves a replacement
+    // character for the next token
+    fn take_bytes(&mut self, count: usize) -> Vec<u8> {
+        let s = match self.buff.take() {
+            Some(s) => s,
+            None => return Vec::new(),
+        };
+        let mut rest = s.as_bytes()[self.begin.min(s.len())..].to_vec();
+        if self.newline {
+            rest.push(b'\n');
+        }
+        if count >= rest.len() {
+            return rest;
+        }
+        let mut left = rest.split_off(count);
+        if self.newline {
+            left.pop();
+        }
+        self.buff = Some(String::from_utf8_lossy(&left).into_owned());
+        self.begin = 0;
+        rest
+    }
+
     fn next_token(&mut self) -> Option<&str> {
         if let Some(s) = &self.buff {
-            let (output, begin) = find_next_token(self.begin, &s)?;
+            let (output, begin) = find_next_token(self.begin, s)?;
             self.begin = begin;
             Some(output)
         } else {
@@ -190,7 +271,7 @@ impl StringBuffer {
     }
 }
 
-fn find_next_token<'a>(mut begin: usize, s: &'a str) -> Option<(&'a str, usize)> {
+fn find_next_token(mut begin: usize, s: &str) -> Option<(&str, usize)> {
     enum TokenState {
         Begin,
         Token,
@@ -231,13 +312,11 @@ fn find_next_token<'a>(mut begin: usize, s: &'a str) -> Option<(&'a str, usize)>
     }
 }
 
-fn get_line() -> Result<String, ReadError> {
-    let stdin = io::stdin();
-    let mut handle = stdin.lock();
+fn get_line(input: &mut dyn BufRead) -> Result<String, ReadError> {
     let mut buff = String::new();
-    let count = handle.read_line(&mut buff)?;
+    let count = input.read_line(&mut buff)?;
     if count == 0 {
-        Err(ReadError::EOF)
+        Err(ReadError::Eof)
     } else {
         Ok(buff)
     }
@@ -276,4 +355,14 @@ mod test {
         assert_eq!(buffer.next_token(), None);
         assert_eq!(buffer.get_buffer(), None);
     }
+
+    #[test]
+    fn test_bytes_after_tokens() {
+        let mut input = &b"1 2 3\nAB"[..];
+        let mut reader = LineReader::new(&mut input, false);
+        assert_eq!(reader.next_i32().unwrap(), 1);
+        assert_eq!(reader.next_bytes(2).unwrap(), b" 2");
+        assert_eq!(reader.next_i32().unwrap(), 3);
+        assert_eq!(reader.next_bytes(5).unwrap(), b"\nAB");
+    }
 }
@@ -0,0 +1,91 @@
+use crate::command_definition::Command;
+
+// A loop is reported when its backward jump is taken this many
+// times in a row with nothing changing in between: no I/O, no store,
+// no call and the same stack fingerprint at every iteration.
+pub const LIVELOCK_THRESHOLD: u64 = 1_000_000;
+
+pub struct LivelockDetector {
+    // block address and index of the last backward jump
+    location: Option<(usize, usize)>,
+    fingerprint: u64,
+    repeats: u64,
+    progress: bool,
+    threshold: u64,
+}
+
+impl LivelockDetector {
+    pub fn new(threshold: u64) -> Self {
+        Self {
+            location: None,
+            fingerprint: 0,
+            repeats: 0,
+            progress: false,
+            threshold,
+        }
+    }
+
+    // commands whose effect may let the loop terminate
+    pub fn observe(&mut self, cmd: &Command) {
+        let progress = matches!(
+            cmd,
+            Command::MemoryStore(..)
+                | Command::StoreSlot(..)
+                | Command::StoreParam(..)
+                | Command::Input(_)
+                | Command::Output(_)
+                | Command::OutputMany(_)
+                | Command::OutputLine
+                | Command::Append(_)
+                | Command::ArrayNew(_)
+                | Command::ArrayStore(_)
+                | Command::RawInput
+                | Command::RawOutput
+                | Command::SetBoolFormat
+                | Command::Flush(_)
+                | Command::ForControl(_)
+                | Command::NewRecord(_)
+                | Command::StringStats
+        );
+        self.progress |= progress;
+    }
+
+    // called on every backward jump, true when the
+    // loop looks stuck
+    pub fn back_jump(&mut self, location: (usize, usize), fingerprint: u64) -> bool {
+        let same =
+            !self.progress && self.location == Some(location) && self.fingerprint == fingerprint;
+        if same {
+            self.repeats += 1;
+        } else {
+            self.location = Some(location);
+            self.fingerprint = fingerprint;
+            self.repeats = 0;
+        }
+        self.progress = false;
+        self.repeats >= self.threshold
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::command_definition::Kind;
+
+    #[test]
+    fn test_detect_livelock() {
+        let mut detector = LivelockDetector::new(3);
+        for _ in 0..3 {
+            assert!(!detector.back_jump((1, 4), 10));
+        }
+        assert!(detector.back_jump((1, 4), 10));
+
+        let mut detector = LivelockDetector::new(3);
+        for i in 0..10 {
+            detector.observe(&Command::Output(Kind::Integer));
+            assert!(!detector.back_jump((1, 4), 10));
+            assert!(!detector.back_jump((1, 8), i));
+        }
+    }
+}
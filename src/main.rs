@@ -1,42 +1,1305 @@
-mod command_definition;
-mod engine;
-mod for_loop_stack;
-mod line_reader;
-mod opcode;
-mod program_load;
-mod reference_memory;
-mod string_memory;
-
-use std::path::PathBuf;
+#[cfg(feature = "alloc-stats")]
+use simpla::alloc_stats;
+use simpla::program_load::{self, LoadWarning};
+#[cfg(feature = "register-ir")]
+use simpla::register_ir;
+use simpla::{
+    asm, batch, bench, check, control, crash_dump, debugger, difftest, disasm, engine, fuzz,
+    fuzz_io, isa, optimizer, profiler, report, semantics, stress, trace, transcript, verify,
+    BlockId,
+};
+use std::cell::RefCell;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
 #[structopt(about = "Execute a Simpla program")]
 struct CLIArguments {
+    #[structopt(subcommand)]
+    cmd: Option<SubCommand>,
     #[structopt(name = "Bytecode File", help = "Simpla bytecode file")]
-    file: PathBuf,
+    file: Option<PathBuf>,
+    #[structopt(flatten)]
+    load: LoadArguments,
+    #[structopt(
+        long = "bool-format",
+        default_value = "true/false",
+        help = "Words used to print booleans, in the TRUE/FALSE form"
+    )]
+    bool_format: engine::BoolFormat,
+    #[structopt(
+        long = "echo-input",
+        help = "Print every input token or line consumed by the program"
+    )]
+    echo_input: bool,
+    #[structopt(
+        long = "transcript",
+        help = "Record the stdin, stdout and stderr traffic of the run into this file"
+    )]
+    transcript: Option<PathBuf>,
+    #[structopt(
+        long = "replay",
+        help = "Use the stdin recorded in a transcript as input"
+    )]
+    replay: Option<PathBuf>,
+    #[structopt(
+        long = "warnings",
+        help = "Print the non fatal problems found while loading the program"
+    )]
+    warnings: bool,
+    #[structopt(
+        long = "audit-init",
+        help = "Fail when the program reads a variable before assigning it"
+    )]
+    audit_init: bool,
+    #[structopt(
+        long = "detect-livelock",
+        help = "Stop the program when a loop keeps running without I/O, stores or stack changes"
+    )]
+    detect_livelock: bool,
+    #[structopt(
+        long = "crash-dump",
+        help = "On a runtime error, save the stacks and memory to this file for the post-mortem subcommand"
+    )]
+    crash_dump: Option<PathBuf>,
+    #[structopt(
+        long = "profile",
+        help = "Sample the call stack while running and write the folded stacks to this file"
+    )]
+    profile: Option<PathBuf>,
+    #[structopt(
+        long = "sample-period",
+        default_value = "10000",
+        help = "Instructions executed between two call stack samples"
+    )]
+    sample_period: u64,
+    #[structopt(
+        long = "latency-report",
+        help = "Time every function call and write the latency histograms, as JSON, to this file"
+    )]
+    latency_report: Option<PathBuf>,
+    #[structopt(
+        long = "count-report",
+        help = "Count the executions of every instruction, opcode and function and write them, as JSON, to this file"
+    )]
+    count_report: Option<PathBuf>,
+    #[structopt(
+        long = "count-summary",
+        help = "Count the executions of every instruction, opcode and function and print the hottest ones to stderr"
+    )]
+    count_summary: bool,
+    #[structopt(
+        long = "control",
+        conflicts_with_all = &["profile", "latency-report", "count-report", "count-summary"],
+        help = "Accept pause, resume, dump-state, set-breakpoint and cancel commands on this TCP address, see the ctl subcommand"
+    )]
+    control: Option<String>,
+    #[structopt(
+        long = "trace",
+        conflicts_with_all = &["profile", "latency-report", "count-report", "count-summary", "control"],
+        help = "Print every executed instruction, with its block and index, to stderr"
+    )]
+    trace: bool,
+    #[structopt(
+        long = "trace-format",
+        requires = "trace",
+        possible_values = &["text", "chrome"],
+        help = "Write the trace as text lines or as Chrome trace events, for about://tracing and Perfetto"
+    )]
+    trace_format: Option<trace::TraceFormat>,
+    #[structopt(
+        long = "trace-file",
+        requires = "trace",
+        help = "Write the trace to this file instead of stderr"
+    )]
+    trace_file: Option<PathBuf>,
+    #[structopt(
+        long = "trace-watermarks",
+        requires = "trace",
+        help = "Add the depth of every stack and the strings held after each traced instruction"
+    )]
+    trace_watermarks: bool,
+    #[structopt(
+        long = "trace-block",
+        requires = "trace",
+        help = "Trace only the instructions of this block, main or a function id"
+    )]
+    trace_block: Option<BlockId>,
+    #[structopt(
+        long = "trace-range",
+        requires = "trace",
+        parse(try_from_str = parse_range),
+        help = "Trace only the instructions at these indices, in the START..END form"
+    )]
+    trace_range: Option<Range<usize>>,
+    #[structopt(
+        long = "trace-class",
+        requires = "trace",
+        number_of_values = 1,
+        help = "Trace only the instructions of this class: control, memory or io"
+    )]
+    trace_class: Vec<trace::TraceClass>,
+    #[structopt(
+        long = "trace-after",
+        requires = "trace",
+        help = "Start the trace after this many executed instructions"
+    )]
+    trace_after: Option<u64>,
+    #[structopt(
+        long = "define",
+        number_of_values = 1,
+        parse(try_from_str = parse_define),
+        help = "Value returned by GETDEF for a name, in the NAME=VALUE form"
+    )]
+    defines: Vec<(String, String)>,
+    #[structopt(
+        long = "memoize",
+        help = "Cache up to this many results of the functions declared pure"
+    )]
+    memoize: Option<usize>,
+    #[structopt(
+        long = "max-call-depth",
+        help = "Stop the program when this many function calls are in progress, 10000 by default"
+    )]
+    max_call_depth: Option<usize>,
+    #[structopt(
+        long = "io-profile",
+        possible_values = &["interactive", "batch"],
+        help = "Run as an interactive or a batch program, whatever the program declares"
+    )]
+    io_profile: Option<engine::IoProfile>,
+    #[structopt(
+        long = "flush-before-read",
+        help = "Flush the output before every read, true or false, the IO profile decides by default"
+    )]
+    flush_before_read: Option<bool>,
+    #[structopt(
+        long = "retry-input",
+        help = "Ask again for values that do not parse, true or false, the IO profile decides by default"
+    )]
+    retry_input: Option<bool>,
+    #[structopt(
+        long = "on-eof",
+        possible_values = &["error", "exit"],
+        help = "Fail or exit when the program reads past the end of the input, the IO profile decides by default"
+    )]
+    on_eof: Option<engine::EofPolicy>,
+    #[structopt(
+        long = "compact-stacks",
+        help = "Give back the spare capacity of the value stacks every this many instructions"
+    )]
+    compact_stacks: Option<u64>,
+    #[structopt(long = "optimize", help = "Optimize the program before running it")]
+    optimize: bool,
+    #[structopt(
+        long = "check",
+        help = "Verify labels, functions, addresses and strings before running the program"
+    )]
+    check: bool,
+    #[structopt(
+        long = "color",
+        default_value = "auto",
+        possible_values = &["auto", "always", "never"],
+        help = "Color the runtime error reports"
+    )]
+    color: report::ColorChoice,
+    #[cfg(feature = "register-ir")]
+    #[structopt(
+        long = "register-ir",
+        help = "Run the main body on the experimental register interpreter when it supports the program"
+    )]
+    register_ir: bool,
+    #[structopt(
+        last = true,
+        help = "Program arguments, bound to the globals listed in the ARGS section"
+    )]
+    args: Vec<String>,
+    #[cfg(feature = "alloc-stats")]
+    #[structopt(
+        long = "alloc-stats",
+        help = "Print the allocations made by the loader, the strings and the activation records"
+    )]
+    alloc_stats: bool,
 }
 
+impl CLIArguments {
+    fn engine_config(&self) -> engine::EngineConfig {
+        let mut config = engine::EngineConfig::new()
+            .bool_format(self.bool_format.clone())
+            .echo_input(self.echo_input)
+            .audit_init(self.audit_init)
+            .detect_livelock(self.detect_livelock)
+            .args(self.args.clone())
+            .defines(self.defines.iter().cloned().collect())
+            .memo_limit(self.memoize)
+            .compact_period(self.compact_stacks);
+        if let Some(depth) = self.max_call_depth {
+            config = config.max_call_depth(depth);
+        }
+        if let Some(profile) = self.io_profile {
+            config = config.io_profile(profile);
+        }
+        if let Some(flush) = self.flush_before_read {
+            config = config.flush_before_read(flush);
+        }
+        if let Some(retry) = self.retry_input {
+            config = config.retry_on_parse_error(retry);
+        }
+        if let Some(eof) = self.on_eof {
+            config = config.eof_policy(eof);
+        }
+        config
+    }
+
+    fn trace_options(&self) -> trace::TraceOptions {
+        let mut options = trace::TraceOptions::new()
+            .format(self.trace_format.unwrap_or_default())
+            .watermarks(self.trace_watermarks)
+            .classes(self.trace_class.clone())
+            .after(self.trace_after.unwrap_or(0));
+        if let Some(block) = self.trace_block {
+            options = options.block(block);
+        }
+        if let Some(range) = &self.trace_range {
+            options = options.indices(range.clone());
+        }
+        options
+    }
+}
+
+fn parse_range(range: &str) -> Result<Range<usize>, String> {
+    let bounds = range
+        .split_once("..")
+        .map(|(start, end)| (start.parse(), end.parse()));
+    match bounds {
+        Some((Ok(start), Ok(end))) => Ok(start..end),
+        _ => Err(format!("{:?} is not in the START..END form", range)),
+    }
+}
+
+fn parse_define(define: &str) -> Result<(String, String), String> {
+    match define.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_owned(), value.to_owned())),
+        _ => Err(format!("{:?} is not in the NAME=VALUE form", define)),
+    }
+}
+
+#[derive(StructOpt)]
+enum SubCommand {
+    #[structopt(about = "Load a Simpla program and report problems without running it")]
+    Check {
+        #[structopt(name = "Bytecode File", help = "Simpla bytecode file")]
+        file: PathBuf,
+        #[structopt(flatten)]
+        load: LoadArguments,
+        #[structopt(
+            long = "memory-analysis",
+            help = "Look for suspicious memory accesses and activation record usage"
+        )]
+        memory_analysis: bool,
+    },
+    #[structopt(
+        about = "Run a program on two backends, or two programs, on the same input and report the first divergence"
+    )]
+    Difftest {
+        #[structopt(help = "Reference bytecode file")]
+        left: PathBuf,
+        #[structopt(
+            help = "Bytecode file compared against the reference, the same one when missing"
+        )]
+        right: Option<PathBuf>,
+        #[structopt(
+            long = "reference",
+            default_value = "plain",
+            help = "Backend running the reference: plain, optimized or register-ir"
+        )]
+        reference: difftest::Backend,
+        #[structopt(
+            long = "against",
+            help = "Backend compared against the reference, optimized for a single file and plain for two"
+        )]
+        against: Option<difftest::Backend>,
+        #[structopt(
+            short,
+            long,
+            help = "File used as program input, read from stdin when missing"
+        )]
+        input: Option<PathBuf>,
+        #[structopt(flatten)]
+        load: LoadArguments,
+    },
+    #[structopt(
+        about = "Run a program with checked integers and single precision reals and report where it behaves differently"
+    )]
+    Semantics {
+        #[structopt(help = "Bytecode file")]
+        file: PathBuf,
+        #[structopt(
+            short,
+            long,
+            help = "File used as program input, read from stdin when missing"
+        )]
+        input: Option<PathBuf>,
+        #[structopt(flatten)]
+        load: LoadArguments,
+    },
+    #[structopt(about = "Run a program on random inputs and report the failing runs")]
+    FuzzIo {
+        #[structopt(help = "Bytecode file")]
+        file: PathBuf,
+        #[structopt(
+            help = "Values read by the program, in order, e.g. \"i:1..10 r:0..1 b s:1..8*3\""
+        )]
+        spec: fuzz_io::InputSpec,
+        #[structopt(long = "runs", default_value = "100", help = "Input sets to try")]
+        runs: u64,
+        #[structopt(
+            long = "seed",
+            default_value = "0",
+            help = "Seed of the input sets, the same seed gives the same sets"
+        )]
+        seed: u64,
+        #[structopt(
+            long = "timeout",
+            default_value = "1000",
+            help = "Milliseconds each run may take"
+        )]
+        timeout: u64,
+        #[structopt(flatten)]
+        load: LoadArguments,
+    },
+    #[structopt(about = "Measure the engine speed")]
+    Bench {
+        #[structopt(
+            long = "micro",
+            help = "Measure the dispatch cost of each opcode family with generated loops"
+        )]
+        micro: bool,
+        #[structopt(long = "iterations", default_value = "20000", help = "Loop iterations")]
+        iterations: u32,
+        #[structopt(
+            long = "copies",
+            default_value = "50",
+            help = "Copies of the measured instructions in each iteration"
+        )]
+        copies: u16,
+    },
+    #[structopt(about = "Run the jobs listed in a JSON manifest and report their results")]
+    Batch {
+        #[structopt(help = "JSON manifest listing program, input and expected output of each job")]
+        manifest: PathBuf,
+        #[structopt(
+            long = "threads",
+            help = "Jobs run at the same time, defaults to the available cores"
+        )]
+        threads: Option<usize>,
+        #[structopt(long = "report", help = "Write the JSON report here instead of stdout")]
+        report: Option<PathBuf>,
+        #[structopt(long = "junit", help = "Also write a JUnit XML report to this file")]
+        junit: Option<PathBuf>,
+        #[structopt(flatten)]
+        load: LoadArguments,
+    },
+    #[structopt(about = "Run a single program as described by a JSON run spec")]
+    Spec {
+        #[structopt(
+            help = "JSON run spec: a batch job with program, input, defines, expected exit code and output, pre and post hooks"
+        )]
+        spec: PathBuf,
+        #[structopt(flatten)]
+        load: LoadArguments,
+    },
+    #[structopt(about = "Print a section of a Simpla program")]
+    Dump {
+        #[structopt(
+            name = "Bytecode File",
+            required_unless = "isa",
+            help = "Simpla bytecode file"
+        )]
+        file: Option<PathBuf>,
+        #[structopt(long = "meta", help = "Print the key/value pairs of the META section")]
+        meta: bool,
+        #[structopt(
+            long = "isa",
+            conflicts_with_all = &["meta", "Bytecode File"],
+            help = "Print the instruction set reference, in Markdown"
+        )]
+        isa: bool,
+        #[structopt(long = "json", requires = "isa", help = "Print the reference as JSON")]
+        json: bool,
+        #[structopt(flatten)]
+        load: LoadArguments,
+    },
+    #[structopt(about = "Translate an assembly source into a bytecode file")]
+    Asm {
+        #[structopt(help = "Assembly source file")]
+        source: PathBuf,
+        #[structopt(help = "Output bytecode file")]
+        output: PathBuf,
+    },
+    #[structopt(about = "Print a readable listing of the instructions of a program")]
+    Disasm {
+        #[structopt(name = "Bytecode File", help = "Simpla bytecode file")]
+        file: PathBuf,
+        #[structopt(flatten)]
+        load: LoadArguments,
+    },
+    #[structopt(about = "Run a program under a debugger reading commands from stdin or a script")]
+    Debug {
+        #[structopt(name = "Bytecode File", help = "Simpla bytecode file")]
+        file: PathBuf,
+        #[structopt(
+            short,
+            long,
+            help = "File used as program input, the program reads nothing when missing"
+        )]
+        input: Option<PathBuf>,
+        #[structopt(
+            long,
+            help = "Read the debugger commands from this file instead of stdin"
+        )]
+        debug_script: Option<PathBuf>,
+        #[structopt(
+            long,
+            requires = "debug-script",
+            help = "Write the replies of a debug script to this file instead of stderr"
+        )]
+        debug_log: Option<PathBuf>,
+        #[structopt(flatten)]
+        load: LoadArguments,
+    },
+    #[structopt(
+        about = "Inspect a file saved by --crash-dump with debugger commands read from stdin"
+    )]
+    PostMortem {
+        #[structopt(help = "Crash dump file")]
+        dump: PathBuf,
+    },
+    #[structopt(about = "Send a command to a program started with --control")]
+    Ctl {
+        #[structopt(help = "Address of the control socket")]
+        address: String,
+        #[structopt(
+            required = true,
+            help = "pause, resume, dump-state, set-breakpoint BLOCK INDEX or cancel"
+        )]
+        command: Vec<String>,
+    },
+    #[structopt(about = "Run random valid programs and report the ones that crash the engine")]
+    Fuzz {
+        #[structopt(
+            long = "runs",
+            default_value = "1000",
+            help = "Programs to generate and run"
+        )]
+        runs: u64,
+        #[structopt(long = "seed", default_value = "0", help = "Seed of the first program")]
+        seed: u64,
+    },
+    #[structopt(about = "Write a large generated program to stress the loader and the engine")]
+    GenerateStress {
+        #[structopt(help = "Output bytecode file")]
+        output: PathBuf,
+        #[structopt(
+            long = "call-depth",
+            default_value = "1000",
+            help = "Length of the chain of nested function calls"
+        )]
+        call_depth: u16,
+        #[structopt(
+            long = "constants",
+            default_value = "100000",
+            help = "Constants loaded by the main body"
+        )]
+        constants: u32,
+        #[structopt(
+            long = "iterations",
+            default_value = "1000",
+            help = "Loop iterations, each one runs the whole call chain"
+        )]
+        iterations: u32,
+    },
+}
+
+#[derive(StructOpt)]
+struct LoadArguments {
+    #[structopt(
+        long = "lenient-strings",
+        help = "Decode malformed UTF-8 string constants lossily instead of failing"
+    )]
+    lenient_strings: bool,
+}
+
+impl LoadArguments {
+    fn options(&self) -> program_load::LoadOptions {
+        program_load::LoadOptions {
+            lenient_strings: self.lenient_strings,
+            byte_order: None,
+        }
+    }
+}
+
+fn compile_and_run(file: &Path, args: &CLIArguments) -> Result<(), String> {
+    let log = match &args.transcript {
+        Some(path) => match transcript::Transcript::create(path) {
+            Ok(log) => Some(log),
+            Err(err) => return Err(format!("Error while creating {:?}\n{}", path, err)),
+        },
+        None => None,
+    };
+
+    let status = load_and_run(file, args, &log);
+    #[cfg(feature = "alloc-stats")]
+    {
+        if args.alloc_stats {
+            eprint!("{}", alloc_stats::report());
+        }
+    }
+    if let Some(log) = log {
+        let mut log = log.borrow_mut();
+        if let Err(err) = &status {
+            log.record(transcript::Stream::Stderr, format!("{}\n", err).as_bytes());
+        }
+        if let Err(err) = log.finish() {
+            eprintln!("Error while writing the transcript\n{}", err);
+        }
+    }
+    status
+}
+
+fn load_and_run(
+    file: &Path,
+    args: &CLIArguments,
+    log: &Option<transcript::SharedTranscript>,
+) -> Result<(), String> {
+    #[cfg(feature = "alloc-stats")]
+    let loader_scope = alloc_stats::scope(alloc_stats::Category::Loader);
+    let res = program_load::load_program(file, &args.load.options());
+    #[cfg(feature = "alloc-stats")]
+    drop(loader_scope);
+    let (mut prog, mut prog_mem, str_mem) = match res {
+        Ok((prog, prog_mem, str_mem, warnings)) => {
+            // lossy strings and deprecated opcodes are reported
+            // anyway: they change or will break the program
+            let warnings = warnings.into_iter().filter(|warn| {
+                args.warnings
+                    || matches!(
+                        warn,
+                        LoadWarning::LossyString(..) | LoadWarning::DeprecatedOpcode(..)
+                    )
+            });
+            for warn in warnings {
+                let msg = format!("Warning: {}\n", warn);
+                eprint!("{}", msg);
+                if let Some(log) = log {
+                    log.borrow_mut()
+                        .record(transcript::Stream::Stderr, msg.as_bytes());
+                }
+            }
+            (prog, prog_mem, str_mem)
+        }
+        Err(err) => return Err(format!("Error while loading {:?}\n{}", file, err)),
+    };
+
+    if args.optimize {
+        optimizer::optimize(&mut prog, &mut prog_mem);
+    }
+    // after the optimizer: verify what is going to run
+    if args.check {
+        if let Err(err) = verify::verify(&prog, &prog_mem, &str_mem) {
+            return Err(format!("Error while verifying {:?}\n{}", file, err));
+        }
+    }
+
+    let stdin = io::stdin();
+    let mut input: Box<dyn BufRead> = match &args.replay {
+        Some(path) => match transcript::replay_input(path) {
+            Ok(data) => Box::new(io::Cursor::new(data)),
+            Err(err) => return Err(format!("Error while loading {:?}\n{}", path, err)),
+        },
+        None => Box::new(stdin.lock()),
+    };
+    let mut output: Box<dyn Write> = Box::new(io::stdout());
+    let mut notices: Box<dyn Write> = Box::new(io::stderr());
+    if let Some(log) = log {
+        input = Box::new(transcript::TranscriptReader::new(input, log.clone()));
+        output = Box::new(transcript::TranscriptWriter::new(
+            output,
+            log.clone(),
+            transcript::Stream::Stdout,
+        ));
+        notices = Box::new(transcript::TranscriptWriter::new(
+            notices,
+            log.clone(),
+            transcript::Stream::Stderr,
+        ));
+    }
+    let config = args.engine_config().notices(Rc::new(RefCell::new(notices)));
+
+    #[cfg(feature = "register-ir")]
+    {
+        let engine_only = args.audit_init
+            || args.detect_livelock
+            || args.profile.is_some()
+            || args.latency_report.is_some()
+            || args.count_report.is_some()
+            || args.count_summary
+            || args.control.is_some()
+            || args.trace
+            // the register interpreter only reads the batch way
+            || config.io_policy(&prog) != engine::IoProfile::Batch.io_policy();
+        if args.register_ir && !engine_only {
+            match register_ir::translate(&prog, &prog_mem) {
+                Ok(reg_prog) => {
+                    let run_stat = register_ir::run_register_program(
+                        &reg_prog,
+                        &str_mem,
+                        &config,
+                        &mut input,
+                        &mut output,
+                    );
+                    return run_stat.map_err(|err| run_error(file, err));
+                }
+                Err(err) => eprintln!("Register interpreter unavailable: {}", err),
+            }
+        }
+    }
+
+    if let Some(addr) = &args.control {
+        let mut str_mem = str_mem;
+        return run_with_control(
+            file,
+            addr,
+            engine::Engine::new(&mut prog, &prog_mem, &mut str_mem, &config, &mut input),
+            &mut output,
+        );
+    }
+
+    if args.trace {
+        let mut str_mem = str_mem;
+        let mut trace: Box<dyn Write> = match &args.trace_file {
+            Some(path) => match std::fs::File::create(path) {
+                Ok(file) => Box::new(io::BufWriter::new(file)),
+                Err(err) => return Err(format!("Error while writing {:?}\n{}", path, err)),
+            },
+            None => Box::new(io::stderr()),
+        };
+        if let (Some(log), None) = (log, &args.trace_file) {
+            trace = Box::new(transcript::TranscriptWriter::new(
+                trace,
+                log.clone(),
+                transcript::Stream::Stderr,
+            ));
+        }
+        let run_stat = engine::Engine::new(&mut prog, &prog_mem, &mut str_mem, &config, &mut input)
+            .and_then(|engine| {
+                engine
+                    .trace_with(&mut trace, args.trace_options())
+                    .run(&mut output)
+            });
+        return run_stat.map_err(|err| run_error(file, err));
+    }
+
+    let counting = args.count_report.is_some() || args.count_summary;
+    if args.profile.is_some() || args.latency_report.is_some() || counting {
+        let mut profiler = profiler::Profiler::new(args.sample_period).counting(counting);
+        let run_stat = engine::run_program_profiled(
+            prog,
+            prog_mem,
+            str_mem,
+            &config,
+            &mut input,
+            &mut output,
+            &mut profiler,
+        );
+        // the profile is useful even when the program fails
+        if let Some(path) = &args.profile {
+            if let Err(err) = std::fs::write(path, profiler.folded()) {
+                return Err(format!("Error while writing {:?}\n{}", path, err));
+            }
+        }
+        if let Some(path) = &args.latency_report {
+            if let Err(err) = std::fs::write(path, profiler.latency_json() + "\n") {
+                return Err(format!("Error while writing {:?}\n{}", path, err));
+            }
+        }
+        if let Some(path) = &args.count_report {
+            if let Err(err) = std::fs::write(path, profiler.counts_json() + "\n") {
+                return Err(format!("Error while writing {:?}\n{}", path, err));
+            }
+        }
+        if args.count_summary {
+            let summary = profiler.counts_summary();
+            eprint!("{}", summary);
+            if let Some(log) = log {
+                log.borrow_mut()
+                    .record(transcript::Stream::Stderr, summary.as_bytes());
+            }
+        }
+        return match run_stat {
+            Ok(_) => Ok(()),
+            Err(err) => Err(run_error(file, err)),
+        };
+    }
+
+    // the transcript records the report as plain text
+    let color = args.color.enabled(io::stderr().is_terminal()) && log.is_none();
+    let mut str_mem = str_mem;
+    let mut engine = engine::Engine::new(&mut prog, &prog_mem, &mut str_mem, &config, &mut input)
+        .map_err(|err| report_error(file, err, None, color))?;
+    let run_stat = match &args.crash_dump {
+        // the dump shows the machine before the finalizer runs
+        Some(path) => {
+            let status = engine.run_until(u64::MAX, &mut output).map(|_| ());
+            if let Err(err) = &status {
+                if let Err(err) = crash_dump::CrashDump::capture(&engine, err).save(path) {
+                    eprintln!("Error while writing {:?}\n{}", path, err);
+                }
+            }
+            let status = engine.finalize(status, &mut output);
+            engine.collect_garbage();
+            status
+        }
+        None => engine.run(&mut output),
+    };
+    let trace = engine.fault_trace().to_vec();
+    drop(engine);
+    run_stat.map_err(|err| {
+        let fault = trace
+            .split_first()
+            .map(|((block, index), callers)| report::Fault {
+                prog: &prog,
+                str_mem: &str_mem,
+                block: *block,
+                index: *index,
+                callers,
+            });
+        report_error(file, err, fault.as_ref(), color)
+    })
+}
+
+fn run_with_control(
+    file: &Path,
+    addr: &str,
+    engine: Result<engine::Engine, engine::RuntimeError>,
+    output: &mut dyn Write,
+) -> Result<(), String> {
+    let server = match control::ControlServer::bind(addr) {
+        Ok(server) => server,
+        Err(err) => {
+            return Err(format!(
+                "Error while opening the control socket {}\n{}",
+                addr, err
+            ))
+        }
+    };
+    eprintln!("Control socket listening on {}", server.local_addr());
+    let mut engine = engine.map_err(|err| run_error(file, err))?;
+    control::run_controlled(&mut engine, server.requests(), output)
+        .map_err(|err| run_error(file, err))
+}
+
+fn send_control(address: &str, command: &[String]) -> Result<(), String> {
+    match control::send_command(address, &command.join(" ")) {
+        Ok(reply) => {
+            println!("{}", reply);
+            Ok(())
+        }
+        Err(err) => Err(format!("Error while talking to {}\n{}", address, err)),
+    }
+}
+
+// status of a run stopped because nobody reads its output
+// anymore, the same a shell reports for a process killed by SIGPIPE
+const BROKEN_PIPE_STATUS: i32 = 141;
+
+// a closed output pipe is the normal end of `simpla prog | head`:
+// leave quietly instead of printing an error
+fn run_error(file: &Path, err: engine::RuntimeError) -> String {
+    if err.is_broken_pipe() {
+        std::process::exit(BROKEN_PIPE_STATUS);
+    }
+    format!("Error while running {:?}\n{}", file, err)
+}
+
+// same as `run_error`, with the failing instruction and its
+// neighbours when the engine knows where the program stopped
+fn report_error(
+    file: &Path,
+    err: engine::RuntimeError,
+    fault: Option<&report::Fault>,
+    color: bool,
+) -> String {
+    if err.is_broken_pipe() {
+        std::process::exit(BROKEN_PIPE_STATUS);
+    }
+    let report = report::render(&err, fault, color);
+    format!("Error while running {:?}\n{}", file, report.trim_end())
+}
+
+fn check_file(
+    file: &Path,
+    options: &program_load::LoadOptions,
+    memory_analysis: bool,
+) -> Result<(), String> {
+    let data = match program_load::load_file(file) {
+        Ok(data) => data,
+        Err(err) => return Err(format!("Error while loading {:?}\n{}", file, err)),
+    };
+    match check::check_program(&data, options, memory_analysis) {
+        Ok(findings) => {
+            for finding in &findings {
+                println!("Warning: {}", finding);
+            }
+            println!("{:?}: {} warning(s)", file, findings.len());
+            Ok(())
+        }
+        Err(err) => Err(format!("Error while checking {:?}\n{}", file, err)),
+    }
+}
+
+fn run_captured(
+    file: &Path,
+    backend: difftest::Backend,
+    input: &[u8],
+    options: &program_load::LoadOptions,
+) -> Result<difftest::RunResult, String> {
+    let (prog, prog_mem, str_mem, _) = match program_load::load_program(file, options) {
+        Ok(loaded) => loaded,
+        Err(err) => return Err(format!("Error while loading {:?}\n{}", file, err)),
+    };
+    difftest::run_backend(backend, prog, prog_mem, str_mem, input)
+        .map_err(|err| format!("{:?} cannot run on the {} backend\n{}", file, backend, err))
+}
+
+fn diff_files(
+    left: &Path,
+    right: &Option<PathBuf>,
+    reference: difftest::Backend,
+    against: Option<difftest::Backend>,
+    input: &Option<PathBuf>,
+    options: &program_load::LoadOptions,
+) -> Result<(), String> {
+    // one file is compared with itself on another backend
+    let against = against.unwrap_or(match right {
+        Some(_) => difftest::Backend::Plain,
+        None => difftest::Backend::Optimized,
+    });
+    let right = right.as_deref().unwrap_or(left);
+    let input = read_input(input)?;
+    let left_run = run_captured(left, reference, &input, options)?;
+    let right_run = run_captured(right, against, &input, options)?;
+    let left = format!("{:?} on {}", left, reference);
+    let right = format!("{:?} on {}", right, against);
+    match difftest::first_divergence(&left_run, &right_run) {
+        Some(div) => Err(format!("{} and {} diverge: {}", left, right, div)),
+        None => {
+            println!("{} and {} behave the same", left, right);
+            Ok(())
+        }
+    }
+}
+
+// the whole program input, for the commands that run a program more than once
+fn read_input(input: &Option<PathBuf>) -> Result<Vec<u8>, String> {
+    let input = match input {
+        Some(file) => std::fs::read(file),
+        None => {
+            let mut buff = Vec::new();
+            std::io::stdin().read_to_end(&mut buff).map(|_| buff)
+        }
+    };
+    input.map_err(|err| format!("Error while reading program input\n{}", err))
+}
+
+fn compare_semantics(
+    file: &Path,
+    input: &Option<PathBuf>,
+    options: &program_load::LoadOptions,
+) -> Result<(), String> {
+    let (mut prog, prog_mem, mut str_mem, _) = match program_load::load_program(file, options) {
+        Ok(loaded) => loaded,
+        Err(err) => return Err(format!("Error while loading {:?}\n{}", file, err)),
+    };
+    let input = read_input(input)?;
+    let config = engine::EngineConfig::new();
+    let comparisons = semantics::shootout(&mut prog, &prog_mem, &mut str_mem, &config, &input);
+    let mut diverging = 0;
+    for cmp in &comparisons {
+        match &cmp.divergence {
+            Some(div) => {
+                diverging += 1;
+                println!("{}: diverges, {}", cmp.variant, div);
+            }
+            None => println!("{}: same behaviour", cmp.variant),
+        }
+    }
+    if diverging > 0 {
+        Err(format!(
+            "{:?} depends on the arithmetic semantics, {} of {} variants diverge from {}",
+            file,
+            diverging,
+            comparisons.len(),
+            semantics::REFERENCE
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn fuzz_inputs(
+    file: &Path,
+    spec: &fuzz_io::InputSpec,
+    runs: u64,
+    seed: u64,
+    timeout: u64,
+    options: &program_load::LoadOptions,
+) -> Result<(), String> {
+    let (mut prog, prog_mem, mut str_mem, _) = match program_load::load_program(file, options) {
+        Ok(loaded) => loaded,
+        Err(err) => return Err(format!("Error while loading {:?}\n{}", file, err)),
+    };
+    let quota = engine::Quota {
+        time: Some(Duration::from_millis(timeout)),
+        ..engine::Quota::default()
+    };
+    let config = engine::EngineConfig::new().quota(quota);
+    let failures = fuzz_io::fuzz(
+        &mut prog,
+        &prog_mem,
+        &mut str_mem,
+        &config,
+        spec,
+        runs,
+        seed,
+    );
+    for failure in &failures {
+        println!("run {}: {}", failure.run, failure.outcome);
+        for line in failure.input.lines() {
+            println!("    {}", line);
+        }
+    }
+    if failures.is_empty() {
+        println!("{} runs passed", runs);
+        Ok(())
+    } else {
+        Err(format!(
+            "{:?} failed {} of {} runs",
+            file,
+            failures.len(),
+            runs
+        ))
+    }
+}
+
+fn run_bench(micro: bool, iterations: u32, copies: u16) -> Result<(), String> {
+    if !micro {
+        return Err("Only micro benchmarks are available, see --micro".to_owned());
+    }
+    if iterations == 0 || iterations > i32::MAX as u32 || copies == 0 {
+        return Err("Iterations and copies must be positive integers".to_owned());
+    }
+    let measures = bench::run_micro(iterations, copies);
+    print!("{}", bench::format_table(&measures));
+    Ok(())
+}
+
+fn run_spec(spec: &Path, options: &program_load::LoadOptions) -> Result<(), String> {
+    let job = match batch::Job::load(spec) {
+        Ok(job) => job,
+        Err(err) => return Err(format!("Error while loading {:?}\n{}", spec, err)),
+    };
+    let report = batch::run_job(&job, options, None, &mut batch::ProgramCache::new());
+    let status = serde_json::to_value(report.status).unwrap();
+    let status = status.as_str().unwrap_or("");
+    match report.message {
+        None => {
+            println!("{}: {} in {} ms", report.name, status, report.millis);
+            Ok(())
+        }
+        Some(message) => Err(format!("{}: {}\n{}", report.name, status, message)),
+    }
+}
+
+fn run_batch(
+    manifest: &Path,
+    threads: Option<usize>,
+    report: &Option<PathBuf>,
+    junit: &Option<PathBuf>,
+    options: &program_load::LoadOptions,
+) -> Result<(), String> {
+    let jobs = match batch::Manifest::load(manifest) {
+        Ok(jobs) => jobs,
+        Err(err) => return Err(format!("Error while loading {:?}\n{}", manifest, err)),
+    };
+    let threads = threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |count| count.get()));
+    let result = batch::run_batch(&jobs, threads, options);
+    let json = serde_json::to_string_pretty(&result).unwrap();
+    match report {
+        Some(path) => {
+            if let Err(err) = std::fs::write(path, json + "\n") {
+                return Err(format!("Error while writing {:?}\n{}", path, err));
+            }
+        }
+        None => println!("{}", json),
+    }
+    if let Some(path) = junit {
+        if let Err(err) = std::fs::write(path, batch::junit_report(&result)) {
+            return Err(format!("Error while writing {:?}\n{}", path, err));
+        }
+    }
+    if result.passed == result.total {
+        Ok(())
+    } else {
+        let failed = result.total - result.passed;
+        Err(format!("{} of {} jobs failed", failed, result.total))
+    }
+}
 
-fn compile_and_run(file: &PathBuf) -> Result<(), String> {
-    let res = program_load::load_program(file);
-    let (prog, prog_mem, str_mem) = match res {
-        Ok((prog, prog_mem, str_mem)) => (prog, prog_mem, str_mem),
-        Err(err) => return Err(format!("Error while loading {:?}\n{}", file, err))
+fn dump_file(file: &Path, meta: bool, options: &program_load::LoadOptions) -> Result<(), String> {
+    if !meta {
+        return Err("Only the metadata can be printed, see --meta".to_owned());
+    }
+    let prog = match program_load::load_program(file, options) {
+        Ok((prog, _, _, _)) => prog,
+        Err(err) => return Err(format!("Error while loading {:?}\n{}", file, err)),
     };
+    for (key, value) in &prog.meta {
+        println!("{}: {}", key, value);
+    }
+    Ok(())
+}
+
+fn dump_isa(json: bool) -> Result<(), String> {
+    if json {
+        println!("{}", isa::json());
+    } else {
+        print!("{}", isa::markdown());
+    }
+    Ok(())
+}
 
-    let run_stat = engine::run_program(prog, prog_mem, str_mem);
-    match run_stat {
+fn assemble_file(source: &Path, output: &Path) -> Result<(), String> {
+    let text = match std::fs::read_to_string(source) {
+        Ok(text) => text,
+        Err(err) => return Err(format!("Error while reading {:?}\n{}", source, err)),
+    };
+    let data = match asm::assemble(&text) {
+        Ok(data) => data,
+        Err(err) => return Err(format!("Error while assembling {:?}\n{}", source, err)),
+    };
+    match std::fs::write(output, data) {
         Ok(()) => Ok(()),
-        Err(err) => Err(format!("Error while running {:?}\n{}", file, err))
+        Err(err) => Err(format!("Error while writing {:?}\n{}", output, err)),
+    }
+}
+
+fn disasm_file(file: &Path, options: &program_load::LoadOptions) -> Result<(), String> {
+    match program_load::load_program(file, options) {
+        Ok((prog, prog_mem, str_mem, _)) => {
+            print!("{}", disasm::disassemble(&prog, &prog_mem, &str_mem));
+            Ok(())
+        }
+        Err(err) => Err(format!("Error while loading {:?}\n{}", file, err)),
+    }
+}
+
+fn debug_file(
+    file: &Path,
+    input: &Option<PathBuf>,
+    script: &Option<PathBuf>,
+    log: &Option<PathBuf>,
+    options: &program_load::LoadOptions,
+) -> Result<(), String> {
+    let (mut prog, prog_mem, mut str_mem, _) = match program_load::load_program(file, options) {
+        Ok(loaded) => loaded,
+        Err(err) => return Err(format!("Error while loading {:?}\n{}", file, err)),
+    };
+    // stdin carries the debugger commands, unless there is a script
+    let data = match input {
+        Some(path) => match std::fs::read(path) {
+            Ok(data) => data,
+            Err(err) => return Err(format!("Error while reading {:?}\n{}", path, err)),
+        },
+        None => vec![],
+    };
+    let mut input = io::Cursor::new(data);
+    let config = engine::EngineConfig::new();
+    let mut engine = engine::Engine::new(&mut prog, &prog_mem, &mut str_mem, &config, &mut input)
+        .map_err(|err| run_error(file, err))?;
+    if let Some(path) = script {
+        let mut script = match std::fs::File::open(path) {
+            Ok(script) => io::BufReader::new(script),
+            Err(err) => return Err(format!("Error while reading {:?}\n{}", path, err)),
+        };
+        let mut log: Box<dyn Write> = match log {
+            Some(path) => match std::fs::File::create(path) {
+                Ok(log) => Box::new(io::BufWriter::new(log)),
+                Err(err) => return Err(format!("Error while creating {:?}\n{}", path, err)),
+            },
+            None => Box::new(io::stderr()),
+        };
+        let status = debugger::run_script(&mut engine, &mut script, &mut log, &mut io::stdout());
+        log.flush()
+            .map_err(|err| format!("Error while writing the debug log\n{}", err))?;
+        return status.map_err(|err| run_error(file, err));
+    }
+    let stdin = io::stdin();
+    debugger::run_debugger(
+        &mut engine,
+        &mut stdin.lock(),
+        &mut io::stderr(),
+        &mut io::stdout(),
+    )
+    .map_err(|err| run_error(file, err))
+}
+
+fn post_mortem(path: &Path) -> Result<(), String> {
+    let dump = match crash_dump::CrashDump::load(path) {
+        Ok(dump) => dump,
+        Err(err) => return Err(format!("Error while loading {:?}\n{}", path, err)),
+    };
+    let stdin = io::stdin();
+    debugger::post_mortem(&dump, &mut stdin.lock(), &mut io::stderr())
+        .map_err(|err| format!("Error while inspecting {:?}\n{}", path, err))
+}
+
+fn run_fuzz(runs: u64, seed: u64) -> Result<(), String> {
+    let mut failed = 0;
+    for seed in seed..seed.saturating_add(runs) {
+        if let Err(failure) = fuzz::run_case(seed) {
+            println!("{}", failure);
+            failed += 1;
+        }
+    }
+    println!("{} program(s) run, {} failed", runs, failed);
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(format!("{} program(s) broke the engine", failed))
+    }
+}
+
+fn generate_stress(output: &Path, shape: &stress::Shape) -> Result<(), String> {
+    if shape.constants > i32::MAX as u32 || shape.iterations > i32::MAX as u32 {
+        return Err("Constants and iterations must fit an integer".to_owned());
+    }
+    match std::fs::write(output, stress::generate(shape)) {
+        Ok(()) => Ok(()),
+        Err(err) => Err(format!("Error while writing {:?}\n{}", output, err)),
     }
 }
 
 fn main() {
     let args = CLIArguments::from_args();
-    let status = compile_and_run(&args.file);
-    match status {
-        Ok(()) => {},
-        Err(err) => eprintln!("{}", err)
+    let status = match (&args.cmd, &args.file) {
+        (
+            Some(SubCommand::Check {
+                file,
+                load,
+                memory_analysis,
+            }),
+            _,
+        ) => check_file(file, &load.options(), *memory_analysis),
+        (
+            Some(SubCommand::Difftest {
+                left,
+                right,
+                reference,
+                against,
+                input,
+                load,
+            }),
+            _,
+        ) => diff_files(left, right, *reference, *against, input, &load.options()),
+        (Some(SubCommand::Semantics { file, input, load }), _) => {
+            compare_semantics(file, input, &load.options())
+        }
+        (
+            Some(SubCommand::FuzzIo {
+                file,
+                spec,
+                runs,
+                seed,
+                timeout,
+                load,
+            }),
+            _,
+        ) => fuzz_inputs(file, spec, *runs, *seed, *timeout, &load.options()),
+        (
+            Some(SubCommand::Bench {
+                micro,
+                iterations,
+                copies,
+            }),
+            _,
+        ) => run_bench(*micro, *iterations, *copies),
+        (
+            Some(SubCommand::Batch {
+                manifest,
+                threads,
+                report,
+                junit,
+                load,
+            }),
+            _,
+        ) => run_batch(manifest, *threads, report, junit, &load.options()),
+        (
+            Some(SubCommand::Dump {
+                file,
+                meta,
+                isa,
+                json,
+                load,
+            }),
+            _,
+        ) => match file {
+            _ if *isa => dump_isa(*json),
+            Some(file) => dump_file(file, *meta, &load.options()),
+            None => Err("Missing bytecode file, see --help".to_owned()),
+        },
+        (Some(SubCommand::Spec { spec, load }), _) => run_spec(spec, &load.options()),
+        (Some(SubCommand::Asm { source, output }), _) => assemble_file(source, output),
+        (Some(SubCommand::Disasm { file, load }), _) => disasm_file(file, &load.options()),
+        (
+            Some(SubCommand::Debug {
+                file,
+                input,
+                debug_script,
+                debug_log,
+                load,
+            }),
+            _,
+        ) => debug_file(file, input, debug_script, debug_log, &load.options()),
+        (Some(SubCommand::PostMortem { dump }), _) => post_mortem(dump),
+        (Some(SubCommand::Ctl { address, command }), _) => send_control(address, command),
+        (Some(SubCommand::Fuzz { runs, seed }), _) => run_fuzz(*runs, *seed),
+        (
+            Some(SubCommand::GenerateStress {
+                output,
+                call_depth,
+                constants,
+                iterations,
+            }),
+            _,
+        ) => generate_stress(
+            output,
+            &stress::Shape {
+                call_depth: *call_depth,
+                constants: *constants,
+                iterations: *iterations,
+            },
+        ),
+        (None, Some(file)) => compile_and_run(file, &args),
+        (None, None) => Err("Missing bytecode file, see --help".to_owned()),
+    };
+    if let Err(err) = status {
+        eprintln!("{}", err);
+        std::process::exit(1);
     }
 }
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+// Results of the functions declared pure, keyed by the function and
+// by the content of its activation record at call time. Reals are
+// compared bit by bit and strings by content. Once `limit` entries
+// are stored new results are no longer cached.
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct MemoKey {
+    pub func: usize,
+    ints: Vec<i32>,
+    reals: Vec<u64>,
+    bools: Vec<bool>,
+    strings: Vec<String>,
+}
+
+impl MemoKey {
+    pub fn new(
+        func: usize,
+        ints: &[i32],
+        reals: &[f64],
+        bools: &[bool],
+        strings: Vec<String>,
+    ) -> Self {
+        Self {
+            func,
+            ints: ints.to_vec(),
+            reals: reals.iter().map(|r| r.to_bits()).collect(),
+            bools: bools.to_vec(),
+            strings,
+        }
+    }
+}
+
+// returned values of each kind, in push order
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MemoValues {
+    pub ints: Vec<i32>,
+    pub reals: Vec<f64>,
+    pub bools: Vec<bool>,
+    pub strings: Vec<String>,
+}
+
+pub struct MemoCache {
+    limit: usize,
+    entries: HashMap<MemoKey, MemoValues>,
+}
+
+impl MemoCache {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, key: &MemoKey) -> Option<&MemoValues> {
+        self.entries.get(key)
+    }
+
+    pub fn insert(&mut self, key: MemoKey, values: MemoValues) {
+        if self.entries.len() < self.limit {
+            self.entries.insert(key, values);
+        }
+    }
+
+    // integers of the cached results, they may be array handles
+    pub fn integers(&self) -> impl Iterator<Item = i32> + '_ {
+        self.entries
+            .values()
+            .flat_map(|values| values.ints.iter().copied())
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_memo_cache() {
+        let mut cache = MemoCache::new(1);
+        let key = |n| MemoKey::new(0, &[n], &[0.5], &[], vec!["a".to_owned()]);
+        let values = MemoValues {
+            ints: vec![8],
+            ..MemoValues::default()
+        };
+        cache.insert(key(5), values.clone());
+        cache.insert(key(6), values.clone());
+        assert_eq!(cache.get(&key(5)), Some(&values));
+        assert_eq!(cache.get(&key(6)), None);
+        assert_eq!(cache.get(&MemoKey::new(1, &[5], &[0.5], &[], vec![])), None);
+    }
+}
@@ -1,45 +1,42 @@
 pub const ADDI: u8 = 0;
-#[allow(dead_code)]
 pub const SUBI: u8 = 1;
-//pub const MULI: u8 = 2;
-//pub const DIVI: u8 = 3;
-#[allow(dead_code)]
+pub const MULI: u8 = 2;
+pub const DIVI: u8 = 3;
 pub const GEQI: u8 = 4;
-//pub const GRI: u8 = 5;
-//pub const LEQI: u8 = 6;
-//pub const LESQI: u8 = 7;
-//pub const EQI: u8 = 8;
+pub const GRI: u8 = 5;
+pub const LEQI: u8 = 6;
+pub const LESQI: u8 = 7;
+pub const EQI: u8 = 8;
 pub const NEI: u8 = 9;
 pub const ADDR: u8 = 10;
-//pub const SUBR: u8 = 11;
-//pub const MULR: u8 = 12;
-//pub const DIVR: u8 = 13;
-#[allow(dead_code)]
+pub const SUBR: u8 = 11;
+pub const MULR: u8 = 12;
+pub const DIVR: u8 = 13;
 pub const GEQR: u8 = 14;
-//pub const GRR: u8 = 15;
-//pub const LEQR: u8 = 16;
-//pub const LESQR: u8 = 17;
-//pub const EQR: u8 = 18;
+pub const GRR: u8 = 15;
+pub const LEQR: u8 = 16;
+pub const LESQR: u8 = 17;
+pub const EQR: u8 = 18;
 pub const NER: u8 = 19;
 pub const CSTI: u8 = 20;
 pub const CSTR: u8 = 21;
 pub const RDI: u8 = 24; // 24 % 4 = 0
-                        //pub const RDR: u8 = 25; // 25 % 4 = 1
-                        //pub const RDB: u8 = 26; // 26 % 4 = 2
+pub const RDR: u8 = 25; // 25 % 4 = 1
+pub const RDB: u8 = 26; // 26 % 4 = 2
 pub const RDS: u8 = 27; // 27 % 4 = 3
 pub const WRI: u8 = 28; // 28 % 4 = 0
-                        //pub const WRR: u8 = 29; // 29 % 4 = 1
-                        //pub const WRB: u8 = 30; // 30 % 4 = 2
+pub const WRR: u8 = 29; // 29 % 4 = 1
+pub const WRB: u8 = 30; // 30 % 4 = 2
 pub const WRS: u8 = 31; // 31 % 4 = 3
 pub const FLU: u8 = 32;
 pub const FLN: u8 = 33;
 pub const LDI: u8 = 36; // 36 % 4 = 0
-                        //pub const LDR: u8 = 37; // 37 % 4 = 1
-                        //pub const LDB: u8 = 38; // 38 % 4 = 2
-                        //pub const LDS: u8 = 39; // 39 % 4 = 3
+pub const LDR: u8 = 37; // 37 % 4 = 1
+pub const LDB: u8 = 38; // 38 % 4 = 2
+pub const LDS: u8 = 39; // 39 % 4 = 3
 pub const STRI: u8 = 40; // 40 % 4 = 0
-                         //pub const STRR: u8 = 41; // 41 % 4 = 1
-                         //pub const STRB: u8 = 42; // 42 % 4 = 2
+pub const STRR: u8 = 41; // 41 % 4 = 1
+pub const STRB: u8 = 42; // 42 % 4 = 2
 pub const STRS: u8 = 43; // 43 % 4 = 3
 pub const JUMP: u8 = 44;
 pub const JEQ: u8 = 45;
@@ -49,14 +46,13 @@ pub const CALL: u8 = 48;
 pub const RET: u8 = 49;
 pub const EXT: u8 = 50;
 pub const LDIC: u8 = 51; // 51 % 4 = 3
-#[allow(dead_code)]
 pub const LDRC: u8 = 52; // 52 % 4 = 0
-                         //pub const LDBC: u8 = 53; // 53 % 4 = 1
+pub const LDBC: u8 = 53; // 53 % 4 = 1
 pub const LDSC: u8 = 54; // 54 % 4 = 2
 pub const PARAM: u8 = 55;
 pub const STRIP: u8 = 56; // 56 % 4 = 0
-                          //pub const STRRP: u8 = 57; // 57 % 4 = 1
-                          //pub const STRBP: u8 = 58; // 58 % 4 = 2
+pub const STRRP: u8 = 57; // 57 % 4 = 1
+pub const STRBP: u8 = 58; // 58 % 4 = 2
 pub const STRSP: u8 = 59; // 59 % 4 = 3
 pub const FUNC: u8 = 60;
 pub const BFOR: u8 = 61;
@@ -67,17 +63,403 @@ pub const NEGR: u8 = 65;
 pub const NOT: u8 = 66;
 
 pub const GEQS: u8 = 67;
-//pub const GRS: u8 = 68;
-//pub const LEQS: u8 = 69;
-//pub const LESQS: u8 = 70;
-//pub const EQS: u8 = 71;
+pub const GRS: u8 = 68;
+pub const LEQS: u8 = 69;
+pub const LESQS: u8 = 70;
+pub const EQS: u8 = 71;
 pub const NES: u8 = 72;
 
 pub const GEQB: u8 = 73;
-//pub const GRB: u8 = 74;
-//pub const LEQB: u8 = 75;
-//pub const LESQB: u8 = 76;
-//pub const EQB: u8 = 77;
+pub const GRB: u8 = 74;
+pub const LEQB: u8 = 75;
+pub const LESQB: u8 = 76;
+pub const EQB: u8 = 77;
 pub const NEB: u8 = 78;
 
 pub const INIT: u8 = 80;
+
+// raw byte I/O: RDB and WRB are already taken by the
+// boolean read/write slots (26 and 30)
+pub const RDRAW: u8 = 81;
+pub const WRRAW: u8 = 82;
+
+// optional file header: HDR followed by a flag byte,
+// allowed only as the very first byte of the file
+pub const HDR: u8 = 83;
+pub const HDR_LITTLE_ENDIAN: u8 = 1;
+// the input comes from a console, see engine::IoProfile
+pub const HDR_INTERACTIVE: u8 = 2;
+pub const HDR_KNOWN_FLAGS: u8 = HDR_LITTLE_ENDIAN | HDR_INTERACTIVE;
+
+pub const SETBOOLFMT: u8 = 84;
+
+// write several values at once, separated by a space:
+// WRVN is followed by the value count and by a descriptor
+// packing the kind of each value in two bits (low bits first,
+// same numbering as the opcode % 4 groups)
+pub const WRVN: u8 = 85;
+
+// bind the program arguments to global slots: ARGS is followed
+// by the argument count and, for each argument, by its kind
+// (opcode % 4 numbering) and by the u16 global address
+pub const ARGS: u8 = 86;
+
+// FINI followed by a u16 function id declares that function
+// as the finalizer, the exit code goes in its first local integer
+pub const FINI: u8 = 87;
+
+// push a copy of the value on top of the stack
+pub const DUPI: u8 = 88; // 88 % 4 = 0
+pub const DUPR: u8 = 89; // 89 % 4 = 1
+pub const DUPB: u8 = 90; // 90 % 4 = 2
+pub const DUPS: u8 = 91; // 91 % 4 = 3
+
+// push the number of strings created at run time
+// and still alive, then their total size in bytes
+pub const STRSTAT: u8 = 92;
+
+// TIMER followed by a u16 function id pops a period in
+// milliseconds and calls that function every time it expires,
+// a period of zero or less cancels the timer. The handler is a
+// function rather than a label: it gets its own record and its
+// RET resumes the interrupted code, whose operand stacks are
+// put aside meanwhile
+pub const TIMER: u8 = 93;
+
+// single byte encodings of the most common constants,
+// same meaning as the matching LDIC and LDBC
+pub const LDI0: u8 = 94;
+pub const LDI1: u8 = 95;
+pub const LDFALSE: u8 = 96;
+pub const LDTRUE: u8 = 97;
+
+// JUMP, JEQ and JNE with a signed 16 bit offset in place of the
+// label: the target is counted in instructions from the jump
+// itself, so code using them can be moved without renumbering
+pub const JUMPR: u8 = 98;
+pub const JEQR: u8 = 99;
+pub const JNER: u8 = 100;
+
+// pop a name and push the value given to it with --define,
+// the empty string when the name is not defined
+pub const GETDEF: u8 = 101;
+
+// META followed by the u16 number of pairs, then every key and
+// every value as a u16 length and its UTF-8 bytes. Provenance
+// only: the engine never looks at it
+pub const META: u8 = 102;
+
+// PURE followed by a u16 function id and by a WRVN like descriptor
+// of the values it leaves on the stack: the function depends only on
+// its parameters and has no other effect, its results may be cached
+pub const PURE: u8 = 103;
+
+// string builders: SBNEW starts an empty text, SBAPPx pops a value
+// and appends it the way WRx would write it, SBFINISH pops the
+// latest builder and pushes its text as a new string. Builders
+// nest, so a message can be built while another one is open
+pub const SBAPPI: u8 = 104; // 104 % 4 = 0
+pub const SBAPPR: u8 = 105; // 105 % 4 = 1
+pub const SBAPPB: u8 = 106; // 106 % 4 = 2
+pub const SBAPPS: u8 = 107; // 107 % 4 = 3
+pub const SBNEW: u8 = 108;
+pub const SBFINISH: u8 = 109;
+
+// pop a string and push its 64 bit FNV-1a hash folded to 32 bits,
+// the same on every run and platform
+pub const HASHS: u8 = 110;
+
+// remainder of the division, with the sign of the dividend
+pub const MODI: u8 = 111;
+pub const MODR: u8 = 112;
+
+// lhs raised to rhs
+pub const POWI: u8 = 113;
+pub const POWR: u8 = 114;
+
+// bitwise operations on integers: NOTI pops one value, the others
+// two. Shift amounts are taken modulo 32 and SHRI keeps the sign
+pub const ANDI: u8 = 115;
+pub const ORI: u8 = 116;
+pub const XORI: u8 = 117;
+pub const NOTI: u8 = 118;
+pub const SHLI: u8 = 119;
+pub const SHRI: u8 = 120;
+
+// functions of the real on top of the stack, LOG is the natural
+// logarithm. Values outside the domain give NaN
+pub const SQRT: u8 = 121;
+pub const SIN: u8 = 122;
+pub const COS: u8 = 123;
+pub const LOG: u8 = 124;
+pub const EXP: u8 = 125;
+
+// absolute value, smaller and greater of two values
+pub const ABSI: u8 = 126;
+pub const ABSR: u8 = 127;
+pub const MINI: u8 = 128;
+pub const MINR: u8 = 129;
+pub const MAXI: u8 = 130;
+pub const MAXR: u8 = 131;
+
+// real to integer with an explicit rounding, unlike CSTI a NaN or
+// a value outside the integer range is a runtime error
+pub const CSTI_TRUNC: u8 = 132;
+pub const CSTI_FLOOR: u8 = 133;
+pub const CSTI_CEIL: u8 = 134;
+pub const CSTI_EVEN: u8 = 135;
+
+// positions count characters, not bytes: SUBSTR pops the length,
+// the start and the string and pushes the new string, CHARAT pops
+// the position and the string and pushes the code of the character
+pub const SUBSTR: u8 = 136;
+pub const CHARAT: u8 = 137;
+
+// pop a string and push a normalized copy
+pub const UPPER: u8 = 138;
+pub const LOWER: u8 = 139;
+pub const TRIM: u8 = 140;
+
+// conversions between strings and numbers: STR2x pops a string and
+// pushes the value, 0 when the text is not a number, and whether
+// the parse succeeded. x2STR formats the value the way WRx does
+pub const STR2INT: u8 = 141;
+pub const STR2REAL: u8 = 142;
+pub const INT2STR: u8 = 143;
+pub const REAL2STR: u8 = 144;
+
+// arrays, referred to by integer handles: ANEWx pops the length
+// and pushes the handle of a new array filled with 0, 0.0, false
+// or the empty string. ALDx pops the index and the handle and
+// pushes the element, ASTx pops the value too and stores it.
+// ALEN pops a handle and pushes the length of its array
+pub const ANEWI: u8 = 145;
+pub const ANEWR: u8 = 146;
+pub const ANEWB: u8 = 147;
+pub const ANEWS: u8 = 148;
+pub const ALDI: u8 = 149;
+pub const ALDR: u8 = 150;
+pub const ALDB: u8 = 151;
+pub const ALDS: u8 = 152;
+pub const ASTI: u8 = 153;
+pub const ASTR: u8 = 154;
+pub const ASTB: u8 = 155;
+pub const ASTS: u8 = 156;
+pub const ALEN: u8 = 157;
+
+// free slots inside the opcode groups, kept for future
+// revisions of the format
+pub const RESERVED: [(u8, u8); 3] = [(22, 23), (34, 35), (79, 79)];
+
+pub fn is_reserved(byte: u8) -> bool {
+    RESERVED
+        .iter()
+        .any(|(first, last)| (*first..=*last).contains(&byte))
+}
+
+// opcodes still executed but scheduled for removal,
+// each one with a hint about its replacement
+pub const DEPRECATED: &[(u8, &str)] = &[];
+
+// operands following the opcode byte, all numbers are big endian
+// unless the header says otherwise
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operands {
+    Nothing,
+    Address,
+    Label,
+    Function,
+    Integer,
+    Real,
+    Bool,
+    Str,
+    Offset,
+    Init,
+    Kinds,
+    Pure,
+    Args,
+    Meta,
+}
+
+pub struct Instruction {
+    pub name: &'static str,
+    pub byte: u8,
+    pub operands: Operands,
+    // values popped -- values pushed, i r b s for the four kinds
+    pub stack: &'static str,
+    pub description: &'static str,
+}
+
+const fn op(
+    name: &'static str,
+    byte: u8,
+    operands: Operands,
+    stack: &'static str,
+    description: &'static str,
+) -> Instruction {
+    Instruction {
+        name,
+        byte,
+        operands,
+        stack,
+        description,
+    }
+}
+
+// every instruction the loader accepts, the assembler and the
+// instruction set reference are both built on this table
+#[rustfmt::skip]
+pub const ISA: &[Instruction] = &[
+    op("ADDI", ADDI, Operands::Nothing, "i i -- i", "integer sum, wraps around on overflow"),
+    op("SUBI", SUBI, Operands::Nothing, "i i -- i", "integer difference, wraps around on overflow"),
+    op("MULI", MULI, Operands::Nothing, "i i -- i", "integer product, wraps around on overflow"),
+    op("DIVI", DIVI, Operands::Nothing, "i i -- i", "integer quotient, a zero divisor is a runtime error"),
+    op("GEQI", GEQI, Operands::Nothing, "i i -- b", "integer greater or equal"),
+    op("GRI", GRI, Operands::Nothing, "i i -- b", "integer greater"),
+    op("LEQI", LEQI, Operands::Nothing, "i i -- b", "integer less or equal"),
+    op("LESQI", LESQI, Operands::Nothing, "i i -- b", "integer less"),
+    op("EQI", EQI, Operands::Nothing, "i i -- b", "integer equal"),
+    op("NEI", NEI, Operands::Nothing, "i i -- b", "integer not equal"),
+    op("ADDR", ADDR, Operands::Nothing, "r r -- r", "real sum"),
+    op("SUBR", SUBR, Operands::Nothing, "r r -- r", "real difference"),
+    op("MULR", MULR, Operands::Nothing, "r r -- r", "real product"),
+    op("DIVR", DIVR, Operands::Nothing, "r r -- r", "real quotient"),
+    op("GEQR", GEQR, Operands::Nothing, "r r -- b", "real greater or equal"),
+    op("GRR", GRR, Operands::Nothing, "r r -- b", "real greater"),
+    op("LEQR", LEQR, Operands::Nothing, "r r -- b", "real less or equal"),
+    op("LESQR", LESQR, Operands::Nothing, "r r -- b", "real less"),
+    op("EQR", EQR, Operands::Nothing, "r r -- b", "real equal"),
+    op("NER", NER, Operands::Nothing, "r r -- b", "real not equal"),
+    op("CSTI", CSTI, Operands::Nothing, "r -- i", "real to integer, truncated and saturated"),
+    op("CSTR", CSTR, Operands::Nothing, "i -- r", "integer to real"),
+    op("RDI", RDI, Operands::Nothing, "-- i", "read an integer from the input"),
+    op("RDR", RDR, Operands::Nothing, "-- r", "read a real from the input"),
+    op("RDB", RDB, Operands::Nothing, "-- b", "read a boolean from the input"),
+    op("RDS", RDS, Operands::Nothing, "-- s", "read a line from the input"),
+    op("WRI", WRI, Operands::Nothing, "i --", "write an integer"),
+    op("WRR", WRR, Operands::Nothing, "r --", "write a real"),
+    op("WRB", WRB, Operands::Nothing, "b --", "write a boolean with the current boolean format"),
+    op("WRS", WRS, Operands::Nothing, "s --", "write a string"),
+    op("FLU", FLU, Operands::Nothing, "--", "flush the output"),
+    op("FLN", FLN, Operands::Nothing, "--", "write a new line"),
+    op("LDI", LDI, Operands::Address, "-- i", "load an integer variable"),
+    op("LDR", LDR, Operands::Address, "-- r", "load a real variable"),
+    op("LDB", LDB, Operands::Address, "-- b", "load a boolean variable"),
+    op("LDS", LDS, Operands::Address, "-- s", "load a string variable"),
+    op("STRI", STRI, Operands::Address, "i --", "store an integer variable"),
+    op("STRR", STRR, Operands::Address, "r --", "store a real variable"),
+    op("STRB", STRB, Operands::Address, "b --", "store a boolean variable"),
+    op("STRS", STRS, Operands::Address, "s --", "store a string variable"),
+    op("JUMP", JUMP, Operands::Label, "--", "jump to a label of the current block"),
+    op("JEQ", JEQ, Operands::Label, "b --", "jump to a label when the value is true"),
+    op("JNE", JNE, Operands::Label, "b --", "jump to a label when the value is false"),
+    op("LBL", LBL, Operands::Label, "--", "declare a label, local to its block"),
+    op("CALL", CALL, Operands::Function, "--", "call a function with the record opened by PARAM"),
+    op("RET", RET, Operands::Nothing, "--", "return from a function, values left on the stacks are its results"),
+    op("EXT", EXT, Operands::Nothing, "--", "stop the program, the finalizer still runs"),
+    op("LDIC", LDIC, Operands::Integer, "-- i", "load an integer constant"),
+    op("LDRC", LDRC, Operands::Real, "-- r", "load a real constant"),
+    op("LDBC", LDBC, Operands::Bool, "-- b", "load a boolean constant"),
+    op("LDSC", LDSC, Operands::Str, "-- s", "load a string constant"),
+    op("PARAM", PARAM, Operands::Function, "--", "open the activation record of the next call to a function"),
+    op("STRIP", STRIP, Operands::Address, "i --", "store an integer parameter into the open record"),
+    op("STRRP", STRRP, Operands::Address, "r --", "store a real parameter into the open record"),
+    op("STRBP", STRBP, Operands::Address, "b --", "store a boolean parameter into the open record"),
+    op("STRSP", STRSP, Operands::Address, "s --", "store a string parameter into the open record"),
+    op("FUNC", FUNC, Operands::Nothing, "--", "end the previous block and start the next function"),
+    op("BFOR", BFOR, Operands::Nothing, "i --", "move the value onto the for loop stack"),
+    op("CFOR", CFOR, Operands::Nothing, "-- i", "copy the top of the for loop stack"),
+    op("EFOR", EFOR, Operands::Nothing, "--", "drop the top of the for loop stack"),
+    op("NEGI", NEGI, Operands::Nothing, "i -- i", "integer negation, wraps around on overflow"),
+    op("NEGR", NEGR, Operands::Nothing, "r -- r", "real negation"),
+    op("NOT", NOT, Operands::Nothing, "b -- b", "boolean negation"),
+    op("GEQS", GEQS, Operands::Nothing, "s s -- b", "string greater or equal"),
+    op("GRS", GRS, Operands::Nothing, "s s -- b", "string greater"),
+    op("LEQS", LEQS, Operands::Nothing, "s s -- b", "string less or equal"),
+    op("LESQS", LESQS, Operands::Nothing, "s s -- b", "string less"),
+    op("EQS", EQS, Operands::Nothing, "s s -- b", "string equal"),
+    op("NES", NES, Operands::Nothing, "s s -- b", "string not equal"),
+    op("GEQB", GEQB, Operands::Nothing, "b b -- b", "boolean greater or equal, false < true"),
+    op("GRB", GRB, Operands::Nothing, "b b -- b", "boolean greater, false < true"),
+    op("LEQB", LEQB, Operands::Nothing, "b b -- b", "boolean less or equal, false < true"),
+    op("LESQB", LESQB, Operands::Nothing, "b b -- b", "boolean less, false < true"),
+    op("EQB", EQB, Operands::Nothing, "b b -- b", "boolean equal"),
+    op("NEB", NEB, Operands::Nothing, "b b -- b", "boolean not equal"),
+    op("INIT", INIT, Operands::Init, "--", "memory of the current block, once at its start"),
+    op("RDRAW", RDRAW, Operands::Nothing, "i -- i... i", "read up to n bytes, push them and the count read"),
+    op("WRRAW", WRRAW, Operands::Nothing, "i... i --", "pop n and write the n bytes below it, in push order"),
+    op("SETBOOLFMT", SETBOOLFMT, Operands::Nothing, "s s --", "words used by WRB for true and false"),
+    op("WRVN", WRVN, Operands::Kinds, "values --", "write several values separated by a space, in push order"),
+    op("ARGS", ARGS, Operands::Args, "--", "bind the program arguments to globals, before the main body"),
+    op("FINI", FINI, Operands::Function, "--", "declare the finalizer, the exit code is its first local integer"),
+    op("DUPI", DUPI, Operands::Nothing, "i -- i i", "duplicate an integer"),
+    op("DUPR", DUPR, Operands::Nothing, "r -- r r", "duplicate a real"),
+    op("DUPB", DUPB, Operands::Nothing, "b -- b b", "duplicate a boolean"),
+    op("DUPS", DUPS, Operands::Nothing, "s -- s s", "duplicate a string"),
+    op("STRSTAT", STRSTAT, Operands::Nothing, "-- i i", "count and bytes of the strings created at run time"),
+    op("TIMER", TIMER, Operands::Function, "i --", "call a function every n milliseconds, n <= 0 cancels"),
+    op("LDI0", LDI0, Operands::Nothing, "-- i", "load the integer 0"),
+    op("LDI1", LDI1, Operands::Nothing, "-- i", "load the integer 1"),
+    op("LDFALSE", LDFALSE, Operands::Nothing, "-- b", "load false"),
+    op("LDTRUE", LDTRUE, Operands::Nothing, "-- b", "load true"),
+    op("JUMPR", JUMPR, Operands::Offset, "--", "jump by an instruction offset"),
+    op("JEQR", JEQR, Operands::Offset, "b --", "jump by an instruction offset when the value is true"),
+    op("JNER", JNER, Operands::Offset, "b --", "jump by an instruction offset when the value is false"),
+    op("GETDEF", GETDEF, Operands::Nothing, "s -- s", "value given to a name with --define, empty when missing"),
+    op("META", META, Operands::Meta, "--", "key/value pairs about the program, never executed"),
+    op("PURE", PURE, Operands::Pure, "--", "declare a function pure and the kinds of its results"),
+    op("SBAPPI", SBAPPI, Operands::Nothing, "i --", "append an integer to the latest string builder"),
+    op("SBAPPR", SBAPPR, Operands::Nothing, "r --", "append a real to the latest string builder"),
+    op("SBAPPB", SBAPPB, Operands::Nothing, "b --", "append a boolean, in the WRB words, to the latest string builder"),
+    op("SBAPPS", SBAPPS, Operands::Nothing, "s --", "append a string to the latest string builder"),
+    op("SBNEW", SBNEW, Operands::Nothing, "--", "start a new empty string builder"),
+    op("SBFINISH", SBFINISH, Operands::Nothing, "-- s", "close the latest string builder and push its text"),
+    op("HASHS", HASHS, Operands::Nothing, "s -- i", "hash of a string, stable across runs"),
+    op("MODI", MODI, Operands::Nothing, "i i -- i", "integer remainder, a zero divisor is a runtime error"),
+    op("MODR", MODR, Operands::Nothing, "r r -- r", "real remainder"),
+    op("POWI", POWI, Operands::Nothing, "i i -- i", "integer power, wraps around on overflow, a negative exponent truncates like DIVI"),
+    op("POWR", POWR, Operands::Nothing, "r r -- r", "real power"),
+    op("ANDI", ANDI, Operands::Nothing, "i i -- i", "bitwise and"),
+    op("ORI", ORI, Operands::Nothing, "i i -- i", "bitwise or"),
+    op("XORI", XORI, Operands::Nothing, "i i -- i", "bitwise exclusive or"),
+    op("NOTI", NOTI, Operands::Nothing, "i -- i", "bitwise complement"),
+    op("SHLI", SHLI, Operands::Nothing, "i i -- i", "shift lhs left by rhs modulo 32 bits"),
+    op("SHRI", SHRI, Operands::Nothing, "i i -- i", "shift lhs right by rhs modulo 32 bits, keeping the sign"),
+    op("SQRT", SQRT, Operands::Nothing, "r -- r", "square root"),
+    op("SIN", SIN, Operands::Nothing, "r -- r", "sine of an angle in radians"),
+    op("COS", COS, Operands::Nothing, "r -- r", "cosine of an angle in radians"),
+    op("LOG", LOG, Operands::Nothing, "r -- r", "natural logarithm"),
+    op("EXP", EXP, Operands::Nothing, "r -- r", "e raised to the value"),
+    op("ABSI", ABSI, Operands::Nothing, "i -- i", "integer absolute value, wraps around like NEGI"),
+    op("ABSR", ABSR, Operands::Nothing, "r -- r", "real absolute value"),
+    op("MINI", MINI, Operands::Nothing, "i i -- i", "smaller of two integers"),
+    op("MINR", MINR, Operands::Nothing, "r r -- r", "smaller of two reals, ignoring a NaN"),
+    op("MAXI", MAXI, Operands::Nothing, "i i -- i", "greater of two integers"),
+    op("MAXR", MAXR, Operands::Nothing, "r r -- r", "greater of two reals, ignoring a NaN"),
+    op("CSTI_TRUNC", CSTI_TRUNC, Operands::Nothing, "r -- i", "real to integer, rounded toward zero"),
+    op("CSTI_FLOOR", CSTI_FLOOR, Operands::Nothing, "r -- i", "real to integer, rounded down"),
+    op("CSTI_CEIL", CSTI_CEIL, Operands::Nothing, "r -- i", "real to integer, rounded up"),
+    op("CSTI_EVEN", CSTI_EVEN, Operands::Nothing, "r -- i", "real to integer, rounded to the nearest, ties to even"),
+    op("SUBSTR", SUBSTR, Operands::Nothing, "s i i -- s", "the characters from start to start + length"),
+    op("CHARAT", CHARAT, Operands::Nothing, "s i -- i", "code of the character at a position"),
+    op("UPPER", UPPER, Operands::Nothing, "s -- s", "the string in upper case"),
+    op("LOWER", LOWER, Operands::Nothing, "s -- s", "the string in lower case"),
+    op("TRIM", TRIM, Operands::Nothing, "s -- s", "the string without leading and trailing whitespace"),
+    op("STR2INT", STR2INT, Operands::Nothing, "s -- i b", "parse an integer, false and 0 when the text is not one"),
+    op("STR2REAL", STR2REAL, Operands::Nothing, "s -- r b", "parse a real, false and 0 when the text is not one"),
+    op("INT2STR", INT2STR, Operands::Nothing, "i -- s", "an integer as WRI writes it"),
+    op("REAL2STR", REAL2STR, Operands::Nothing, "r -- s", "a real as WRR writes it"),
+    op("ANEWI", ANEWI, Operands::Nothing, "i -- i", "allocate an integer array, push its handle"),
+    op("ANEWR", ANEWR, Operands::Nothing, "i -- i", "allocate a real array, push its handle"),
+    op("ANEWB", ANEWB, Operands::Nothing, "i -- i", "allocate a boolean array, push its handle"),
+    op("ANEWS", ANEWS, Operands::Nothing, "i -- i", "allocate a string array, push its handle"),
+    op("ALDI", ALDI, Operands::Nothing, "i i -- i", "load an element of an integer array"),
+    op("ALDR", ALDR, Operands::Nothing, "i i -- r", "load an element of a real array"),
+    op("ALDB", ALDB, Operands::Nothing, "i i -- b", "load an element of a boolean array"),
+    op("ALDS", ALDS, Operands::Nothing, "i i -- s", "load an element of a string array"),
+    op("ASTI", ASTI, Operands::Nothing, "i i i --", "store an element of an integer array"),
+    op("ASTR", ASTR, Operands::Nothing, "i i r --", "store an element of a real array"),
+    op("ASTB", ASTB, Operands::Nothing, "i i b --", "store an element of a boolean array"),
+    op("ASTS", ASTS, Operands::Nothing, "i i s --", "store an element of a string array"),
+    op("ALEN", ALEN, Operands::Nothing, "i -- i", "length of an array"),
+];
@@ -0,0 +1,844 @@
+use crate::command_definition::{
+    AddrSize, BitOp, Block, BlockId, Command, Constant, ControlFlow, Kind, MemorySize, Operator,
+    Program, ProgramMemory, LOCAL_MASK,
+};
+use std::collections::{HashMap, HashSet};
+
+// functions longer than this are never inlined
+const INLINE_THRESHOLD: usize = 32;
+
+pub fn optimize(prog: &mut Program, mem: &mut ProgramMemory) {
+    inline_functions(prog, mem);
+    hoist_invariants(prog, mem);
+    reuse_loads(prog);
+}
+
+// the passes find basic blocks by their LBL commands,
+// relative jumps of loaded programs have none
+fn explicit_labels(prog: &mut Program) {
+    let blocks = std::iter::once(&mut prog.body).chain(prog.func.iter_mut());
+    for block in blocks {
+        block.make_labels_explicit();
+    }
+}
+
+// a timer handler can run between any two commands
+// and store into any global variable
+fn sets_timer(prog: &Program) -> bool {
+    prog.blocks()
+        .flat_map(|(_, block)| block.code.iter())
+        .any(|cmd| matches!(cmd, Command::SetTimer(_)))
+}
+
+// a load of the address whose value is still on top of
+// its stack becomes a DUP. Any command popping that stack
+// forgets the address, so stores cannot alias it
+pub fn reuse_loads(prog: &mut Program) {
+    explicit_labels(prog);
+    let timers = sets_timer(prog);
+    let blocks = std::iter::once(&mut prog.body).chain(prog.func.iter_mut());
+    for block in blocks {
+        let mut top: HashMap<Kind, AddrSize> = HashMap::new();
+        for cmd in block.code.iter_mut() {
+            if let Command::MemoryLoad(kind, addr) = *cmd {
+                let global = addr & LOCAL_MASK == 0;
+                if top.get(&kind) == Some(&addr) && !(global && timers) {
+                    *cmd = Command::Duplicate(kind);
+                } else {
+                    top.insert(kind, addr);
+                }
+                continue;
+            }
+            match touched_stacks(cmd) {
+                Some(kinds) => {
+                    for kind in kinds {
+                        top.remove(&kind);
+                    }
+                }
+                None => top.clear(),
+            }
+        }
+    }
+}
+
+// None at basic block boundaries
+fn touched_stacks(cmd: &Command) -> Option<Vec<Kind>> {
+    let kinds = match cmd {
+        Command::Integer(_) => vec![Kind::Integer, Kind::Bool],
+        Command::Real(_) => vec![Kind::Real, Kind::Bool],
+        Command::CastInt | Command::CastReal | Command::RoundInt(_) => {
+            vec![Kind::Integer, Kind::Real]
+        }
+        Command::MemoryLoad(kind, _)
+        | Command::MemoryStore(kind, _)
+        | Command::StoreParam(kind, _)
+        | Command::Input(kind)
+        | Command::Output(kind)
+        | Command::Unary(kind)
+        | Command::Absolute(kind)
+        | Command::Append(kind)
+        | Command::LoadSlot(kind, _)
+        | Command::StoreSlot(kind, _) => vec![*kind],
+        Command::OutputMany(kinds) => kinds.clone(),
+        Command::RawInput
+        | Command::RawOutput
+        | Command::ForControl(_)
+        | Command::StringStats
+        | Command::SetTimer(_) => vec![Kind::Integer],
+        Command::SetBoolFormat
+        | Command::GetDefine
+        | Command::OutputLine
+        | Command::FinishBuilder
+        | Command::Transform(_) => vec![Kind::Str],
+        Command::ConstantLoad(Constant::Integer(_)) => vec![Kind::Integer],
+        Command::ConstantLoad(Constant::Real(_)) => vec![Kind::Real],
+        Command::ConstantLoad(Constant::Bool(_)) => vec![Kind::Bool],
+        Command::ConstantLoad(Constant::Str(_)) => vec![Kind::Str],
+        Command::StrCompare(_) => vec![Kind::Str, Kind::Bool],
+        Command::HashString | Command::Substring | Command::CharAt => {
+            vec![Kind::Str, Kind::Integer]
+        }
+        Command::ParseNumber(kind) => vec![Kind::Str, *kind, Kind::Bool],
+        Command::FormatNumber(kind) => vec![*kind, Kind::Str],
+        Command::ArrayNew(_) | Command::ArrayLength => vec![Kind::Integer],
+        Command::ArrayLoad(kind) | Command::ArrayStore(kind) => vec![Kind::Integer, *kind],
+        Command::Bitwise(_) => vec![Kind::Integer],
+        Command::MathIntrinsic(_) => vec![Kind::Real],
+        Command::BoolCompare(_) => vec![Kind::Bool],
+        // the copy has the same value
+        Command::Duplicate(_) | Command::Flush(_) | Command::NewRecord(_) | Command::NewBuilder => {
+            vec![]
+        }
+        Command::Control(_, _) | Command::Exit => return None,
+    };
+    Some(kinds)
+}
+
+// compute the invariant expressions of innermost loops once,
+// into a new memory slot stored right before the loop label
+pub fn hoist_invariants(prog: &mut Program, mem: &mut ProgramMemory) {
+    explicit_labels(prog);
+    let timers = sets_timer(prog);
+    let blocks = std::iter::once((BlockId::Main, &mut prog.body, &mut mem.main)).chain(
+        prog.func
+            .iter_mut()
+            .zip(mem.func.iter_mut())
+            .enumerate()
+            .map(|(id, (block, size))| (BlockId::Function(id), block, size)),
+    );
+    for (id, block, size) in blocks {
+        let mut code = block.code.clone();
+        let mut changed = false;
+        // loops are disjoint: going backward keeps
+        // the indices of the previous ones valid
+        for (begin, end) in innermost_loops(block).into_iter().rev() {
+            let hoisted = invariant_expressions(&block.code, begin, end, timers);
+            if let Some(new_code) = hoist_loop(&code, begin, end, &hoisted, id, size) {
+                code = new_code;
+                changed = true;
+            }
+        }
+        if changed {
+            *block = Block::new(code);
+        }
+    }
+}
+
+// a loop goes from a label to the last jump back to it
+fn innermost_loops(block: &Block) -> Vec<(usize, usize)> {
+    let mut loops: HashMap<usize, usize> = HashMap::new();
+    let mut jumps = Vec::new();
+    for (index, cmd) in block.code.iter().enumerate() {
+        if let Command::Control(ControlFlow::Jump, label)
+        | Command::Control(ControlFlow::JumpTrue, label)
+        | Command::Control(ControlFlow::JumpFalse, label) = cmd
+        {
+            let target = match block.labels.get(label) {
+                Some(target) => *target,
+                None => continue,
+            };
+            jumps.push((index, target));
+            if target < index {
+                let end = loops.entry(target).or_insert(index);
+                *end = (*end).max(index);
+            }
+        }
+    }
+
+    let mut output: Vec<(usize, usize)> = loops
+        .iter()
+        .map(|(begin, end)| (*begin, *end))
+        .filter(|(begin, end)| {
+            // no other loop inside or partially overlapping
+            let alone = loops
+                .iter()
+                .all(|(b, e)| (b == begin) || e < begin || b > end || (b < begin && e >= end));
+            // entered only through the label, by falling into it
+            let closed = jumps
+                .iter()
+                .all(|(from, to)| !(begin..=end).contains(&to) || (begin..=end).contains(&from));
+            alone && closed
+        })
+        .collect();
+    output.sort_unstable();
+    output
+}
+
+#[derive(Clone, Copy)]
+struct Expression {
+    kind: Kind,
+    begin: usize,
+    end: usize,
+    // integer arithmetic can panic
+    traps: bool,
+}
+
+// maximal expressions of at least two commands using only constants
+// and variables never stored inside the loop. Expressions that may
+// trap are taken only before the first control flow command, where
+// they would run at least once anyway. With timers every
+// global variable may change between two iterations
+fn invariant_expressions(
+    code: &[Command],
+    begin: usize,
+    end: usize,
+    timers: bool,
+) -> Vec<Expression> {
+    let body = &code[begin + 1..end];
+    let has_call = timers
+        || body
+            .iter()
+            .any(|cmd| matches!(cmd, Command::Control(ControlFlow::Call, _)));
+    let stored: HashSet<(Kind, AddrSize)> = body
+        .iter()
+        .filter_map(|cmd| match cmd {
+            Command::MemoryStore(kind, addr) => Some((*kind, *addr)),
+            Command::StoreParam(kind, addr) if addr & LOCAL_MASK == 0 => Some((*kind, *addr)),
+            _ => None,
+        })
+        .collect();
+    let header_end = body
+        .iter()
+        .position(|cmd| matches!(cmd, Command::Control(_, _)))
+        .map_or(end, |pos| pos + begin + 1);
+
+    let mut finder = InvariantFinder {
+        stacks: HashMap::new(),
+        header_end,
+        output: Vec::new(),
+    };
+    for (index, cmd) in body.iter().enumerate() {
+        let index = index + begin + 1;
+        match cmd {
+            Command::ConstantLoad(constant) => {
+                let kind = match constant {
+                    Constant::Integer(_) => Kind::Integer,
+                    Constant::Real(_) => Kind::Real,
+                    Constant::Bool(_) => Kind::Bool,
+                    Constant::Str(_) => Kind::Str,
+                };
+                finder.push_leaf(kind, index, true);
+            }
+            Command::MemoryLoad(kind, addr) => {
+                let global = addr & LOCAL_MASK == 0;
+                let variant = stored.contains(&(*kind, *addr)) || (global && has_call);
+                finder.push_leaf(*kind, index, !variant);
+            }
+            Command::Integer(Operator::Math(_)) => {
+                finder.binary(Kind::Integer, Kind::Integer, index, true)
+            }
+            Command::Integer(Operator::Rel(_)) => {
+                finder.binary(Kind::Integer, Kind::Bool, index, false)
+            }
+            Command::Real(Operator::Math(_)) => finder.binary(Kind::Real, Kind::Real, index, false),
+            Command::Real(Operator::Rel(_)) => finder.binary(Kind::Real, Kind::Bool, index, false),
+            Command::StrCompare(_) => finder.binary(Kind::Str, Kind::Bool, index, false),
+            Command::BoolCompare(_) => finder.binary(Kind::Bool, Kind::Bool, index, false),
+            Command::Unary(kind) | Command::Absolute(kind) => {
+                finder.unary(*kind, *kind, index, *kind == Kind::Integer)
+            }
+            Command::Bitwise(BitOp::Not) => {
+                finder.unary(Kind::Integer, Kind::Integer, index, false)
+            }
+            Command::Bitwise(_) => finder.binary(Kind::Integer, Kind::Integer, index, false),
+            Command::MathIntrinsic(_) => finder.unary(Kind::Real, Kind::Real, index, false),
+            Command::CastInt => finder.unary(Kind::Real, Kind::Integer, index, false),
+            Command::RoundInt(_) => finder.unary(Kind::Real, Kind::Integer, index, true),
+            Command::CastReal => finder.unary(Kind::Integer, Kind::Real, index, false),
+            _ => finder.clear(),
+        }
+    }
+    finder.clear();
+    finder.output.sort_unstable_by_key(|expr| expr.begin);
+    finder.output
+}
+
+struct InvariantFinder {
+    // symbolic typed stacks: None for values
+    // not computed by an invariant expression
+    stacks: HashMap<Kind, Vec<Option<Expression>>>,
+    header_end: usize,
+    output: Vec<Expression>,
+}
+
+impl InvariantFinder {
+    fn push_leaf(&mut self, kind: Kind, index: usize, invariant: bool) {
+        let expr = if invariant {
+            Some(Expression {
+                kind,
+                begin: index,
+                end: index,
+                traps: false,
+            })
+        } else {
+            None
+        };
+        self.stacks.entry(kind).or_default().push(expr);
+    }
+
+    fn pop(&mut self, kind: Kind) -> Option<Expression> {
+        self.stacks
+            .get_mut(&kind)
+            .and_then(|stack| stack.pop())
+            .flatten()
+    }
+
+    fn binary(&mut self, from: Kind, to: Kind, index: usize, traps: bool) {
+        let rhs = self.pop(from);
+        let lhs = self.pop(from);
+        let expr = match (lhs, rhs) {
+            (Some(lhs), Some(rhs)) if lhs.end + 1 == rhs.begin && rhs.end + 1 == index => {
+                Some(Expression {
+                    kind: to,
+                    begin: lhs.begin,
+                    end: index,
+                    traps: lhs.traps || rhs.traps || traps,
+                })
+            }
+            (lhs, rhs) => {
+                self.finish(lhs);
+                self.finish(rhs);
+                None
+            }
+        };
+        self.stacks.entry(to).or_default().push(expr);
+    }
+
+    fn unary(&mut self, from: Kind, to: Kind, index: usize, traps: bool) {
+        let expr = match self.pop(from) {
+            Some(arg) if arg.end + 1 == index => Some(Expression {
+                kind: to,
+                begin: arg.begin,
+                end: index,
+                traps: arg.traps || traps,
+            }),
+            arg => {
+                self.finish(arg);
+                None
+            }
+        };
+        self.stacks.entry(to).or_default().push(expr);
+    }
+
+    // commands not modelled here may pop anything
+    fn clear(&mut self) {
+        let exprs: Vec<Option<Expression>> =
+            self.stacks.drain().flat_map(|(_, stack)| stack).collect();
+        for expr in exprs {
+            self.finish(expr);
+        }
+    }
+
+    fn finish(&mut self, expr: Option<Expression>) {
+        if let Some(expr) = expr {
+            if expr.end > expr.begin && (!expr.traps || expr.end < self.header_end) {
+                self.output.push(expr);
+            }
+        }
+    }
+}
+
+fn hoist_loop(
+    code: &[Command],
+    begin: usize,
+    end: usize,
+    hoisted: &[Expression],
+    id: BlockId,
+    size: &mut MemorySize,
+) -> Option<Vec<Command>> {
+    let full = hoisted
+        .iter()
+        .any(|expr| size.count(&expr.kind) + hoisted.len() > LOCAL_MASK as usize);
+    if hoisted.is_empty() || full {
+        return None;
+    }
+    let mut preheader = Vec::new();
+    let mut body = Vec::new();
+    let mut index = begin;
+    for expr in hoisted {
+        let addr = new_slot(size, expr.kind, id);
+        preheader.extend_from_slice(&code[expr.begin..=expr.end]);
+        preheader.push(Command::MemoryStore(expr.kind, addr));
+        body.extend_from_slice(&code[index..expr.begin]);
+        body.push(Command::MemoryLoad(expr.kind, addr));
+        index = expr.end + 1;
+    }
+    body.extend_from_slice(&code[index..=end]);
+
+    let mut output = code[..begin].to_vec();
+    output.extend(preheader);
+    output.extend(body);
+    output.extend_from_slice(&code[end + 1..]);
+    Some(output)
+}
+
+fn new_slot(size: &mut MemorySize, kind: Kind, id: BlockId) -> AddrSize {
+    let count = size.count_mut(&kind);
+    let slot = *count as AddrSize;
+    *count += 1;
+    match id {
+        BlockId::Main => slot,
+        BlockId::Function(_) => slot | LOCAL_MASK,
+    }
+}
+
+// replace the calls to small leaf functions with their body,
+// callee locals are moved to fresh slots of the caller memory:
+// globals for the main body, locals for a function
+pub fn inline_functions(prog: &mut Program, mem: &mut ProgramMemory) {
+    explicit_labels(prog);
+    let candidates: Vec<Option<InlineCandidate>> =
+        prog.func.iter().map(InlineCandidate::new).collect();
+    // leaf functions never grow, so the sizes
+    // before inlining are the right ones
+    let callee_size = mem.func.clone();
+
+    let ids: Vec<BlockId> = prog.blocks().map(|(id, _)| id).collect();
+    for id in ids {
+        let (block, size) = match id {
+            BlockId::Main => (&mut prog.body, &mut mem.main),
+            BlockId::Function(func) => match mem.func.get_mut(func) {
+                Some(size) => (&mut prog.func[func], size),
+                None => continue,
+            },
+        };
+        let mut inliner = Inliner {
+            caller: id,
+            caller_size: size,
+            callee_size: &callee_size,
+            candidates: &candidates,
+            slots: HashMap::new(),
+        };
+        if let Some(code) = inliner.rewrite(&block.code) {
+            *block = Block::new(code);
+        }
+    }
+}
+
+struct InlineCandidate {
+    body: Vec<Command>,
+    // local slots the callee reads before storing to them:
+    // every call site must set them as parameters
+    inputs: HashSet<(Kind, AddrSize)>,
+}
+
+impl InlineCandidate {
+    fn new(block: &Block) -> Option<Self> {
+        if block.code.len() > INLINE_THRESHOLD {
+            return None;
+        }
+        let (ret, body) = block.code.split_last()?;
+        if !matches!(ret, Command::Control(ControlFlow::Ret, _)) {
+            return None;
+        }
+        let mut stored = HashSet::new();
+        let mut inputs = HashSet::new();
+        for cmd in body {
+            match cmd {
+                Command::Control(_, _)
+                | Command::NewRecord(_)
+                | Command::StoreParam(_, _)
+                | Command::ForControl(_)
+                | Command::Exit => return None,
+                Command::MemoryLoad(kind, addr)
+                    if addr & LOCAL_MASK != 0 && !stored.contains(&(*kind, *addr)) =>
+                {
+                    inputs.insert((*kind, *addr));
+                }
+                Command::MemoryStore(kind, addr) if addr & LOCAL_MASK != 0 => {
+                    stored.insert((*kind, *addr));
+                }
+                _ => {}
+            }
+        }
+        Some(Self {
+            body: body.to_vec(),
+            inputs,
+        })
+    }
+}
+
+struct Inliner<'a> {
+    caller: BlockId,
+    caller_size: &'a mut MemorySize,
+    callee_size: &'a [MemorySize],
+    candidates: &'a [Option<InlineCandidate>],
+    // first caller slot given to the locals of each inlined callee
+    slots: HashMap<usize, MemorySize>,
+}
+
+impl<'a> Inliner<'a> {
+    // None when no call was inlined
+    fn rewrite(&mut self, code: &[Command]) -> Option<Vec<Command>> {
+        let mut output = Vec::with_capacity(code.len());
+        let mut changed = false;
+        let mut index = 0;
+        while index < code.len() {
+            if let Command::NewRecord(func) = code[index] {
+                if let Some((call, inlined)) = self.inline_call(func, &code[index + 1..]) {
+                    output.extend(inlined);
+                    index += call + 2;
+                    changed = true;
+                    continue;
+                }
+            }
+            output.push(code[index].clone());
+            index += 1;
+        }
+        if changed {
+            Some(output)
+        } else {
+            None
+        }
+    }
+
+    // code starts right after NewRecord(func), returns the index
+    // of the matching call and the code replacing the whole sequence
+    fn inline_call(&mut self, func: usize, code: &[Command]) -> Option<(usize, Vec<Command>)> {
+        let candidate = self.candidates.get(func)?.as_ref()?;
+        let call = code.iter().position(|cmd| {
+            matches!(
+                cmd,
+                Command::Control(_, _) | Command::NewRecord(_) | Command::Exit
+            )
+        })?;
+        match code[call] {
+            Command::Control(ControlFlow::Call, f) if f == func => {}
+            _ => return None,
+        }
+
+        let params: HashSet<(Kind, AddrSize)> = code[..call]
+            .iter()
+            .filter_map(|cmd| match cmd {
+                Command::StoreParam(kind, addr) => Some((*kind, *addr)),
+                _ => None,
+            })
+            .collect();
+        if !candidate.inputs.is_subset(&params) {
+            return None;
+        }
+
+        let base = self.allocate(func)?;
+        let mut output = Vec::with_capacity(call + candidate.body.len());
+        for cmd in &code[..call] {
+            let cmd = match cmd {
+                Command::StoreParam(kind, addr) => {
+                    Command::MemoryStore(*kind, self.move_addr(&base, *kind, *addr))
+                }
+                cmd => cmd.clone(),
+            };
+            output.push(cmd);
+        }
+        for cmd in &candidate.body {
+            let cmd = match cmd {
+                Command::MemoryLoad(kind, addr) => {
+                    Command::MemoryLoad(*kind, self.move_addr(&base, *kind, *addr))
+                }
+                Command::MemoryStore(kind, addr) => {
+                    Command::MemoryStore(*kind, self.move_addr(&base, *kind, *addr))
+                }
+                cmd => cmd.clone(),
+            };
+            output.push(cmd);
+        }
+        Some((call, output))
+    }
+
+    // grow the caller memory once per callee, None
+    // when the new slots would not be addressable
+    fn allocate(&mut self, func: usize) -> Option<MemorySize> {
+        if let Some(base) = self.slots.get(&func) {
+            return Some(base.clone());
+        }
+        let callee = self.callee_size.get(func)?;
+        let base = self.caller_size.clone();
+        for kind in &Kind::ALL {
+            if base.count(kind) + callee.count(kind) > LOCAL_MASK as usize {
+                return None;
+            }
+        }
+        for kind in &Kind::ALL {
+            *self.caller_size.count_mut(kind) += callee.count(kind);
+        }
+        self.slots.insert(func, base.clone());
+        Some(base)
+    }
+
+    fn move_addr(&self, base: &MemorySize, kind: Kind, addr: AddrSize) -> AddrSize {
+        if addr & LOCAL_MASK == 0 {
+            return addr;
+        }
+        let slot = (addr - LOCAL_MASK) + base.count(&kind) as AddrSize;
+        match self.caller {
+            BlockId::Main => slot,
+            BlockId::Function(_) => slot | LOCAL_MASK,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::command_definition::{MathOperator, RelationalOperator};
+    use crate::engine::{run_program_with_io, EngineConfig};
+    use crate::string_memory::StringMemory;
+
+    fn int_memory(count: usize) -> MemorySize {
+        MemorySize {
+            integer_count: count,
+            ..MemorySize::default()
+        }
+    }
+
+    // f(x) = x + 1, leaves the result on the stack
+    fn increment() -> Block {
+        Block::new(vec![
+            Command::MemoryLoad(Kind::Integer, LOCAL_MASK),
+            Command::ConstantLoad(Constant::Integer(1)),
+            Command::Integer(Operator::Math(MathOperator::Add)),
+            Command::Control(ControlFlow::Ret, 0),
+        ])
+    }
+
+    fn call_increment(value: i32) -> Vec<Command> {
+        vec![
+            Command::NewRecord(0),
+            Command::ConstantLoad(Constant::Integer(value)),
+            Command::StoreParam(Kind::Integer, LOCAL_MASK),
+            Command::Control(ControlFlow::Call, 0),
+            Command::Output(Kind::Integer),
+        ]
+    }
+
+    fn run(prog: Program, mem: ProgramMemory) -> Vec<u8> {
+        let mut output = Vec::new();
+        let config = EngineConfig::new();
+        let mut input = &b""[..];
+        run_program_with_io(
+            prog,
+            mem,
+            StringMemory::new(),
+            &config,
+            &mut input,
+            &mut output,
+        )
+        .unwrap();
+        output
+    }
+
+    #[test]
+    fn test_inline_leaf_function() {
+        let mut body = call_increment(4);
+        body.extend(call_increment(9));
+        let build = || {
+            let prog = Program {
+                body: Block::new(body.clone()),
+                func: vec![increment()],
+                fini: None,
+                meta: vec![],
+                interactive: false,
+                pure: HashMap::new(),
+            };
+            let mem = ProgramMemory {
+                main: int_memory(1),
+                func: vec![int_memory(1)],
+                args: vec![],
+            };
+            (prog, mem)
+        };
+
+        let (mut prog, mut mem) = build();
+        inline_functions(&mut prog, &mut mem);
+        assert_eq!(mem.main.integer_count, 2);
+        assert!(prog
+            .body
+            .code
+            .iter()
+            .all(|cmd| !matches!(cmd, Command::NewRecord(_) | Command::Control(_, _))));
+        assert!(matches!(
+            prog.body.code[1],
+            Command::MemoryStore(Kind::Integer, 1)
+        ));
+
+        let (orig_prog, orig_mem) = build();
+        assert_eq!(run(prog, mem), run(orig_prog, orig_mem));
+    }
+
+    #[test]
+    fn test_skip_unsafe_functions() {
+        // reads a local that no call site sets
+        let reads_local = Block::new(vec![
+            Command::MemoryLoad(Kind::Integer, LOCAL_MASK | 1),
+            Command::Control(ControlFlow::Ret, 0),
+        ]);
+        let with_jump = Block::new(vec![
+            Command::Control(ControlFlow::Label, 0),
+            Command::Control(ControlFlow::Ret, 0),
+        ]);
+        let mut body = call_increment(1);
+        body[0] = Command::NewRecord(1);
+        body[3] = Command::Control(ControlFlow::Call, 1);
+        body.extend(vec![
+            Command::NewRecord(2),
+            Command::Control(ControlFlow::Call, 2),
+        ]);
+        let mut prog = Program {
+            body: Block::new(body),
+            func: vec![increment(), reads_local, with_jump],
+            fini: None,
+            meta: vec![],
+            interactive: false,
+            pure: HashMap::new(),
+        };
+        let mut mem = ProgramMemory {
+            main: int_memory(0),
+            func: vec![int_memory(1), int_memory(2), int_memory(0)],
+            args: vec![],
+        };
+        inline_functions(&mut prog, &mut mem);
+        assert_eq!(prog.body.code.len(), 7);
+        assert_eq!(mem.main.integer_count, 0);
+    }
+
+    #[test]
+    fn test_reuse_loads() {
+        let add = || Command::Integer(Operator::Math(MathOperator::Add));
+        let body = vec![
+            Command::MemoryLoad(Kind::Integer, 0),
+            Command::MemoryLoad(Kind::Str, 0),
+            Command::MemoryLoad(Kind::Integer, 0),
+            Command::MemoryLoad(Kind::Str, 0),
+            add(),
+            Command::MemoryLoad(Kind::Integer, 0),
+            Command::MemoryStore(Kind::Integer, 1),
+            Command::MemoryLoad(Kind::Integer, 0),
+            Command::Control(ControlFlow::Label, 0),
+            Command::MemoryLoad(Kind::Integer, 0),
+        ];
+        let mut prog = Program {
+            body: Block::new(body.clone()),
+            func: vec![],
+            fini: None,
+            meta: vec![],
+            interactive: false,
+            pure: HashMap::new(),
+        };
+        reuse_loads(&mut prog);
+        let dups: Vec<usize> = prog
+            .body
+            .code
+            .iter()
+            .enumerate()
+            .filter(|(_, cmd)| matches!(cmd, Command::Duplicate(_)))
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(dups, vec![2, 3]);
+        assert!(matches!(prog.body.code[3], Command::Duplicate(Kind::Str)));
+
+        // a timer handler may store into globals between the loads
+        let mut timed = body;
+        timed.insert(0, Command::SetTimer(0));
+        prog.body = Block::new(timed);
+        reuse_loads(&mut prog);
+        assert!(!prog
+            .body
+            .code
+            .iter()
+            .any(|cmd| matches!(cmd, Command::Duplicate(_))));
+    }
+
+    #[test]
+    fn test_hoist_invariants() {
+        let int = |n| Command::ConstantLoad(Constant::Integer(n));
+        let int_op = |op| Command::Integer(Operator::Math(op));
+        let body = vec![
+            int(3),
+            Command::MemoryStore(Kind::Integer, 1),
+            Command::Control(ControlFlow::Label, 0),
+            // while i < n * 2
+            Command::MemoryLoad(Kind::Integer, 0),
+            Command::MemoryLoad(Kind::Integer, 1),
+            int(2),
+            int_op(MathOperator::Mul),
+            Command::Integer(Operator::Rel(RelationalOperator::Less)),
+            Command::Control(ControlFlow::JumpFalse, 1),
+            // i = i + 1, write r * 0.5 and n / 2
+            Command::MemoryLoad(Kind::Integer, 0),
+            int(1),
+            int_op(MathOperator::Add),
+            Command::MemoryStore(Kind::Integer, 0),
+            Command::MemoryLoad(Kind::Real, 0),
+            Command::ConstantLoad(Constant::Real(0.5)),
+            Command::Real(Operator::Math(MathOperator::Mul)),
+            Command::Output(Kind::Real),
+            Command::MemoryLoad(Kind::Integer, 1),
+            int(2),
+            int_op(MathOperator::Div),
+            Command::Output(Kind::Integer),
+            Command::Control(ControlFlow::Jump, 0),
+            Command::Control(ControlFlow::Label, 1),
+        ];
+        let build = || {
+            let prog = Program {
+                body: Block::new(body.clone()),
+                func: vec![],
+                fini: None,
+                meta: vec![],
+                interactive: false,
+                pure: HashMap::new(),
+            };
+            let main = MemorySize {
+                integer_count: 2,
+                real_count: 1,
+                ..MemorySize::default()
+            };
+            let mem = ProgramMemory {
+                main,
+                func: vec![],
+                args: vec![],
+            };
+            (prog, mem)
+        };
+
+        let (mut prog, mut mem) = build();
+        hoist_invariants(&mut prog, &mut mem);
+        // n * 2 and r * 0.5 move, the body division may trap
+        assert_eq!(mem.main.integer_count, 3);
+        assert_eq!(mem.main.real_count, 2);
+        assert!(matches!(
+            prog.body.code[5],
+            Command::MemoryStore(Kind::Integer, 2)
+        ));
+        assert!(matches!(
+            prog.body.code[9],
+            Command::MemoryStore(Kind::Real, 1)
+        ));
+        // each expression is copied before the loop with a store
+        // and replaced by a load inside it
+        assert_eq!(prog.body.code.len(), body.len() + 4);
+
+        let (orig_prog, orig_mem) = build();
+        assert_eq!(run(prog, mem), run(orig_prog, orig_mem));
+    }
+}
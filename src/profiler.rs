@@ -0,0 +1,357 @@
+use crate::command_definition::{BlockId, Command};
+use crate::disasm::mnemonic;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+// Sampling profiler: once every `period` instructions the chain of
+// blocks on the call stack, outermost first, is recorded. The result
+// uses the folded stack format read by flamegraph tools: one chain
+// per line, frames separated by `;`, followed by its sample count.
+// The wall clock time of every call is kept too, in a latency
+// histogram for each function. On request every executed
+// instruction is counted as well, the counts are reported per
+// instruction, per opcode and per function.
+
+pub struct Profiler {
+    period: u64,
+    samples: HashMap<Vec<BlockId>, u64>,
+    latency: HashMap<BlockId, Latency>,
+    counts: Option<HashMap<(BlockId, usize), Counter>>,
+}
+
+// rows printed in each section of the summary
+const SUMMARY_ROWS: usize = 10;
+
+struct Counter {
+    opcode: String,
+    executed: u64,
+}
+
+// bucket i counts the calls that took less than 2^i
+// nanoseconds and at least 2^(i - 1)
+#[derive(Default)]
+struct Latency {
+    calls: u64,
+    total: Duration,
+    max: Duration,
+    buckets: Vec<u64>,
+}
+
+impl Latency {
+    fn record(&mut self, time: Duration) {
+        let nanos = time.as_nanos().min(u64::MAX as u128) as u64;
+        let bucket = (64 - nanos.leading_zeros()) as usize;
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += 1;
+        self.calls += 1;
+        self.total += time;
+        self.max = self.max.max(time);
+    }
+
+    fn report(&self, block: &BlockId) -> LatencyReport {
+        let buckets = self
+            .buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(i, count)| Bucket {
+                below_ns: 1u128 << i,
+                count: *count,
+            })
+            .collect();
+        LatencyReport {
+            function: frame_name(block),
+            calls: self.calls,
+            total_ns: self.total.as_nanos(),
+            max_ns: self.max.as_nanos(),
+            buckets,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct LatencyReport {
+    function: String,
+    calls: u64,
+    total_ns: u128,
+    max_ns: u128,
+    buckets: Vec<Bucket>,
+}
+
+#[derive(Serialize)]
+struct Bucket {
+    below_ns: u128,
+    count: u64,
+}
+
+#[derive(Serialize)]
+struct CountReport {
+    executed: u64,
+    functions: Vec<FunctionCount>,
+    opcodes: Vec<OpcodeCount>,
+    instructions: Vec<InstructionCount>,
+}
+
+#[derive(Serialize)]
+struct FunctionCount {
+    function: String,
+    executed: u64,
+}
+
+#[derive(Serialize)]
+struct OpcodeCount {
+    opcode: String,
+    executed: u64,
+}
+
+#[derive(Serialize)]
+struct InstructionCount {
+    function: String,
+    index: usize,
+    opcode: String,
+    executed: u64,
+}
+
+impl Profiler {
+    pub fn new(period: u64) -> Self {
+        Self {
+            period: period.max(1),
+            samples: HashMap::new(),
+            latency: HashMap::new(),
+            counts: None,
+        }
+    }
+
+    // count every executed instruction, see `count`
+    pub fn counting(mut self, counting: bool) -> Self {
+        self.counts = if counting { Some(HashMap::new()) } else { None };
+        self
+    }
+
+    pub fn is_counting(&self) -> bool {
+        self.counts.is_some()
+    }
+
+    // one execution of instruction `index` of `block`
+    pub fn count(&mut self, block: BlockId, index: usize, cmd: &Command) {
+        if let Some(counts) = &mut self.counts {
+            let counter = counts.entry((block, index)).or_insert_with(|| Counter {
+                opcode: mnemonic(cmd),
+                executed: 0,
+            });
+            counter.executed += 1;
+        }
+    }
+
+    // the execution counts as a JSON object, every list
+    // sorted from the most executed entry
+    pub fn counts_json(&self) -> String {
+        serde_json::to_string_pretty(&self.count_report()).unwrap()
+    }
+
+    // the first rows of each list of the count report
+    pub fn counts_summary(&self) -> String {
+        let report = self.count_report();
+        let functions = report
+            .functions
+            .iter()
+            .map(|row| (row.function.clone(), row.executed));
+        let opcodes = report
+            .opcodes
+            .iter()
+            .map(|row| (row.opcode.clone(), row.executed));
+        let instructions = report.instructions.iter().map(|row| {
+            let name = format!("{} {} {}", row.function, row.index, row.opcode);
+            (name, row.executed)
+        });
+        let mut output = format!("{} instructions executed\n", report.executed);
+        summary_section(&mut output, "functions", functions, report.executed);
+        summary_section(&mut output, "opcodes", opcodes, report.executed);
+        summary_section(&mut output, "instructions", instructions, report.executed);
+        output
+    }
+
+    fn count_report(&self) -> CountReport {
+        let mut functions: HashMap<&BlockId, u64> = HashMap::new();
+        let mut opcodes: HashMap<&str, u64> = HashMap::new();
+        let mut instructions = Vec::new();
+        for ((block, index), counter) in self.counts.iter().flatten() {
+            *functions.entry(block).or_insert(0) += counter.executed;
+            *opcodes.entry(&counter.opcode).or_insert(0) += counter.executed;
+            instructions.push(InstructionCount {
+                function: frame_name(block),
+                index: *index,
+                opcode: counter.opcode.clone(),
+                executed: counter.executed,
+            });
+        }
+        let mut functions: Vec<FunctionCount> = functions
+            .into_iter()
+            .map(|(block, executed)| FunctionCount {
+                function: frame_name(block),
+                executed,
+            })
+            .collect();
+        let mut opcodes: Vec<OpcodeCount> = opcodes
+            .into_iter()
+            .map(|(opcode, executed)| OpcodeCount {
+                opcode: opcode.to_owned(),
+                executed,
+            })
+            .collect();
+        // ties are broken by name to keep the report stable
+        functions.sort_by(|a, b| (b.executed, &a.function).cmp(&(a.executed, &b.function)));
+        opcodes.sort_by(|a, b| (b.executed, &a.opcode).cmp(&(a.executed, &b.opcode)));
+        instructions.sort_by(|a, b| {
+            (b.executed, &a.function, a.index).cmp(&(a.executed, &b.function, b.index))
+        });
+        CountReport {
+            executed: functions.iter().map(|row| row.executed).sum(),
+            functions,
+            opcodes,
+            instructions,
+        }
+    }
+
+    pub fn period(&self) -> u64 {
+        self.period
+    }
+
+    pub fn record(&mut self, chain: Vec<BlockId>) {
+        *self.samples.entry(chain).or_insert(0) += 1;
+    }
+
+    // wall clock time of a complete call of `block`
+    pub fn record_call(&mut self, block: BlockId, time: Duration) {
+        self.latency.entry(block).or_default().record(time);
+    }
+
+    // the latency histograms as a JSON array, main body
+    // first then the functions by id
+    pub fn latency_json(&self) -> String {
+        let mut blocks: Vec<&BlockId> = self.latency.keys().collect();
+        blocks.sort_by_key(|block| match block {
+            BlockId::Main => 0,
+            BlockId::Function(id) => id + 1,
+        });
+        let reports: Vec<LatencyReport> = blocks
+            .into_iter()
+            .map(|block| self.latency[block].report(block))
+            .collect();
+        serde_json::to_string_pretty(&reports).unwrap()
+    }
+
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<String> = self
+            .samples
+            .iter()
+            .map(|(chain, count)| {
+                let frames: Vec<String> = chain.iter().map(frame_name).collect();
+                format!("{} {}\n", frames.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+        lines.concat()
+    }
+}
+
+fn summary_section<I>(output: &mut String, title: &str, rows: I, total: u64)
+where
+    I: Iterator<Item = (String, u64)>,
+{
+    writeln!(output, "\n{}:", title).unwrap();
+    for (name, executed) in rows.take(SUMMARY_ROWS) {
+        let share = 100.0 * executed as f64 / total.max(1) as f64;
+        writeln!(output, "  {:<24}{:>12}{:>7.1}%", name, executed, share).unwrap();
+    }
+}
+
+fn frame_name(block: &BlockId) -> String {
+    match block {
+        BlockId::Main => "main".to_owned(),
+        BlockId::Function(id) => format!("function_{}", id),
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::command_definition::Kind;
+
+    #[test]
+    fn test_folded_stacks() {
+        let mut profiler = Profiler::new(0);
+        assert_eq!(profiler.period(), 1);
+        profiler.record(vec![BlockId::Main, BlockId::Function(2)]);
+        profiler.record(vec![BlockId::Main]);
+        profiler.record(vec![BlockId::Main, BlockId::Function(2)]);
+        assert_eq!(profiler.folded(), "main 1\nmain;function_2 2\n");
+    }
+
+    #[test]
+    fn test_latency_histogram() {
+        let mut profiler = Profiler::new(1);
+        profiler.record_call(BlockId::Function(1), Duration::from_nanos(5));
+        profiler.record_call(BlockId::Function(1), Duration::from_nanos(7));
+        profiler.record_call(BlockId::Function(1), Duration::from_nanos(1000));
+        profiler.record_call(BlockId::Function(0), Duration::from_nanos(0));
+        let report: serde_json::Value = serde_json::from_str(&profiler.latency_json()).unwrap();
+        let expected = serde_json::json!([
+            {
+                "function": "function_0",
+                "calls": 1,
+                "total_ns": 0,
+                "max_ns": 0,
+                "buckets": [{"below_ns": 1, "count": 1}]
+            },
+            {
+                "function": "function_1",
+                "calls": 3,
+                "total_ns": 1012,
+                "max_ns": 1000,
+                "buckets": [
+                    {"below_ns": 8, "count": 2},
+                    {"below_ns": 1024, "count": 1}
+                ]
+            }
+        ]);
+        assert_eq!(report, expected);
+    }
+
+    #[test]
+    fn test_instruction_counts() {
+        let mut profiler = Profiler::new(1).counting(true);
+        let load = Command::MemoryLoad(Kind::Integer, 0);
+        for _ in 0..3 {
+            profiler.count(BlockId::Function(0), 2, &load);
+        }
+        profiler.count(BlockId::Main, 0, &load);
+        profiler.count(BlockId::Main, 1, &Command::Exit);
+        let report: serde_json::Value = serde_json::from_str(&profiler.counts_json()).unwrap();
+        let expected = serde_json::json!({
+            "executed": 5,
+            "functions": [
+                {"function": "function_0", "executed": 3},
+                {"function": "main", "executed": 2}
+            ],
+            "opcodes": [
+                {"opcode": "LDI", "executed": 4},
+                {"opcode": "EXT", "executed": 1}
+            ],
+            "instructions": [
+                {"function": "function_0", "index": 2, "opcode": "LDI", "executed": 3},
+                {"function": "main", "index": 0, "opcode": "LDI", "executed": 1},
+                {"function": "main", "index": 1, "opcode": "EXT", "executed": 1}
+            ]
+        });
+        assert_eq!(report, expected);
+        assert!(profiler
+            .counts_summary()
+            .starts_with("5 instructions executed\n"));
+    }
+}
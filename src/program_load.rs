@@ -1,3 +1,4 @@
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
@@ -12,13 +13,32 @@ enum ProgramBuildState {
     Function,
 }
 
+// commands with the byte offset they were decoded from
+type Code = Vec<(Command, usize)>;
+
 struct ProgramFactory {
     state: ProgramBuildState,
-    body: Vec<Command>,
-    func: Vec<Vec<Command>>,
-    curr: Vec<Command>,
+    body: Code,
+    func: Vec<Code>,
+    curr: Code,
     main_mem: Option<MemorySize>,
     func_mem: Vec<MemorySize>,
+    // INIT found in the current function
+    declared: bool,
+    args: Vec<(Kind, AddrSize)>,
+    fini: Option<usize>,
+    meta: Vec<(String, String)>,
+    pure: HashMap<usize, Vec<Kind>>,
+    // relative jumps of the block under construction
+    jumps: Vec<RelativeJump>,
+    // labels without a LBL command, of the closed blocks
+    targets: HashMap<BlockId, HashMap<usize, usize>>,
+}
+
+struct RelativeJump {
+    position: usize,
+    offset: i16,
+    index: usize,
 }
 
 impl ProgramFactory {
@@ -30,30 +50,81 @@ impl ProgramFactory {
             curr: vec![],
             main_mem: None,
             func_mem: vec![],
+            declared: false,
+            args: vec![],
+            fini: None,
+            meta: vec![],
+            pure: HashMap::new(),
+            jumps: vec![],
+            targets: HashMap::new(),
         }
     }
 
-    fn switch_function(mut self) -> Self {
-        if self.curr.len() > 0 {
+    fn switch_function(mut self) -> Result<Self, LoadError> {
+        self.close_block()?;
+        self.check_declared()?;
+        if !self.curr.is_empty() {
             self.func.push(self.curr);
         }
-        Self {
+        Ok(Self {
             body: self.body,
             func: self.func,
             state: ProgramBuildState::Function,
             curr: vec![],
             main_mem: self.main_mem,
             func_mem: self.func_mem,
+            declared: false,
+            args: self.args,
+            fini: self.fini,
+            meta: self.meta,
+            pure: self.pure,
+            jumps: vec![],
+            targets: self.targets,
+        })
+    }
+
+    fn current_block(&mut self) -> &mut Code {
+        match self.state {
+            ProgramBuildState::Body => &mut self.body,
+            ProgramBuildState::Function => &mut self.curr,
         }
     }
 
-    fn add_command(&mut self, cmd: Command) {
+    // the jump is completed once the whole block is known
+    fn add_relative_jump(&mut self, cond: ControlFlow, offset: i16, index: usize) {
+        let block = self.current_block();
+        let position = block.len();
+        block.push((Command::Control(cond, 0), index));
+        self.jumps.push(RelativeJump {
+            position,
+            offset,
+            index,
+        });
+    }
+
+    fn current_id(&self) -> BlockId {
         match self.state {
-            ProgramBuildState::Body => self.body.push(cmd),
-            ProgramBuildState::Function => self.curr.push(cmd),
+            ProgramBuildState::Body => BlockId::Main,
+            ProgramBuildState::Function => BlockId::Function(self.func.len()),
         }
     }
 
+    fn close_block(&mut self) -> Result<(), LoadError> {
+        let jumps = std::mem::take(&mut self.jumps);
+        let mut code = std::mem::take(self.current_block());
+        let mut targets = resolve_relative_jumps(&mut code, &jumps)?;
+        *self.current_block() = fuse_output_lines(code, &mut targets);
+        if !targets.is_empty() {
+            let id = self.current_id();
+            self.targets.insert(id, targets);
+        }
+        Ok(())
+    }
+
+    fn add_command(&mut self, cmd: Command, offset: usize) {
+        self.current_block().push((cmd, offset));
+    }
+
     fn add_memory_size(
         &mut self,
         int_count: AddrSize,
@@ -71,36 +142,149 @@ impl ProgramFactory {
             ProgramBuildState::Body => self.main_mem = Some(mem_size),
             ProgramBuildState::Function => self.func_mem.push(mem_size),
         }
+        self.declared = true;
     }
 
-    fn build_program(mut self) -> (Program, ProgramMemory) {
-        if self.curr.len() > 0 {
+    // the function being closed needs its INIT, the
+    // main body is checked once the whole program is read
+    fn check_declared(&self) -> Result<(), LoadError> {
+        match self.state {
+            ProgramBuildState::Function if !self.declared => {
+                Err(LoadError::MissingFunctionMemory(self.func.len()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn add_arguments(&mut self, args: Vec<(Kind, AddrSize)>) {
+        self.args.extend(args);
+    }
+
+    fn build_program(mut self) -> Result<(Program, ProgramMemory), LoadError> {
+        self.close_block()?;
+        self.check_declared()?;
+        let main_mem = self.main_mem.ok_or(LoadError::MissingMemoryDeclaration)?;
+        if !self.curr.is_empty() {
             self.func.push(self.curr);
         }
 
-        let functions = self.func.into_iter().map(|blk| Block::new(blk)).collect();
+        let mut targets = self.targets;
+        let functions = self
+            .func
+            .into_iter()
+            .enumerate()
+            .map(|(id, code)| new_block(code, targets.remove(&BlockId::Function(id))))
+            .collect();
 
         let prog = Program {
-            body: Block::new(self.body),
+            body: new_block(self.body, targets.remove(&BlockId::Main)),
             func: functions,
+            fini: self.fini,
+            meta: self.meta,
+            interactive: false,
+            pure: self.pure,
         };
 
         let mem = ProgramMemory {
-            main: self.main_mem.unwrap(),
+            main: main_mem,
             func: self.func_mem,
+            args: self.args,
         };
 
-        (prog, mem)
+        Ok((prog, mem))
+    }
+}
+
+fn new_block(code: Code, targets: Option<HashMap<usize, usize>>) -> Block {
+    let (code, offsets) = code.into_iter().unzip();
+    let mut block = Block::with_offsets(code, offsets);
+    block.labels.extend(targets.unwrap_or_default());
+    block
+}
+
+// a label in between keeps the two instructions apart, so
+// fusion only has to skip the FLN targeted by a relative jump
+// and move the targets after it. The fused command keeps the
+// offset of the WRS
+fn fuse_output_lines(code: Code, targets: &mut HashMap<usize, usize>) -> Code {
+    let targeted: HashSet<usize> = targets.values().copied().collect();
+    let mut output: Code = Vec::with_capacity(code.len());
+    // index after fusion of every command and of the block end
+    let mut moved = Vec::with_capacity(code.len() + 1);
+    for (position, (cmd, offset)) in code.into_iter().enumerate() {
+        moved.push(output.len());
+        match (output.last(), &cmd) {
+            (Some((Command::Output(Kind::Str), _)), Command::Flush(FlushMode::NewLine))
+                if !targeted.contains(&position) =>
+            {
+                output.last_mut().unwrap().0 = Command::OutputLine;
+            }
+            _ => output.push((cmd, offset)),
+        }
+    }
+    moved.push(output.len());
+    for target in targets.values_mut() {
+        *target = moved[*target];
+    }
+    output
+}
+
+// relative jumps become ordinary jumps to new labels, which
+// point straight to their targets without a LBL in the code:
+// instruction indices stay those of the file
+fn resolve_relative_jumps(
+    code: &mut Code,
+    jumps: &[RelativeJump],
+) -> Result<HashMap<usize, usize>, LoadError> {
+    let mut next_label = code
+        .iter()
+        .filter_map(|(cmd, _)| match cmd {
+            Command::Control(ControlFlow::Label, label) => Some(label + 1),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0);
+    let mut labels: HashMap<usize, usize> = HashMap::new();
+    let mut targets: HashMap<usize, usize> = HashMap::new();
+    for jump in jumps {
+        let target = jump.position as isize + jump.offset as isize;
+        if target < 0 || target as usize > code.len() {
+            return Err(LoadError::BadRelativeJump(jump.index, jump.offset));
+        }
+        let label = *labels.entry(target as usize).or_insert_with(|| {
+            targets.insert(next_label, target as usize);
+            next_label += 1;
+            next_label - 1
+        });
+        if let (Command::Control(_, addr), _) = &mut code[jump.position] {
+            *addr = label;
+        }
     }
+    Ok(targets)
 }
 
 #[derive(Debug)]
 pub enum LoadError {
     UnknownByte(UnknownByteError),
+    ReservedOpcode(UnknownByteError),
+    AddressOutOfRange(MemoryAccessError),
+    BadFinalizer(usize),
+    BadTimerHandler(usize),
+    BadPureFunction(usize),
+    DuplicateLabel(BlockId, usize, usize, usize),
+    CallProtocol(BlockId, usize, CallError),
+    ArgumentOutOfRange(usize, Kind, AddrSize),
     MissingBytes(ErrorLocation),
     InputOutputError(std::io::Error),
     StringEncodeError(str::Utf8Error),
     BooleanEncodeError(u8),
+    UnknownHeaderFlags(u8),
+    BadRelativeJump(usize, i16),
+    // no INIT before the first FUNC
+    MissingMemoryDeclaration,
+    MissingFunctionMemory(usize),
+    // functions and memory sizes
+    FunctionTableMismatch(usize, usize),
 }
 
 impl std::error::Error for LoadError {}
@@ -113,12 +297,57 @@ impl std::fmt::Display for LoadError {
                 "Found unknown byte {} at index {}",
                 unknown.value, unknown.index
             ),
+            Self::ReservedOpcode(reserved) => write!(
+                f,
+                "Found reserved opcode {} at index {}: the program needs a newer engine",
+                reserved.value, reserved.index
+            ),
+            Self::AddressOutOfRange(access) => write!(f, "Invalid memory access: {}", access),
+            Self::BadFinalizer(func) => write!(
+                f,
+                "Finalizer {} is not a function with a local integer for the exit code",
+                func
+            ),
+            Self::BadTimerHandler(func) => write!(f, "Timer handler {} is not a function", func),
+            Self::BadPureFunction(func) => write!(f, "Pure function {} does not exist", func),
+            Self::DuplicateLabel(block, label, first, second) => write!(
+                f,
+                "Label {} defined twice in {}, at instructions {} and {}",
+                label, block, first, second
+            ),
+            Self::CallProtocol(block, index, err) => {
+                write!(f, "In {}, instruction {}: {}", block, index, err)
+            }
+            Self::ArgumentOutOfRange(arg, kind, addr) => write!(
+                f,
+                "Argument {} is bound to global {} {}, outside the declared memory",
+                arg, kind, addr
+            ),
             Self::MissingBytes(location) => write!(f, "Missing Bytes in input: {}", location),
             Self::InputOutputError(err) => write!(f, "Error reading input file: {}", err),
             Self::StringEncodeError(err) => write!(f, "Malformatted UTF-8 string: {}", err),
             Self::BooleanEncodeError(n) => {
                 write!(f, "Malformatted boolean value: {} - expected 0 or 255", n)
             }
+            Self::UnknownHeaderFlags(flags) => {
+                write!(f, "Unknown header flags: {:#010b}", flags)
+            }
+            Self::BadRelativeJump(index, offset) => write!(
+                f,
+                "Relative jump at index {} lands outside its block (offset {})",
+                index, offset
+            ),
+            Self::MissingMemoryDeclaration => {
+                write!(f, "The main body has no INIT memory declaration")
+            }
+            Self::MissingFunctionMemory(func) => {
+                write!(f, "Function {} has no INIT memory declaration", func)
+            }
+            Self::FunctionTableMismatch(functions, sizes) => write!(
+                f,
+                "{} functions but {} function memory declarations",
+                functions, sizes
+            ),
         }
     }
 }
@@ -135,6 +364,76 @@ impl From<str::Utf8Error> for LoadError {
     }
 }
 
+#[derive(Debug, Default)]
+pub struct LoadOptions {
+    pub lenient_strings: bool,
+    // override the byte order declared in the header
+    pub byte_order: Option<ByteOrder>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ByteOrder {
+    Big,
+    Little,
+}
+
+impl ByteOrder {
+    pub fn swap(self) -> Self {
+        match self {
+            Self::Big => Self::Little,
+            Self::Little => Self::Big,
+        }
+    }
+}
+
+impl std::fmt::Display for ByteOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Big => write!(f, "big endian"),
+            Self::Little => write!(f, "little endian"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadWarning {
+    LossyString(usize, str::Utf8Error),
+    NonFiniteConstant(usize, f64),
+    UnusedFunction(usize),
+    OversizedMemory(BlockId, Kind, usize),
+    DeprecatedOpcode(u8, usize, &'static str),
+    UnusedMemory(BlockId, Kind, usize, usize),
+}
+
+impl std::fmt::Display for LoadWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LossyString(index, err) => write!(
+                f,
+                "Malformatted UTF-8 string at index {}: {} - invalid bytes replaced",
+                index, err
+            ),
+            Self::NonFiniteConstant(index, val) => {
+                write!(f, "Real constant {} at index {}", val, index)
+            }
+            Self::UnusedFunction(func) => write!(f, "Function {} is never called", func),
+            Self::OversizedMemory(block, kind, count) => write!(
+                f,
+                "{} declares {} {} variables, only {} are addressable",
+                block, count, kind, LOCAL_MASK
+            ),
+            Self::DeprecatedOpcode(byte, index, hint) => {
+                write!(f, "Deprecated opcode {} at index {}: {}", byte, index, hint)
+            }
+            Self::UnusedMemory(block, kind, declared, used) => write!(
+                f,
+                "{} declares {} {} variables but uses only {}",
+                block, declared, kind, used
+            ),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct UnknownByteError {
     pub value: u8,
@@ -147,6 +446,30 @@ impl UnknownByteError {
     }
 }
 
+#[derive(Debug)]
+pub struct MemoryAccessError {
+    pub block: BlockId,
+    pub index: usize,
+    pub kind: Kind,
+    pub addr: AddrSize,
+    pub declared: usize,
+}
+
+impl std::fmt::Display for MemoryAccessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (scope, addr) = if self.addr & LOCAL_MASK == 0 {
+            ("global", self.addr)
+        } else {
+            ("local", self.addr - LOCAL_MASK)
+        };
+        write!(
+            f,
+            "{}, instruction {}: {} {} {} is outside the {} declared",
+            self.block, self.index, scope, self.kind, addr, self.declared
+        )
+    }
+}
+
 #[derive(Debug)]
 pub struct ErrorLocation {
     pub index: usize,
@@ -161,6 +484,9 @@ pub enum ErrorOperation {
     LoadingF64,
     LoadingStr,
     LoadingBool,
+    LoadingHeader,
+    LoadingDescriptor,
+    LoadingArguments,
 }
 impl std::fmt::Display for ErrorOperation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -170,6 +496,9 @@ impl std::fmt::Display for ErrorOperation {
             Self::LoadingI32 => "32 bit integer",
             Self::LoadingStr => "String constant",
             Self::LoadingU16 => "16 bit integer",
+            Self::LoadingHeader => "header flags",
+            Self::LoadingDescriptor => "output descriptor",
+            Self::LoadingArguments => "argument bindings",
         };
         write!(f, "{}", msg)
     }
@@ -191,55 +520,452 @@ impl std::fmt::Display for ErrorLocation {
     }
 }
 
-pub fn load_program(file: &Path) -> Result<(Program, ProgramMemory, StringMemory), LoadError> {
+pub type LoadOutput = (Program, ProgramMemory, StringMemory, Vec<LoadWarning>);
+
+pub fn load_program(file: &Path, options: &LoadOptions) -> Result<LoadOutput, LoadError> {
     let data = load_file(file)?;
-    parse_data(&data)
+    parse_data(&data, options)
 }
 
-fn parse_data(data: &[u8]) -> Result<(Program, ProgramMemory, StringMemory), LoadError> {
+pub fn parse_data(data: &[u8], options: &LoadOptions) -> Result<LoadOutput, LoadError> {
     let mut factory = ProgramFactory::new();
-    let mut index = 0;
+    let (header_order, interactive, mut index) = parse_header(data)?;
+    let order = options.byte_order.unwrap_or(header_order);
     let mut string_memory = StringMemory::new();
+    let mut warnings = Vec::new();
     while index < data.len() {
+        check_deprecated(data[index], index, opcode::DEPRECATED, &mut warnings);
         if let Some(cmd) = is_single_command(data[index]) {
-            factory.add_command(cmd);
+            factory.add_command(cmd, index);
             index += 1;
-        } else if let Some((cmd, offset)) = is_address_command(index, &data)? {
-            factory.add_command(cmd);
+        } else if let Some((cmd, offset)) = is_address_command(index, data, order)? {
+            factory.add_command(cmd, index);
             index += offset;
-        } else if let Some((cmd, offset)) = is_constant_command(index, &data, &mut string_memory)? {
-            factory.add_command(cmd);
+        } else if let Some((cmd, offset)) = is_constant_command(
+            index,
+            data,
+            order,
+            &mut string_memory,
+            options,
+            &mut warnings,
+        )? {
+            factory.add_command(cmd, index);
             index += offset;
         } else if data[index] == opcode::FUNC {
-            factory = factory.switch_function();
+            factory = factory.switch_function()?;
             index += 1;
         } else if data[index] == opcode::INIT {
             let (int_count, real_count, bool_count, str_count) =
-                get_memory_command(index + 1, data)?;
+                get_memory_command(index + 1, data, order)?;
             factory.add_memory_size(int_count, real_count, bool_count, str_count);
             index += 9;
+        } else if let Some(cond) = relative_jump(data[index]) {
+            let offset = get_u16(data, index + 1, order)? as i16;
+            factory.add_relative_jump(cond, offset, index);
+            index += 3;
+        } else if data[index] == opcode::FINI {
+            factory.fini = Some(get_u16(data, index + 1, order)? as usize);
+            index += 3;
+        } else if data[index] == opcode::PURE {
+            let func = get_u16(data, index + 1, order)? as usize;
+            let (kinds, offset) = get_descriptor(data, index + 3)?;
+            factory.pure.insert(func, kinds);
+            index += offset + 3;
+        } else if data[index] == opcode::META {
+            let (meta, offset) = get_metadata(index + 1, data, order, options, &mut warnings)?;
+            factory.meta.extend(meta);
+            index += offset + 1;
+        } else if data[index] == opcode::ARGS {
+            let (args, offset) = get_arguments(index + 1, data, order)?;
+            factory.add_arguments(args);
+            index += offset + 1;
+        } else if opcode::is_reserved(data[index]) {
+            let err = UnknownByteError::new(data[index], index);
+            return Err(LoadError::ReservedOpcode(err));
         } else {
             let err = UnknownByteError::new(data[index], index);
             return Err(LoadError::UnknownByte(err));
         }
     }
 
-    let (prog, mem) = factory.build_program();
-    Ok((prog, mem, string_memory))
+    let (mut prog, mem) = factory.build_program()?;
+    prog.interactive = interactive;
+    check_function_table(&prog, &mem)?;
+    check_finalizer(&prog, &mem)?;
+    check_timers(&prog)?;
+    check_pure_functions(&prog)?;
+    check_labels(&prog)?;
+    check_call_protocol(&prog)?;
+    unused_functions(&prog, &mut warnings);
+    oversized_memory(&mem, &mut warnings);
+    check_memory_usage(&prog, &mem, &mut warnings)?;
+    Ok((prog, mem, string_memory, warnings))
+}
+
+// the checks every loaded program passes, for programs built
+// in memory instead of loaded from a file
+pub fn validate(prog: &Program, mem: &ProgramMemory) -> Result<(), LoadError> {
+    check_function_table(prog, mem)?;
+    check_finalizer(prog, mem)?;
+    check_timers(prog)?;
+    check_pure_functions(prog)?;
+    check_labels(prog)?;
+    check_call_protocol(prog)?;
+    check_memory_usage(prog, mem, &mut vec![])
+}
+
+// rewrite every MemoryLoad and MemoryStore into a slot reference,
+// checked once here instead of on every access. Meant for programs
+// about to run: the optimizer and the analyses only know addresses
+pub fn resolve_slots(prog: &mut Program, mem: &ProgramMemory) -> Result<(), MemoryAccessError> {
+    let no_memory = MemorySize::default();
+    let blocks = std::iter::once((BlockId::Main, &mut prog.body)).chain(
+        prog.func
+            .iter_mut()
+            .enumerate()
+            .map(|(id, block)| (BlockId::Function(id), block)),
+    );
+    for (id, block) in blocks {
+        let local_size = match id {
+            BlockId::Main => &no_memory,
+            BlockId::Function(func) => mem.func.get(func).unwrap_or(&no_memory),
+        };
+        for (index, cmd) in block.code.iter_mut().enumerate() {
+            let (kind, addr) = match cmd {
+                Command::MemoryLoad(kind, addr) | Command::MemoryStore(kind, addr) => {
+                    (*kind, *addr)
+                }
+                _ => continue,
+            };
+            let slot = Slot::new(addr);
+            let (size, number) = match slot {
+                Slot::Global(number) => (&mem.main, number),
+                Slot::Local(number) => (local_size, number),
+            };
+            let declared = size.count(&kind);
+            if number >= declared {
+                return Err(MemoryAccessError {
+                    block: id,
+                    index,
+                    kind,
+                    addr,
+                    declared,
+                });
+            }
+            *cmd = match cmd {
+                Command::MemoryLoad(..) => Command::LoadSlot(kind, slot),
+                _ => Command::StoreSlot(kind, slot),
+            };
+        }
+    }
+    Ok(())
+}
+
+// warn only on the first use of each deprecated opcode
+fn check_deprecated(
+    byte: u8,
+    index: usize,
+    table: &[(u8, &'static str)],
+    warnings: &mut Vec<LoadWarning>,
+) {
+    let hint = match table.iter().find(|(op, _)| *op == byte) {
+        Some((_, hint)) => hint,
+        None => return,
+    };
+    let reported = warnings
+        .iter()
+        .any(|warn| matches!(warn, LoadWarning::DeprecatedOpcode(op, _, _) if *op == byte));
+    if !reported {
+        warnings.push(LoadWarning::DeprecatedOpcode(byte, index, hint));
+    }
+}
+
+// every function needs exactly one memory size, the engine
+// indexes both tables with the same id
+fn check_function_table(prog: &Program, mem: &ProgramMemory) -> Result<(), LoadError> {
+    if prog.func.len() == mem.func.len() {
+        Ok(())
+    } else {
+        Err(LoadError::FunctionTableMismatch(
+            prog.func.len(),
+            mem.func.len(),
+        ))
+    }
+}
+
+fn check_finalizer(prog: &Program, mem: &ProgramMemory) -> Result<(), LoadError> {
+    match prog.fini {
+        Some(fini) if fini >= prog.func.len() => Err(LoadError::BadFinalizer(fini)),
+        Some(fini) if mem.func.get(fini).map_or(0, |size| size.integer_count) == 0 => {
+            Err(LoadError::BadFinalizer(fini))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check_pure_functions(prog: &Program) -> Result<(), LoadError> {
+    match prog.pure.keys().find(|func| **func >= prog.func.len()) {
+        Some(func) => Err(LoadError::BadPureFunction(*func)),
+        None => Ok(()),
+    }
+}
+
+fn check_timers(prog: &Program) -> Result<(), LoadError> {
+    for (_, block) in prog.blocks() {
+        for cmd in &block.code {
+            match cmd {
+                Command::SetTimer(func) if *func >= prog.func.len() => {
+                    return Err(LoadError::BadTimerHandler(*func))
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+// labels are local to their block, the same id
+// may appear once in each one
+fn check_labels(prog: &Program) -> Result<(), LoadError> {
+    for (id, block) in prog.blocks() {
+        let mut seen = HashMap::new();
+        for (position, cmd) in block.code.iter().enumerate() {
+            if let Command::Control(ControlFlow::Label, label) = cmd {
+                if let Some(first) = seen.insert(*label, position) {
+                    return Err(LoadError::DuplicateLabel(id, *label, first, position));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// every PARAM is followed by its STRxP and by a CALL to the same
+// function before the end of its basic block, so every path through
+// the block builds and consumes the record in the same way
+fn check_call_protocol(prog: &Program) -> Result<(), LoadError> {
+    for (id, block) in prog.blocks() {
+        // index and function of the record being filled
+        let mut pending: Option<(usize, usize)> = None;
+        // relative jumps can land on any instruction
+        let targets: HashSet<usize> = block.labels.values().copied().collect();
+        for (index, cmd) in block.code.iter().enumerate() {
+            if let Some((start, prev)) = pending.filter(|_| targets.contains(&index)) {
+                return Err(LoadError::CallProtocol(
+                    id,
+                    start,
+                    CallError::UnmatchedRecord(prev),
+                ));
+            }
+            let err = match (cmd, pending) {
+                (Command::NewRecord(func), _) | (Command::Control(ControlFlow::Call, func), _)
+                    if *func >= prog.func.len() =>
+                {
+                    CallError::UndefinedFunction(*func)
+                }
+                (Command::NewRecord(func), Some((_, prev))) => CallError::NestedRecord(prev, *func),
+                (Command::NewRecord(func), None) => {
+                    pending = Some((index, *func));
+                    continue;
+                }
+                (Command::StoreParam(..), None) => CallError::ParamWithoutRecord,
+                (Command::Control(ControlFlow::Call, func), Some((_, prev))) if prev != *func => {
+                    CallError::CallMismatch(prev, *func)
+                }
+                (Command::Control(ControlFlow::Call, _), Some(_)) => {
+                    pending = None;
+                    continue;
+                }
+                (Command::Control(ControlFlow::Call, func), None) => {
+                    CallError::CallWithoutRecord(*func)
+                }
+                (Command::Control(..), Some((start, prev)))
+                | (Command::Exit, Some((start, prev))) => {
+                    return Err(LoadError::CallProtocol(
+                        id,
+                        start,
+                        CallError::UnmatchedRecord(prev),
+                    ))
+                }
+                _ => continue,
+            };
+            return Err(LoadError::CallProtocol(id, index, err));
+        }
+        if let Some((start, prev)) = pending {
+            return Err(LoadError::CallProtocol(
+                id,
+                start,
+                CallError::UnmatchedRecord(prev),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn unused_functions(prog: &Program, warnings: &mut Vec<LoadWarning>) {
+    let mut called = vec![false; prog.func.len()];
+    if let Some(fini) = prog.fini {
+        called[fini] = true;
+    }
+    for (id, block) in prog.blocks() {
+        for cmd in &block.code {
+            match cmd {
+                // recursive calls do not count as uses
+                Command::Control(ControlFlow::Call, func) | Command::SetTimer(func)
+                    if id != BlockId::Function(*func) && *func < called.len() =>
+                {
+                    called[*func] = true;
+                }
+                _ => {}
+            }
+        }
+    }
+    let unused = called
+        .iter()
+        .enumerate()
+        .filter(|(_, called)| !**called)
+        .map(|(func, _)| LoadWarning::UnusedFunction(func));
+    warnings.extend(unused);
+}
+
+fn oversized_memory(mem: &ProgramMemory, warnings: &mut Vec<LoadWarning>) {
+    for (id, size) in mem.blocks() {
+        for kind in &Kind::ALL {
+            let count = size.count(kind);
+            if count > LOCAL_MASK as usize {
+                warnings.push(LoadWarning::OversizedMemory(id, *kind, count));
+            }
+        }
+    }
+}
+
+// the engine panics on accesses outside the declared
+// memory: reject them before running the program
+fn check_memory_usage(
+    prog: &Program,
+    mem: &ProgramMemory,
+    warnings: &mut Vec<LoadWarning>,
+) -> Result<(), LoadError> {
+    let no_memory = MemorySize::default();
+    let mut global_used = HashMap::new();
+    for (id, block) in prog.blocks() {
+        let local_size = match id {
+            BlockId::Main => &no_memory,
+            BlockId::Function(func) => mem.func.get(func).unwrap_or(&no_memory),
+        };
+        let mut local_used = HashMap::new();
+        for (index, cmd) in block.code.iter().enumerate() {
+            let (kind, addr) = match cmd {
+                Command::MemoryLoad(kind, addr) | Command::MemoryStore(kind, addr) => {
+                    (*kind, *addr)
+                }
+                _ => continue,
+            };
+            let (size, slot, used) = if addr & LOCAL_MASK == 0 {
+                (&mem.main, addr, &mut global_used)
+            } else {
+                (local_size, addr - LOCAL_MASK, &mut local_used)
+            };
+            let declared = size.count(&kind);
+            if slot as usize >= declared {
+                let err = MemoryAccessError {
+                    block: id,
+                    index,
+                    kind,
+                    addr,
+                    declared,
+                };
+                return Err(LoadError::AddressOutOfRange(err));
+            }
+            let top = used.entry(kind).or_insert(0);
+            *top = (*top).max(slot as usize + 1);
+        }
+        if let BlockId::Function(_) = id {
+            unused_memory(id, local_size, &local_used, warnings);
+        }
+    }
+    for (i, (kind, addr)) in mem.args.iter().enumerate() {
+        let declared = mem.main.count(kind);
+        if *addr as usize >= declared {
+            return Err(LoadError::ArgumentOutOfRange(i, *kind, *addr));
+        }
+        let top = global_used.entry(*kind).or_insert(0);
+        *top = (*top).max(*addr as usize + 1);
+    }
+    unused_memory(BlockId::Main, &mem.main, &global_used, warnings);
+    Ok(())
+}
+
+const UNUSED_MEMORY_THRESHOLD: usize = 256;
+
+fn unused_memory(
+    id: BlockId,
+    size: &MemorySize,
+    used: &HashMap<Kind, usize>,
+    warnings: &mut Vec<LoadWarning>,
+) {
+    for kind in &Kind::ALL {
+        let declared = size.count(kind);
+        let used = used.get(kind).copied().unwrap_or(0);
+        if declared - used >= UNUSED_MEMORY_THRESHOLD {
+            warnings.push(LoadWarning::UnusedMemory(id, *kind, declared, used));
+        }
+    }
+}
+
+pub fn declared_byte_order(data: &[u8]) -> ByteOrder {
+    match parse_header(data) {
+        Ok((order, _, _)) => order,
+        Err(_) => ByteOrder::Big,
+    }
+}
+
+// byte order, interactive IO profile and header size. The header
+// is optional: without it operands are big endian and the input batch
+fn parse_header(data: &[u8]) -> Result<(ByteOrder, bool, usize), LoadError> {
+    if data.first() != Some(&opcode::HDR) {
+        return Ok((ByteOrder::Big, false, 0));
+    }
+    let flags = match data.get(1) {
+        Some(flags) => *flags,
+        None => {
+            let err = ErrorLocation::new(1, 1, ErrorOperation::LoadingHeader);
+            return Err(LoadError::MissingBytes(err));
+        }
+    };
+    if flags & !opcode::HDR_KNOWN_FLAGS != 0 {
+        return Err(LoadError::UnknownHeaderFlags(flags));
+    }
+    let order = if flags & opcode::HDR_LITTLE_ENDIAN == 0 {
+        ByteOrder::Big
+    } else {
+        ByteOrder::Little
+    };
+    Ok((order, flags & opcode::HDR_INTERACTIVE != 0, 2))
 }
 
 fn get_memory_command(
     index: usize,
     buff: &[u8],
+    order: ByteOrder,
 ) -> Result<(AddrSize, AddrSize, AddrSize, AddrSize), LoadError> {
     Ok((
-        get_u16(buff, index)?,
-        get_u16(buff, index + 2)?,
-        get_u16(buff, index + 4)?,
-        get_u16(buff, index + 6)?,
+        get_u16(buff, index, order)?,
+        get_u16(buff, index + 2, order)?,
+        get_u16(buff, index + 4, order)?,
+        get_u16(buff, index + 6, order)?,
     ))
 }
 
+fn relative_jump(byte: u8) -> Option<ControlFlow> {
+    match byte {
+        opcode::JUMPR => Some(ControlFlow::Jump),
+        opcode::JEQR => Some(ControlFlow::JumpTrue),
+        opcode::JNER => Some(ControlFlow::JumpFalse),
+        _ => None,
+    }
+}
+
 fn is_single_command(byte: u8) -> Option<Command> {
     match byte {
         opcode::ADDI..=opcode::CSTR
@@ -248,21 +974,35 @@ fn is_single_command(byte: u8) -> Option<Command> {
         | opcode::FLU
         | opcode::EXT
         | opcode::BFOR..=opcode::NOT
-        | opcode::GEQS..=opcode::NEB => Some(convert_single(byte)),
+        | opcode::GEQS..=opcode::NEB
+        | opcode::RDRAW
+        | opcode::WRRAW
+        | opcode::SETBOOLFMT
+        | opcode::DUPI..=opcode::DUPS
+        | opcode::STRSTAT
+        | opcode::GETDEF
+        | opcode::SBAPPI..=opcode::SBFINISH
+        | opcode::HASHS
+        | opcode::MODI..=opcode::POWR
+        | opcode::ANDI..=opcode::ALEN => Some(convert_single(byte)),
         _ => None,
     }
 }
 
-fn is_address_command(index: usize, buff: &[u8]) -> Result<Option<(Command, usize)>, LoadError> {
+fn is_address_command(
+    index: usize,
+    buff: &[u8],
+    order: ByteOrder,
+) -> Result<Option<(Command, usize)>, LoadError> {
     let byte = buff[index];
     let output = match byte {
         opcode::LDI..=opcode::STRS => {
             let k = Kind::new(byte);
             let cmd = if byte < opcode::STRI {
-                let addr = get_u16(buff, index + 1)?;
+                let addr = get_u16(buff, index + 1, order)?;
                 Command::MemoryLoad(k, addr)
             } else {
-                let addr = get_u16(buff, index + 1)?;
+                let addr = get_u16(buff, index + 1, order)?;
                 Command::MemoryStore(k, addr)
             };
             Some((cmd, 3))
@@ -272,39 +1012,130 @@ fn is_address_command(index: usize, buff: &[u8]) -> Result<Option<(Command, usiz
             let (addr, offset) = if byte == opcode::RET {
                 (0, 1)
             } else {
-                let tmp = get_u16(buff, index + 1)? as usize;
+                let tmp = get_u16(buff, index + 1, order)? as usize;
                 (tmp, 3)
             };
             Some((Command::Control(cond, addr), offset))
         }
         opcode::STRIP..=opcode::STRSP => {
             let kind = Kind::new(byte);
-            let addr = get_u16(buff, index + 1)?;
+            let addr = get_u16(buff, index + 1, order)?;
             let cmd = Command::StoreParam(kind, addr);
             Some((cmd, 3))
         }
         opcode::PARAM => {
-            let tmp = get_u16(buff, index + 1)? as usize;
+            let tmp = get_u16(buff, index + 1, order)? as usize;
             Some((Command::NewRecord(tmp), 3))
         }
+        opcode::TIMER => {
+            let tmp = get_u16(buff, index + 1, order)? as usize;
+            Some((Command::SetTimer(tmp), 3))
+        }
+        opcode::WRVN => {
+            let (kinds, offset) = get_descriptor(buff, index + 1)?;
+            Some((Command::OutputMany(kinds), offset + 1))
+        }
 
         _ => None,
     };
     Ok(output)
 }
 
+// returns the bindings and the size of the whole section
+fn get_arguments(
+    index: usize,
+    buff: &[u8],
+    order: ByteOrder,
+) -> Result<(Vec<(Kind, AddrSize)>, usize), LoadError> {
+    let count = buff.get(index).copied().unwrap_or(0) as usize;
+    let size = 1 + 3 * count;
+    if buff.len() < index + size {
+        let err = ErrorLocation::new(index, size, ErrorOperation::LoadingArguments);
+        return Err(LoadError::MissingBytes(err));
+    }
+    let mut args = Vec::with_capacity(count);
+    for i in 0..count {
+        let start = index + 1 + 3 * i;
+        let kind = Kind::new(buff[start]);
+        let addr = get_u16(buff, start + 1, order)?;
+        args.push((kind, addr));
+    }
+    Ok((args, size))
+}
+
+// returns the pairs and the size of the whole section
+fn get_metadata(
+    index: usize,
+    buff: &[u8],
+    order: ByteOrder,
+    options: &LoadOptions,
+    warnings: &mut Vec<LoadWarning>,
+) -> Result<(Vec<(String, String)>, usize), LoadError> {
+    let count = get_u16(buff, index, order)? as usize;
+    let mut size = 2;
+    let mut meta = Vec::new();
+    for _ in 0..count {
+        let (key, key_size) = get_meta_string(index + size, buff, order, options, warnings)?;
+        size += key_size;
+        let (value, value_size) = get_meta_string(index + size, buff, order, options, warnings)?;
+        size += value_size;
+        meta.push((key, value));
+    }
+    Ok((meta, size))
+}
+
+fn get_meta_string(
+    index: usize,
+    buff: &[u8],
+    order: ByteOrder,
+    options: &LoadOptions,
+    warnings: &mut Vec<LoadWarning>,
+) -> Result<(String, usize), LoadError> {
+    let len = get_u16(buff, index, order)? as usize;
+    let bytes = take_bytes(buff, index + 2, len)?;
+    let string = decode_string(bytes, index + 2, options, warnings)?;
+    Ok((string, len + 2))
+}
+
+// returns the kinds and the size of count and descriptor
+fn get_descriptor(buff: &[u8], index: usize) -> Result<(Vec<Kind>, usize), LoadError> {
+    let count = match buff.get(index) {
+        Some(count) => *count as usize,
+        None => {
+            let err = ErrorLocation::new(index, 1, ErrorOperation::LoadingDescriptor);
+            return Err(LoadError::MissingBytes(err));
+        }
+    };
+    let size = count.div_ceil(4);
+    if buff.len() < index + 1 + size {
+        let err = ErrorLocation::new(index + 1, size, ErrorOperation::LoadingDescriptor);
+        return Err(LoadError::MissingBytes(err));
+    }
+    let kinds = (0..count)
+        .map(|i| Kind::new(buff[index + 1 + i / 4] >> (2 * (i % 4))))
+        .collect();
+    Ok((kinds, size + 1))
+}
+
 fn is_constant_command(
     index: usize,
     buff: &[u8],
+    order: ByteOrder,
     str_mem: &mut StringMemory,
+    options: &LoadOptions,
+    warnings: &mut Vec<LoadWarning>,
 ) -> Result<Option<(Command, usize)>, LoadError> {
     let byte = buff[index];
     let output = match byte {
         opcode::LDIC..=opcode::LDSC => {
-            let (tmp, offset) = convert_constant(index, buff, str_mem)?;
+            let (tmp, offset) = convert_constant(index, buff, order, str_mem, options, warnings)?;
             let out = Command::ConstantLoad(tmp);
             Some((out, offset + 1))
         }
+        opcode::LDI0 => Some((Command::ConstantLoad(Constant::Integer(0)), 1)),
+        opcode::LDI1 => Some((Command::ConstantLoad(Constant::Integer(1)), 1)),
+        opcode::LDFALSE => Some((Command::ConstantLoad(Constant::Bool(false)), 1)),
+        opcode::LDTRUE => Some((Command::ConstantLoad(Constant::Bool(true)), 1)),
         _ => None,
     };
 
@@ -314,17 +1145,23 @@ fn is_constant_command(
 fn convert_constant(
     index: usize,
     buff: &[u8],
+    order: ByteOrder,
     str_mem: &mut StringMemory,
+    options: &LoadOptions,
+    warnings: &mut Vec<LoadWarning>,
 ) -> Result<(Constant, usize), LoadError> {
     // load and store constant modulo 4 follows
     // the same pattern, check opcode list
     match buff[index] % 4 {
         3 => {
-            let int_val = get_i32(buff, index + 1)?;
+            let int_val = get_i32(buff, index + 1, order)?;
             Ok((Constant::Integer(int_val), 4))
         }
         0 => {
-            let real_val = get_f64(buff, index + 1)?;
+            let real_val = get_f64(buff, index + 1, order)?;
+            if !real_val.is_finite() {
+                warnings.push(LoadWarning::NonFiniteConstant(index, real_val));
+            }
             Ok((Constant::Real(real_val), 8))
         }
         1 => {
@@ -332,10 +1169,9 @@ fn convert_constant(
             Ok((Constant::Bool(bool_val), 1))
         }
         2 => {
-            let size = get_u16(buff, index + 1)? as usize;
+            let size = get_u16(buff, index + 1, order)? as usize;
             let byte_string = take_bytes(buff, index + 3, size)?;
-            let tmp_str = str::from_utf8(byte_string)?;
-            let string = tmp_str.to_owned();
+            let string = decode_string(byte_string, index + 3, options, warnings)?;
             let index = str_mem.insert_static_string(string);
             Ok((Constant::Str(index), size + 2))
         }
@@ -343,13 +1179,36 @@ fn convert_constant(
     }
 }
 
+fn decode_string(
+    bytes: &[u8],
+    index: usize,
+    options: &LoadOptions,
+    warnings: &mut Vec<LoadWarning>,
+) -> Result<String, LoadError> {
+    match str::from_utf8(bytes) {
+        Ok(s) => Ok(s.to_owned()),
+        Err(err) if options.lenient_strings => {
+            warnings.push(LoadWarning::LossyString(index, err));
+            Ok(String::from_utf8_lossy(bytes).into_owned())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
 fn convert_single(byte: u8) -> Command {
     match byte {
         opcode::EXT => Command::Exit,
         opcode::ADDI..=opcode::NEI => Command::Integer(Operator::new(byte)),
         opcode::ADDR..=opcode::NER => Command::Real(Operator::new(byte - 10)),
+        opcode::MODI => Command::Integer(Operator::Math(MathOperator::Mod)),
+        opcode::MODR => Command::Real(Operator::Math(MathOperator::Mod)),
+        opcode::POWI => Command::Integer(Operator::Math(MathOperator::Pow)),
+        opcode::POWR => Command::Real(Operator::Math(MathOperator::Pow)),
         opcode::RDI..=opcode::RDS => Command::Input(Kind::new(byte)),
         opcode::WRI..=opcode::WRS => Command::Output(Kind::new(byte)),
+        opcode::RDRAW => Command::RawInput,
+        opcode::WRRAW => Command::RawOutput,
+        opcode::SETBOOLFMT => Command::SetBoolFormat,
         opcode::FLU => Command::Flush(FlushMode::Flush),
         opcode::FLN => Command::Flush(FlushMode::NewLine),
         opcode::CSTI => Command::CastInt,
@@ -360,13 +1219,40 @@ fn convert_single(byte: u8) -> Command {
         opcode::NEGI => Command::Unary(Kind::Integer),
         opcode::NEGR => Command::Unary(Kind::Real),
         opcode::NOT => Command::Unary(Kind::Bool),
+        opcode::DUPI..=opcode::DUPS => Command::Duplicate(Kind::new(byte)),
+        opcode::STRSTAT => Command::StringStats,
+        opcode::GETDEF => Command::GetDefine,
+        opcode::SBAPPI..=opcode::SBAPPS => Command::Append(Kind::new(byte)),
+        opcode::SBNEW => Command::NewBuilder,
+        opcode::SBFINISH => Command::FinishBuilder,
+        opcode::HASHS => Command::HashString,
+        opcode::ANDI..=opcode::SHRI => Command::Bitwise(BitOp::new(byte)),
+        opcode::SQRT..=opcode::EXP => Command::MathIntrinsic(Intrinsic::new(byte)),
+        opcode::ABSI => Command::Absolute(Kind::Integer),
+        opcode::ABSR => Command::Absolute(Kind::Real),
+        opcode::MINI => Command::Integer(Operator::Math(MathOperator::Min)),
+        opcode::MINR => Command::Real(Operator::Math(MathOperator::Min)),
+        opcode::MAXI => Command::Integer(Operator::Math(MathOperator::Max)),
+        opcode::MAXR => Command::Real(Operator::Math(MathOperator::Max)),
+        opcode::CSTI_TRUNC..=opcode::CSTI_EVEN => Command::RoundInt(Rounding::new(byte)),
+        opcode::SUBSTR => Command::Substring,
+        opcode::CHARAT => Command::CharAt,
+        opcode::UPPER..=opcode::TRIM => Command::Transform(TextOp::new(byte)),
+        opcode::STR2INT => Command::ParseNumber(Kind::Integer),
+        opcode::STR2REAL => Command::ParseNumber(Kind::Real),
+        opcode::INT2STR => Command::FormatNumber(Kind::Integer),
+        opcode::REAL2STR => Command::FormatNumber(Kind::Real),
+        opcode::ANEWI..=opcode::ANEWS => Command::ArrayNew(Kind::new(byte - opcode::ANEWI)),
+        opcode::ALDI..=opcode::ALDS => Command::ArrayLoad(Kind::new(byte - opcode::ALDI)),
+        opcode::ASTI..=opcode::ASTS => Command::ArrayStore(Kind::new(byte - opcode::ASTI)),
+        opcode::ALEN => Command::ArrayLength,
         opcode::GEQS..=opcode::NES => Command::StrCompare(RelationalOperator::new(byte - 63)),
         opcode::GEQB..=opcode::NEB => Command::BoolCompare(RelationalOperator::new(byte - 69)),
         _ => unreachable!(),
     }
 }
 
-fn load_file(file: &Path) -> std::io::Result<Vec<u8>> {
+pub fn load_file(file: &Path) -> std::io::Result<Vec<u8>> {
     let mut file = File::open(file)?;
     let meta = file.metadata()?;
     let mut output = Vec::with_capacity(meta.len() as usize);
@@ -374,7 +1260,7 @@ fn load_file(file: &Path) -> std::io::Result<Vec<u8>> {
     Ok(output)
 }
 
-fn take_bytes<'a>(buff: &'a [u8], start: usize, len: usize) -> Result<&'a [u8], LoadError> {
+fn take_bytes(buff: &[u8], start: usize, len: usize) -> Result<&[u8], LoadError> {
     if buff.len() > start + len - 1 {
         let end = start + len;
         let tmp = &buff[start..end];
@@ -385,10 +1271,13 @@ fn take_bytes<'a>(buff: &'a [u8], start: usize, len: usize) -> Result<&'a [u8],
     }
 }
 
-fn get_u16(buff: &[u8], index: usize) -> Result<u16, LoadError> {
+fn get_u16(buff: &[u8], index: usize, order: ByteOrder) -> Result<u16, LoadError> {
     if buff.len() > index + 1 {
         let value = [buff[index], buff[index + 1]];
-        let output = u16::from_be_bytes(value);
+        let output = match order {
+            ByteOrder::Big => u16::from_be_bytes(value),
+            ByteOrder::Little => u16::from_le_bytes(value),
+        };
         Ok(output)
     } else {
         let err = ErrorLocation::new(index, 2, ErrorOperation::LoadingU16);
@@ -396,7 +1285,7 @@ fn get_u16(buff: &[u8], index: usize) -> Result<u16, LoadError> {
     }
 }
 
-fn get_i32(buff: &[u8], index: usize) -> Result<i32, LoadError> {
+fn get_i32(buff: &[u8], index: usize, order: ByteOrder) -> Result<i32, LoadError> {
     if buff.len() > index + 3 {
         let value = [
             buff[index],
@@ -404,7 +1293,10 @@ fn get_i32(buff: &[u8], index: usize) -> Result<i32, LoadError> {
             buff[index + 2],
             buff[index + 3],
         ];
-        let output = i32::from_be_bytes(value);
+        let output = match order {
+            ByteOrder::Big => i32::from_be_bytes(value),
+            ByteOrder::Little => i32::from_le_bytes(value),
+        };
         Ok(output)
     } else {
         let err = ErrorLocation::new(index, 4, ErrorOperation::LoadingI32);
@@ -412,7 +1304,7 @@ fn get_i32(buff: &[u8], index: usize) -> Result<i32, LoadError> {
     }
 }
 
-fn get_f64(buff: &[u8], index: usize) -> Result<f64, LoadError> {
+fn get_f64(buff: &[u8], index: usize, order: ByteOrder) -> Result<f64, LoadError> {
     if buff.len() > index + 7 {
         let value = [
             buff[index],
@@ -424,7 +1316,10 @@ fn get_f64(buff: &[u8], index: usize) -> Result<f64, LoadError> {
             buff[index + 6],
             buff[index + 7],
         ];
-        let output = f64::from_be_bytes(value);
+        let output = match order {
+            ByteOrder::Big => f64::from_be_bytes(value),
+            ByteOrder::Little => f64::from_le_bytes(value),
+        };
         Ok(output)
     } else {
         let err = ErrorLocation::new(index, 8, ErrorOperation::LoadingF64);
@@ -464,12 +1359,12 @@ mod test {
     #[test]
     fn test_correct_parse() {
         let simple = add_init_header(vec![opcode::ADDI, opcode::SUBI, opcode::ADDR, opcode::SUBI]);
-        parse_data(&simple).unwrap();
+        parse_data(&simple, &LoadOptions::default()).unwrap();
 
         // 5 chars
-        let a = 'a' as u8;
+        let a = b'a';
         let with_string = add_init_header(vec![opcode::LDSC, 0, 5, a, a, a, a, a]);
-        let (prog, _, mem) = parse_data(&with_string).unwrap();
+        let (prog, _, mem, _) = parse_data(&with_string, &LoadOptions::default()).unwrap();
         assert_eq!(prog.body.code.len(), 1);
         assert_eq!(prog.func.len(), 0);
 
@@ -497,12 +1392,12 @@ mod test {
 
         // 255 is an invalid opcode
         data.push(255);
-        let stat = parse_data(&data).unwrap_err();
+        let stat = parse_data(&data, &LoadOptions::default()).unwrap_err();
         match stat {
             LoadError::UnknownByte(err) => {
                 assert_eq!(err.value, 255);
             }
-            _ => assert!(false, "{:?}", stat),
+            _ => panic!("{:?}", stat),
         }
     }
 
@@ -516,7 +1411,7 @@ mod test {
             data.push(*b);
         }
 
-        let (prog, _, _) = parse_data(&data).unwrap();
+        let (prog, _, _, _) = parse_data(&data, &LoadOptions::default()).unwrap();
         assert_eq!(prog.body.code.len(), 1);
         assert_eq!(prog.func.len(), 0);
 
@@ -526,28 +1421,655 @@ mod test {
         ))
     }
 
+    #[test]
+    fn test_lenient_strings() {
+        let data = add_init_header(vec![opcode::LDSC, 0, 3, b'a', 0xff, b'b']);
+        let stat = parse_data(&data, &LoadOptions::default()).unwrap_err();
+        assert!(
+            matches!(stat, LoadError::StringEncodeError(_)),
+            "{:?}",
+            stat
+        );
+
+        let options = LoadOptions {
+            lenient_strings: true,
+            ..LoadOptions::default()
+        };
+        let (prog, _, mem, warnings) = parse_data(&data, &options).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(warnings[0], LoadWarning::LossyString(12, _)));
+        assert!(
+            matches!(prog.body.code[0], Command::ConstantLoad(Constant::Str(s)) if
+                mem.get_string(s) == "a\u{fffd}b"
+            )
+        );
+    }
+
+    #[test]
+    fn test_byte_order() {
+        let number: i32 = 0x0102_0304;
+        let mut data = vec![opcode::HDR, opcode::HDR_LITTLE_ENDIAN, opcode::INIT];
+        data.extend(&[2, 0, 0, 0, 0, 0, 0, 0, opcode::LDIC]);
+        data.extend(&number.to_le_bytes());
+
+        let (prog, mem, _, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        assert_eq!(mem.main.integer_count, 2);
+        assert!(
+            matches!(prog.body.code[0], Command::ConstantLoad(Constant::Integer(n)) if n == number)
+        );
+
+        data[1] = 0;
+        let (_, mem, _, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        assert_eq!(mem.main.integer_count, 512);
+
+        data[1] = opcode::HDR_LITTLE_ENDIAN | opcode::HDR_INTERACTIVE;
+        let (prog, _, _, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        assert!(prog.interactive);
+
+        data[1] = 0x80;
+        let stat = parse_data(&data, &LoadOptions::default()).unwrap_err();
+        assert!(matches!(stat, LoadError::UnknownHeaderFlags(0x80)));
+    }
+
+    #[test]
+    fn test_raw_io() {
+        let data = add_init_header(vec![opcode::RDRAW, opcode::WRRAW]);
+        let (prog, _, _, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        assert_eq!(prog.body.code.len(), 2);
+        assert!(matches!(prog.body.code[0], Command::RawInput));
+        assert!(matches!(prog.body.code[1], Command::RawOutput));
+    }
+
+    #[test]
+    fn test_output_descriptor() {
+        let data = add_init_header(vec![opcode::WRVN, 5, 0b11100100, 0b01]);
+        let (prog, _, _, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        assert_eq!(prog.body.code.len(), 1);
+        let kinds = match &prog.body.code[0] {
+            Command::OutputMany(kinds) => kinds.clone(),
+            cmd => panic!("unexpected {:?}", cmd),
+        };
+        let expected = [Kind::Integer, Kind::Real, Kind::Bool, Kind::Str, Kind::Real];
+        assert_eq!(kinds, expected);
+
+        let data = add_init_header(vec![opcode::WRVN, 5, 0]);
+        let stat = parse_data(&data, &LoadOptions::default()).unwrap_err();
+        assert!(matches!(stat, LoadError::MissingBytes(_)));
+    }
+
+    #[test]
+    fn test_argument_bindings() {
+        let mut data = vec![opcode::INIT, 0, 1, 0, 0, 0, 0, 0, 2];
+        data.extend(&[opcode::ARGS, 2, 0, 0, 0, 3, 0, 1]);
+        let (_, mem, _, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        assert_eq!(mem.args, vec![(Kind::Integer, 0), (Kind::Str, 1)]);
+
+        data[16] = 2;
+        let stat = parse_data(&data, &LoadOptions::default()).unwrap_err();
+        assert!(matches!(
+            stat,
+            LoadError::ArgumentOutOfRange(1, Kind::Str, 2)
+        ));
+
+        data.pop();
+        let stat = parse_data(&data, &LoadOptions::default()).unwrap_err();
+        assert!(matches!(stat, LoadError::MissingBytes(_)));
+    }
+
     #[test]
     fn test_function_build() {
         let data = vec![
             opcode::ADDI,
             opcode::GEQI,
+            opcode::PARAM,
+            0,
+            1,
             opcode::CALL,
             0,
             1,
             opcode::EXT,
             opcode::FUNC,
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
             opcode::ADDI,
             opcode::RET,
             opcode::FUNC,
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
             opcode::GEQR,
             opcode::RET,
         ];
         let data = add_init_header(data);
-        let (prog, _, _) = parse_data(&data).unwrap();
-        assert_eq!(prog.body.code.len(), 4);
+        let (prog, _, _, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        assert_eq!(prog.body.code.len(), 5);
         assert_eq!(prog.func.len(), 2, "{:?}", prog.func);
         for func in &prog.func {
             assert_eq!(func.code.len(), 2);
         }
     }
+
+    #[test]
+    fn test_memory_declaration() {
+        let stat = parse_data(&[opcode::LDI0, opcode::WRI], &LoadOptions::default());
+        assert!(matches!(stat, Err(LoadError::MissingMemoryDeclaration)));
+
+        let data = add_init_header(vec![opcode::FUNC, opcode::RET, opcode::FUNC, opcode::RET]);
+        let stat = parse_data(&data, &LoadOptions::default());
+        assert!(matches!(stat, Err(LoadError::MissingFunctionMemory(0))));
+
+        let mut data = add_init_header(vec![opcode::FUNC]);
+        data.extend(add_init_header(vec![
+            opcode::RET,
+            opcode::FUNC,
+            opcode::RET,
+        ]));
+        let stat = parse_data(&data, &LoadOptions::default());
+        assert!(matches!(stat, Err(LoadError::MissingFunctionMemory(1))));
+    }
+
+    #[test]
+    fn test_finalizer_declaration() {
+        let data = add_init_header(vec![
+            opcode::FINI,
+            0,
+            0,
+            opcode::FUNC,
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::RET,
+        ]);
+        let stat = parse_data(&data, &LoadOptions::default()).unwrap_err();
+        assert!(matches!(stat, LoadError::BadFinalizer(0)));
+
+        let data = add_init_header(vec![
+            opcode::FINI,
+            0,
+            1,
+            opcode::FUNC,
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::RET,
+        ]);
+        let stat = parse_data(&data, &LoadOptions::default()).unwrap_err();
+        assert!(matches!(stat, LoadError::BadFinalizer(1)));
+    }
+
+    #[test]
+    fn test_timer_handler() {
+        let data = add_init_header(vec![
+            opcode::TIMER,
+            0,
+            0,
+            opcode::FUNC,
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::RET,
+        ]);
+        let (prog, _, _, warnings) = parse_data(&data, &LoadOptions::default()).unwrap();
+        assert!(matches!(prog.body.code[0], Command::SetTimer(0)));
+        assert!(warnings.is_empty());
+
+        let data = add_init_header(vec![
+            opcode::TIMER,
+            0,
+            1,
+            opcode::FUNC,
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::RET,
+        ]);
+        let stat = parse_data(&data, &LoadOptions::default()).unwrap_err();
+        assert!(matches!(stat, LoadError::BadTimerHandler(1)));
+    }
+
+    #[test]
+    fn test_relative_jumps() {
+        // jump over the first constant, then back to the label
+        let code = vec![
+            opcode::LBL,
+            0,
+            5,
+            opcode::JUMPR,
+            0,
+            2,
+            opcode::LDI0,
+            opcode::LDI1,
+            opcode::JUMPR,
+            0xff,
+            0xfc,
+        ];
+        let data = add_init_header(code);
+        let (prog, _, _, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        // no LBL is added: indices are those of the file
+        let code = &prog.body.code;
+        assert_eq!(code.len(), 5);
+        assert!(matches!(code[1], Command::Control(ControlFlow::Jump, 6)));
+        assert!(matches!(code[4], Command::Control(ControlFlow::Jump, 7)));
+        assert_eq!(prog.body.labels[&6], 3);
+        assert_eq!(prog.body.labels[&7], 0);
+        assert_eq!(prog.body.implicit_labels(), vec![(0, 7), (3, 6)]);
+
+        // a targeted FLN stays apart, later targets follow the fusion
+        let code = vec![
+            opcode::WRS,
+            opcode::FLN,
+            opcode::JNER,
+            0,
+            4,
+            opcode::WRS,
+            opcode::FLN,
+            opcode::JUMPR,
+            0xff,
+            0xff,
+        ];
+        let data = add_init_header(code);
+        let (prog, _, _, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let code = &prog.body.code;
+        assert_eq!(code.len(), 5);
+        assert!(matches!(code[0], Command::OutputLine));
+        assert!(matches!(code[3], Command::Flush(FlushMode::NewLine)));
+        assert_eq!(prog.body.labels[&0], 5);
+        assert_eq!(prog.body.labels[&1], 3);
+
+        let data = add_init_header(vec![opcode::FUNC, opcode::JNER, 0, 2]);
+        let stat = parse_data(&data, &LoadOptions::default()).unwrap_err();
+        assert!(matches!(stat, LoadError::BadRelativeJump(10, 2)));
+    }
+
+    #[test]
+    fn test_duplicate_labels() {
+        let code = vec![
+            opcode::LBL,
+            0,
+            1,
+            opcode::FUNC,
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::LBL,
+            0,
+            1,
+            opcode::RET,
+        ];
+        assert!(parse_data(&add_init_header(code), &LoadOptions::default()).is_ok());
+
+        let code = vec![opcode::LBL, 0, 1, opcode::LDI0, opcode::LBL, 0, 1];
+        let stat = parse_data(&add_init_header(code), &LoadOptions::default()).unwrap_err();
+        assert!(matches!(
+            stat,
+            LoadError::DuplicateLabel(BlockId::Main, 1, 0, 2)
+        ));
+    }
+
+    #[test]
+    fn test_call_protocol() {
+        let load = |code: &[u8]| {
+            let mut code = code.to_vec();
+            code.extend(&[
+                opcode::FUNC,
+                opcode::INIT,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                opcode::RET,
+            ]);
+            parse_data(&add_init_header(code), &LoadOptions::default())
+        };
+        assert!(load(&[opcode::PARAM, 0, 0, opcode::LDI0, opcode::CALL, 0, 0]).is_ok());
+
+        let stat = load(&[opcode::PARAM, 0, 0, opcode::LBL, 0, 1, opcode::CALL, 0, 0]);
+        assert!(matches!(
+            stat.unwrap_err(),
+            LoadError::CallProtocol(BlockId::Main, 0, CallError::UnmatchedRecord(0))
+        ));
+        // the target of a relative jump starts a basic block too
+        let stat = load(&[
+            opcode::PARAM,
+            0,
+            0,
+            opcode::LDI0,
+            opcode::CALL,
+            0,
+            0,
+            opcode::JUMPR,
+            0xff,
+            0xff,
+        ]);
+        assert!(matches!(
+            stat.unwrap_err(),
+            LoadError::CallProtocol(BlockId::Main, 0, CallError::UnmatchedRecord(0))
+        ));
+        let stat = load(&[opcode::LDI0, opcode::CALL, 0, 0]);
+        assert!(matches!(
+            stat.unwrap_err(),
+            LoadError::CallProtocol(BlockId::Main, 1, CallError::CallWithoutRecord(0))
+        ));
+        let stat = load(&[opcode::PARAM, 0, 0, opcode::PARAM, 0, 0]);
+        assert!(matches!(
+            stat.unwrap_err(),
+            LoadError::CallProtocol(BlockId::Main, 1, CallError::NestedRecord(0, 0))
+        ));
+        let stat = load(&[opcode::LDI0, opcode::STRIP, 0x80, 0]);
+        assert!(matches!(
+            stat.unwrap_err(),
+            LoadError::CallProtocol(BlockId::Main, 1, CallError::ParamWithoutRecord)
+        ));
+        let stat = load(&[opcode::PARAM, 0, 1, opcode::CALL, 0, 1]);
+        assert!(matches!(
+            stat.unwrap_err(),
+            LoadError::CallProtocol(BlockId::Main, 0, CallError::UndefinedFunction(1))
+        ));
+
+        // FUNC INIT without any code gets no block
+        let data = add_init_header(vec![opcode::FUNC, opcode::INIT, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let stat = parse_data(&data, &LoadOptions::default());
+        assert!(matches!(stat, Err(LoadError::FunctionTableMismatch(0, 1))));
+    }
+
+    #[test]
+    fn test_fuse_output_lines() {
+        let code = vec![
+            opcode::LDSC,
+            0,
+            1,
+            b'a',
+            opcode::WRS,
+            opcode::FLN,
+            opcode::LDSC,
+            0,
+            1,
+            b'b',
+            opcode::WRS,
+            opcode::LBL,
+            0,
+            0,
+            opcode::FLN,
+        ];
+        let (prog, _, _, _) = parse_data(&add_init_header(code), &LoadOptions::default()).unwrap();
+        let code = &prog.body.code;
+        assert_eq!(code.len(), 6);
+        assert!(matches!(code[1], Command::OutputLine));
+        assert!(matches!(code[3], Command::Output(Kind::Str)));
+        assert!(matches!(code[5], Command::Flush(FlushMode::NewLine)));
+        // the fused pair keeps the offset of the WRS
+        assert_eq!(prog.body.offsets, vec![9, 13, 15, 19, 20, 23]);
+    }
+
+    #[test]
+    fn test_pure_declaration() {
+        let data = add_init_header(vec![
+            opcode::PURE,
+            0,
+            0,
+            2,
+            0b0111,
+            opcode::FUNC,
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::RET,
+        ]);
+        let (prog, _, _, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        assert_eq!(prog.pure[&0], vec![Kind::Str, Kind::Real]);
+
+        let data = add_init_header(vec![
+            opcode::PURE,
+            0,
+            1,
+            0,
+            opcode::FUNC,
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::RET,
+        ]);
+        let stat = parse_data(&data, &LoadOptions::default()).unwrap_err();
+        assert!(matches!(stat, LoadError::BadPureFunction(1)));
+    }
+
+    #[test]
+    fn test_metadata() {
+        let code = vec![
+            opcode::META,
+            0,
+            2,
+            0,
+            1,
+            b'k',
+            0,
+            2,
+            b'v',
+            b'1',
+            0,
+            3,
+            b'k',
+            b'e',
+            b'y',
+            0,
+            0,
+        ];
+        let (prog, _, _, _) = parse_data(&add_init_header(code), &LoadOptions::default()).unwrap();
+        assert!(prog.body.code.is_empty());
+        let expected = vec![
+            ("k".to_owned(), "v1".to_owned()),
+            ("key".to_owned(), String::new()),
+        ];
+        assert_eq!(prog.meta, expected);
+
+        let code = vec![opcode::META, 0, 1, 0, 3, b'k'];
+        let stat = parse_data(&add_init_header(code), &LoadOptions::default());
+        assert!(matches!(stat, Err(LoadError::MissingBytes(_))));
+    }
+
+    #[test]
+    fn test_compact_constants() {
+        let code = vec![opcode::LDI0, opcode::LDI1, opcode::LDFALSE, opcode::LDTRUE];
+        let data = add_init_header(code);
+        let (prog, _, _, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        let code = &prog.body.code;
+        assert!(matches!(
+            code[0],
+            Command::ConstantLoad(Constant::Integer(0))
+        ));
+        assert!(matches!(
+            code[1],
+            Command::ConstantLoad(Constant::Integer(1))
+        ));
+        assert!(matches!(
+            code[2],
+            Command::ConstantLoad(Constant::Bool(false))
+        ));
+        assert!(matches!(
+            code[3],
+            Command::ConstantLoad(Constant::Bool(true))
+        ));
+    }
+
+    #[test]
+    fn test_load_warnings() {
+        let mut data = add_init_header(vec![opcode::LDRC]);
+        data.extend(&f64::NAN.to_be_bytes());
+        data.extend(&[opcode::PARAM, 0, 0, opcode::CALL, 0, 0, opcode::EXT]);
+        data.extend(&[opcode::FUNC, opcode::INIT, 0, 0, 0xff, 0xff, 0, 0, 0, 0]);
+        data.extend(&[
+            opcode::RET,
+            opcode::FUNC,
+            opcode::INIT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            opcode::PARAM,
+            0,
+            1,
+        ]);
+        data.extend(&[opcode::CALL, 0, 1, opcode::RET]);
+
+        let (_, _, _, warnings) = parse_data(&data, &LoadOptions::default()).unwrap();
+        assert_eq!(warnings.len(), 4, "{:?}", warnings);
+        assert!(matches!(warnings[0], LoadWarning::NonFiniteConstant(9, _)));
+        assert!(matches!(warnings[1], LoadWarning::UnusedFunction(1)));
+        assert!(matches!(
+            warnings[2],
+            LoadWarning::OversizedMemory(BlockId::Function(0), Kind::Real, 0xffff)
+        ));
+        assert!(matches!(
+            warnings[3],
+            LoadWarning::UnusedMemory(BlockId::Function(0), Kind::Real, 0xffff, 0)
+        ));
+    }
+
+    #[test]
+    fn test_reserved_and_deprecated() {
+        let data = add_init_header(vec![opcode::ADDI, 22]);
+        let stat = parse_data(&data, &LoadOptions::default()).unwrap_err();
+        assert!(matches!(stat, LoadError::ReservedOpcode(err) if err.index == 10));
+
+        let table = [(opcode::ADDI, "use something else")];
+        let mut warnings = Vec::new();
+        for (index, byte) in [opcode::ADDI, opcode::NEI, opcode::ADDI].iter().enumerate() {
+            check_deprecated(*byte, index, &table, &mut warnings);
+        }
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(
+            warnings[0],
+            LoadWarning::DeprecatedOpcode(opcode::ADDI, 0, _)
+        ));
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let mut data = vec![opcode::INIT, 0, 2, 0, 0, 0, 0, 0, 0];
+        data.extend(&[opcode::STRI, 0, 1, opcode::EXT, opcode::FUNC]);
+        data.extend(&[opcode::INIT, 0, 1, 1, 0, 0, 0, 0, 0, opcode::LDI, 0x80, 0]);
+        let (_, _, _, warnings) = parse_data(&data, &LoadOptions::default()).unwrap();
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+        assert!(matches!(warnings[0], LoadWarning::UnusedFunction(0)));
+        assert!(matches!(
+            warnings[1],
+            LoadWarning::UnusedMemory(BlockId::Function(0), Kind::Real, 256, 0)
+        ));
+
+        // local integer 1 in a function declaring a single one
+        data.extend(&[opcode::LDI, 0x80, 1]);
+        let stat = parse_data(&data, &LoadOptions::default()).unwrap_err();
+        assert!(matches!(
+            stat,
+            LoadError::AddressOutOfRange(MemoryAccessError {
+                block: BlockId::Function(0),
+                index: 1,
+                ..
+            })
+        ));
+
+        let data = add_init_header(vec![opcode::LDI, 0x80, 0]);
+        let stat = parse_data(&data, &LoadOptions::default()).unwrap_err();
+        assert!(matches!(stat, LoadError::AddressOutOfRange(_)));
+    }
+
+    #[test]
+    fn test_resolve_slots() {
+        let mut data = vec![opcode::INIT, 0, 1, 0, 0, 0, 0, 0, 0];
+        data.extend(&[
+            opcode::LDI1,
+            opcode::STRI,
+            0,
+            0,
+            opcode::LDI,
+            0,
+            0,
+            opcode::WRI,
+        ]);
+        let (mut prog, mem, mut str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        resolve_slots(&mut prog, &mem).unwrap();
+        assert!(matches!(
+            prog.body.code[1],
+            Command::StoreSlot(Kind::Integer, Slot::Global(0))
+        ));
+        assert!(matches!(
+            prog.body.code[2],
+            Command::LoadSlot(Kind::Integer, Slot::Global(0))
+        ));
+        let config = crate::engine::EngineConfig::new();
+        let mut input = std::io::empty();
+        let mut out = vec![];
+        crate::engine::Engine::new(&mut prog, &mem, &mut str_mem, &config, &mut input)
+            .and_then(|mut engine| engine.run(&mut out))
+            .unwrap();
+        assert_eq!(out, b"1");
+
+        // no local memory in the main body
+        prog.body.code[2] = Command::MemoryLoad(Kind::Integer, LOCAL_MASK);
+        let err = resolve_slots(&mut prog, &mem).unwrap_err();
+        assert_eq!(err.index, 2);
+    }
 }
@@ -0,0 +1,467 @@
+use crate::command_definition::{
+    Block, BlockId, Command, Constant, ControlFlow, FlushMode, ForControl, Kind, MathOperator,
+    MemorySize, Operator, Program, ProgramMemory, RelationalOperator,
+};
+use crate::opcode;
+use crate::program_load::ByteOrder;
+use crate::string_memory::StringMemory;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+// Inverse of program_load: encode a loaded program back to the
+// binary format. The loader is not one to one, so the bytes follow
+// the program as it was loaded: constants use the shortest form,
+// LDI0, LDI1, LDFALSE and LDTRUE where they apply and LDxC for the
+// rest, and fused WRS FLN pairs are split again. Storing a loaded
+// copy of the output returns the same bytes.
+
+#[derive(Debug)]
+pub enum StoreError {
+    // instruction whose operand does not fit its encoding
+    Operand(BlockId, usize),
+    MemorySize(BlockId),
+    Section(&'static str),
+}
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Operand(block, index) => write!(
+                f,
+                "operand of {} instruction {} does not fit its encoding",
+                block, index
+            ),
+            Self::MemorySize(block) => {
+                write!(f, "memory of {} does not fit in INIT", block)
+            }
+            Self::Section(name) => write!(f, "{} section is too large", name),
+        }
+    }
+}
+
+pub fn store(
+    prog: &Program,
+    prog_mem: &ProgramMemory,
+    str_mem: &StringMemory,
+    order: ByteOrder,
+) -> Result<Vec<u8>, StoreError> {
+    let mut encoder = Encoder {
+        output: vec![],
+        order,
+    };
+    // big endian batch programs are the default, they need no header
+    let mut flags = 0;
+    if order == ByteOrder::Little {
+        flags |= opcode::HDR_LITTLE_ENDIAN;
+    }
+    if prog.interactive {
+        flags |= opcode::HDR_INTERACTIVE;
+    }
+    if flags != 0 {
+        encoder.byte(opcode::HDR);
+        encoder.byte(flags);
+    }
+    encoder.memory(BlockId::Main, &prog_mem.main)?;
+    encoder.sections(prog, prog_mem)?;
+    encoder.block(BlockId::Main, &prog.body, str_mem)?;
+    // the loader drops empty functions but keeps their memory
+    let count = prog.func.len().max(prog_mem.func.len());
+    for id in 0..count {
+        encoder.byte(opcode::FUNC);
+        if let Some(size) = prog_mem.func.get(id) {
+            encoder.memory(BlockId::Function(id), size)?;
+        }
+        if let Some(block) = prog.func.get(id) {
+            encoder.block(BlockId::Function(id), block, str_mem)?;
+        }
+    }
+    Ok(encoder.output)
+}
+
+struct Encoder {
+    output: Vec<u8>,
+    order: ByteOrder,
+}
+
+impl Encoder {
+    fn byte(&mut self, byte: u8) {
+        self.output.push(byte);
+    }
+
+    fn u16(&mut self, value: u16) {
+        match self.order {
+            ByteOrder::Big => self.output.extend(&value.to_be_bytes()),
+            ByteOrder::Little => self.output.extend(&value.to_le_bytes()),
+        }
+    }
+
+    fn i32(&mut self, value: i32) {
+        match self.order {
+            ByteOrder::Big => self.output.extend(&value.to_be_bytes()),
+            ByteOrder::Little => self.output.extend(&value.to_le_bytes()),
+        }
+    }
+
+    fn f64(&mut self, value: f64) {
+        match self.order {
+            ByteOrder::Big => self.output.extend(&value.to_be_bytes()),
+            ByteOrder::Little => self.output.extend(&value.to_le_bytes()),
+        }
+    }
+
+    // u16 length followed by the bytes
+    fn string(&mut self, value: &str) -> Option<()> {
+        self.u16(u16::try_from(value.len()).ok()?);
+        self.output.extend(value.as_bytes());
+        Some(())
+    }
+
+    // count followed by two bits per kind, four kinds per byte
+    fn kinds(&mut self, kinds: &[Kind]) -> Option<()> {
+        self.byte(u8::try_from(kinds.len()).ok()?);
+        for chunk in kinds.chunks(4) {
+            let packed = chunk
+                .iter()
+                .enumerate()
+                .fold(0, |acc, (i, kind)| acc | kind_code(kind) << (2 * i));
+            self.byte(packed);
+        }
+        Some(())
+    }
+
+    fn memory(&mut self, block: BlockId, size: &MemorySize) -> Result<(), StoreError> {
+        self.byte(opcode::INIT);
+        for kind in &Kind::ALL {
+            let count =
+                u16::try_from(size.count(kind)).map_err(|_| StoreError::MemorySize(block))?;
+            self.u16(count);
+        }
+        Ok(())
+    }
+
+    fn sections(&mut self, prog: &Program, prog_mem: &ProgramMemory) -> Result<(), StoreError> {
+        // the loader appends every ARGS section to the previous ones
+        for args in prog_mem.args.chunks(u8::MAX as usize) {
+            self.byte(opcode::ARGS);
+            self.byte(args.len() as u8);
+            for (kind, addr) in args {
+                self.byte(kind_code(kind));
+                self.u16(*addr);
+            }
+        }
+        if let Some(fini) = prog.fini {
+            self.byte(opcode::FINI);
+            self.u16(u16::try_from(fini).map_err(|_| StoreError::Section("FINI"))?);
+        }
+        if !prog.meta.is_empty() {
+            let count = u16::try_from(prog.meta.len()).map_err(|_| StoreError::Section("META"))?;
+            self.byte(opcode::META);
+            self.u16(count);
+            for (key, value) in &prog.meta {
+                self.string(key)
+                    .and_then(|_| self.string(value))
+                    .ok_or(StoreError::Section("META"))?;
+            }
+        }
+        let mut pure: Vec<_> = prog.pure.iter().collect();
+        pure.sort_by_key(|(func, _)| **func);
+        for (func, kinds) in pure {
+            self.byte(opcode::PURE);
+            self.u16(u16::try_from(*func).map_err(|_| StoreError::Section("PURE"))?);
+            self.kinds(kinds).ok_or(StoreError::Section("PURE"))?;
+        }
+        Ok(())
+    }
+
+    fn block(
+        &mut self,
+        id: BlockId,
+        block: &Block,
+        str_mem: &StringMemory,
+    ) -> Result<(), StoreError> {
+        // jumps to a label without LBL were relative in the file:
+        // offsets count file instructions, a fused WRS FLN is two
+        let implicit: HashMap<usize, usize> = block
+            .implicit_labels()
+            .into_iter()
+            .map(|(index, label)| (label, index))
+            .collect();
+        let mut position = Vec::with_capacity(block.code.len() + 1);
+        let mut next = 0;
+        for cmd in &block.code {
+            position.push(next);
+            next += if let Command::OutputLine = cmd { 2 } else { 1 };
+        }
+        position.push(next);
+        for (index, cmd) in block.code.iter().enumerate() {
+            let stored = match cmd {
+                Command::Control(ctrl, label) if implicit.contains_key(label) => {
+                    let offset = position[implicit[label]] as isize - position[index] as isize;
+                    self.relative_jump(ctrl, offset)
+                }
+                cmd => self.command(cmd, str_mem),
+            };
+            stored.ok_or(StoreError::Operand(id, index))?;
+        }
+        Ok(())
+    }
+
+    fn relative_jump(&mut self, ctrl: &ControlFlow, offset: isize) -> Option<()> {
+        let code = match ctrl {
+            ControlFlow::Jump => opcode::JUMPR,
+            ControlFlow::JumpTrue => opcode::JEQR,
+            ControlFlow::JumpFalse => opcode::JNER,
+            _ => return None,
+        };
+        self.byte(code);
+        self.u16(i16::try_from(offset).ok()? as u16);
+        Some(())
+    }
+
+    // None when an operand does not fit
+    fn command(&mut self, cmd: &Command, str_mem: &StringMemory) -> Option<()> {
+        match cmd {
+            Command::Integer(Operator::Math(MathOperator::Mod)) => self.byte(opcode::MODI),
+            Command::Real(Operator::Math(MathOperator::Mod)) => self.byte(opcode::MODR),
+            Command::Integer(Operator::Math(MathOperator::Pow)) => self.byte(opcode::POWI),
+            Command::Real(Operator::Math(MathOperator::Pow)) => self.byte(opcode::POWR),
+            Command::Integer(Operator::Math(MathOperator::Min)) => self.byte(opcode::MINI),
+            Command::Real(Operator::Math(MathOperator::Min)) => self.byte(opcode::MINR),
+            Command::Integer(Operator::Math(MathOperator::Max)) => self.byte(opcode::MAXI),
+            Command::Real(Operator::Math(MathOperator::Max)) => self.byte(opcode::MAXR),
+            Command::Integer(op) => self.byte(operator(op)),
+            Command::Real(op) => self.byte(opcode::ADDR + operator(op)),
+            Command::StrCompare(op) => self.byte(opcode::GEQS + relational(op)),
+            Command::BoolCompare(op) => self.byte(opcode::GEQB + relational(op)),
+            Command::CastInt => self.byte(opcode::CSTI),
+            Command::CastReal => self.byte(opcode::CSTR),
+            Command::MemoryLoad(kind, addr) => {
+                self.byte(opcode::LDI + kind_code(kind));
+                self.u16(*addr);
+            }
+            Command::MemoryStore(kind, addr) => {
+                self.byte(opcode::STRI + kind_code(kind));
+                self.u16(*addr);
+            }
+            Command::LoadSlot(kind, slot) => {
+                self.byte(opcode::LDI + kind_code(kind));
+                self.u16(slot.addr());
+            }
+            Command::StoreSlot(kind, slot) => {
+                self.byte(opcode::STRI + kind_code(kind));
+                self.u16(slot.addr());
+            }
+            Command::StoreParam(kind, addr) => {
+                self.byte(opcode::STRIP + kind_code(kind));
+                self.u16(*addr);
+            }
+            Command::Input(kind) => self.byte(opcode::RDI + kind_code(kind)),
+            Command::Output(kind) => self.byte(opcode::WRI + kind_code(kind)),
+            Command::Duplicate(kind) => self.byte(opcode::DUPI + kind_code(kind)),
+            Command::Append(kind) => self.byte(opcode::SBAPPI + kind_code(kind)),
+            Command::Control(ctrl, addr) => {
+                self.byte(control(ctrl));
+                if !matches!(ctrl, ControlFlow::Ret) {
+                    self.u16(u16::try_from(*addr).ok()?);
+                }
+            }
+            Command::OutputMany(kinds) => {
+                self.byte(opcode::WRVN);
+                self.kinds(kinds)?;
+            }
+            Command::OutputLine => {
+                self.byte(opcode::WRS);
+                self.byte(opcode::FLN);
+            }
+            Command::RawInput => self.byte(opcode::RDRAW),
+            Command::RawOutput => self.byte(opcode::WRRAW),
+            Command::SetBoolFormat => self.byte(opcode::SETBOOLFMT),
+            Command::Flush(FlushMode::Flush) => self.byte(opcode::FLU),
+            Command::Flush(FlushMode::NewLine) => self.byte(opcode::FLN),
+            Command::ForControl(ForControl::New) => self.byte(opcode::BFOR),
+            Command::ForControl(ForControl::Check) => self.byte(opcode::CFOR),
+            Command::ForControl(ForControl::End) => self.byte(opcode::EFOR),
+            Command::Exit => self.byte(opcode::EXT),
+            Command::ConstantLoad(Constant::Integer(0)) => self.byte(opcode::LDI0),
+            Command::ConstantLoad(Constant::Integer(1)) => self.byte(opcode::LDI1),
+            Command::ConstantLoad(Constant::Integer(n)) => {
+                self.byte(opcode::LDIC);
+                self.i32(*n);
+            }
+            Command::ConstantLoad(Constant::Real(n)) => {
+                self.byte(opcode::LDRC);
+                self.f64(*n);
+            }
+            Command::ConstantLoad(Constant::Bool(false)) => self.byte(opcode::LDFALSE),
+            Command::ConstantLoad(Constant::Bool(true)) => self.byte(opcode::LDTRUE),
+            Command::ConstantLoad(Constant::Str(index)) => {
+                self.byte(opcode::LDSC);
+                self.string(str_mem.get_string(*index))?;
+            }
+            Command::NewRecord(func) => {
+                self.byte(opcode::PARAM);
+                self.u16(u16::try_from(*func).ok()?);
+            }
+            Command::SetTimer(func) => {
+                self.byte(opcode::TIMER);
+                self.u16(u16::try_from(*func).ok()?);
+            }
+            Command::Unary(Kind::Integer) => self.byte(opcode::NEGI),
+            Command::Unary(Kind::Real) => self.byte(opcode::NEGR),
+            Command::Unary(_) => self.byte(opcode::NOT),
+            Command::StringStats => self.byte(opcode::STRSTAT),
+            Command::GetDefine => self.byte(opcode::GETDEF),
+            Command::NewBuilder => self.byte(opcode::SBNEW),
+            Command::FinishBuilder => self.byte(opcode::SBFINISH),
+            Command::HashString => self.byte(opcode::HASHS),
+            // BitOp, Intrinsic, Rounding and TextOp variants follow
+            // the opcode order
+            Command::Bitwise(op) => self.byte(opcode::ANDI + *op as u8),
+            Command::MathIntrinsic(func) => self.byte(opcode::SQRT + *func as u8),
+            Command::RoundInt(mode) => self.byte(opcode::CSTI_TRUNC + *mode as u8),
+            Command::Substring => self.byte(opcode::SUBSTR),
+            Command::CharAt => self.byte(opcode::CHARAT),
+            Command::Transform(op) => self.byte(opcode::UPPER + *op as u8),
+            Command::ParseNumber(Kind::Integer) => self.byte(opcode::STR2INT),
+            Command::ParseNumber(_) => self.byte(opcode::STR2REAL),
+            Command::FormatNumber(Kind::Integer) => self.byte(opcode::INT2STR),
+            Command::FormatNumber(_) => self.byte(opcode::REAL2STR),
+            Command::ArrayNew(kind) => self.byte(opcode::ANEWI + kind_code(kind)),
+            Command::ArrayLoad(kind) => self.byte(opcode::ALDI + kind_code(kind)),
+            Command::ArrayStore(kind) => self.byte(opcode::ASTI + kind_code(kind)),
+            Command::ArrayLength => self.byte(opcode::ALEN),
+            Command::Absolute(Kind::Integer) => self.byte(opcode::ABSI),
+            Command::Absolute(_) => self.byte(opcode::ABSR),
+        }
+        Some(())
+    }
+}
+
+// opcodes of the same family are ordered by kind, like Kind::new
+fn kind_code(kind: &Kind) -> u8 {
+    match kind {
+        Kind::Integer => 0,
+        Kind::Real => 1,
+        Kind::Bool => 2,
+        Kind::Str => 3,
+    }
+}
+
+// offset from ADDI, inverse of Operator::new
+fn operator(op: &Operator) -> u8 {
+    match op {
+        Operator::Math(MathOperator::Add) => 0,
+        Operator::Math(MathOperator::Sub) => 1,
+        Operator::Math(MathOperator::Mul) => 2,
+        Operator::Math(MathOperator::Div) => 3,
+        Operator::Math(MathOperator::Mod)
+        | Operator::Math(MathOperator::Pow)
+        | Operator::Math(MathOperator::Min)
+        | Operator::Math(MathOperator::Max) => {
+            unreachable!("MODx, POWx, MINx and MAXx have their own opcodes")
+        }
+        Operator::Rel(op) => 4 + relational(op),
+    }
+}
+
+// offset from the GEQ opcode of each kind
+fn relational(op: &RelationalOperator) -> u8 {
+    match op {
+        RelationalOperator::GreatEq => 0,
+        RelationalOperator::Greater => 1,
+        RelationalOperator::LessEq => 2,
+        RelationalOperator::Less => 3,
+        RelationalOperator::Equal => 4,
+        RelationalOperator::NotEqual => 5,
+    }
+}
+
+fn control(ctrl: &ControlFlow) -> u8 {
+    match ctrl {
+        ControlFlow::Jump => opcode::JUMP,
+        ControlFlow::JumpTrue => opcode::JEQ,
+        ControlFlow::JumpFalse => opcode::JNE,
+        ControlFlow::Label => opcode::LBL,
+        ControlFlow::Call => opcode::CALL,
+        ControlFlow::Ret => opcode::RET,
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::asm::assemble;
+    use crate::disasm::disassemble;
+    use crate::fuzz::random_program;
+    use crate::program_load::{parse_data, LoadOptions};
+
+    // store a loaded program, reload it and compare the listings
+    fn round_trip(data: &[u8], order: ByteOrder) -> Vec<u8> {
+        let (prog, prog_mem, str_mem, _) = parse_data(data, &LoadOptions::default()).unwrap();
+        let stored = store(&prog, &prog_mem, &str_mem, order).unwrap();
+        let (prog2, prog_mem2, str_mem2, _) = parse_data(&stored, &LoadOptions::default()).unwrap();
+        assert_eq!(
+            disassemble(&prog, &prog_mem, &str_mem),
+            disassemble(&prog2, &prog_mem2, &str_mem2)
+        );
+        let again = store(&prog2, &prog_mem2, &str_mem2, order).unwrap();
+        assert_eq!(stored, again);
+        stored
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let source = "
+            INIT 1 0 1 1
+            ARGS I 0
+            META \"name\" \"test\"
+            FINI 0
+            PURE 0 I
+            LDI0
+            LDTRUE
+            STRB 0
+            LBL 0
+            LDI 0
+            LDIC 10
+            LESQI
+            JNER 4
+            LDSC \"hi\"
+            WRS
+            FLN
+            JUMPR -7
+            LDRC 1.5
+            WRVN R
+            FLN
+            FUNC
+            INIT 1 0 0 0
+            LDI local 0
+            RET
+            FUNC
+            INIT 0 0 0 0
+            RET
+        ";
+        let data = assemble(source).unwrap();
+        round_trip(&data, ByteOrder::Big);
+        let little = round_trip(&data, ByteOrder::Little);
+        assert_eq!(&little[..2], &[opcode::HDR, opcode::HDR_LITTLE_ENDIAN]);
+        let data = assemble(&format!("HDR interactive\n{}", source)).unwrap();
+        let interactive = round_trip(&data, ByteOrder::Big);
+        assert_eq!(&interactive[..2], &[opcode::HDR, opcode::HDR_INTERACTIVE]);
+
+        // 0, 1, false and true loaded the long way take one byte
+        let mut long = vec![opcode::INIT, 0, 0, 0, 0, 0, 0, 0, 0];
+        long.extend(&[opcode::LDIC, 0, 0, 0, 0, opcode::LDIC, 0, 0, 0, 1]);
+        long.extend(&[opcode::LDBC, 0, opcode::LDBC, 255]);
+        let short = round_trip(&long, ByteOrder::Big);
+        assert_eq!(
+            &short[9..],
+            &[opcode::LDI0, opcode::LDI1, opcode::LDFALSE, opcode::LDTRUE]
+        );
+
+        for seed in 0..50 {
+            let data = assemble(&random_program(seed)).unwrap();
+            round_trip(&data, ByteOrder::Big);
+        }
+    }
+}
@@ -0,0 +1,641 @@
+use crate::command_definition::{
+    AddrSize, Command, Constant, ControlFlow, FlushMode, Kind, MathOperator, Operator, Program,
+    ProgramMemory, RelationalOperator,
+};
+use crate::engine::{
+    binary_rel_operation, handle_flush, Arithmetic, EngineConfig, MemorySnapshot, RuntimeError,
+};
+use crate::line_reader::LineReader;
+use crate::string_memory::StringMemory;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, Write};
+
+// Experimental register form of the main body: every typed stack
+// slot becomes a virtual register of its kind and memory or constant
+// operands are folded into the instruction using them, so
+// `LDI a; LDI b; ADDI; STRI c` runs as a single `c = a + b`.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand<T> {
+    Reg(usize),
+    Mem(AddrSize),
+    Const(T),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Reg(usize),
+    Mem(AddrSize),
+}
+
+#[derive(Debug)]
+pub enum Instr {
+    IntMath(MathOperator, usize, Operand<i32>, Operand<i32>),
+    RealMath(MathOperator, usize, Operand<f64>, Operand<f64>),
+    IntCompare(RelationalOperator, usize, Operand<i32>, Operand<i32>),
+    RealCompare(RelationalOperator, usize, Operand<f64>, Operand<f64>),
+    BoolCompare(RelationalOperator, usize, Operand<bool>, Operand<bool>),
+    IntMove(Target, Operand<i32>),
+    RealMove(Target, Operand<f64>),
+    BoolMove(Target, Operand<bool>),
+    IntNeg(usize, Operand<i32>),
+    RealNeg(usize, Operand<f64>),
+    Not(usize, Operand<bool>),
+    CastInt(usize, Operand<f64>),
+    CastReal(usize, Operand<i32>),
+    Read(Kind, usize),
+    WriteInt(Operand<i32>),
+    WriteReal(Operand<f64>),
+    WriteBool(Operand<bool>),
+    WriteStr(usize),
+    Flush(FlushMode),
+    Jump(usize),
+    // jump when the operand equals the flag
+    Branch(Operand<bool>, bool, usize),
+    Exit,
+}
+
+pub struct RegisterProgram {
+    pub code: Vec<Instr>,
+    // register count for integers, reals and booleans
+    registers: (usize, usize, usize),
+    memory: (usize, usize, usize),
+    // string globals, never touched by the register code
+    strings: usize,
+}
+
+#[derive(Debug)]
+pub enum Unsupported {
+    Command(usize),
+    StackAtJump(usize),
+    // malformed bytecode, left to the stack machine to report
+    StackUnderflow(usize),
+    Arguments,
+    Finalizer,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Command(index) => write!(f, "instruction {} has no register form", index),
+            Self::StackAtJump(index) => {
+                write!(f, "values left on the stack at instruction {}", index)
+            }
+            Self::StackUnderflow(index) => {
+                write!(f, "instruction {} pops from an empty stack", index)
+            }
+            Self::Arguments => write!(f, "program arguments are not bound"),
+            Self::Finalizer => write!(f, "finalizers are not run"),
+        }
+    }
+}
+
+pub fn translate(prog: &Program, mem: &ProgramMemory) -> Result<RegisterProgram, Unsupported> {
+    if !mem.args.is_empty() {
+        return Err(Unsupported::Arguments);
+    }
+    if prog.fini.is_some() {
+        return Err(Unsupported::Finalizer);
+    }
+    let mut trans = Translator::default();
+    // targets of relative jumps have no LBL in the code
+    let mut implicit = prog.body.implicit_labels().into_iter().peekable();
+    for (index, cmd) in prog.body.code.iter().enumerate() {
+        while let Some((_, label)) = implicit.next_if(|(target, _)| *target == index) {
+            trans.command(index, &Command::Control(ControlFlow::Label, label))?;
+        }
+        trans.command(index, cmd)?;
+    }
+    for (index, label) in implicit {
+        trans.command(index, &Command::Control(ControlFlow::Label, label))?;
+    }
+    let registers = (trans.ints.max, trans.reals.max, trans.bools.max);
+    let labels = trans.labels;
+    let code = trans
+        .code
+        .into_iter()
+        .map(|instr| match instr {
+            Instr::Jump(label) => Instr::Jump(labels[&label]),
+            Instr::Branch(cond, flag, label) => Instr::Branch(cond, flag, labels[&label]),
+            instr => instr,
+        })
+        .collect();
+    Ok(RegisterProgram {
+        code,
+        registers,
+        memory: (
+            mem.main.integer_count,
+            mem.main.real_count,
+            mem.main.boolean_count,
+        ),
+        strings: mem.main.string_count,
+    })
+}
+
+// the operands waiting on a typed stack
+struct Pending<T> {
+    stack: Vec<Operand<T>>,
+    max: usize,
+}
+
+impl<T: Copy> Pending<T> {
+    fn push(&mut self, op: Operand<T>) {
+        self.stack.push(op);
+        self.max = self.max.max(self.stack.len());
+    }
+
+    // `index` is the instruction popping
+    fn pop(&mut self, index: usize) -> Result<Operand<T>, Unsupported> {
+        self.stack.pop().ok_or(Unsupported::StackUnderflow(index))
+    }
+
+    fn duplicate(&mut self, index: usize) -> Result<(), Unsupported> {
+        let op = *self
+            .stack
+            .last()
+            .ok_or(Unsupported::StackUnderflow(index))?;
+        self.push(op);
+        Ok(())
+    }
+
+    fn next_reg(&self) -> usize {
+        self.stack.len()
+    }
+
+    // stores must not change the value of loads still waiting
+    fn materialize<F>(&mut self, addr: AddrSize, code: &mut Vec<Instr>, mv: F)
+    where
+        F: Fn(Target, Operand<T>) -> Instr,
+    {
+        for (reg, op) in self.stack.iter_mut().enumerate() {
+            if let Operand::Mem(a) = op {
+                if *a == addr {
+                    code.push(mv(Target::Reg(reg), *op));
+                    *op = Operand::Reg(reg);
+                }
+            }
+        }
+    }
+}
+
+impl<T> Default for Pending<T> {
+    fn default() -> Self {
+        Self {
+            stack: Vec::new(),
+            max: 0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Translator {
+    code: Vec<Instr>,
+    ints: Pending<i32>,
+    reals: Pending<f64>,
+    bools: Pending<bool>,
+    strings: Vec<usize>,
+    labels: HashMap<usize, usize>,
+}
+
+impl Translator {
+    fn command(&mut self, index: usize, cmd: &Command) -> Result<(), Unsupported> {
+        match cmd {
+            Command::Integer(Operator::Math(op)) => {
+                let (rhs, lhs) = (self.ints.pop(index)?, self.ints.pop(index)?);
+                let reg = self.ints.next_reg();
+                self.code.push(Instr::IntMath(op.clone(), reg, lhs, rhs));
+                self.ints.push(Operand::Reg(reg));
+            }
+            Command::Real(Operator::Math(op)) => {
+                let (rhs, lhs) = (self.reals.pop(index)?, self.reals.pop(index)?);
+                let reg = self.reals.next_reg();
+                self.code.push(Instr::RealMath(op.clone(), reg, lhs, rhs));
+                self.reals.push(Operand::Reg(reg));
+            }
+            Command::Integer(Operator::Rel(op)) => {
+                let (rhs, lhs) = (self.ints.pop(index)?, self.ints.pop(index)?);
+                let reg = self.bools.next_reg();
+                self.code.push(Instr::IntCompare(op.clone(), reg, lhs, rhs));
+                self.bools.push(Operand::Reg(reg));
+            }
+            Command::Real(Operator::Rel(op)) => {
+                let (rhs, lhs) = (self.reals.pop(index)?, self.reals.pop(index)?);
+                let reg = self.bools.next_reg();
+                self.code
+                    .push(Instr::RealCompare(op.clone(), reg, lhs, rhs));
+                self.bools.push(Operand::Reg(reg));
+            }
+            Command::BoolCompare(op) => {
+                let (rhs, lhs) = (self.bools.pop(index)?, self.bools.pop(index)?);
+                let reg = self.bools.next_reg();
+                self.code
+                    .push(Instr::BoolCompare(op.clone(), reg, lhs, rhs));
+                self.bools.push(Operand::Reg(reg));
+            }
+            Command::CastInt => {
+                let op = self.reals.pop(index)?;
+                let reg = self.ints.next_reg();
+                self.code.push(Instr::CastInt(reg, op));
+                self.ints.push(Operand::Reg(reg));
+            }
+            Command::CastReal => {
+                let op = self.ints.pop(index)?;
+                let reg = self.reals.next_reg();
+                self.code.push(Instr::CastReal(reg, op));
+                self.reals.push(Operand::Reg(reg));
+            }
+            Command::Unary(Kind::Integer) => {
+                let op = self.ints.pop(index)?;
+                let reg = self.ints.next_reg();
+                self.code.push(Instr::IntNeg(reg, op));
+                self.ints.push(Operand::Reg(reg));
+            }
+            Command::Unary(Kind::Real) => {
+                let op = self.reals.pop(index)?;
+                let reg = self.reals.next_reg();
+                self.code.push(Instr::RealNeg(reg, op));
+                self.reals.push(Operand::Reg(reg));
+            }
+            Command::Unary(Kind::Bool) => {
+                let op = self.bools.pop(index)?;
+                let reg = self.bools.next_reg();
+                self.code.push(Instr::Not(reg, op));
+                self.bools.push(Operand::Reg(reg));
+            }
+            Command::ConstantLoad(Constant::Integer(i)) => self.ints.push(Operand::Const(*i)),
+            Command::ConstantLoad(Constant::Real(r)) => self.reals.push(Operand::Const(*r)),
+            Command::ConstantLoad(Constant::Bool(b)) => self.bools.push(Operand::Const(*b)),
+            Command::ConstantLoad(Constant::Str(s)) => self.strings.push(*s),
+            Command::MemoryLoad(kind, addr) => self.load(index, *kind, *addr)?,
+            Command::MemoryStore(kind, addr) => self.store(index, *kind, *addr)?,
+            Command::LoadSlot(kind, slot) => self.load(index, *kind, slot.addr())?,
+            Command::StoreSlot(kind, slot) => self.store(index, *kind, slot.addr())?,
+            Command::Duplicate(Kind::Integer) => self.ints.duplicate(index)?,
+            Command::Duplicate(Kind::Real) => self.reals.duplicate(index)?,
+            Command::Duplicate(Kind::Bool) => self.bools.duplicate(index)?,
+            Command::Input(kind) => self.read(index, *kind)?,
+            Command::Output(Kind::Integer) => {
+                let op = self.ints.pop(index)?;
+                self.code.push(Instr::WriteInt(op));
+            }
+            Command::Output(Kind::Real) => {
+                let op = self.reals.pop(index)?;
+                self.code.push(Instr::WriteReal(op));
+            }
+            Command::Output(Kind::Bool) => {
+                let op = self.bools.pop(index)?;
+                self.code.push(Instr::WriteBool(op));
+            }
+            Command::Output(Kind::Str) => match self.strings.pop() {
+                Some(s) => self.code.push(Instr::WriteStr(s)),
+                None => return Err(Unsupported::Command(index)),
+            },
+            Command::OutputLine => match self.strings.pop() {
+                Some(s) => {
+                    self.code.push(Instr::WriteStr(s));
+                    self.code.push(Instr::Flush(FlushMode::NewLine));
+                }
+                None => return Err(Unsupported::Command(index)),
+            },
+            Command::Flush(mode) => self.code.push(Instr::Flush(mode.clone())),
+            Command::Exit => self.code.push(Instr::Exit),
+            Command::Control(ControlFlow::Label, label) => {
+                self.check_empty(index)?;
+                self.labels.insert(*label, self.code.len());
+            }
+            Command::Control(ControlFlow::Jump, label) => {
+                self.check_empty(index)?;
+                self.code.push(Instr::Jump(*label));
+            }
+            Command::Control(ControlFlow::JumpTrue, label) => {
+                let cond = self.bools.pop(index)?;
+                self.check_empty(index)?;
+                self.code.push(Instr::Branch(cond, true, *label));
+            }
+            Command::Control(ControlFlow::JumpFalse, label) => {
+                let cond = self.bools.pop(index)?;
+                self.check_empty(index)?;
+                self.code.push(Instr::Branch(cond, false, *label));
+            }
+            _ => return Err(Unsupported::Command(index)),
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, index: usize, kind: Kind, addr: AddrSize) -> Result<(), Unsupported> {
+        match kind {
+            Kind::Integer => self.ints.push(Operand::Mem(addr)),
+            Kind::Real => self.reals.push(Operand::Mem(addr)),
+            Kind::Bool => self.bools.push(Operand::Mem(addr)),
+            Kind::Str => return Err(Unsupported::Command(index)),
+        }
+        Ok(())
+    }
+
+    fn store(&mut self, index: usize, kind: Kind, addr: AddrSize) -> Result<(), Unsupported> {
+        match kind {
+            Kind::Integer => {
+                let op = self.ints.pop(index)?;
+                self.ints.materialize(addr, &mut self.code, Instr::IntMove);
+                self.code.push(Instr::IntMove(Target::Mem(addr), op));
+            }
+            Kind::Real => {
+                let op = self.reals.pop(index)?;
+                self.reals
+                    .materialize(addr, &mut self.code, Instr::RealMove);
+                self.code.push(Instr::RealMove(Target::Mem(addr), op));
+            }
+            Kind::Bool => {
+                let op = self.bools.pop(index)?;
+                self.bools
+                    .materialize(addr, &mut self.code, Instr::BoolMove);
+                self.code.push(Instr::BoolMove(Target::Mem(addr), op));
+            }
+            Kind::Str => return Err(Unsupported::Command(index)),
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, index: usize, kind: Kind) -> Result<(), Unsupported> {
+        let reg = match kind {
+            Kind::Integer => self.ints.next_reg(),
+            Kind::Real => self.reals.next_reg(),
+            Kind::Bool => self.bools.next_reg(),
+            Kind::Str => return Err(Unsupported::Command(index)),
+        };
+        self.code.push(Instr::Read(kind, reg));
+        match kind {
+            Kind::Integer => self.ints.push(Operand::Reg(reg)),
+            Kind::Real => self.reals.push(Operand::Reg(reg)),
+            _ => self.bools.push(Operand::Reg(reg)),
+        }
+        Ok(())
+    }
+
+    // values crossing a jump would need the same
+    // registers on every path, keep it simple
+    fn check_empty(&self, index: usize) -> Result<(), Unsupported> {
+        let empty = self.ints.stack.is_empty()
+            && self.reals.stack.is_empty()
+            && self.bools.stack.is_empty()
+            && self.strings.is_empty();
+        if empty {
+            Ok(())
+        } else {
+            Err(Unsupported::StackAtJump(index))
+        }
+    }
+}
+
+struct RegisterFile<T> {
+    regs: Vec<T>,
+    mem: Vec<T>,
+}
+
+impl<T: Copy + Default> RegisterFile<T> {
+    fn new(regs: usize, mem: usize) -> Self {
+        Self {
+            regs: vec![T::default(); regs],
+            mem: vec![T::default(); mem],
+        }
+    }
+
+    fn get(&self, op: Operand<T>) -> T {
+        match op {
+            Operand::Reg(reg) => self.regs[reg],
+            Operand::Mem(addr) => self.mem[addr as usize],
+            Operand::Const(value) => value,
+        }
+    }
+
+    fn set(&mut self, target: Target, value: T) {
+        match target {
+            Target::Reg(reg) => self.regs[reg] = value,
+            Target::Mem(addr) => self.mem[addr as usize] = value,
+        }
+    }
+}
+
+pub fn run_register_program(
+    prog: &RegisterProgram,
+    string_memory: &StringMemory,
+    config: &EngineConfig,
+    in_stream: &mut dyn BufRead,
+    out: &mut dyn Write,
+) -> Result<(), RuntimeError> {
+    run_register_snapshot(prog, string_memory, config, in_stream, out).map(|_| ())
+}
+
+// same as `run_register_program`, returning the
+// global memory the program ended with
+pub fn run_register_snapshot(
+    prog: &RegisterProgram,
+    string_memory: &StringMemory,
+    config: &EngineConfig,
+    in_stream: &mut dyn BufRead,
+    out: &mut dyn Write,
+) -> Result<MemorySnapshot, RuntimeError> {
+    let mut ints = RegisterFile::new(prog.registers.0, prog.memory.0);
+    let mut reals = RegisterFile::new(prog.registers.1, prog.memory.1);
+    let mut bools = RegisterFile::new(prog.registers.2, prog.memory.2);
+    let mut reader = LineReader::new(in_stream, config.get_echo_input());
+
+    let mut index = 0;
+    while index < prog.code.len() {
+        let instr = &prog.code[index];
+        index += 1;
+        match instr {
+            Instr::IntMath(op, reg, lhs, rhs) => {
+                let value = i32::apply(op, ints.get(*lhs), ints.get(*rhs))
+                    .ok_or(RuntimeError::DivisionByZero)?;
+                ints.regs[*reg] = value;
+            }
+            Instr::RealMath(op, reg, lhs, rhs) => {
+                let value = f64::apply(op, reals.get(*lhs), reals.get(*rhs))
+                    .ok_or(RuntimeError::DivisionByZero)?;
+                reals.regs[*reg] = value;
+            }
+            Instr::IntCompare(op, reg, lhs, rhs) => {
+                bools.regs[*reg] = binary_rel_operation(op, ints.get(*lhs), ints.get(*rhs));
+            }
+            Instr::RealCompare(op, reg, lhs, rhs) => {
+                bools.regs[*reg] = binary_rel_operation(op, reals.get(*lhs), reals.get(*rhs));
+            }
+            Instr::BoolCompare(op, reg, lhs, rhs) => {
+                bools.regs[*reg] = binary_rel_operation(op, bools.get(*lhs), bools.get(*rhs));
+            }
+            Instr::IntMove(target, op) => ints.set(*target, ints.get(*op)),
+            Instr::RealMove(target, op) => reals.set(*target, reals.get(*op)),
+            Instr::BoolMove(target, op) => bools.set(*target, bools.get(*op)),
+            Instr::IntNeg(reg, op) => ints.regs[*reg] = ints.get(*op).wrapping_neg(),
+            Instr::RealNeg(reg, op) => reals.regs[*reg] = -reals.get(*op),
+            Instr::Not(reg, op) => bools.regs[*reg] = !bools.get(*op),
+            Instr::CastInt(reg, op) => ints.regs[*reg] = reals.get(*op) as i32,
+            Instr::CastReal(reg, op) => reals.regs[*reg] = ints.get(*op) as f64,
+            Instr::Read(kind, reg) => {
+                match kind {
+                    Kind::Integer => ints.regs[*reg] = reader.next_i32()?,
+                    Kind::Real => reals.regs[*reg] = reader.next_f64()?,
+                    Kind::Bool => bools.regs[*reg] = reader.next_bool()?,
+                    Kind::Str => unreachable!(),
+                }
+                reader.flush_echo(out)?;
+            }
+            Instr::WriteInt(op) => write!(out, "{}", ints.get(*op))?,
+            Instr::WriteReal(op) => write!(out, "{}", reals.get(*op))?,
+            Instr::WriteBool(op) => {
+                let word = config.get_bool_format().format(bools.get(*op));
+                write!(out, "{}", word)?
+            }
+            Instr::WriteStr(s) => write!(out, "{}", string_memory.get_string(*s))?,
+            Instr::Flush(mode) => handle_flush(mode, out)?,
+            Instr::Jump(next) => index = *next,
+            Instr::Branch(cond, flag, next) => {
+                if bools.get(*cond) == *flag {
+                    index = *next;
+                }
+            }
+            Instr::Exit => break,
+        }
+    }
+    Ok(MemorySnapshot {
+        integers: ints.mem,
+        reals: reals.mem,
+        booleans: bools.mem,
+        strings: vec![String::new(); prog.strings],
+    })
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::engine::run_program_with_io;
+    use crate::opcode;
+    use crate::program_load::{parse_data, LoadOptions};
+
+    fn load(code: &[u8]) -> (Program, ProgramMemory, StringMemory) {
+        let mut data = vec![opcode::INIT, 0, 2, 0, 0, 0, 0, 0, 0];
+        data.extend(code);
+        let (prog, mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        (prog, mem, str_mem)
+    }
+
+    #[test]
+    fn test_same_output() {
+        // read n, print n, n - 1, ... 1 separated by spaces
+        let code = [
+            opcode::RDI,
+            opcode::STRI,
+            0,
+            0,
+            opcode::LBL,
+            0,
+            0,
+            opcode::LDI,
+            0,
+            0,
+            opcode::LDIC,
+            0,
+            0,
+            0,
+            0,
+            opcode::NEI,
+            opcode::JNE,
+            0,
+            1,
+            opcode::LDI,
+            0,
+            0,
+            opcode::WRI,
+            opcode::LDSC,
+            0,
+            1,
+            b' ',
+            opcode::WRS,
+            opcode::LDI,
+            0,
+            0,
+            opcode::LDIC,
+            0,
+            0,
+            0,
+            1,
+            opcode::SUBI,
+            opcode::STRI,
+            0,
+            0,
+            opcode::JUMP,
+            0,
+            0,
+            opcode::LBL,
+            0,
+            1,
+            opcode::FLN,
+        ];
+        let config = EngineConfig::new();
+
+        let (prog, mem, str_mem) = load(&code);
+        let reg_prog = translate(&prog, &mem).unwrap();
+        // the subtraction reads its operand straight from memory
+        assert!(reg_prog.code.iter().any(|instr| matches!(
+            instr,
+            Instr::IntMath(MathOperator::Sub, _, Operand::Mem(0), Operand::Const(1))
+        )));
+        let mut reg_output = Vec::new();
+        let mut input = &b"4\n"[..];
+        run_register_program(&reg_prog, &str_mem, &config, &mut input, &mut reg_output).unwrap();
+
+        let mut output = Vec::new();
+        let mut input = &b"4\n"[..];
+        run_program_with_io(prog, mem, str_mem, &config, &mut input, &mut output).unwrap();
+        assert_eq!(reg_output, b"4 3 2 1 \n");
+        assert_eq!(reg_output, output);
+    }
+
+    #[test]
+    fn test_pending_load_before_store() {
+        // print the old value of a after overwriting it
+        let code = [
+            opcode::LDIC,
+            0,
+            0,
+            0,
+            7,
+            opcode::STRI,
+            0,
+            0,
+            opcode::LDI,
+            0,
+            0,
+            opcode::LDIC,
+            0,
+            0,
+            0,
+            1,
+            opcode::STRI,
+            0,
+            0,
+            opcode::WRI,
+        ];
+        let (prog, mem, str_mem) = load(&code);
+        let reg_prog = translate(&prog, &mem).unwrap();
+        let mut output = Vec::new();
+        let mut input = &b""[..];
+        let config = EngineConfig::new();
+        run_register_program(&reg_prog, &str_mem, &config, &mut input, &mut output).unwrap();
+        assert_eq!(output, b"7");
+
+        let (prog, mem, _) = load(&[opcode::RDS]);
+        assert!(matches!(
+            translate(&prog, &mem),
+            Err(Unsupported::Command(0))
+        ));
+        let (prog, mem, _) = load(&[opcode::LDI1, opcode::WRI, opcode::DUPI]);
+        assert!(matches!(
+            translate(&prog, &mem),
+            Err(Unsupported::StackUnderflow(2))
+        ));
+    }
+}
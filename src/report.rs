@@ -0,0 +1,246 @@
+use crate::command_definition::{Block, BlockId, Program};
+use crate::disasm;
+use crate::engine::RuntimeError;
+use crate::string_memory::StringMemory;
+use std::fmt::Write;
+
+// Runtime errors rendered for people: a colored severity label,
+// the position of the failing instruction and the instructions
+// around it, with a caret under the one that failed. The bytecode
+// carries no debug info, so the excerpt is the disassembled block
+// instead of the program source.
+
+// instructions shown before and after the failing one
+pub const CONTEXT: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    // `terminal` tells whether the report goes to a terminal,
+    // NO_COLOR turns the automatic colors off
+    pub fn enabled(self, terminal: bool) -> bool {
+        match self {
+            Self::Auto => terminal && std::env::var_os("NO_COLOR").is_none(),
+            Self::Always => true,
+            Self::Never => false,
+        }
+    }
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => Err(format!("`{}` is not one of auto, always and never", s)),
+        }
+    }
+}
+
+// where the program failed, from `Engine::fault_trace`
+pub struct Fault<'a> {
+    pub prog: &'a Program,
+    pub str_mem: &'a StringMemory,
+    pub block: BlockId,
+    pub index: usize,
+    // the calls that led there, innermost first
+    pub callers: &'a [(BlockId, usize)],
+}
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+struct Painter {
+    color: bool,
+}
+
+impl Painter {
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_owned()
+        }
+    }
+}
+
+pub fn render(err: &RuntimeError, fault: Option<&Fault>, color: bool) -> String {
+    let painter = Painter { color };
+    let mut output = format!(
+        "{}{}\n",
+        painter.paint(RED, "error"),
+        painter.paint(BOLD, &format!(": {}", err))
+    );
+    if let Some(fault) = fault {
+        excerpt(fault, &painter, &mut output);
+    }
+    output
+}
+
+fn excerpt(fault: &Fault, painter: &Painter, output: &mut String) {
+    let block = code(fault.prog, fault.block);
+    let last = block.code.len().min(fault.index + CONTEXT + 1);
+    let first = fault.index.saturating_sub(CONTEXT).min(last);
+    let width = last.to_string().len();
+    let gutter = painter.paint(BLUE, &format!("{:w$} |", "", w = width));
+    writeln!(
+        output,
+        "{} {}",
+        painter.paint(BLUE, &format!("{:w$}-->", "", w = width)),
+        position(fault.prog, fault.block, fault.index)
+    )
+    .unwrap();
+    writeln!(output, "{}", gutter).unwrap();
+    for (index, cmd) in block.code[first..last].iter().enumerate() {
+        let index = first + index;
+        let text = disasm::instruction(cmd, block, fault.str_mem);
+        let number = painter.paint(BLUE, &format!("{:w$} |", index, w = width));
+        writeln!(output, "{} {}", number, text).unwrap();
+        if index == fault.index {
+            let caret = "^".repeat(text.chars().count());
+            writeln!(output, "{} {}", gutter, painter.paint(RED, &caret)).unwrap();
+        }
+    }
+    let note = painter.paint(BLUE, &format!("{:w$} =", "", w = width));
+    for (block, index) in fault.callers {
+        let caller = position(fault.prog, *block, *index);
+        writeln!(output, "{} called from {}", note, caller).unwrap();
+    }
+}
+
+fn code(prog: &Program, block: BlockId) -> &Block {
+    match block {
+        BlockId::Main => &prog.body,
+        BlockId::Function(id) => &prog.func[id],
+    }
+}
+
+// the byte offset lets compiler authors find the
+// instruction in the file they emitted
+fn position(prog: &Program, block: BlockId, index: usize) -> String {
+    match code(prog, block).offset(index) {
+        Some(offset) => format!("{}, instruction {} at byte {}", block, index, offset),
+        None => format!("{}, instruction {}", block, index),
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::asm::assemble;
+    use crate::engine::{Engine, EngineConfig};
+    use crate::program_load::{parse_data, LoadOptions};
+    use std::io;
+
+    #[test]
+    fn test_render() {
+        let source = "
+            INIT 1 0 0 0
+            LDIC 7
+            STRI 0
+            LDI 0
+            LDI0
+            DIVI
+            WRI
+            FLN
+        ";
+        let data = assemble(source).unwrap();
+        let (mut prog, prog_mem, mut str_mem, _) =
+            parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        let mut input = io::empty();
+        let mut out = vec![];
+        let mut engine =
+            Engine::new(&mut prog, &prog_mem, &mut str_mem, &config, &mut input).unwrap();
+        let err = engine.run(&mut out).unwrap_err();
+        let (block, index) = engine.fault().unwrap();
+        drop(engine);
+        let fault = Fault {
+            prog: &prog,
+            str_mem: &str_mem,
+            block,
+            index,
+            callers: &[],
+        };
+        let expected = "error: integer division by zero
+ --> main body, instruction 4 at byte 21
+  |
+2 | LDI global 0
+3 | LDIC 0
+4 | DIVI
+  | ^^^^
+5 | WRI
+6 | FLN
+";
+        assert_eq!(render(&err, Some(&fault), false), expected);
+        let colored = render(&err, Some(&fault), true);
+        assert!(colored.contains("\x1b[1;31m^^^^\x1b[0m"));
+        assert_eq!(
+            render(&err, None, false),
+            "error: integer division by zero\n"
+        );
+        // programs built in memory have no offsets
+        let mut built = prog;
+        built.body = Block::new(built.body.code);
+        let fault = Fault {
+            prog: &built,
+            str_mem: &str_mem,
+            block,
+            index,
+            callers: &[],
+        };
+        assert!(render(&err, Some(&fault), false).contains(" --> main body, instruction 4\n"));
+
+        // main body calls function 0, which calls function 1
+        let source = "
+            INIT 0 0 0 0
+            PARAM 0
+            CALL 0
+            FUNC
+            INIT 0 0 0 0
+            LDI1
+            PARAM 1
+            CALL 1
+            RET
+            FUNC
+            INIT 0 0 0 0
+            LDI1
+            LDI0
+            DIVI
+            RET
+        ";
+        let data = assemble(source).unwrap();
+        let (mut prog, prog_mem, mut str_mem, _) =
+            parse_data(&data, &LoadOptions::default()).unwrap();
+        let mut engine =
+            Engine::new(&mut prog, &prog_mem, &mut str_mem, &config, &mut input).unwrap();
+        let err = engine.run(&mut out).unwrap_err();
+        let trace = engine.fault_trace().to_vec();
+        drop(engine);
+        let fault = Fault {
+            prog: &prog,
+            str_mem: &str_mem,
+            block: trace[0].0,
+            index: trace[0].1,
+            callers: &trace[1..],
+        };
+        let report = render(&err, Some(&fault), false);
+        assert!(report.contains(" --> function 1, instruction 2 at byte 45\n"));
+        assert!(report.ends_with(
+            "  = called from function 0, instruction 2 at byte 29
+  = called from main body, instruction 1 at byte 12
+"
+        ));
+    }
+}
@@ -0,0 +1,159 @@
+use crate::command_definition::{Program, ProgramMemory};
+use crate::difftest::{first_divergence, run_with_config, Divergence};
+use crate::engine::{EngineConfig, IntegerSemantics, RealPrecision};
+use crate::string_memory::StringMemory;
+use std::fmt;
+
+// Arithmetic shootout: run a program under every combination of
+// integer and real semantics and compare each run with the
+// reference one, wrapping integers and double precision reals. A
+// divergence means the program depends on overflow or on the
+// precision of reals.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Variant {
+    pub integers: IntegerSemantics,
+    pub reals: RealPrecision,
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let integers = match self.integers {
+            IntegerSemantics::Wrapping => "wrapping",
+            IntegerSemantics::Checked => "checked",
+        };
+        let reals = match self.reals {
+            RealPrecision::Double => "double",
+            RealPrecision::Single => "single",
+        };
+        write!(f, "{} integers, {} precision reals", integers, reals)
+    }
+}
+
+pub const REFERENCE: Variant = Variant {
+    integers: IntegerSemantics::Wrapping,
+    reals: RealPrecision::Double,
+};
+
+// compared against the reference
+pub const VARIANTS: [Variant; 3] = [
+    Variant {
+        integers: IntegerSemantics::Checked,
+        reals: RealPrecision::Double,
+    },
+    Variant {
+        integers: IntegerSemantics::Wrapping,
+        reals: RealPrecision::Single,
+    },
+    Variant {
+        integers: IntegerSemantics::Checked,
+        reals: RealPrecision::Single,
+    },
+];
+
+pub struct Comparison {
+    pub variant: Variant,
+    pub divergence: Option<Divergence>,
+}
+
+pub fn shootout(
+    prog: &mut Program,
+    prog_mem: &ProgramMemory,
+    str_mem: &mut StringMemory,
+    config: &EngineConfig,
+    input: &[u8],
+) -> Vec<Comparison> {
+    let mut run = |variant: Variant| {
+        let config = config
+            .clone()
+            .integer_semantics(variant.integers)
+            .real_precision(variant.reals);
+        run_with_config(prog, prog_mem, str_mem, &config, input)
+    };
+    let reference = run(REFERENCE);
+    VARIANTS
+        .iter()
+        .map(|variant| {
+            let result = run(*variant);
+            // a variant that fails where the reference succeeds
+            // says why better than its truncated output
+            let divergence = match (&reference.status, &result.status) {
+                (Ok(_), Err(err)) => Some(Divergence::Status(None, Some(err.clone()))),
+                _ => first_divergence(&reference, &result),
+            };
+            Comparison {
+                variant: *variant,
+                divergence,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::asm::assemble;
+    use crate::program_load::{parse_data, LoadOptions};
+
+    fn compare(source: &str) -> Vec<Option<Divergence>> {
+        let data = assemble(source).unwrap();
+        let (mut prog, prog_mem, mut str_mem, _) =
+            parse_data(&data, &LoadOptions::default()).unwrap();
+        let config = EngineConfig::new();
+        shootout(&mut prog, &prog_mem, &mut str_mem, &config, b"")
+            .into_iter()
+            .map(|cmp| cmp.divergence)
+            .collect()
+    }
+
+    #[test]
+    fn test_shootout() {
+        // 2147483647 + 1 only works with wrapping integers
+        let overflow = compare(
+            "
+            INIT 0 0 0 0
+            LDIC 2147483647
+            LDI1
+            ADDI
+            WRI
+            FLN
+        ",
+        );
+        assert_eq!(
+            overflow[0],
+            Some(Divergence::Status(
+                None,
+                Some("integer overflow".to_owned())
+            ))
+        );
+        assert_eq!(overflow[1], None);
+        assert!(overflow[2].is_some());
+
+        // 0.1 has no exact f32 representation
+        let precision = compare(
+            "
+            INIT 0 0 0 0
+            LDRC 0.1
+            WRR
+            FLN
+        ",
+        );
+        assert_eq!(precision[0], None);
+        assert!(precision[1].is_some());
+        assert!(precision[2].is_some());
+
+        let portable = compare(
+            "
+            INIT 0 0 0 0
+            LDIC 20
+            LDIC 22
+            ADDI
+            WRI
+            LDRC 0.5
+            WRR
+        ",
+        );
+        assert_eq!(portable, vec![None, None, None]);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::bench::{int_constant, real_constant, str_constant};
+use crate::opcode;
+
+// Synthesize valid programs far bigger than the usual compiler
+// output. The main body loads and stores `constants` constants
+// (integer, real and string in turn) then runs a loop of
+// `iterations` rounds, each one calling the head of a chain of
+// `call_depth` functions where function i calls function i + 1.
+
+pub struct Shape {
+    pub call_depth: u16,
+    pub constants: u32,
+    pub iterations: u32,
+}
+
+pub fn generate(shape: &Shape) -> Vec<u8> {
+    // two integers (counter and scratch), one real and one string
+    let mut output = vec![opcode::INIT, 0, 2, 0, 1, 0, 0, 0, 1];
+    for i in 0..shape.constants {
+        match i % 3 {
+            0 => {
+                output.extend(int_constant(i as i32));
+                output.extend(&[opcode::STRI, 0, 1]);
+            }
+            1 => {
+                output.extend(real_constant(i as f64 / 2.0));
+                output.extend(&[opcode::STRR, 0, 0]);
+            }
+            _ => {
+                output.extend(str_constant(&format!("constant {}", i)));
+                output.extend(&[opcode::STRS, 0, 0]);
+            }
+        }
+    }
+    if shape.iterations > 0 {
+        output.extend(counted_loop(shape.call_depth > 0, shape.iterations));
+    }
+    for func in 0..shape.call_depth {
+        output.extend(call_chain_link(func, shape.call_depth));
+    }
+    output
+}
+
+fn counted_loop(call: bool, iterations: u32) -> Vec<u8> {
+    let mut output = int_constant(0);
+    output.extend(&[opcode::STRI, 0, 0, opcode::LBL, 0, 0]);
+    if call {
+        output.extend(&[opcode::PARAM, 0, 0, opcode::CALL, 0, 0]);
+    }
+    output.extend(&[opcode::LDI, 0, 0]);
+    output.extend(int_constant(1));
+    output.extend(&[opcode::ADDI, opcode::STRI, 0, 0, opcode::LDI, 0, 0]);
+    output.extend(int_constant(iterations as i32));
+    output.extend(&[opcode::NEI, opcode::JEQ, 0, 0]);
+    output
+}
+
+// one local integer, set before calling the next link
+fn call_chain_link(func: u16, call_depth: u16) -> Vec<u8> {
+    let mut output = vec![opcode::FUNC, opcode::INIT, 0, 1, 0, 0, 0, 0, 0, 0];
+    output.extend(int_constant(func as i32));
+    output.extend(&[opcode::STRI, 0x80, 0]);
+    if func + 1 < call_depth {
+        let [high, low] = (func + 1).to_be_bytes();
+        output.extend(&[opcode::PARAM, high, low, opcode::CALL, high, low]);
+    }
+    output.push(opcode::RET);
+    output
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::engine::{run_program_with_io, EngineConfig};
+    use crate::program_load::{parse_data, LoadOptions};
+    use std::io;
+
+    #[test]
+    fn test_generate_stress() {
+        let shape = Shape {
+            call_depth: 300,
+            constants: 1000,
+            iterations: 20,
+        };
+        let data = generate(&shape);
+        let (prog, prog_mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        assert_eq!(prog.func.len(), 300);
+        run_program_with_io(
+            prog,
+            prog_mem,
+            str_mem,
+            &EngineConfig::new(),
+            &mut io::empty(),
+            &mut io::sink(),
+        )
+        .unwrap();
+
+        let empty = Shape {
+            call_depth: 0,
+            constants: 0,
+            iterations: 0,
+        };
+        assert!(parse_data(&generate(&empty), &LoadOptions::default()).is_ok());
+    }
+}
@@ -1,11 +1,27 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::mem::size_of;
 
-use crate::reference_memory::{ReferenceCount, ReferenceStack};
+// Strings are never freed one at a time: the strings created at
+// run time stay here until a collection of the heap module finds
+// them unreachable and sweeps them. Static strings stay forever.
+
+// bytes every string created at run time costs besides its text:
+// its entry in the table and its index, so that empty strings are
+// not free
+pub const STRING_OVERHEAD: usize = size_of::<(usize, StringValue)>() + size_of::<usize>();
+
+// index of the scratch string: a string created at run time and
+// consumed by the very next instruction is kept there, out of the
+// table, so it costs neither an insertion nor a sweep
+pub const SCRATCH: usize = usize::MAX;
 
 #[derive(Debug)]
 pub struct StringMemory {
     buff: HashMap<usize, StringValue>,
     index: usize,
+    dynamic_count: usize,
+    dynamic_bytes: usize,
+    scratch: StringValue,
 }
 
 #[derive(Debug)]
@@ -19,6 +35,9 @@ impl StringMemory {
         let mut output = Self {
             buff: HashMap::new(),
             index: 0,
+            dynamic_count: 0,
+            dynamic_bytes: 0,
+            scratch: StringValue::new(String::new(), StringType::Dynamic),
         };
         output.insert_static_string(String::new());
         output
@@ -29,9 +48,42 @@ impl StringMemory {
     }
 
     pub fn insert_string(&mut self, s: String) -> usize {
+        #[cfg(feature = "alloc-stats")]
+        let _scope = crate::alloc_stats::scope(crate::alloc_stats::Category::Strings);
+        self.dynamic_count += 1;
+        self.dynamic_bytes += s.len();
         self.insert_new_string(s, StringType::Dynamic)
     }
 
+    // drop everything created at run time, static strings
+    // are inserted by the loader first so their indexes
+    // are 0 to the number of static strings
+    pub fn reset(&mut self) {
+        if self.dynamic_count > 0 {
+            self.buff
+                .retain(|_, str_val| matches!(str_val.str_type, StringType::Static));
+        }
+        self.index = self.buff.len();
+        self.dynamic_count = 0;
+        self.dynamic_bytes = 0;
+    }
+
+    // strings created at run time and not swept yet
+    pub fn dynamic_count(&self) -> usize {
+        self.dynamic_count
+    }
+
+    // bytes held by the strings created at run time
+    pub fn dynamic_bytes(&self) -> usize {
+        self.dynamic_bytes
+    }
+
+    // the bytes above and the overhead of every string, the
+    // figure the collector and the memory quota go by
+    pub fn dynamic_footprint(&self) -> usize {
+        self.dynamic_bytes + self.dynamic_count * STRING_OVERHEAD
+    }
+
     fn insert_new_string(&mut self, s: String, str_type: StringType) -> usize {
         let key = self.index;
         self.index += 1;
@@ -40,81 +92,184 @@ impl StringMemory {
         key
     }
 
-    pub fn remove_strings(&mut self, string_mem: &Vec<usize>) {
-        for i in string_mem {
-            self.decrement(i);
+    // replace the scratch string, the previous one is gone
+    pub fn set_scratch(&mut self, s: String) -> usize {
+        self.scratch = StringValue::new(s, StringType::Dynamic);
+        SCRATCH
+    }
+
+    // move the scratch string to the table, for a value that
+    // may outlive the next instruction. Other indexes stay
+    pub fn keep_scratch(&mut self, index: usize) -> usize {
+        if index != SCRATCH {
+            return index;
+        }
+        let s = std::mem::take(&mut self.scratch.string);
+        self.insert_string(s)
+    }
+
+    fn value(&self, index: usize) -> &StringValue {
+        if index == SCRATCH {
+            return &self.scratch;
         }
+        self.buff.get(&index).unwrap()
+    }
+
+    // drop the strings created at run time that are not `live`
+    pub fn sweep(&mut self, live: &HashSet<usize>) {
+        let (mut count, mut bytes) = (0, 0);
+        self.buff.retain(|index, str_val| match str_val.str_type {
+            StringType::Static => true,
+            StringType::Dynamic if live.contains(index) => true,
+            StringType::Dynamic => {
+                count += 1;
+                bytes += str_val.string.len();
+                false
+            }
+        });
+        self.dynamic_count -= count;
+        self.dynamic_bytes -= bytes;
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.buff.contains_key(&index)
     }
 
     pub fn get_string(&self, index: usize) -> &str {
-        let tmp = self.buff.get(&index);
-        let str_val = tmp.unwrap();
-        str_val.get_str()
+        self.value(index).get_str()
     }
 
-    pub fn binary_operation<F, T>(&mut self, callback: F, stack: &mut ReferenceStack) -> T
+    pub fn binary_operation<F, T>(&self, callback: F, stack: &mut Vec<usize>) -> T
     where
         F: Fn(&str, &str) -> T,
     {
-        let rhs_index = stack.pop(self);
-        let lhs_index = stack.pop(self);
+        let rhs_index = stack.pop().unwrap();
+        let lhs_index = stack.pop().unwrap();
 
-        let rhs = self.buff.get(&rhs_index).unwrap();
-        let lhs = self.buff.get(&lhs_index).unwrap();
+        let rhs = self.value(rhs_index);
+        let lhs = self.value(lhs_index);
 
         callback(lhs.get_str(), rhs.get_str())
     }
-}
 
-impl ReferenceCount for StringMemory {
-    fn increment(&mut self, index: &usize) {
-        let tmp = self.buff.get_mut(index);
-        let str_val = tmp.unwrap();
-        str_val.incr_ref();
+    // pop two strings and compare them, the stored lengths and
+    // hashes tell most different strings apart without their bytes
+    pub fn equal_operation(&self, stack: &mut Vec<usize>) -> bool {
+        let rhs_index = stack.pop().unwrap();
+        let lhs_index = stack.pop().unwrap();
+        if rhs_index == lhs_index {
+            return true;
+        }
+
+        let rhs = self.value(rhs_index);
+        let lhs = self.value(lhs_index);
+        lhs.hash == rhs.hash && lhs.string == rhs.string
     }
 
-    fn decrement(&mut self, index: &usize) {
-        if let Some(str_val) = self.buff.get_mut(index) {
-            str_val.decr_ref();
-        }
+    pub fn hash(&self, index: usize) -> u64 {
+        self.value(index).hash
     }
+}
 
-    fn clean(&mut self) {
-        self.buff.retain(|_, v| v.ref_count > 0)
+// 64 bit FNV-1a: programs see the hash through HASHS,
+// it must not change between runs or platforms
+pub fn string_hash(s: &str) -> u64 {
+    s.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+impl Default for StringMemory {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[derive(Debug)]
 struct StringValue {
     string: String,
-    ref_count: usize,
+    // strings never change once stored
+    hash: u64,
     str_type: StringType,
 }
 
 impl StringValue {
     fn new(string: String, str_type: StringType) -> Self {
         Self {
+            hash: string_hash(&string),
             string,
-            ref_count: 1,
             str_type,
         }
     }
 
-    fn incr_ref(&mut self) {
-        if let StringType::Dynamic = self.str_type {
-            self.ref_count += 1;
-        }
+    fn get_str(&self) -> &str {
+        &self.string
     }
+}
 
-    fn decr_ref(&mut self) {
-        if let StringType::Dynamic = self.str_type {
-            if self.ref_count > 0 {
-                self.ref_count -= 1;
-            }
-        }
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_sweep() {
+        let mut mem = StringMemory::new();
+        let kept = mem.insert_string("ab".to_owned());
+        let dropped = mem.insert_string("cde".to_owned());
+        let fixed = mem.insert_static_string("f".to_owned());
+        assert_eq!((mem.dynamic_count(), mem.dynamic_bytes()), (2, 5));
+
+        mem.sweep(&[kept].iter().copied().collect());
+        assert_eq!(mem.get_string(kept), "ab");
+        assert!(!mem.contains(dropped));
+        assert_eq!(mem.get_string(fixed), "f");
+        assert_eq!((mem.dynamic_count(), mem.dynamic_bytes()), (1, 2));
+        assert_eq!(mem.dynamic_footprint(), 2 + STRING_OVERHEAD);
+
+        mem.sweep(&HashSet::new());
+        assert_eq!((mem.dynamic_count(), mem.dynamic_bytes()), (0, 0));
+        assert_eq!(mem.get_string(0), "");
     }
 
-    fn get_str(&self) -> &str {
-        &self.string
+    #[test]
+    fn test_scratch() {
+        let mut mem = StringMemory::new();
+        let fixed = mem.insert_static_string("ab".to_owned());
+        let scratch = mem.set_scratch("ab".to_owned());
+        assert_eq!(mem.get_string(scratch), "ab");
+        assert_eq!(mem.dynamic_count(), 0);
+        let mut stack = vec![fixed, scratch];
+        assert!(mem.equal_operation(&mut stack));
+
+        let kept = mem.keep_scratch(scratch);
+        assert_ne!(kept, SCRATCH);
+        assert_eq!(mem.keep_scratch(fixed), fixed);
+        mem.set_scratch("c".to_owned());
+        assert_eq!(mem.get_string(kept), "ab");
+        assert_eq!(mem.dynamic_count(), 1);
+    }
+
+    #[test]
+    fn test_equal_operation() {
+        let mut mem = StringMemory::new();
+        let mut stack = vec![];
+        let first = mem.insert_static_string("abc".to_owned());
+        let second = mem.insert_string("abc".to_owned());
+        let third = mem.insert_string("abd".to_owned());
+        assert_eq!(mem.hash(first), mem.hash(second));
+        assert_ne!(mem.hash(first), mem.hash(third));
+        // published FNV-1a test vector
+        assert_eq!(string_hash("a"), 0xaf63_dc4c_8601_ec8c);
+
+        for (lhs, rhs, equal) in [
+            (first, second, true),
+            (first, third, false),
+            (third, third, true),
+        ] {
+            stack.push(lhs);
+            stack.push(rhs);
+            assert_eq!(mem.equal_operation(&mut stack), equal);
+        }
     }
 }
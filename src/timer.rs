@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+// periodic timers registered by the program, each one
+// calls its handler function when it expires. The engine
+// looks for expired timers every few instructions and runs
+// the handler on empty stacks, restoring the interrupted
+// ones when it returns
+#[derive(Default)]
+pub struct Timers {
+    timers: Vec<Timer>,
+}
+
+struct Timer {
+    func: usize,
+    period: Duration,
+    next: Instant,
+}
+
+impl Timers {
+    pub fn new() -> Self {
+        Self { timers: Vec::new() }
+    }
+
+    // a period of zero or less cancels the timer of `func`
+    pub fn set(&mut self, func: usize, millis: i32, now: Instant) {
+        self.timers.retain(|timer| timer.func != func);
+        if millis > 0 {
+            let period = Duration::from_millis(millis as u64);
+            let timer = Timer {
+                func,
+                period,
+                next: now + period,
+            };
+            self.timers.push(timer);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    // handler of the first expired timer, which
+    // is rescheduled starting from `now`
+    pub fn expired(&mut self, now: Instant) -> Option<usize> {
+        let timer = self.timers.iter_mut().find(|timer| timer.next <= now)?;
+        timer.next = now + timer.period;
+        Some(timer.func)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_timers() {
+        let start = Instant::now();
+        let mut timers = Timers::new();
+        timers.set(0, 10, start);
+        timers.set(1, 25, start);
+        assert_eq!(timers.expired(start), None);
+
+        let now = start + Duration::from_millis(10);
+        assert_eq!(timers.expired(now), Some(0));
+        assert_eq!(timers.expired(now), None);
+
+        let now = start + Duration::from_millis(30);
+        assert_eq!(timers.expired(now), Some(0));
+        assert_eq!(timers.expired(now), Some(1));
+
+        timers.set(0, 0, now);
+        timers.set(1, -1, now);
+        assert!(timers.is_empty());
+    }
+}
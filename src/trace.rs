@@ -0,0 +1,428 @@
+use crate::command_definition::{BlockId, Command};
+use serde::Serialize;
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::time::Instant;
+
+// The instruction trace written by `Engine::trace`: one line per
+// executed instruction with its block and index. With watermarks
+// every line also tells the depth of the typed stacks and the
+// strings held once the instruction is done, so a stack that
+// grows at every iteration of a loop shows up as a drifting column.
+// The filters keep the trace of a long run to the part of interest:
+// they all have to accept an instruction for it to be written.
+//
+// The chrome format is a JSON array of trace events for
+// about://tracing and Perfetto: a span for every activation of a
+// block, an instant for every I/O instruction and, with watermarks,
+// a counter for the stacks. Spans follow the call depth of the
+// traced instructions, so timer handlers and the finalizer get
+// theirs too.
+
+#[derive(Debug, Clone, Default)]
+pub struct TraceOptions {
+    format: TraceFormat,
+    watermarks: bool,
+    block: Option<BlockId>,
+    indices: Option<Range<usize>>,
+    // empty for every class
+    classes: Vec<TraceClass>,
+    after: u64,
+}
+
+impl TraceOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn format(mut self, format: TraceFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    // stack depths and strings held after every instruction
+    pub fn watermarks(mut self, watermarks: bool) -> Self {
+        self.watermarks = watermarks;
+        self
+    }
+
+    // only the instructions of `block`
+    pub fn block(mut self, block: BlockId) -> Self {
+        self.block = Some(block);
+        self
+    }
+
+    // only the instructions at these indices, in any block
+    pub fn indices(mut self, indices: Range<usize>) -> Self {
+        self.indices = Some(indices);
+        self
+    }
+
+    // only the instructions of these classes
+    pub fn classes(mut self, classes: Vec<TraceClass>) -> Self {
+        self.classes = classes;
+        self
+    }
+
+    // nothing for the first `after` executed instructions
+    pub fn after(mut self, after: u64) -> Self {
+        self.after = after;
+        self
+    }
+
+    // `executed` instructions ran before `cmd`
+    pub fn accepts(&self, executed: u64, block: BlockId, index: usize, cmd: &Command) -> bool {
+        executed >= self.after
+            && self.block.is_none_or(|only| only == block)
+            && self
+                .indices
+                .as_ref()
+                .is_none_or(|only| only.contains(&index))
+            && (self.classes.is_empty()
+                || TraceClass::of(cmd).is_some_and(|class| self.classes.contains(&class)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TraceFormat {
+    // one line per instruction
+    #[default]
+    Text,
+    // trace events in a JSON array
+    Chrome,
+}
+
+impl std::str::FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "chrome" => Ok(Self::Chrome),
+            _ => Err(format!("`{}` is not one of text and chrome", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceClass {
+    // jumps, calls and returns, loops and timers
+    Control,
+    // loads and stores of variables and arrays
+    Memory,
+    // reads, writes and flushes
+    Io,
+}
+
+impl TraceClass {
+    pub fn of(cmd: &Command) -> Option<Self> {
+        match cmd {
+            Command::Control(..)
+            | Command::ForControl(_)
+            | Command::NewRecord(_)
+            | Command::StoreParam(..)
+            | Command::SetTimer(_)
+            | Command::Exit => Some(Self::Control),
+            Command::MemoryLoad(..)
+            | Command::MemoryStore(..)
+            | Command::LoadSlot(..)
+            | Command::StoreSlot(..)
+            | Command::ArrayNew(_)
+            | Command::ArrayLoad(_)
+            | Command::ArrayStore(_)
+            | Command::ArrayLength => Some(Self::Memory),
+            Command::Input(_)
+            | Command::Output(_)
+            | Command::OutputMany(_)
+            | Command::OutputLine
+            | Command::RawInput
+            | Command::RawOutput
+            | Command::Flush(_) => Some(Self::Io),
+            _ => None,
+        }
+    }
+}
+
+impl std::str::FromStr for TraceClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "control" => Ok(Self::Control),
+            "memory" => Ok(Self::Memory),
+            "io" => Ok(Self::Io),
+            _ => Err(format!("`{}` is not one of control, memory and io", s)),
+        }
+    }
+}
+
+// state of the machine after an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Watermarks {
+    pub integers: usize,
+    pub reals: usize,
+    pub booleans: usize,
+    pub strings: usize,
+    // run time strings not swept yet
+    pub held_strings: usize,
+}
+
+impl fmt::Display for Watermarks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "stacks I {} R {} B {} S {}, {} strings held",
+            self.integers, self.reals, self.booleans, self.strings, self.held_strings
+        )
+    }
+}
+
+pub struct Tracer<'a> {
+    out: &'a mut dyn Write,
+    options: TraceOptions,
+    // line of the running instruction, waiting for its watermarks
+    pending: Option<String>,
+    start: Instant,
+    // chrome format: call depth and block of the open spans,
+    // events written so far
+    spans: Vec<(usize, BlockId)>,
+    events: usize,
+}
+
+// a chrome trace event, `ts` in microseconds
+#[derive(Serialize)]
+struct Event<'e> {
+    name: &'e str,
+    ph: &'static str,
+    ts: f64,
+    pid: u32,
+    tid: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    s: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    args: Option<Watermarks>,
+}
+
+impl<'a> Tracer<'a> {
+    pub fn new(out: &'a mut dyn Write, options: TraceOptions) -> Self {
+        Self {
+            out,
+            options,
+            pending: None,
+            start: Instant::now(),
+            spans: Vec::new(),
+            events: 0,
+        }
+    }
+
+    pub fn wants_watermarks(&self) -> bool {
+        self.options.watermarks
+    }
+
+    pub fn accepts(&self, executed: u64, block: BlockId, index: usize, cmd: &Command) -> bool {
+        self.options.accepts(executed, block, index, cmd)
+    }
+
+    // `cmd` is about to run at `index` of `block`, with `depth`
+    // calls in progress. `text` is its disassembly
+    pub fn instruction(
+        &mut self,
+        block: BlockId,
+        index: usize,
+        depth: usize,
+        cmd: &Command,
+        text: String,
+    ) -> io::Result<()> {
+        let line = match self.options.format {
+            TraceFormat::Text => format!("{}, instruction {}: {}", block, index, text),
+            TraceFormat::Chrome => {
+                self.enter(depth, block)?;
+                if TraceClass::of(cmd) == Some(TraceClass::Io) {
+                    self.event(&text, "i", Some("t"), None)?;
+                }
+                text
+            }
+        };
+        if self.options.watermarks {
+            self.pending = Some(line);
+            Ok(())
+        } else if self.options.format == TraceFormat::Text {
+            writeln!(self.out, "{}", line)
+        } else {
+            Ok(())
+        }
+    }
+
+    // the instruction is over, None when it failed
+    pub fn executed(&mut self, marks: Option<Watermarks>) -> io::Result<()> {
+        match (self.pending.take(), marks, self.options.format) {
+            (Some(line), Some(marks), TraceFormat::Text) => {
+                writeln!(self.out, "{} | {}", line, marks)
+            }
+            (Some(line), None, TraceFormat::Text) => writeln!(self.out, "{}", line),
+            (Some(_), Some(marks), TraceFormat::Chrome) => {
+                self.event("stacks", "C", None, Some(marks))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // close the spans of the blocks left since the last traced
+    // instruction and open the one of `block`
+    fn enter(&mut self, depth: usize, block: BlockId) -> io::Result<()> {
+        while let Some(&(open, id)) = self.spans.last() {
+            if open < depth || (open == depth && id == block) {
+                break;
+            }
+            self.spans.pop();
+            self.event(&id.to_string(), "E", None, None)?;
+        }
+        if self.spans.last().map(|(open, _)| *open) != Some(depth) {
+            self.spans.push((depth, block));
+            self.event(&block.to_string(), "B", None, None)?;
+        }
+        Ok(())
+    }
+
+    fn event(
+        &mut self,
+        name: &str,
+        ph: &'static str,
+        s: Option<&'static str>,
+        args: Option<Watermarks>,
+    ) -> io::Result<()> {
+        let event = Event {
+            name,
+            ph,
+            ts: self.start.elapsed().as_secs_f64() * 1e6,
+            pid: 1,
+            tid: 1,
+            s,
+            args,
+        };
+        let sep = if self.events == 0 { "[\n" } else { ",\n" };
+        self.events += 1;
+        write!(self.out, "{}{}", sep, serde_json::to_string(&event)?)
+    }
+
+    // close the open spans and the array
+    fn finish(&mut self) -> io::Result<()> {
+        while let Some((_, id)) = self.spans.pop() {
+            self.event(&id.to_string(), "E", None, None)?;
+        }
+        if self.events == 0 {
+            writeln!(self.out, "[]")
+        } else {
+            writeln!(self.out, "\n]")
+        }
+    }
+}
+
+// the engine drops the tracer when it is done
+impl Drop for Tracer<'_> {
+    fn drop(&mut self) {
+        if self.options.format == TraceFormat::Chrome {
+            // nowhere left to report a failed write
+            let _ = self.finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::command_definition::{Constant, ControlFlow, Kind, MathOperator, Operator};
+
+    #[test]
+    fn test_watermarks() {
+        let marks = Watermarks {
+            integers: 2,
+            reals: 0,
+            booleans: 1,
+            strings: 0,
+            held_strings: 3,
+        };
+        let mut out = Vec::new();
+        let mut tracer = Tracer::new(&mut out, TraceOptions::new().watermarks(true));
+        let load = Command::ConstantLoad(Constant::Integer(1));
+        let add = Command::Integer(Operator::Math(MathOperator::Add));
+        tracer
+            .instruction(BlockId::Main, 0, 0, &load, "LDI1".to_owned())
+            .unwrap();
+        tracer.executed(Some(marks)).unwrap();
+        tracer
+            .instruction(BlockId::Main, 1, 0, &add, "ADDI".to_owned())
+            .unwrap();
+        tracer.executed(None).unwrap();
+        drop(tracer);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "main body, instruction 0: LDI1 | stacks I 2 R 0 B 1 S 0, 3 strings held\n\
+             main body, instruction 1: ADDI\n"
+        );
+    }
+
+    #[test]
+    fn test_filters() {
+        let jump = Command::Control(ControlFlow::Jump, 0);
+        let write = Command::Output(Kind::Integer);
+        let options = TraceOptions::new()
+            .block(BlockId::Function(1))
+            .indices(2..4)
+            .classes(vec![TraceClass::Control, TraceClass::Io])
+            .after(10);
+        assert!(options.accepts(10, BlockId::Function(1), 2, &jump));
+        assert!(options.accepts(11, BlockId::Function(1), 3, &write));
+        assert!(!options.accepts(9, BlockId::Function(1), 2, &jump));
+        assert!(!options.accepts(10, BlockId::Main, 2, &jump));
+        assert!(!options.accepts(10, BlockId::Function(1), 4, &jump));
+        assert!(!options.accepts(10, BlockId::Function(1), 2, &Command::CastInt));
+        assert!(TraceOptions::new().accepts(0, BlockId::Main, 0, &Command::CastInt));
+        assert_eq!("io".parse(), Ok(TraceClass::Io));
+    }
+
+    #[test]
+    fn test_chrome_format() {
+        let call = Command::Control(ControlFlow::Call, 0);
+        let write = Command::Output(Kind::Integer);
+        let ret = Command::Control(ControlFlow::Ret, 0);
+        let mut out = Vec::new();
+        let options = TraceOptions::new().format(TraceFormat::Chrome);
+        let mut tracer = Tracer::new(&mut out, options);
+        let steps = [
+            (BlockId::Main, 0, &call, "CALL 0"),
+            (BlockId::Function(0), 1, &write, "WRI"),
+            (BlockId::Function(0), 1, &ret, "RET"),
+            (BlockId::Main, 0, &Command::Exit, "EXT"),
+        ];
+        for (block, depth, cmd, text) in steps.iter() {
+            tracer
+                .instruction(*block, 0, *depth, cmd, text.to_string())
+                .unwrap();
+            tracer.executed(None).unwrap();
+        }
+        drop(tracer);
+        let events: Vec<serde_json::Value> = serde_json::from_slice(&out).unwrap();
+        let events: Vec<_> = events
+            .iter()
+            .map(|event| {
+                (
+                    event["ph"].as_str().unwrap(),
+                    event["name"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                ("B", "main body"),
+                ("B", "function 0"),
+                ("i", "WRI"),
+                ("E", "function 0"),
+                ("E", "main body"),
+            ]
+        );
+    }
+}
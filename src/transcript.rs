@@ -0,0 +1,239 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Instant;
+
+// Every record is a `<milliseconds> <stream> <length>` header line
+// followed by exactly `length` raw bytes and a newline, so the
+// file stays readable for text sessions and can be replayed
+// byte for byte.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stream {
+    Stdin,
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Stdin => "stdin",
+            Self::Stdout => "stdout",
+            Self::Stderr => "stderr",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "stdin" => Some(Self::Stdin),
+            "stdout" => Some(Self::Stdout),
+            "stderr" => Some(Self::Stderr),
+            _ => None,
+        }
+    }
+}
+
+pub type SharedTranscript = Rc<RefCell<Transcript>>;
+
+pub struct Transcript {
+    start: Instant,
+    file: Box<dyn Write>,
+    // first write error, reported by finish
+    error: Option<io::Error>,
+}
+
+impl Transcript {
+    pub fn create(path: &Path) -> io::Result<SharedTranscript> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(Self::new(Box::new(file)))
+    }
+
+    pub fn new(file: Box<dyn Write>) -> SharedTranscript {
+        let output = Self {
+            start: Instant::now(),
+            file,
+            error: None,
+        };
+        Rc::new(RefCell::new(output))
+    }
+
+    pub fn record(&mut self, stream: Stream, data: &[u8]) {
+        if data.is_empty() || self.error.is_some() {
+            return;
+        }
+        let millis = self.start.elapsed().as_millis();
+        let res = writeln!(self.file, "{} {} {}", millis, stream.name(), data.len())
+            .and_then(|_| self.file.write_all(data))
+            .and_then(|_| writeln!(self.file));
+        if let Err(err) = res {
+            self.error = Some(err);
+        }
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.file.flush()
+    }
+}
+
+pub struct TranscriptReader<R> {
+    inner: R,
+    log: SharedTranscript,
+}
+
+impl<R: BufRead> TranscriptReader<R> {
+    pub fn new(inner: R, log: SharedTranscript) -> Self {
+        Self { inner, log }
+    }
+}
+
+impl<R: BufRead> Read for TranscriptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.log.borrow_mut().record(Stream::Stdin, &buf[..count]);
+        Ok(count)
+    }
+}
+
+impl<R: BufRead> BufRead for TranscriptReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    // bytes are recorded when the program consumes them,
+    // not when they are buffered
+    fn consume(&mut self, amt: usize) {
+        if let Ok(buff) = self.inner.fill_buf() {
+            let count = amt.min(buff.len());
+            self.log.borrow_mut().record(Stream::Stdin, &buff[..count]);
+        }
+        self.inner.consume(amt);
+    }
+}
+
+pub struct TranscriptWriter<W> {
+    inner: W,
+    log: SharedTranscript,
+    stream: Stream,
+}
+
+impl<W: Write> TranscriptWriter<W> {
+    pub fn new(inner: W, log: SharedTranscript, stream: Stream) -> Self {
+        Self { inner, log, stream }
+    }
+}
+
+impl<W: Write> Write for TranscriptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.log.borrow_mut().record(self.stream, &buf[..count]);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// collect the stdin traffic of a transcript, ready
+// to be fed again to the program
+pub fn replay_input(path: &Path) -> io::Result<Vec<u8>> {
+    let data = std::fs::read(path)?;
+    let records = parse_records(&data)?;
+    let output = records
+        .into_iter()
+        .filter(|(stream, _)| *stream == Stream::Stdin)
+        .flat_map(|(_, data)| data.iter().copied())
+        .collect();
+    Ok(output)
+}
+
+fn parse_records(mut data: &[u8]) -> io::Result<Vec<(Stream, &[u8])>> {
+    let mut output = Vec::new();
+    while !data.is_empty() {
+        let end = match data.iter().position(|b| *b == b'\n') {
+            Some(end) => end,
+            None => return Err(malformed("truncated record header")),
+        };
+        let header = String::from_utf8_lossy(&data[..end]);
+        let mut fields = header.split(' ');
+        let _millis = fields.next();
+        let stream = fields.next().and_then(Stream::from_name);
+        let length = fields.next().and_then(|len| len.parse::<usize>().ok());
+        let (stream, length) = match (stream, length) {
+            (Some(stream), Some(length)) => (stream, length),
+            _ => return Err(malformed(&format!("bad record header `{}`", header))),
+        };
+        let begin = end + 1;
+        if data.len() < begin + length + 1 {
+            return Err(malformed("truncated record data"));
+        }
+        output.push((stream, &data[begin..begin + length]));
+        data = &data[begin + length + 1..];
+    }
+    Ok(output)
+}
+
+fn malformed(msg: &str) -> io::Error {
+    let msg = format!("malformed transcript: {}", msg);
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let buffer = Rc::new(RefCell::new(Vec::new()));
+        let log = Transcript::new(Box::new(SharedBuffer(buffer.clone())));
+
+        let mut reader = TranscriptReader::new(&b"12\nab\n"[..], log.clone());
+        let mut writer = TranscriptWriter::new(Vec::new(), log.clone(), Stream::Stdout);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        writer
+            .write_all(format!("got {}", line).as_bytes())
+            .unwrap();
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        log.borrow_mut().record(Stream::Stderr, b"oops");
+        log.borrow_mut().finish().unwrap();
+
+        let data = buffer.borrow();
+        let records = parse_records(&data).unwrap();
+        let expected: Vec<(Stream, &[u8])> = vec![
+            (Stream::Stdin, b"12\n"),
+            (Stream::Stdout, b"got 12\n"),
+            (Stream::Stdin, b"ab\n"),
+            (Stream::Stderr, b"oops"),
+        ];
+        assert_eq!(records, expected);
+    }
+
+    #[test]
+    fn test_malformed_transcript() {
+        assert!(parse_records(b"0 stdin 10\nabc\n").is_err());
+        assert!(parse_records(b"0 stdout\n").is_err());
+        assert!(parse_records(b"0 other 1\na\n").is_err());
+        assert!(parse_records(b"").unwrap().is_empty());
+    }
+}
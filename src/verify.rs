@@ -0,0 +1,255 @@
+use crate::command_definition::{
+    AddrSize, BlockId, Command, Constant, ControlFlow, Kind, MemorySize, Program, ProgramMemory,
+    LOCAL_MASK,
+};
+use crate::program_load::MemoryAccessError;
+use crate::string_memory::StringMemory;
+use std::fmt;
+
+// Structural checks on a program about to run: every operand the
+// engine indexes with must point to something, or the engine
+// panics in the middle of the run. Loaded programs pass most of
+// these already, programs built or rewritten in memory may not.
+
+#[derive(Debug)]
+pub enum VerifyError {
+    // block, instruction and missing label
+    UndefinedLabel(BlockId, usize, usize),
+    // block, instruction and missing function
+    UndefinedFunction(BlockId, usize, usize),
+    AddressOutOfRange(MemoryAccessError),
+    // block, instruction and missing string
+    UndefinedString(BlockId, usize, usize),
+    // function without a memory size
+    MissingMemory(usize),
+    ArgumentOutOfRange(usize, Kind, AddrSize),
+    // finalizer that cannot receive the exit code
+    BadFinalizer(usize),
+}
+
+impl std::error::Error for VerifyError {}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UndefinedLabel(block, index, label) => write!(
+                f,
+                "{} instruction {} jumps to label {}, which is not in the block",
+                block, index, label
+            ),
+            Self::UndefinedFunction(block, index, func) => write!(
+                f,
+                "{} instruction {} refers to function {}, which does not exist",
+                block, index, func
+            ),
+            Self::AddressOutOfRange(err) => write!(f, "{}", err),
+            Self::UndefinedString(block, index, string) => write!(
+                f,
+                "{} instruction {} loads string constant {}, which does not exist",
+                block, index, string
+            ),
+            Self::MissingMemory(func) => {
+                write!(f, "function {} has no memory declaration", func)
+            }
+            Self::ArgumentOutOfRange(i, kind, addr) => write!(
+                f,
+                "argument {} is bound to global {} {}, outside the declared memory",
+                i, kind, addr
+            ),
+            Self::BadFinalizer(func) => write!(
+                f,
+                "finalizer {} is not a function with a local integer for the exit code",
+                func
+            ),
+        }
+    }
+}
+
+pub fn verify(
+    prog: &Program,
+    prog_mem: &ProgramMemory,
+    str_mem: &StringMemory,
+) -> Result<(), VerifyError> {
+    if prog.func.len() > prog_mem.func.len() {
+        return Err(VerifyError::MissingMemory(prog_mem.func.len()));
+    }
+    for (i, (kind, addr)) in prog_mem.args.iter().enumerate() {
+        if *addr as usize >= prog_mem.main.count(kind) {
+            return Err(VerifyError::ArgumentOutOfRange(i, *kind, *addr));
+        }
+    }
+    // the engine calls the finalizer itself, the record
+    // holds the exit code in its first local integer
+    if let Some(fini) = prog.fini {
+        let size = prog_mem.func.get(fini).filter(|_| fini < prog.func.len());
+        if size.map_or(0, |size| size.integer_count) == 0 {
+            return Err(VerifyError::BadFinalizer(fini));
+        }
+    }
+    let no_memory = MemorySize::default();
+    for (id, block) in prog.blocks() {
+        let local = match id {
+            BlockId::Main => &no_memory,
+            BlockId::Function(func) => &prog_mem.func[func],
+        };
+        // memory of the function whose record is being filled
+        let mut callee = &no_memory;
+        for (index, cmd) in block.code.iter().enumerate() {
+            let function = |func: usize| {
+                prog_mem
+                    .func
+                    .get(func)
+                    .filter(|_| func < prog.func.len())
+                    .ok_or(VerifyError::UndefinedFunction(id, index, func))
+            };
+            let address = |kind: Kind, addr: AddrSize, local: &MemorySize| {
+                let (size, slot) = if addr & LOCAL_MASK == 0 {
+                    (&prog_mem.main, addr)
+                } else {
+                    (local, addr & !LOCAL_MASK)
+                };
+                let declared = size.count(&kind);
+                if (slot as usize) < declared {
+                    return Ok(());
+                }
+                Err(VerifyError::AddressOutOfRange(MemoryAccessError {
+                    block: id,
+                    index,
+                    kind,
+                    addr,
+                    declared,
+                }))
+            };
+            match cmd {
+                Command::Control(ControlFlow::Jump, label)
+                | Command::Control(ControlFlow::JumpTrue, label)
+                | Command::Control(ControlFlow::JumpFalse, label)
+                    if !block.labels.contains_key(label) =>
+                {
+                    return Err(VerifyError::UndefinedLabel(id, index, *label))
+                }
+                Command::Control(ControlFlow::Call, func) | Command::SetTimer(func) => {
+                    function(*func)?;
+                }
+                Command::NewRecord(func) => callee = function(*func)?,
+                Command::MemoryLoad(kind, addr) | Command::MemoryStore(kind, addr) => {
+                    address(*kind, *addr, local)?
+                }
+                Command::LoadSlot(kind, slot) | Command::StoreSlot(kind, slot) => {
+                    address(*kind, slot.addr(), local)?
+                }
+                // local parameters go to the record of the callee
+                Command::StoreParam(kind, addr) => address(*kind, *addr, callee)?,
+                Command::ConstantLoad(Constant::Str(string)) if !str_mem.contains(*string) => {
+                    return Err(VerifyError::UndefinedString(id, index, *string))
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::asm::assemble;
+    use crate::program_load::{parse_data, LoadOptions};
+
+    fn load(source: &str) -> (Program, ProgramMemory, StringMemory) {
+        let data = assemble(source).unwrap();
+        let (prog, prog_mem, str_mem, _) = parse_data(&data, &LoadOptions::default()).unwrap();
+        (prog, prog_mem, str_mem)
+    }
+
+    #[test]
+    fn test_verify() {
+        let (mut prog, prog_mem, str_mem) = load(
+            "
+            INIT 1 0 0 1
+            LDSC \"hi\"
+            STRS 0
+            PARAM 0
+            LDI 0
+            STRIP local 0
+            CALL 0
+            FUNC
+            INIT 1 0 0 0
+            RET
+        ",
+        );
+        verify(&prog, &prog_mem, &str_mem).unwrap();
+
+        prog.body.code[0] = Command::ConstantLoad(Constant::Str(99));
+        let err = verify(&prog, &prog_mem, &str_mem);
+        assert!(matches!(
+            err,
+            Err(VerifyError::UndefinedString(BlockId::Main, 0, 99))
+        ));
+
+        let (prog, prog_mem, str_mem) = load(
+            "
+            INIT 0 0 0 0
+            LBL 1
+            JUMP 2
+        ",
+        );
+        let err = verify(&prog, &prog_mem, &str_mem);
+        assert!(matches!(
+            err,
+            Err(VerifyError::UndefinedLabel(BlockId::Main, 1, 2))
+        ));
+
+        // the loader rejects calls to missing functions too
+        let (mut prog, prog_mem, str_mem) = load(
+            "
+            INIT 0 0 0 0
+            PARAM 0
+            CALL 0
+            FUNC
+            INIT 0 0 0 0
+            RET
+        ",
+        );
+        prog.body.code[0] = Command::NewRecord(3);
+        prog.body.code[1] = Command::Control(ControlFlow::Call, 3);
+        let err = verify(&prog, &prog_mem, &str_mem);
+        assert!(matches!(
+            err,
+            Err(VerifyError::UndefinedFunction(BlockId::Main, 0, 3))
+        ));
+
+        let (prog, prog_mem, str_mem) = load(
+            "
+            INIT 0 0 0 0
+            PARAM 0
+            LDI0
+            STRIP local 1
+            CALL 0
+            FUNC
+            INIT 1 0 0 0
+            RET
+        ",
+        );
+        let err = verify(&prog, &prog_mem, &str_mem);
+        assert!(matches!(err, Err(VerifyError::AddressOutOfRange(_))));
+
+        let (mut prog, mut prog_mem, str_mem) = load(
+            "
+            INIT 0 0 0 0
+            FINI 0
+            FUNC
+            INIT 1 0 0 0
+            RET
+        ",
+        );
+        verify(&prog, &prog_mem, &str_mem).unwrap();
+        prog_mem.func[0].integer_count = 0;
+        let err = verify(&prog, &prog_mem, &str_mem);
+        assert!(matches!(err, Err(VerifyError::BadFinalizer(0))));
+        prog.fini = Some(2);
+        let err = verify(&prog, &prog_mem, &str_mem);
+        assert!(matches!(err, Err(VerifyError::BadFinalizer(2))));
+    }
+}